@@ -1,3 +1,16 @@
+pub mod context;
+pub mod persona;
+pub mod runner;
+pub mod tools;
+
+pub use context::{estimate_tokens, CompactionPolicy, ContextConfig};
+pub use persona::Persona;
+pub use runner::{AgentEvent, AgentRun, RunLimits, StopReason, TextGenerator, ToolExecutor};
+pub use tools::ToolCallFormat;
+
+use crate::types::{Message, MessagePart, MessagePartTool};
+use jarvis_mcp::{McpTool, ToolCallParams, ToolCallResult};
+
 /// JARVIS system prompt - sophisticated AI assistant from Iron Man
 pub const SYSTEM_PROMPT: &str = r#"You are JARVIS, the sophisticated AI assistant from Iron Man.
 
@@ -38,7 +51,10 @@ Thank you for the conversation! Have a great day.
 /// Agent for managing conversations and AI interactions
 pub struct Agent {
     system_prompt: String,
-    conversation_end_keyword: String,
+    persona: Persona,
+    tools: Vec<McpTool>,
+    tool_format: ToolCallFormat,
+    context_config: ContextConfig,
 }
 
 impl Agent {
@@ -49,20 +65,118 @@ impl Agent {
 
     /// Create a new agent with a custom end keyword
     pub fn with_keyword(keyword: &str) -> Self {
-        Self {
-            system_prompt: Self::build_system_prompt(keyword),
-            conversation_end_keyword: keyword.to_string(),
-        }
+        Self::with_persona_unchecked(Persona {
+            end_keyword: keyword.to_string(),
+            ..Persona::jarvis()
+        })
+    }
+
+    fn with_persona_unchecked(persona: Persona) -> Self {
+        let mut agent = Self {
+            system_prompt: String::new(),
+            persona,
+            tools: Vec::new(),
+            tool_format: ToolCallFormat::Hermes,
+            context_config: ContextConfig::default(),
+        };
+        agent.build_system_prompt();
+        agent
     }
 
     /// Build the complete system prompt
-    fn build_system_prompt(keyword: &str) -> String {
-        let mut prompt = SYSTEM_PROMPT.to_string();
-        prompt.push_str("\n\n");
-        prompt.push_str(&INSTRUCTIONS.join("\n"));
-        prompt.push_str("\n\n");
-        prompt.push_str(&get_end_instructions(keyword));
-        prompt
+    fn build_system_prompt(&mut self) {
+        let mut prompt = self.persona_prompt();
+        if !self.tools.is_empty() {
+            prompt.push_str("\n\n");
+            prompt.push_str(&self.tool_format.render_tools(&self.tools));
+        }
+        self.system_prompt = prompt;
+    }
+
+    /// Set the tools the model may call, e.g. the active tools of connected MCP servers
+    pub fn set_tools(&mut self, tools: Vec<McpTool>) {
+        self.tools = tools;
+        self.build_system_prompt();
+    }
+
+    /// Get the tools the model may call
+    pub fn tools(&self) -> &[McpTool] {
+        &self.tools
+    }
+
+    /// Set the syntax the model calls tools with; use the
+    /// [`ChatTemplate::tool_format`](crate::models::ChatTemplate::tool_format) of the loaded model
+    pub fn set_tool_format(&mut self, format: ToolCallFormat) {
+        self.tool_format = format;
+        self.build_system_prompt();
+    }
+
+    /// Get the syntax the model calls tools with
+    pub fn tool_format(&self) -> ToolCallFormat {
+        self.tool_format
+    }
+
+    /// Set how the history is fitted into the model's context window
+    pub fn set_context_config(&mut self, config: ContextConfig) {
+        self.context_config = config;
+    }
+
+    /// Get how the history is fitted into the model's context window
+    pub fn context_config(&self) -> &ContextConfig {
+        &self.context_config
+    }
+
+    /// Turn model output into an assistant message with its tool calls
+    ///
+    /// Calls to tools that are not available are answered with an error
+    /// right away, so the model can correct itself on the next turn.
+    pub fn parse_response(&self, output: &str) -> Message {
+        let (text, mut calls) = self.tool_format.parse(output);
+        for call in &mut calls {
+            if !self.tools.iter().any(|tool| tool.name == call.function_name) {
+                call.response = format!("Error: there is no tool named {}", call.function_name);
+            }
+        }
+        Message::assistant_with_tool_calls(text, calls)
+    }
+
+    /// Get the calls in a message that have not been answered yet
+    pub fn pending_tool_calls(message: &Message) -> Vec<&MessagePartTool> {
+        message.tool_calls().into_iter().filter(|call| call.response.is_empty()).collect()
+    }
+
+    /// Get the parameters to run a call with on an MCP server
+    pub fn tool_call_params(call: &MessagePartTool) -> Result<ToolCallParams, String> {
+        let arguments = match &call.parameters {
+            serde_json::Value::Object(map) => map.clone().into_iter().collect(),
+            serde_json::Value::Null => Default::default(),
+            other => return Err(format!("Arguments of {} must be an object, got {}", call.function_name, other)),
+        };
+        Ok(ToolCallParams {
+            name: call.function_name.clone(),
+            arguments,
+        })
+    }
+
+    /// Record the result of a call in the message that made it, so the model
+    /// sees the response on the next turn
+    pub fn record_tool_result(message: &mut Message, call_id: &str, result: &ToolCallResult) -> Result<(), String> {
+        let call = message
+            .message_parts
+            .iter_mut()
+            .find_map(|part| match part {
+                MessagePart::ToolCall(call) if call.base.id == call_id => Some(call),
+                _ => None,
+            })
+            .ok_or_else(|| format!("Message has no tool call {}", call_id))?;
+        call.response = if !result.success {
+            format!("Error: {}", result.error.as_deref().unwrap_or("the tool failed"))
+        } else if result.result.is_empty() {
+            "Done".to_string()
+        } else {
+            result.result.clone()
+        };
+        Ok(())
     }
 
     /// Get the system prompt
@@ -70,14 +184,19 @@ impl Agent {
         &self.system_prompt
     }
 
+    /// Get the persona the agent speaks as
+    pub fn persona(&self) -> &Persona {
+        &self.persona
+    }
+
     /// Get the conversation end keyword
     pub fn conversation_end_keyword(&self) -> &str {
-        &self.conversation_end_keyword
+        &self.persona.end_keyword
     }
 
     /// Check if a response contains the end keyword
     pub fn is_conversation_ended(&self, response: &str) -> bool {
-        response.contains(&self.conversation_end_keyword)
+        response.contains(&self.persona.end_keyword)
     }
 }
 
@@ -105,6 +224,53 @@ mod tests {
         assert!(agent.system_prompt().contains("GOODBYE"));
     }
 
+    fn weather_tool() -> McpTool {
+        McpTool {
+            name: "get_weather".to_string(),
+            description: "Current weather for a city".to_string(),
+            input_schema: serde_json::json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        }
+    }
+
+    #[test]
+    fn test_tools_in_system_prompt() {
+        let mut agent = Agent::new();
+        assert!(!agent.system_prompt().contains("# Tools"));
+        agent.set_tools(vec![weather_tool()]);
+        assert!(agent.system_prompt().contains("<tools>"));
+        assert!(agent.system_prompt().contains("Current weather for a city"));
+        agent.set_tool_format(ToolCallFormat::Json);
+        assert!(!agent.system_prompt().contains("<tools>"));
+        assert!(agent.system_prompt().contains("CONVERSATION_ENDED"));
+    }
+
+    #[test]
+    fn test_tool_call_round_trip() {
+        let mut agent = Agent::new();
+        agent.set_tools(vec![weather_tool()]);
+        let output = "<tool_call>{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}</tool_call>\n<tool_call>{\"name\": \"launch\"}</tool_call>";
+        let mut message = agent.parse_response(output);
+        assert_eq!(message.tool_calls().len(), 2);
+        assert!(message.text().is_empty());
+
+        // The unknown tool is answered at once; the other waits for the MCP server
+        let pending = Agent::pending_tool_calls(&message);
+        assert_eq!(pending.len(), 1);
+        let params = Agent::tool_call_params(pending[0]).unwrap();
+        assert_eq!(params.name, "get_weather");
+        assert_eq!(params.arguments["city"], "Oslo");
+
+        let id = pending[0].base.id.clone();
+        let result = ToolCallResult { success: true, result: "Rain, 8°C".to_string(), error: None };
+        Agent::record_tool_result(&mut message, &id, &result).unwrap();
+        assert!(Agent::pending_tool_calls(&message).is_empty());
+        assert!(Agent::record_tool_result(&mut message, "missing", &result).is_err());
+
+        let prompt = crate::models::ChatTemplate::ChatMl.apply(&[message]);
+        assert!(prompt.contains("<tool_response>\nRain, 8°C\n</tool_response>"));
+        assert!(prompt.contains("there is no tool named launch"));
+    }
+
     #[test]
     fn test_conversation_ended() {
         let agent = Agent::new();
@@ -0,0 +1,279 @@
+//! Context-window management
+//!
+//! The system prompt, the history and the reply must fit in the model's
+//! context window together. When the history does not, a
+//! [`CompactionPolicy`] shrinks it; the oldest messages are dropped as a last
+//! resort, and the latest message is always kept.
+
+use super::runner::TextGenerator;
+use super::Agent;
+use crate::types::{Message, MessagePart};
+use serde::{Deserialize, Serialize};
+
+/// Tokens for the role markers around each message
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Marker appended to cut tool responses
+const TRUNCATED: &str = " [truncated]";
+
+/// Rough token count for models without a tokenizer: about four characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// How to shrink a history that does not fit in the context window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompactionPolicy {
+    /// Drop the oldest messages
+    DropOldest,
+    /// Cut tool responses down to `max_tool_response_tokens` first
+    TruncateToolResponses,
+    /// Replace older messages with a summary written by the model
+    Summarize,
+}
+
+/// Token budget for the conversation sent to the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextConfig {
+    /// Tokens kept free for the reply, usually `InferenceConfig::max_tokens`
+    pub reserve_tokens: usize,
+    pub policy: CompactionPolicy,
+    /// Longest tool response kept by [`CompactionPolicy::TruncateToolResponses`]
+    pub max_tool_response_tokens: usize,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            reserve_tokens: 256,
+            policy: CompactionPolicy::DropOldest,
+            max_tool_response_tokens: 256,
+        }
+    }
+}
+
+impl Agent {
+    /// Tokens a message takes up in the prompt, tool calls and responses included
+    pub fn count_message_tokens<G: TextGenerator + ?Sized>(&self, model: &G, message: &Message) -> usize {
+        let calls: usize = message
+            .tool_calls()
+            .iter()
+            .map(|call| {
+                let response = if call.response.is_empty() {
+                    0
+                } else {
+                    model.count_tokens(&self.tool_format().render_response(&call.function_name, &call.response))
+                };
+                model.count_tokens(&self.tool_format().render_call(&call.function_name, &call.parameters)) + response
+            })
+            .sum();
+        MESSAGE_OVERHEAD + model.count_tokens(&message.text()) + calls
+    }
+
+    /// Shrink a history (without the system prompt) to fit the model's context window
+    ///
+    /// Histories that fit, and models that do not report a context length,
+    /// are returned unchanged.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Message>)` that fits alongside the system prompt and the reply
+    /// * `Err(String)` if even the latest message does not fit, or summarizing failed
+    pub fn compact_history<G: TextGenerator + ?Sized>(&self, model: &G, history: &[Message]) -> Result<Vec<Message>, String> {
+        let Some(context_length) = model.context_length() else {
+            return Ok(history.to_vec());
+        };
+        let config = self.context_config();
+        let system_tokens = MESSAGE_OVERHEAD + model.count_tokens(self.system_prompt());
+        let budget = context_length.saturating_sub(config.reserve_tokens + system_tokens);
+
+        let mut history = history.to_vec();
+        let total = |history: &[Message]| history.iter().map(|m| self.count_message_tokens(model, m)).sum::<usize>();
+        if total(&history) <= budget {
+            return Ok(history);
+        }
+
+        // A summary stays ahead of the messages dropped below
+        let mut keep_first = 0;
+        match config.policy {
+            CompactionPolicy::DropOldest => {}
+            CompactionPolicy::TruncateToolResponses => {
+                for part in history.iter_mut().flat_map(|m| m.message_parts.iter_mut()) {
+                    if let MessagePart::ToolCall(call) = part {
+                        if model.count_tokens(&call.response) > config.max_tool_response_tokens {
+                            call.response = truncate_to_tokens(model, &call.response, config.max_tool_response_tokens);
+                        }
+                    }
+                }
+            }
+            CompactionPolicy::Summarize => {
+                // Keep the recent messages that fit in half the budget
+                let mut split = history.len() - 1;
+                while split > 0 && total(&history[split - 1..]) <= budget / 2 {
+                    split -= 1;
+                }
+                if split > 0 {
+                    let summary = self.summarize(model, &history[..split], budget)?;
+                    history.splice(..split, [summary]);
+                    keep_first = 1;
+                }
+            }
+        }
+
+        while total(&history) > budget && history.len() > keep_first + 1 {
+            history.remove(keep_first);
+        }
+        if total(&history) > budget && keep_first > 0 {
+            history.remove(0);
+        }
+        if total(&history) > budget {
+            return Err("The latest message does not fit in the context window".to_string());
+        }
+        Ok(history)
+    }
+
+    /// Ask the model to summarize messages into a system note
+    fn summarize<G: TextGenerator + ?Sized>(&self, model: &G, messages: &[Message], budget: usize) -> Result<Message, String> {
+        let instructions = Message::system(
+            "Summarize the conversation below in a few sentences. Keep names, facts, decisions and open questions."
+                .to_string(),
+        );
+        let mut lines: Vec<String> = messages
+            .iter()
+            .map(|message| {
+                let mut line = format!("{:?}: {}", message.role, message.text());
+                for call in message.tool_calls() {
+                    line.push_str(&format!("\n[{} returned: {}]", call.function_name, call.response));
+                }
+                line
+            })
+            .collect();
+        // The summary prompt has to fit as well
+        let available = budget.saturating_sub(self.count_message_tokens(model, &instructions) + MESSAGE_OVERHEAD);
+        while lines.len() > 1 && model.count_tokens(&lines.join("\n")) > available {
+            lines.remove(0);
+        }
+        let transcript = truncate_to_tokens(model, &lines.join("\n"), available);
+
+        let summary = model.generate(&[instructions, Message::user(transcript)])?;
+        Ok(Message::system(format!("Summary of the earlier conversation: {}", summary.trim())))
+    }
+}
+
+/// The longest prefix of `text` within `max_tokens`, marked as cut
+fn truncate_to_tokens<G: TextGenerator + ?Sized>(model: &G, text: &str, max_tokens: usize) -> String {
+    if model.count_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+    let limit = max_tokens.saturating_sub(model.count_tokens(TRUNCATED));
+    // Byte offsets of the character boundaries; the whole text does not fit
+    let boundaries: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    let (mut low, mut high) = (0, boundaries.len() - 1);
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        if model.count_tokens(&text[..boundaries[mid]]) <= limit {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    format!("{}{}", text[..boundaries[low]].trim_end(), TRUNCATED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MessagePartTool;
+    use std::cell::RefCell;
+
+    /// One token per word, with a small window; summaries are canned
+    struct TinyModel {
+        context_length: usize,
+        summarized: RefCell<Vec<String>>,
+    }
+
+    impl TinyModel {
+        fn new(context_length: usize) -> Self {
+            Self { context_length, summarized: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl TextGenerator for TinyModel {
+        fn generate(&self, messages: &[Message]) -> Result<String, String> {
+            self.summarized.borrow_mut().push(messages[1].text());
+            Ok("The user asked about the weather.".to_string())
+        }
+
+        fn count_tokens(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+
+        fn context_length(&self) -> Option<usize> {
+            Some(self.context_length)
+        }
+    }
+
+    fn agent(policy: CompactionPolicy) -> (Agent, usize) {
+        let mut agent = Agent::new();
+        agent.set_context_config(ContextConfig { reserve_tokens: 10, policy, max_tool_response_tokens: 3 });
+        let system_tokens = MESSAGE_OVERHEAD + agent.system_prompt().split_whitespace().count();
+        (agent, system_tokens + 10)
+    }
+
+    fn history() -> Vec<Message> {
+        // 4 overhead + 5 words each
+        (0..6).map(|i| Message::user(format!("message {} with some words", i))).collect()
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let (agent, fixed) = agent(CompactionPolicy::DropOldest);
+        let history = history();
+        assert_eq!(agent.compact_history(&TinyModel::new(fixed + 60), &history).unwrap().len(), 6);
+
+        let compacted = agent.compact_history(&TinyModel::new(fixed + 20), &history).unwrap();
+        assert_eq!(compacted.iter().map(Message::text).collect::<Vec<_>>(), vec![
+            "message 4 with some words",
+            "message 5 with some words"
+        ]);
+        assert!(agent.compact_history(&TinyModel::new(fixed + 5), &history).is_err());
+    }
+
+    #[test]
+    fn test_truncate_tool_responses() {
+        let (agent, fixed) = agent(CompactionPolicy::TruncateToolResponses);
+        let mut call = MessagePartTool::new("read_file".to_string(), serde_json::json!({}));
+        call.response = "one two three four five six seven eight".to_string();
+        let history = vec![
+            Message::user("read it".to_string()),
+            Message::assistant_with_tool_calls(String::new(), vec![call]),
+        ];
+        let full: usize = history.iter().map(|m| agent.count_message_tokens(&TinyModel::new(0), m)).sum();
+
+        let compacted = agent.compact_history(&TinyModel::new(fixed + full - 4), &history).unwrap();
+        assert_eq!(compacted.len(), 2);
+        assert_eq!(compacted[1].tool_calls()[0].response, "one two [truncated]");
+    }
+
+    #[test]
+    fn test_summarize() {
+        let (agent, fixed) = agent(CompactionPolicy::Summarize);
+        let model = TinyModel::new(fixed + 40);
+        let compacted = agent.compact_history(&model, &history()).unwrap();
+        assert_eq!(compacted[0].role, crate::types::MessageRole::System);
+        assert_eq!(compacted[0].text(), "Summary of the earlier conversation: The user asked about the weather.");
+        assert_eq!(compacted.last().unwrap().text(), "message 5 with some words");
+        assert!(compacted.len() < 6);
+        // Only the part of the older history that fits the summary prompt is sent
+        assert!(model.summarized.borrow()[0].ends_with("User: message 3 with some words"));
+
+        // Models that do not report a window are left alone
+        struct Unbounded;
+        impl TextGenerator for Unbounded {
+            fn generate(&self, _messages: &[Message]) -> Result<String, String> {
+                Ok(String::new())
+            }
+        }
+        assert_eq!(agent.compact_history(&Unbounded, &history()).unwrap().len(), 6);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+}
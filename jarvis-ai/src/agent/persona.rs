@@ -0,0 +1,253 @@
+//! Personas
+//!
+//! A [`Persona`] describes who the assistant is: its system prompt, extra
+//! instructions, end keyword, preferred voice and default model. Personas are
+//! plain TOML or JSON files, so a new personality needs no rebuild.
+//!
+//! The system prompt and instructions are templates: `{{name}}` is replaced
+//! with the value of the variable `name`. The persona's own `name` and today's
+//! `date` (`YYYY-MM-DD`) are always defined; everything else, such as
+//! `user_name` or `location`, comes from `variables`.
+
+use super::runner::now_ms;
+use super::{get_end_instructions, Agent, INSTRUCTIONS, SYSTEM_PROMPT};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+fn default_end_keyword() -> String {
+    "CONVERSATION_ENDED".to_string()
+}
+
+/// Personality of the assistant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Persona {
+    pub name: String,
+    /// Template for the start of the system prompt
+    pub system_prompt: String,
+    /// Templates for extra rules, one line each
+    #[serde(default)]
+    pub instructions: Vec<String>,
+    /// Values for the template variables
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// Keyword the model adds to its reply when the user ends the conversation
+    #[serde(default = "default_end_keyword")]
+    pub end_keyword: String,
+    /// Name of the speech synthesis voice to answer with
+    #[serde(default)]
+    pub voice: Option<String>,
+    /// Registry id of the model to load for this persona
+    #[serde(default)]
+    pub default_model: Option<String>,
+}
+
+impl Persona {
+    /// The built-in JARVIS persona
+    pub fn jarvis() -> Self {
+        Self {
+            name: "JARVIS".to_string(),
+            system_prompt: SYSTEM_PROMPT.to_string(),
+            instructions: INSTRUCTIONS.iter().map(|i| i.to_string()).collect(),
+            variables: BTreeMap::new(),
+            end_keyword: default_end_keyword(),
+            voice: None,
+            default_model: None,
+        }
+    }
+
+    /// Parse a persona from JSON
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let persona: Self = serde_json::from_str(json).map_err(|e| format!("Invalid persona: {}", e))?;
+        persona.validate()?;
+        Ok(persona)
+    }
+
+    /// Parse a persona from TOML
+    pub fn from_toml(toml: &str) -> Result<Self, String> {
+        let persona: Self = toml::from_str(toml).map_err(|e| format!("Invalid persona: {}", e))?;
+        persona.validate()?;
+        Ok(persona)
+    }
+
+    /// Load a persona from a `.json` or `.toml` file
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::from_json(&text),
+            Some("toml") => Self::from_toml(&text),
+            _ => Err(format!("{} is not a .json or .toml persona", path.display())),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Set a template variable, e.g. the user's name
+    pub fn with_variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Check that the persona is complete and every template variable is defined
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Persona has no name".to_string());
+        }
+        if self.system_prompt.trim().is_empty() {
+            return Err(format!("Persona {} has no system prompt", self.name));
+        }
+        if self.end_keyword.is_empty() || self.end_keyword.contains(char::is_whitespace) {
+            return Err(format!("End keyword of persona {} must be a single word", self.name));
+        }
+        self.render().map(|_| ())
+    }
+
+    /// Render the system prompt followed by the instructions
+    ///
+    /// # Returns
+    /// * `Ok(String)` with every variable filled in
+    /// * `Err(String)` naming an undefined variable or an unclosed `{{`
+    pub fn render(&self) -> Result<String, String> {
+        let mut prompt = self.render_template(&self.system_prompt)?;
+        let instructions = self
+            .instructions
+            .iter()
+            .map(|instruction| self.render_template(instruction))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !instructions.is_empty() {
+            prompt.push_str("\n\n");
+            prompt.push_str(&instructions.join("\n"));
+        }
+        Ok(prompt)
+    }
+
+    fn variable(&self, name: &str) -> Option<String> {
+        self.variables.get(name).cloned().or_else(|| match name {
+            "name" => Some(self.name.clone()),
+            "date" => Some(date_from_days((now_ms() / 86_400_000.0).floor() as i64)),
+            _ => None,
+        })
+    }
+
+    fn render_template(&self, template: &str) -> Result<String, String> {
+        let mut output = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            output.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = after
+                .find("}}")
+                .ok_or_else(|| format!("Unclosed {{{{ in persona {}", self.name))?;
+            let name = after[..end].trim();
+            let value = self
+                .variable(name)
+                .ok_or_else(|| format!("Persona {} uses undefined variable {}", self.name, name))?;
+            output.push_str(&value);
+            rest = &after[end + 2..];
+        }
+        output.push_str(rest);
+        Ok(output)
+    }
+}
+
+impl Default for Persona {
+    fn default() -> Self {
+        Self::jarvis()
+    }
+}
+
+/// `YYYY-MM-DD` of a day counted from the Unix epoch
+fn date_from_days(days: i64) -> String {
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+impl Agent {
+    /// Create an agent with a persona's prompt and end keyword
+    ///
+    /// # Returns
+    /// * `Ok(Agent)` if the persona is valid
+    /// * `Err(String)` describing what is wrong with it
+    pub fn from_persona(persona: Persona) -> Result<Self, String> {
+        persona.validate()?;
+        Ok(Self::with_persona_unchecked(persona))
+    }
+
+    /// Rendered persona prompt, with the end-of-conversation instructions
+    pub(super) fn persona_prompt(&self) -> String {
+        // Personas are validated before an agent is built from them
+        let prompt = self.persona().render().unwrap_or_else(|_| self.persona().system_prompt.clone());
+        format!("{}\n\n{}", prompt, get_end_instructions(&self.persona().end_keyword))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUTLER: &str = r#"
+        name = "Alfred"
+        system_prompt = "You are {{name}}, butler to {{ user_name }} in {{location}}. Today is {{date}}."
+        instructions = ["Address the user as Master {{user_name}}", "Be discreet"]
+        end_keyword = "GOODNIGHT"
+        voice = "en-GB-Male"
+        default_model = "tinyllama-q8"
+
+        [variables]
+        user_name = "Bruce"
+        location = "Gotham"
+    "#;
+
+    #[test]
+    fn test_persona_from_toml() {
+        let persona = Persona::from_toml(BUTLER).unwrap();
+        assert_eq!(persona.voice.as_deref(), Some("en-GB-Male"));
+        assert_eq!(persona.default_model.as_deref(), Some("tinyllama-q8"));
+
+        let agent = Agent::from_persona(persona.with_variable("location", "Wayne Manor")).unwrap();
+        let prompt = agent.system_prompt();
+        assert!(prompt.starts_with("You are Alfred, butler to Bruce in Wayne Manor. Today is 2"));
+        assert!(prompt.contains("\n\nAddress the user as Master Bruce\nBe discreet\n\n# End conversation"));
+        assert!(!prompt.contains("JARVIS"));
+        assert_eq!(agent.conversation_end_keyword(), "GOODNIGHT");
+        assert!(agent.is_conversation_ended("Goodnight, sir.\nGOODNIGHT"));
+
+        let json = serde_json::to_string(agent.persona()).unwrap();
+        assert_eq!(Persona::from_json(&json).unwrap(), *agent.persona());
+    }
+
+    #[test]
+    fn test_persona_validation() {
+        let minimal = Persona::from_json(r#"{"name": "Friday", "system_prompt": "You are {{name}}."}"#).unwrap();
+        assert_eq!(minimal.end_keyword, "CONVERSATION_ENDED");
+        assert_eq!(minimal.render().unwrap(), "You are Friday.");
+
+        let invalid = [
+            r#"{"name": "Friday", "system_prompt": "Hello {{user_name}}"}"#,
+            r#"{"name": "Friday", "system_prompt": "Hello {{user_name"}"#,
+            r#"{"name": "Friday", "system_prompt": "Hi", "end_keyword": "GOOD BYE"}"#,
+            r#"{"name": "", "system_prompt": "Hi"}"#,
+            r#"{"name": "Friday"}"#,
+        ];
+        for json in invalid {
+            assert!(Persona::from_json(json).is_err(), "{}", json);
+        }
+        assert!(Agent::from_persona(Persona { system_prompt: "{{mood}}".to_string(), ..Persona::jarvis() }).is_err());
+    }
+
+    #[test]
+    fn test_date_from_days() {
+        assert_eq!(date_from_days(0), "1970-01-01");
+        assert_eq!(date_from_days(19_782), "2024-02-29");
+        assert_eq!(date_from_days(-1), "1969-12-31");
+    }
+}
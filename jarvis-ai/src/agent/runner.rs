@@ -0,0 +1,386 @@
+//! Agent run loop
+//!
+//! [`Agent::run`] generates a reply, runs the tools it calls, adds their
+//! responses to the conversation and generates again, until the model
+//! answers without calling tools or a [`RunLimits`] budget runs out.
+
+use super::context::estimate_tokens;
+use super::Agent;
+use crate::types::{Message, MessagePartTool};
+use jarvis_mcp::{McpClient, ToolCallParams, ToolCallResult};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Anything that can reply to a conversation
+pub trait TextGenerator {
+    /// Generate the next assistant message from a conversation
+    fn generate(&self, messages: &[Message]) -> Result<String, String>;
+
+    /// Number of tokens `text` takes up in the prompt
+    fn count_tokens(&self, text: &str) -> usize {
+        estimate_tokens(text)
+    }
+
+    /// Tokens the model can attend to, prompt and reply together; `None` if unbounded
+    fn context_length(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Runs the tools the model calls
+#[allow(async_fn_in_trait)]
+pub trait ToolExecutor {
+    /// Run a tool; failures are reported in the result so the model can see them
+    async fn execute(&mut self, params: ToolCallParams) -> ToolCallResult;
+}
+
+/// Local tools, e.g. the built-in MCP servers
+impl<F: FnMut(ToolCallParams) -> ToolCallResult> ToolExecutor for F {
+    async fn execute(&mut self, params: ToolCallParams) -> ToolCallResult {
+        self(params)
+    }
+}
+
+impl ToolExecutor for McpClient {
+    async fn execute(&mut self, params: ToolCallParams) -> ToolCallResult {
+        self.call_tool(params).await.unwrap_or_else(|e| failure(e.to_string()))
+    }
+}
+
+/// Several MCP servers; each call goes to the server that has the tool
+impl ToolExecutor for Vec<McpClient> {
+    async fn execute(&mut self, params: ToolCallParams) -> ToolCallResult {
+        let server = self
+            .iter_mut()
+            .find(|client| client.state().tools.iter().any(|tool| tool.name == params.name));
+        match server {
+            Some(client) => client.execute(params).await,
+            None => failure(format!("No connected server has a tool named {}", params.name)),
+        }
+    }
+}
+
+fn failure(error: String) -> ToolCallResult {
+    ToolCallResult {
+        success: false,
+        result: String::new(),
+        error: Some(error),
+    }
+}
+
+/// Budget for one run of the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunLimits {
+    /// Most replies to generate, including the final answer
+    pub max_iterations: usize,
+    /// Wall-clock time after which no further reply is generated
+    pub max_time: Option<Duration>,
+}
+
+impl Default for RunLimits {
+    fn default() -> Self {
+        Self {
+            max_iterations: 5,
+            max_time: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Why a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopReason {
+    /// The model replied without calling tools
+    Answered,
+    /// The model was still calling tools after `max_iterations` replies
+    MaxIterations,
+    /// `max_time` passed while the model was still calling tools
+    TimeLimit,
+}
+
+/// Intermediate step of a run, for showing progress
+#[derive(Debug, Clone)]
+pub enum AgentEvent {
+    /// The model replied; its tool calls run next
+    Response(Message),
+    /// A tool is about to run
+    ToolStarted(MessagePartTool),
+    /// A tool has run and its response is filled in
+    ToolFinished(MessagePartTool),
+}
+
+/// Outcome of [`Agent::run`]
+#[derive(Debug, Clone)]
+pub struct AgentRun {
+    /// Messages the run added to the conversation, tool responses included
+    pub messages: Vec<Message>,
+    /// The history as compacted for the run, followed by `messages`; pass
+    /// it to the next run so a summary of older messages is not redone
+    pub history: Vec<Message>,
+    pub stop_reason: StopReason,
+}
+
+impl AgentRun {
+    /// Text of the last reply
+    pub fn answer(&self) -> String {
+        self.messages.last().map(Message::text).unwrap_or_default()
+    }
+}
+
+/// Milliseconds since the Unix epoch; `std::time::Instant` panics in the browser
+pub(crate) fn now_ms() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+        now.map_or(0.0, |elapsed| elapsed.as_secs_f64() * 1000.0)
+    }
+}
+
+impl Agent {
+    /// Reply to a conversation, running tools until the model answers
+    ///
+    /// # Arguments
+    /// * `model` - Generates replies, e.g. the [`InferenceEngine`](crate::InferenceEngine)
+    /// * `tools` - Runs the calls the model makes
+    /// * `history` - Conversation so far, without the system prompt; compacted to
+    ///   fit the model's context window as in [`Agent::compact_history`]. Pass
+    ///   the previous run's [`AgentRun::history`] to keep its compaction
+    /// * `limits` - Budget for the run
+    /// * `on_event` - Called with each reply and tool call as it happens
+    ///
+    /// # Returns
+    /// * `Ok(AgentRun)` with the new messages and why the run stopped
+    /// * `Err(String)` if generation failed
+    pub async fn run<G: TextGenerator + ?Sized, E: ToolExecutor + ?Sized>(
+        &self,
+        model: &G,
+        tools: &mut E,
+        history: &[Message],
+        limits: &RunLimits,
+        mut on_event: impl FnMut(&AgentEvent),
+    ) -> Result<AgentRun, String> {
+        let start = now_ms();
+        let mut conversation = history.to_vec();
+        let mut messages = Vec::new();
+
+        for _ in 0..limits.max_iterations {
+            // Compacting the working copy keeps a summary from being redone every step
+            conversation = self.compact_history(model, &conversation)?;
+            let mut prompt = vec![Message::system(self.system_prompt().to_string())];
+            prompt.extend_from_slice(&conversation);
+            let output = model.generate(&prompt)?;
+            let mut message = self.parse_response(&output);
+            on_event(&AgentEvent::Response(message.clone()));
+
+            let calls: Vec<MessagePartTool> = message.tool_calls().into_iter().cloned().collect();
+            if calls.is_empty() {
+                conversation.push(message.clone());
+                messages.push(message);
+                return Ok(AgentRun { messages, history: conversation, stop_reason: StopReason::Answered });
+            }
+
+            // Calls to unknown tools were answered when parsing
+            for call in calls {
+                if call.response.is_empty() {
+                    on_event(&AgentEvent::ToolStarted(call.clone()));
+                    let result = match Agent::tool_call_params(&call) {
+                        Ok(params) => tools.execute(params).await,
+                        Err(e) => failure(e),
+                    };
+                    Agent::record_tool_result(&mut message, &call.base.id, &result)?;
+                }
+                if let Some(done) = message.tool_calls().into_iter().find(|done| done.base.id == call.base.id) {
+                    on_event(&AgentEvent::ToolFinished(done.clone()));
+                }
+            }
+            conversation.push(message.clone());
+            messages.push(message);
+
+            if limits.max_time.is_some_and(|max| now_ms() - start >= max.as_secs_f64() * 1000.0) {
+                return Ok(AgentRun { messages, history: conversation, stop_reason: StopReason::TimeLimit });
+            }
+        }
+        Ok(AgentRun { messages, history: conversation, stop_reason: StopReason::MaxIterations })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use jarvis_mcp::McpTool;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Replies from a script and records the prompts it was given
+    struct ScriptedModel {
+        replies: RefCell<VecDeque<&'static str>>,
+        prompts: RefCell<Vec<Vec<Message>>>,
+    }
+
+    impl ScriptedModel {
+        fn new(replies: &[&'static str]) -> Self {
+            Self {
+                replies: RefCell::new(replies.iter().copied().collect()),
+                prompts: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl TextGenerator for ScriptedModel {
+        fn generate(&self, messages: &[Message]) -> Result<String, String> {
+            self.prompts.borrow_mut().push(messages.to_vec());
+            self.replies.borrow_mut().pop_front().map(str::to_string).ok_or_else(|| "script exhausted".to_string())
+        }
+    }
+
+    fn agent() -> Agent {
+        let mut agent = Agent::new();
+        let tool = |name: &str| McpTool {
+            name: name.to_string(),
+            description: String::new(),
+            input_schema: serde_json::json!({"type": "object"}),
+        };
+        agent.set_tools(vec![tool("get_weather"), tool("get_time")]);
+        agent
+    }
+
+    fn fake_tools(calls: &RefCell<Vec<String>>) -> impl FnMut(ToolCallParams) -> ToolCallResult + '_ {
+        move |params: ToolCallParams| {
+            calls.borrow_mut().push(params.name.clone());
+            match params.name.as_str() {
+                "get_weather" => ToolCallResult { success: true, result: format!("Rain in {}", params.arguments["city"]), error: None },
+                _ => failure("clock unavailable".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_with_tools() {
+        let model = ScriptedModel::new(&[
+            "<tool_call>{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}</tool_call>\n<tool_call>{\"name\": \"get_time\"}</tool_call>",
+            "<tool_call>{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Bergen\"}}</tool_call>",
+            "Rain in both cities, sir.",
+        ]);
+        let calls = RefCell::new(Vec::new());
+        let mut events = Vec::new();
+        let history = [Message::user("Weather in Oslo and Bergen?".to_string())];
+        let run = block_on(agent().run(&model, &mut fake_tools(&calls), &history, &RunLimits::default(), |event| {
+            events.push(event.clone())
+        }))
+        .unwrap();
+
+        assert_eq!(run.stop_reason, StopReason::Answered);
+        assert_eq!(run.answer(), "Rain in both cities, sir.");
+        assert_eq!(run.messages.len(), 3);
+        assert_eq!(run.history.len(), 4);
+        assert_eq!(run.history.last().unwrap().text(), run.answer());
+        assert_eq!(*calls.borrow(), vec!["get_weather", "get_time", "get_weather"]);
+        let responses: Vec<String> = run.messages[0].tool_calls().iter().map(|call| call.response.clone()).collect();
+        assert_eq!(responses, vec!["Rain in \"Oslo\"", "Error: clock unavailable"]);
+
+        // Each generation sees the system prompt, the history and the earlier tool responses
+        let prompts = model.prompts.borrow();
+        assert_eq!(prompts.len(), 3);
+        assert!(prompts[0][0].text().contains("get_weather"));
+        assert_eq!(prompts[2].len(), 4);
+        assert_eq!(prompts[2][3].tool_calls()[0].response, "Rain in \"Bergen\"");
+
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|event| match event {
+                AgentEvent::Response(_) => "response",
+                AgentEvent::ToolStarted(_) => "started",
+                AgentEvent::ToolFinished(_) => "finished",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec!["response", "started", "finished", "started", "finished", "response", "started", "finished", "response"]
+        );
+    }
+
+    #[test]
+    fn test_run_limits() {
+        let looping = "<tool_call>{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}</tool_call>";
+        let calls = RefCell::new(Vec::new());
+        let limits = RunLimits { max_iterations: 2, max_time: None };
+        let model = ScriptedModel::new(&[looping, looping, looping]);
+        let run = block_on(agent().run(&model, &mut fake_tools(&calls), &[], &limits, |_| {})).unwrap();
+        assert_eq!(run.stop_reason, StopReason::MaxIterations);
+        assert_eq!(run.messages.len(), 2);
+
+        let limits = RunLimits { max_iterations: 5, max_time: Some(Duration::ZERO) };
+        let run = block_on(agent().run(&model, &mut fake_tools(&calls), &[], &limits, |_| {})).unwrap();
+        assert_eq!(run.stop_reason, StopReason::TimeLimit);
+        assert_eq!(run.messages.len(), 1);
+
+        // Unknown tools never reach the executor
+        let model = ScriptedModel::new(&["<tool_call>{\"name\": \"launch\"}</tool_call>", "I cannot do that."]);
+        calls.borrow_mut().clear();
+        let run = block_on(agent().run(&model, &mut fake_tools(&calls), &[], &limits, |_| {})).unwrap();
+        assert!(calls.borrow().is_empty());
+        assert!(run.messages[0].tool_calls()[0].response.contains("no tool named launch"));
+
+        let model = ScriptedModel::new(&[]);
+        assert!(block_on(agent().run(&model, &mut fake_tools(&calls), &[], &limits, |_| {})).is_err());
+    }
+
+    #[test]
+    fn test_history_keeps_summary() {
+        use crate::agent::{CompactionPolicy, ContextConfig};
+
+        /// One token per word; counts the summaries it writes
+        struct WindowedModel {
+            context_length: usize,
+            summaries: RefCell<usize>,
+        }
+
+        impl TextGenerator for WindowedModel {
+            fn generate(&self, messages: &[Message]) -> Result<String, String> {
+                if messages[0].text().starts_with("Summarize") {
+                    *self.summaries.borrow_mut() += 1;
+                    return Ok("The user asked about the weather.".to_string());
+                }
+                Ok("Noted.".to_string())
+            }
+
+            fn count_tokens(&self, text: &str) -> usize {
+                text.split_whitespace().count()
+            }
+
+            fn context_length(&self) -> Option<usize> {
+                Some(self.context_length)
+            }
+        }
+
+        let mut agent = Agent::new();
+        agent.set_context_config(ContextConfig { reserve_tokens: 10, policy: CompactionPolicy::Summarize, max_tool_response_tokens: 3 });
+        let probe = WindowedModel { context_length: 0, summaries: RefCell::new(0) };
+        let system_tokens = agent.count_message_tokens(&probe, &Message::system(agent.system_prompt().to_string()));
+        let model = WindowedModel { context_length: system_tokens + 10 + 50, summaries: RefCell::new(0) };
+        let history: Vec<Message> = (0..6).map(|i| Message::user(format!("message {} with some words", i))).collect();
+        let limits = RunLimits::default();
+
+        let run = block_on(agent.run(&model, &mut fake_tools(&RefCell::new(Vec::new())), &history, &limits, |_| {})).unwrap();
+        assert_eq!(*model.summaries.borrow(), 1);
+        assert!(run.history[0].text().starts_with("Summary of the earlier conversation"));
+        assert_eq!(run.history.last().unwrap().text(), "Noted.");
+
+        // The next turn starts from the compacted history, so it fits without a new summary
+        let mut next = run.history.clone();
+        next.push(Message::user("thanks".to_string()));
+        block_on(agent.run(&model, &mut fake_tools(&RefCell::new(Vec::new())), &next, &limits, |_| {})).unwrap();
+        assert_eq!(*model.summaries.borrow(), 1);
+
+        // The full history would have been summarized again
+        let mut full = history;
+        full.extend(run.messages);
+        full.push(Message::user("thanks".to_string()));
+        block_on(agent.run(&model, &mut fake_tools(&RefCell::new(Vec::new())), &full, &limits, |_| {})).unwrap();
+        assert_eq!(*model.summaries.borrow(), 2);
+    }
+}
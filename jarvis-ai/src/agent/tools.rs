@@ -0,0 +1,185 @@
+//! Tool calling with MCP tool definitions
+//!
+//! Chat models are fine-tuned on different syntaxes for calling functions, so
+//! a [`ToolCallFormat`] both tells the model which tools exist and how to
+//! call them, and parses the calls back out of its output.
+
+use crate::types::MessagePartTool;
+use jarvis_mcp::McpTool;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Syntax a model uses to call tools and read their responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolCallFormat {
+    /// `<tool_call>{"name": ..., "arguments": ...}</tool_call>` (Hermes, Qwen, SmolLM)
+    Hermes,
+    /// A bare JSON object `{"name": ..., "parameters": ...}` (Llama 3, and
+    /// models not trained on a format)
+    Json,
+}
+
+impl ToolCallFormat {
+    /// Describe the tools and how to call them, for the system prompt
+    pub fn render_tools(&self, tools: &[McpTool]) -> String {
+        let definitions = tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {"name": tool.name, "description": tool.description, "parameters": tool.input_schema},
+                })
+                .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let definitions = match self {
+            ToolCallFormat::Hermes => format!("<tools>\n{}\n</tools>", definitions),
+            ToolCallFormat::Json => definitions,
+        };
+        format!(
+            "# Tools\n\nYou can call these functions:\n{}\n\nTo call a function, reply with:\n{}\nThe result will be in the next message.",
+            definitions,
+            self.render_call("function_name", &json!({"argument": "value"}))
+        )
+    }
+
+    /// Write a call the way the model would
+    pub fn render_call(&self, name: &str, arguments: &Value) -> String {
+        match self {
+            ToolCallFormat::Hermes => {
+                format!("<tool_call>\n{}\n</tool_call>", json!({"name": name, "arguments": arguments}))
+            }
+            ToolCallFormat::Json => json!({"name": name, "parameters": arguments}).to_string(),
+        }
+    }
+
+    /// Wrap a tool's response for the model to read
+    pub fn render_response(&self, name: &str, response: &str) -> String {
+        match self {
+            ToolCallFormat::Hermes => format!("<tool_response>\n{}\n</tool_response>", response),
+            ToolCallFormat::Json => json!({"name": name, "output": response}).to_string(),
+        }
+    }
+
+    /// Split model output into its text and the tool calls it makes
+    ///
+    /// Calls that are not valid JSON are left in the text.
+    pub fn parse(&self, output: &str) -> (String, Vec<MessagePartTool>) {
+        match self {
+            ToolCallFormat::Hermes => parse_tagged(output),
+            ToolCallFormat::Json => {
+                let trimmed = output.trim();
+                let unfenced = trimmed
+                    .strip_prefix("```json")
+                    .or_else(|| trimmed.strip_prefix("```"))
+                    .and_then(|rest| rest.strip_suffix("```"))
+                    .unwrap_or(trimmed);
+                let calls = match serde_json::from_str::<Value>(unfenced) {
+                    Ok(Value::Array(values)) => values.iter().map(to_call).collect(),
+                    Ok(value) => to_call(&value).map(|call| vec![call]),
+                    Err(_) => None,
+                };
+                match calls {
+                    Some(calls) if !calls.is_empty() => (String::new(), calls),
+                    _ => (output.trim().to_string(), Vec::new()),
+                }
+            }
+        }
+    }
+}
+
+/// Read `<tool_call>` blocks; an unclosed block runs to the end of the output
+fn parse_tagged(output: &str) -> (String, Vec<MessagePartTool>) {
+    const OPEN: &str = "<tool_call>";
+    const CLOSE: &str = "</tool_call>";
+
+    let mut text = String::new();
+    let mut calls = Vec::new();
+    let mut rest = output;
+    while let Some(start) = rest.find(OPEN) {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + OPEN.len()..];
+        let (body, next) = match after.find(CLOSE) {
+            Some(end) => (&after[..end], &after[end + CLOSE.len()..]),
+            None => (after, ""),
+        };
+        match serde_json::from_str::<Value>(body.trim()).ok().as_ref().and_then(to_call) {
+            Some(call) => calls.push(call),
+            None => text.push_str(&rest[start..rest.len() - next.len()]),
+        }
+        rest = next;
+    }
+    text.push_str(rest);
+    (text.trim().to_string(), calls)
+}
+
+/// A call from `{"name": ..., "arguments" | "parameters": ...}`; arguments may
+/// be an object or a string holding one
+fn to_call(value: &Value) -> Option<MessagePartTool> {
+    let name = value.get("name")?.as_str()?;
+    let arguments = match value.get("arguments").or_else(|| value.get("parameters")) {
+        Some(Value::String(encoded)) => serde_json::from_str(encoded).ok()?,
+        Some(arguments) => arguments.clone(),
+        None => json!({}),
+    };
+    Some(MessagePartTool::new(name.to_string(), arguments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hermes() {
+        let output = "Let me check.\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}\n</tool_call>\n<tool_call>{\"name\": \"get_time\", \"arguments\": \"{}\"}";
+        let (text, calls) = ToolCallFormat::Hermes.parse(output);
+        assert_eq!(text, "Let me check.");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].function_name, "get_weather");
+        assert_eq!(calls[0].parameters, json!({"city": "Oslo"}));
+        assert_eq!(calls[1].function_name, "get_time");
+        assert_eq!(calls[1].parameters, json!({}));
+        assert!(calls[0].response.is_empty());
+
+        let (text, calls) = ToolCallFormat::Hermes.parse("Oops <tool_call>{not json}</tool_call> done");
+        assert_eq!(text, "Oops <tool_call>{not json}</tool_call> done");
+        assert!(calls.is_empty());
+
+        let call = ToolCallFormat::Hermes.render_call("get_time", &json!({}));
+        assert_eq!(ToolCallFormat::Hermes.parse(&call).1[0].function_name, "get_time");
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let (text, calls) = ToolCallFormat::Json.parse("```json\n{\"name\": \"store_memory\", \"parameters\": {\"memory\": \"Likes tea\"}}\n```");
+        assert!(text.is_empty());
+        assert_eq!(calls[0].function_name, "store_memory");
+        assert_eq!(calls[0].parameters["memory"], "Likes tea");
+
+        let (_, calls) = ToolCallFormat::Json.parse(r#"[{"name": "a"}, {"name": "b", "arguments": {"x": 1}}]"#);
+        assert_eq!(calls.iter().map(|c| c.function_name.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+
+        // Plain answers and JSON that is not a call stay text
+        assert_eq!(ToolCallFormat::Json.parse(" Good evening, sir. ").0, "Good evening, sir.");
+        let (text, calls) = ToolCallFormat::Json.parse(r#"{"temperature": 21}"#);
+        assert_eq!(text, r#"{"temperature": 21}"#);
+        assert!(calls.is_empty());
+    }
+
+    #[test]
+    fn test_render_tools() {
+        let tools = vec![McpTool {
+            name: "get_weather".to_string(),
+            description: "Current weather for a city".to_string(),
+            input_schema: json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        }];
+        let prompt = ToolCallFormat::Hermes.render_tools(&tools);
+        assert!(prompt.contains("<tools>\n{\"function\":{\"description\":\"Current weather for a city\""));
+        assert!(prompt.contains("\"city\":{\"type\":\"string\"}"));
+        assert!(prompt.contains("<tool_call>"));
+        assert!(!ToolCallFormat::Json.render_tools(&tools).contains("<tool"));
+        assert_eq!(ToolCallFormat::Json.render_response("get_time", "12:00"), r#"{"name":"get_time","output":"12:00"}"#);
+    }
+}
@@ -3,6 +3,12 @@
 //! This module provides audio capture, processing, and playback capabilities
 //! including resampling and mel spectrogram conversion for Whisper.
 
+pub mod decode;
+pub mod wav;
+
+pub use decode::{decode_audio, decode_audio_for_whisper, AudioContainer, DecodedAudio};
+pub use wav::{decode_wav, downmix_to_mono, encode_wav, load_wav_for_whisper, WavAudio, WavSampleFormat, WavSpec};
+
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{AudioContext, MediaStream};
@@ -17,6 +23,8 @@ pub const N_FFT: usize = 400;
 pub const HOP_LENGTH: usize = 160;
 /// Chunk length in samples (30 seconds at 16kHz)
 pub const CHUNK_LENGTH: usize = 480000;
+/// Mel frames in one chunk (30 seconds at 100 frames per second)
+pub const N_FRAMES: usize = CHUNK_LENGTH / HOP_LENGTH;
 
 /// Audio capture handler
 pub struct AudioCapture {
@@ -132,6 +140,75 @@ pub fn resample_audio(
     Ok(output)
 }
 
+/// Linear-interpolation resampler for audio that arrives in chunks
+///
+/// Interpolates across chunk boundaries and carries the fractional source
+/// position over, so pushing a signal in pieces gives the same samples as
+/// [`resample_audio`] on the whole signal, up to the final sample.
+#[derive(Debug, Clone)]
+pub struct StreamResampler {
+    source_rate: u32,
+    target_rate: u32,
+    /// Source samples not yet passed by the output position
+    pending: Vec<f32>,
+    /// Output samples produced so far
+    produced: u64,
+    /// Source samples dropped from the front of `pending`
+    consumed: u64,
+}
+
+impl StreamResampler {
+    pub fn new(source_rate: u32, target_rate: u32) -> Self {
+        Self {
+            source_rate,
+            target_rate,
+            pending: Vec::new(),
+            produced: 0,
+            consumed: 0,
+        }
+    }
+
+    /// Resample the next chunk, returning the output samples it completes
+    pub fn push(&mut self, input: &[f32]) -> Result<Vec<f32>, String> {
+        if self.source_rate == self.target_rate {
+            return Ok(input.to_vec());
+        }
+        if self.source_rate == 0 || self.target_rate == 0 {
+            return Err("Sample rate cannot be zero".to_string());
+        }
+
+        self.pending.extend_from_slice(input);
+        let ratio = self.source_rate as f64 / self.target_rate as f64;
+        let mut output = Vec::new();
+        loop {
+            // Computed from the totals rather than accumulated, so rounding does not drift
+            let src_pos = self.produced as f64 * ratio - self.consumed as f64;
+            let src_idx = src_pos.floor() as usize;
+            if src_idx + 1 >= self.pending.len() {
+                break;
+            }
+            let frac = src_pos - src_idx as f64;
+            let sample = self.pending[src_idx] as f64 * (1.0 - frac) + self.pending[src_idx + 1] as f64 * frac;
+            output.push(sample as f32);
+            self.produced += 1;
+        }
+
+        // Keep the sample the next output interpolates from
+        let next_idx = (self.produced as f64 * ratio - self.consumed as f64).floor() as usize;
+        let drop = next_idx.min(self.pending.len().saturating_sub(1));
+        self.pending.drain(..drop);
+        self.consumed += drop as u64;
+        Ok(output)
+    }
+
+    /// Forget buffered audio and start a new signal
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.produced = 0;
+        self.consumed = 0;
+    }
+}
+
 /// Create mel filterbank matrix
 ///
 /// # Arguments
@@ -285,10 +362,21 @@ pub fn audio_to_mel(audio: &[f32], sample_rate: u32) -> Result<Vec<f32>, String>
     let filterbank = create_mel_filterbank(N_MEL_BINS, N_FFT, WHISPER_SAMPLE_RATE);
 
     // Apply mel filterbank and convert to log scale
-    let mut mel_spec = Vec::with_capacity(N_MEL_BINS * stft.len());
+    let mut mel_spec = apply_log_mel(&stft, &filterbank);
+
+    normalize_log_mel(&mut mel_spec);
+
+    Ok(mel_spec)
+}
+
+/// Apply a mel filterbank to STFT magnitude frames and convert to natural log
+///
+/// Returns frame-major values (`N_MEL_BINS` per frame).
+fn apply_log_mel(stft: &[Vec<f32>], filterbank: &[Vec<f32>]) -> Vec<f32> {
+    let mut mel_spec = Vec::with_capacity(filterbank.len() * stft.len());
 
-    for frame in &stft {
-        for filter in &filterbank {
+    for frame in stft {
+        for filter in filterbank {
             let mut sum = 0.0f32;
             for (i, &f) in filter.iter().enumerate() {
                 if i < frame.len() {
@@ -302,15 +390,94 @@ pub fn audio_to_mel(audio: &[f32], sample_rate: u32) -> Result<Vec<f32>, String>
         }
     }
 
-    // Normalize to match Whisper's expected range
+    mel_spec
+}
+
+/// Normalize log mel values to Whisper's expected [-1, 1] range
+fn normalize_log_mel(mel_spec: &mut [f32]) {
     let max_val = mel_spec.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
     let min_val = max_val - 8.0; // Dynamic range of 8 (about 80dB)
 
-    for val in &mut mel_spec {
+    for val in mel_spec.iter_mut() {
         *val = ((*val - min_val) / (max_val - min_val)).clamp(0.0, 1.0) * 2.0 - 1.0;
     }
+}
 
-    Ok(mel_spec)
+/// Rolling log mel spectrogram for streaming audio
+///
+/// Samples are pushed as they arrive and converted to mel frames once a full
+/// FFT window is available, so each frame is computed only once. The oldest
+/// frames can be dropped to keep a sliding window over the stream.
+pub struct MelBuffer {
+    filterbank: Vec<Vec<f32>>,
+    /// Samples not yet consumed by a complete frame (16kHz)
+    pending: Vec<f32>,
+    /// Un-normalized log mel values, frame-major
+    frames: Vec<f32>,
+}
+
+impl MelBuffer {
+    /// Create an empty mel buffer for 16kHz audio
+    pub fn new() -> Self {
+        Self {
+            filterbank: create_mel_filterbank(N_MEL_BINS, N_FFT, WHISPER_SAMPLE_RATE),
+            pending: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Append 16kHz mono samples
+    ///
+    /// # Returns
+    /// The number of new mel frames produced
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        self.pending.extend_from_slice(samples);
+        if self.pending.len() < N_FFT {
+            return 0;
+        }
+
+        let stft = compute_stft_magnitude(&self.pending, N_FFT, HOP_LENGTH);
+        self.frames.extend(apply_log_mel(&stft, &self.filterbank));
+        self.pending.drain(..stft.len() * HOP_LENGTH);
+        stft.len()
+    }
+
+    /// Number of mel frames currently held
+    pub fn n_frames(&self) -> usize {
+        self.frames.len() / N_MEL_BINS
+    }
+
+    /// Duration covered by the held frames in seconds
+    pub fn duration_secs(&self) -> f32 {
+        (self.n_frames() * HOP_LENGTH) as f32 / WHISPER_SAMPLE_RATE as f32
+    }
+
+    /// Normalized log mel spectrogram of the current window, frame-major
+    pub fn window(&self) -> Vec<f32> {
+        let mut window = self.frames.clone();
+        normalize_log_mel(&mut window);
+        window
+    }
+
+    /// Drop frames from the front, keeping at most `keep_frames` of the newest
+    pub fn keep_last(&mut self, keep_frames: usize) {
+        let n_frames = self.n_frames();
+        if n_frames > keep_frames {
+            self.frames.drain(..(n_frames - keep_frames) * N_MEL_BINS);
+        }
+    }
+
+    /// Discard all frames and pending samples
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.frames.clear();
+    }
+}
+
+impl Default for MelBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Normalize audio samples to [-1, 1] range
@@ -357,12 +524,49 @@ mod tests {
         assert!(result.is_empty());
     }
 
+    #[test]
+    fn test_stream_resampler_matches_one_shot() {
+        for source_rate in [44100, 48000] {
+            let input: Vec<f32> = (0..source_rate).map(|i| (i as f32 * 0.01).sin()).collect();
+            let one_shot = resample_audio(&input, source_rate, 16000).unwrap();
+
+            // Browser audio worklets deliver 128-sample chunks
+            let mut resampler = StreamResampler::new(source_rate, 16000);
+            let mut chunked = Vec::new();
+            for chunk in input.chunks(128) {
+                chunked.extend(resampler.push(chunk).unwrap());
+            }
+            assert!(one_shot.len() - chunked.len() <= 1, "{} vs {} samples", chunked.len(), one_shot.len());
+            assert!(chunked.iter().zip(&one_shot).all(|(a, b)| (a - b).abs() < 1e-5));
+        }
+        assert!(StreamResampler::new(0, 16000).push(&[0.0]).is_err());
+    }
+
     #[test]
     fn test_audio_to_mel_empty() {
         let result = audio_to_mel(&[], 16000);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_mel_buffer_incremental_matches_batch() {
+        let audio: Vec<f32> = (0..4000).map(|i| (i as f32 * 0.05).sin() * 0.3).collect();
+
+        let mut whole = MelBuffer::new();
+        whole.push(&audio);
+
+        let mut chunked = MelBuffer::new();
+        let produced: usize = audio.chunks(333).map(|c| chunked.push(c)).sum();
+
+        assert_eq!(produced, (audio.len() - N_FFT) / HOP_LENGTH + 1);
+        assert_eq!(chunked.n_frames(), whole.n_frames());
+        assert_eq!(chunked.window(), whole.window());
+
+        chunked.keep_last(5);
+        assert_eq!(chunked.n_frames(), 5);
+        assert_eq!(chunked.window().len(), 5 * N_MEL_BINS);
+    }
+
     #[test]
     fn test_normalize_audio() {
         let mut audio = vec![0.5, -1.0, 0.25, 0.0];
@@ -0,0 +1,506 @@
+//! Compressed audio decoding
+//!
+//! Decodes uploaded files and `MediaRecorder` blobs into mono f32 PCM in pure
+//! Rust, so it behaves the same natively and in WASM without going through
+//! `AudioContext.decodeAudioData`.
+//!
+//! Supported inputs:
+//! - WAV (via [`super::wav`])
+//! - MP3, FLAC
+//! - M4A/MP4 (AAC)
+//! - Ogg (Opus, Vorbis, FLAC)
+//! - WebM/Matroska (Opus, Vorbis)
+
+use super::wav::{decode_wav, downmix_to_mono};
+use super::{resample_audio, WHISPER_SAMPLE_RATE};
+use opus_decoder::OpusDecoder;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CodecParameters, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Opus always decodes at 48kHz internally
+const OPUS_SAMPLE_RATE: u32 = 48000;
+/// Largest Opus frame (120ms) at 48kHz, per channel
+const OPUS_MAX_FRAME: usize = 5760;
+
+/// Container formats recognised by [`decode_audio`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioContainer {
+    Wav,
+    Mp3,
+    Flac,
+    Ogg,
+    WebM,
+    Mp4,
+}
+
+impl AudioContainer {
+    /// Detect the container from the leading bytes of a file
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+            Some(Self::Wav)
+        } else if bytes.starts_with(b"fLaC") {
+            Some(Self::Flac)
+        } else if bytes.starts_with(b"OggS") {
+            Some(Self::Ogg)
+        } else if bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            Some(Self::WebM)
+        } else if bytes.len() >= 8 && &bytes[4..8] == b"ftyp" {
+            Some(Self::Mp4)
+        } else if bytes.starts_with(b"ID3")
+            || (bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] & 0xE0 == 0xE0)
+        {
+            Some(Self::Mp3)
+        } else {
+            None
+        }
+    }
+
+    /// Map a MIME type (e.g. `Blob.type`) to a container
+    ///
+    /// Codec parameters such as `;codecs=opus` are ignored.
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        let essence = mime_type.split(';').next()?.trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "audio/wav" | "audio/wave" | "audio/x-wav" | "audio/vnd.wave" => Some(Self::Wav),
+            "audio/mpeg" | "audio/mp3" => Some(Self::Mp3),
+            "audio/flac" | "audio/x-flac" => Some(Self::Flac),
+            "audio/ogg" | "audio/opus" | "application/ogg" => Some(Self::Ogg),
+            "audio/webm" | "video/webm" | "audio/x-matroska" | "video/x-matroska" => Some(Self::WebM),
+            "audio/mp4" | "audio/m4a" | "audio/x-m4a" | "audio/aac" | "video/mp4" => Some(Self::Mp4),
+            _ => None,
+        }
+    }
+
+    /// File extension used as a probe hint
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Ogg => "ogg",
+            Self::WebM => "webm",
+            Self::Mp4 => "m4a",
+        }
+    }
+}
+
+/// Decoded mono audio
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedAudio {
+    /// Mono samples in [-1, 1]
+    pub samples: Vec<f32>,
+    /// Sample rate of `samples` in Hz
+    pub sample_rate: u32,
+}
+
+impl DecodedAudio {
+    /// Duration in seconds
+    pub fn duration_secs(&self) -> f32 {
+        self.samples.len() as f32 / self.sample_rate as f32
+    }
+
+    /// Resample to Whisper's 16kHz input rate
+    pub fn to_whisper_input(&self) -> Result<Vec<f32>, String> {
+        resample_audio(&self.samples, self.sample_rate, WHISPER_SAMPLE_RATE)
+    }
+}
+
+/// Decode a compressed or uncompressed audio file into mono f32 PCM
+///
+/// # Arguments
+/// * `bytes` - File or blob contents
+/// * `mime_type` - Optional MIME type, used when the bytes alone are ambiguous
+///
+/// # Returns
+/// Mono samples at the file's native sample rate
+pub fn decode_audio(bytes: &[u8], mime_type: Option<&str>) -> Result<DecodedAudio, String> {
+    if bytes.is_empty() {
+        return Err("Empty audio input".to_string());
+    }
+
+    let container = AudioContainer::detect(bytes).or_else(|| mime_type.and_then(AudioContainer::from_mime_type));
+
+    if container == Some(AudioContainer::Wav) {
+        let wav = decode_wav(bytes)?;
+        return Ok(DecodedAudio {
+            samples: wav.to_mono(),
+            sample_rate: wav.spec.sample_rate,
+        });
+    }
+
+    let mut hint = Hint::new();
+    if let Some(container) = container {
+        hint.with_extension(container.extension());
+    }
+    if let Some(mime_type) = mime_type {
+        hint.mime_type(mime_type);
+    }
+
+    // Symphonia needs an owned, 'static media source
+    let source = std::io::Cursor::new(bytes.to_vec());
+    let stream = MediaSourceStream::new(Box::new(source), Default::default());
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Unrecognized audio format: {}", e))?;
+    let mut reader = probed.format;
+
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track found")?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    let decoded = if params.codec == CODEC_TYPE_OPUS {
+        let end_granule = if container == Some(AudioContainer::Ogg) { ogg_opus_end_granule(bytes) } else { None };
+        decode_opus_track(reader.as_mut(), track_id, &params, end_granule)?
+    } else {
+        decode_symphonia_track(reader.as_mut(), track_id, &params)?
+    };
+
+    if decoded.samples.is_empty() {
+        return Err("Audio file contains no samples".to_string());
+    }
+
+    Ok(decoded)
+}
+
+/// Decode an audio file into 16kHz mono samples ready for Whisper
+pub fn decode_audio_for_whisper(bytes: &[u8], mime_type: Option<&str>) -> Result<Vec<f32>, String> {
+    decode_audio(bytes, mime_type)?.to_whisper_input()
+}
+
+/// Read the next packet of `track_id`, returning `None` at end of stream
+fn next_track_packet(
+    reader: &mut dyn FormatReader,
+    track_id: u32,
+) -> Result<Option<symphonia::core::formats::Packet>, String> {
+    loop {
+        match reader.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => return Ok(Some(packet)),
+            Ok(_) => continue,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Ok(None)
+            }
+            // Chained streams are not supported; treat as end of the first stream
+            Err(SymphoniaError::ResetRequired) => return Ok(None),
+            Err(e) => return Err(format!("Failed to read audio packet: {}", e)),
+        }
+    }
+}
+
+/// Decode a track with one of Symphonia's built-in codecs
+fn decode_symphonia_track(
+    reader: &mut dyn FormatReader,
+    track_id: u32,
+    params: &CodecParameters,
+) -> Result<DecodedAudio, String> {
+    let mut decoder = symphonia::default::get_codecs()
+        .make(params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported audio codec: {}", e))?;
+
+    let mut samples = Vec::new();
+    let mut sample_rate = params.sample_rate.unwrap_or(0);
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    while let Some(packet) = next_track_packet(reader, track_id)? {
+        let buffer = match decoder.decode(&packet) {
+            Ok(buffer) => buffer,
+            // Corrupt packets are skipped rather than failing the whole file
+            Err(SymphoniaError::DecodeError(e)) => {
+                log::warn!("Skipping undecodable audio packet: {}", e);
+                continue;
+            }
+            Err(e) => return Err(format!("Audio decoding failed: {}", e)),
+        };
+
+        let spec = *buffer.spec();
+        sample_rate = spec.rate;
+
+        let buf = match &mut sample_buf {
+            Some(buf) if buf.capacity() >= buffer.capacity() * spec.channels.count() => buf,
+            slot => slot.insert(SampleBuffer::new(buffer.capacity() as u64, spec)),
+        };
+        buf.copy_interleaved_ref(buffer);
+        samples.extend(downmix_to_mono(buf.samples(), spec.channels.count() as u16));
+    }
+
+    if sample_rate == 0 {
+        return Err("Audio stream has no sample rate".to_string());
+    }
+
+    Ok(DecodedAudio { samples, sample_rate })
+}
+
+/// Granule position of the last page of the first Opus stream in an Ogg file
+///
+/// Opus granule positions count 48kHz samples including the pre-skip, so the
+/// last one marks where the signal ends inside the final, padded frame.
+fn ogg_opus_end_granule(bytes: &[u8]) -> Option<u64> {
+    let mut pos = 0;
+    let mut serial = None;
+    let mut end = None;
+    while let Some(header) = bytes.get(pos..pos + 27) {
+        if &header[..4] != b"OggS" {
+            break;
+        }
+        let segments = header[26] as usize;
+        let table = bytes.get(pos + 27..pos + 27 + segments)?;
+        let body = pos + 27 + segments;
+        let body_len: usize = table.iter().map(|&len| len as usize).sum();
+        let page_serial = u32::from_le_bytes(header[14..18].try_into().ok()?);
+        // The stream starts with a page holding only the OpusHead packet
+        if serial.is_none() && header[5] & 0x02 != 0 && bytes.get(body..body + 8) == Some(b"OpusHead") {
+            serial = Some(page_serial);
+        }
+        let granule = u64::from_le_bytes(header[6..14].try_into().ok()?);
+        // Pages on which no packet ends have a granule position of -1
+        if serial == Some(page_serial) && granule != u64::MAX {
+            end = Some(granule);
+        }
+        pos = body + body_len;
+    }
+    end
+}
+
+/// Decode an Opus track with the pure Rust Opus decoder
+///
+/// `end_granule` is the Ogg end position, used to drop the padding of the last frame.
+fn decode_opus_track(
+    reader: &mut dyn FormatReader,
+    track_id: u32,
+    params: &CodecParameters,
+    end_granule: Option<u64>,
+) -> Result<DecodedAudio, String> {
+    let channels = params.channels.map(|c| c.count()).unwrap_or(1);
+    if channels > 2 {
+        return Err(format!("Multichannel Opus ({} channels) is not supported", channels));
+    }
+
+    let mut decoder =
+        OpusDecoder::new(OPUS_SAMPLE_RATE, channels).map_err(|e| format!("Failed to create Opus decoder: {}", e))?;
+    let mut pcm = vec![0.0f32; OPUS_MAX_FRAME * channels];
+    let mut samples = Vec::new();
+
+    while let Some(packet) = next_track_packet(reader, track_id)? {
+        let frames = match decoder.decode_float(packet.buf(), &mut pcm, false) {
+            Ok(frames) => frames,
+            Err(e) => {
+                log::warn!("Skipping undecodable Opus packet: {}", e);
+                continue;
+            }
+        };
+        samples.extend(downmix_to_mono(&pcm[..frames * channels], channels as u16));
+    }
+
+    // The encoder's lookahead (pre-skip) is not part of the signal
+    // and neither are the samples past the end position, which counts the pre-skip too
+    if let Some(end) = end_granule {
+        samples.truncate(end as usize);
+    }
+    let pre_skip = (params.delay.unwrap_or(0) as usize).min(samples.len());
+    samples.drain(..pre_skip);
+
+    Ok(DecodedAudio {
+        samples,
+        sample_rate: OPUS_SAMPLE_RATE,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{encode_wav, WavSpec};
+
+    /// CRC-8 (poly 0x07) used by FLAC frame headers
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |mut crc, &byte| {
+            crc ^= byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+            }
+            crc
+        })
+    }
+
+    /// CRC-16 (poly 0x8005) used by FLAC frame footers
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |mut crc, &byte| {
+            crc ^= (byte as u16) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+            }
+            crc
+        })
+    }
+
+    /// Build a mono 16-bit 16kHz FLAC file with a single verbatim frame
+    fn flac_fixture(samples: &[i16]) -> Vec<u8> {
+        let n = samples.len() as u32;
+        let mut out = b"fLaC".to_vec();
+
+        // STREAMINFO, marked as the last metadata block
+        out.push(0x80);
+        out.extend_from_slice(&34u32.to_be_bytes()[1..]);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+        out.extend_from_slice(&[0; 6]);
+        // 20 bits rate, 3 bits channels-1, 5 bits bps-1, 36 bits total samples
+        let packed: u64 = (16000u64 << 44) | (15u64 << 36) | n as u64;
+        out.extend_from_slice(&packed.to_be_bytes());
+        out.extend_from_slice(&[0; 16]);
+
+        // Frame header: fixed blocksize, block size from end of header (16 bit),
+        // 16kHz rate code, mono, 16 bits per sample, frame number 0
+        let mut frame = vec![0xFF, 0xF8, 0x75, 0x08, 0x00];
+        frame.extend_from_slice(&((n - 1) as u16).to_be_bytes());
+        frame.push(crc8(&frame));
+        // Verbatim subframe with no wasted bits
+        frame.push(0x02);
+        for &s in samples {
+            frame.extend_from_slice(&s.to_be_bytes());
+        }
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+
+        out.extend_from_slice(&frame);
+        out
+    }
+
+    /// CRC-32 (poly 0x04C11DB7, unreflected) used by Ogg pages
+    fn ogg_crc(data: &[u8]) -> u32 {
+        data.iter().fold(0u32, |mut crc, &byte| {
+            crc ^= (byte as u32) << 24;
+            for _ in 0..8 {
+                crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+            }
+            crc
+        })
+    }
+
+    fn ogg_page(packet: &[u8], flags: u8, granule: u64, sequence: u32) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.push(0);
+        page.push(flags);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&0x4A41_5256u32.to_le_bytes());
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        // Packets used here are all shorter than 255 bytes
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        let crc = ogg_crc(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    /// Build a mono Ogg Opus file of `frames` 20ms packets, the last `padding`
+    /// samples of which are past the end position
+    ///
+    /// Each packet is a bare CELT fullband TOC byte with an empty frame, which
+    /// decoders treat as a dropped frame and fill with concealment (silence).
+    fn ogg_opus_fixture(frames: u64, pre_skip: u16, padding: u64) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(1);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes());
+        head.push(0);
+
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&5u32.to_le_bytes());
+        tags.extend_from_slice(b"jarvs");
+        tags.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut out = ogg_page(&head, 0x02, 0, 0);
+        out.extend(ogg_page(&tags, 0x00, 0, 1));
+        for i in 0..frames {
+            let (flags, granule) = if i + 1 == frames { (0x04, frames * 960 - padding) } else { (0x00, (i + 1) * 960) };
+            out.extend(ogg_page(&[0xF8], flags, granule, i as u32 + 2));
+        }
+        out
+    }
+
+    #[test]
+    fn test_decode_ogg_opus() {
+        let bytes = ogg_opus_fixture(5, 312, 0);
+        assert_eq!(AudioContainer::detect(&bytes), Some(AudioContainer::Ogg));
+
+        let decoded = decode_audio(&bytes, Some("audio/ogg;codecs=opus")).unwrap();
+        assert_eq!(decoded.sample_rate, OPUS_SAMPLE_RATE);
+        assert_eq!(decoded.samples.len(), 5 * 960 - 312);
+        assert!(decoded.samples.iter().all(|s| s.abs() < 1e-3));
+
+        let whisper = decoded.to_whisper_input().unwrap();
+        assert_eq!(whisper.len(), (5 * 960 - 312) / 3);
+
+        // The padding of the last frame is cut at the final granule position
+        let padded = decode_audio(&ogg_opus_fixture(5, 312, 500), None).unwrap();
+        assert_eq!(padded.samples.len(), 5 * 960 - 500 - 312);
+    }
+
+    #[test]
+    fn test_detect_container() {
+        assert_eq!(AudioContainer::detect(b"fLaC\0\0\0\0"), Some(AudioContainer::Flac));
+        assert_eq!(AudioContainer::detect(b"OggS\0\x02"), Some(AudioContainer::Ogg));
+        assert_eq!(AudioContainer::detect(&[0x1A, 0x45, 0xDF, 0xA3, 0x01]), Some(AudioContainer::WebM));
+        assert_eq!(AudioContainer::detect(b"\0\0\0\x20ftypM4A "), Some(AudioContainer::Mp4));
+        assert_eq!(AudioContainer::detect(b"ID3\x04\0"), Some(AudioContainer::Mp3));
+        assert_eq!(AudioContainer::detect(&[0xFF, 0xFB, 0x90, 0x00]), Some(AudioContainer::Mp3));
+        assert_eq!(AudioContainer::detect(b"hello"), None);
+    }
+
+    #[test]
+    fn test_container_from_mime_type() {
+        assert_eq!(
+            AudioContainer::from_mime_type("audio/webm;codecs=opus"),
+            Some(AudioContainer::WebM)
+        );
+        assert_eq!(AudioContainer::from_mime_type("audio/ogg; codecs=opus"), Some(AudioContainer::Ogg));
+        assert_eq!(AudioContainer::from_mime_type("audio/x-m4a"), Some(AudioContainer::Mp4));
+        assert_eq!(AudioContainer::from_mime_type("text/plain"), None);
+    }
+
+    #[test]
+    fn test_decode_wav_through_generic_path() {
+        let stereo = [0.5f32, 0.25, -0.5, -0.25];
+        let bytes = encode_wav(&stereo, WavSpec::float32(2, 22050)).unwrap();
+        let decoded = decode_audio(&bytes, None).unwrap();
+        assert_eq!(decoded.sample_rate, 22050);
+        assert_eq!(decoded.samples, vec![0.375, -0.375]);
+    }
+
+    #[test]
+    fn test_decode_flac() {
+        let samples: Vec<i16> = (0..64).map(|i| (i * 256 - 8192) as i16).collect();
+        let decoded = decode_audio(&flac_fixture(&samples), Some("audio/flac")).unwrap();
+
+        assert_eq!(decoded.sample_rate, 16000);
+        assert_eq!(decoded.samples.len(), samples.len());
+        for (a, &b) in decoded.samples.iter().zip(&samples) {
+            assert!((a - b as f32 / 32768.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_decode_for_whisper_resamples() {
+        let bytes = encode_wav(&vec![0.0; 44100], WavSpec::pcm16(1, 44100)).unwrap();
+        let input = decode_audio_for_whisper(&bytes, Some("audio/wav")).unwrap();
+        assert_eq!(input.len(), WHISPER_SAMPLE_RATE as usize);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(decode_audio(&[], None).is_err());
+        assert!(decode_audio(b"definitely not audio data", None).is_err());
+    }
+}
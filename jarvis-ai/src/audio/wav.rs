@@ -0,0 +1,431 @@
+//! WAV (RIFF/WAVE) reading and writing
+//!
+//! Supports integer PCM (8/16/24/32-bit), IEEE float (32/64-bit) and the
+//! `WAVE_FORMAT_EXTENSIBLE` header with any number of interleaved channels.
+
+use super::{resample_audio, WHISPER_SAMPLE_RATE};
+
+/// `WAVE_FORMAT_PCM` format tag
+const FORMAT_PCM: u16 = 0x0001;
+/// `WAVE_FORMAT_IEEE_FLOAT` format tag
+const FORMAT_IEEE_FLOAT: u16 = 0x0003;
+/// `WAVE_FORMAT_EXTENSIBLE` format tag
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Sample encoding of a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavSampleFormat {
+    /// Integer PCM (8-bit unsigned, 16/24/32-bit signed)
+    Pcm,
+    /// IEEE floating point (32 or 64-bit)
+    Float,
+}
+
+/// Format description of a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavSpec {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub sample_format: WavSampleFormat,
+}
+
+impl WavSpec {
+    /// 16-bit PCM spec, the most widely supported WAV flavour
+    pub fn pcm16(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: WavSampleFormat::Pcm,
+        }
+    }
+
+    /// 32-bit float spec, lossless for `f32` samples
+    pub fn float32(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: WavSampleFormat::Float,
+        }
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        self.bits_per_sample as usize / 8
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.channels == 0 {
+            return Err("WAV must have at least one channel".to_string());
+        }
+        if self.sample_rate == 0 {
+            return Err("Sample rate cannot be zero".to_string());
+        }
+        match (self.sample_format, self.bits_per_sample) {
+            (WavSampleFormat::Pcm, 8 | 16 | 24 | 32) => Ok(()),
+            (WavSampleFormat::Float, 32 | 64) => Ok(()),
+            (format, bits) => Err(format!(
+                "Unsupported WAV sample format: {:?} with {} bits per sample",
+                format, bits
+            )),
+        }
+    }
+}
+
+/// Decoded WAV audio with interleaved samples in [-1, 1]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WavAudio {
+    pub spec: WavSpec,
+    /// Interleaved samples (`frames * channels` values)
+    pub samples: Vec<f32>,
+}
+
+impl WavAudio {
+    /// Number of frames (samples per channel)
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.spec.channels as usize
+    }
+
+    /// Duration in seconds
+    pub fn duration_secs(&self) -> f32 {
+        self.frames() as f32 / self.spec.sample_rate as f32
+    }
+
+    /// Average all channels into a single mono channel
+    pub fn to_mono(&self) -> Vec<f32> {
+        downmix_to_mono(&self.samples, self.spec.channels)
+    }
+
+    /// Downmix to mono and resample to Whisper's 16kHz input rate
+    pub fn to_whisper_input(&self) -> Result<Vec<f32>, String> {
+        resample_audio(&self.to_mono(), self.spec.sample_rate, WHISPER_SAMPLE_RATE)
+    }
+}
+
+/// Average interleaved multi-channel audio into mono
+///
+/// Any trailing partial frame is ignored.
+pub fn downmix_to_mono(samples: &[f32], channels: u16) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+    if channels == 1 {
+        return samples.to_vec();
+    }
+    samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Parse the body of a `fmt ` chunk
+fn parse_fmt_chunk(chunk: &[u8]) -> Result<WavSpec, String> {
+    if chunk.len() < 16 {
+        return Err("WAV fmt chunk is too short".to_string());
+    }
+
+    let mut format_tag = read_u16(chunk, 0);
+    let channels = read_u16(chunk, 2);
+    let sample_rate = read_u32(chunk, 4);
+    let bits_per_sample = read_u16(chunk, 14);
+
+    if format_tag == FORMAT_EXTENSIBLE {
+        // cbSize(2) + validBits(2) + channelMask(4) + SubFormat GUID(16)
+        if chunk.len() < 40 {
+            return Err("WAV extensible fmt chunk is too short".to_string());
+        }
+        // The first two bytes of the SubFormat GUID carry the actual format tag
+        format_tag = read_u16(chunk, 24);
+    }
+
+    let sample_format = match format_tag {
+        FORMAT_PCM => WavSampleFormat::Pcm,
+        FORMAT_IEEE_FLOAT => WavSampleFormat::Float,
+        other => return Err(format!("Unsupported WAV format tag: 0x{:04X}", other)),
+    };
+
+    let spec = WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample,
+        sample_format,
+    };
+    spec.validate()?;
+    Ok(spec)
+}
+
+/// Convert raw little-endian sample bytes into f32 samples in [-1, 1]
+fn decode_samples(data: &[u8], spec: &WavSpec) -> Vec<f32> {
+    let width = spec.bytes_per_sample();
+    let chunks = data.chunks_exact(width);
+
+    match (spec.sample_format, spec.bits_per_sample) {
+        (WavSampleFormat::Pcm, 8) => chunks.map(|b| (b[0] as f32 - 128.0) / 128.0).collect(),
+        (WavSampleFormat::Pcm, 16) => chunks
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+        (WavSampleFormat::Pcm, 24) => chunks
+            .map(|b| {
+                // Place the 24 bits in the top of an i32 to sign-extend
+                let value = i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8;
+                value as f32 / 8_388_608.0
+            })
+            .collect(),
+        (WavSampleFormat::Pcm, 32) => chunks
+            .map(|b| (i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2_147_483_648.0) as f32)
+            .collect(),
+        (WavSampleFormat::Float, 32) => chunks
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        (WavSampleFormat::Float, 64) => chunks
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+            .collect(),
+        // Rejected by WavSpec::validate
+        _ => Vec::new(),
+    }
+}
+
+/// Decode a WAV file into interleaved f32 samples
+///
+/// # Arguments
+/// * `bytes` - Contents of a RIFF/WAVE file
+///
+/// # Returns
+/// The file's format and its samples scaled to [-1, 1]
+pub fn decode_wav(bytes: &[u8]) -> Result<WavAudio, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("Not a RIFF/WAVE file".to_string());
+    }
+
+    let mut spec = None;
+    let mut data = None;
+    let mut offset = 12;
+
+    while offset + 8 <= bytes.len() {
+        let chunk_id = &bytes[offset..offset + 4];
+        let chunk_size = read_u32(bytes, offset + 4) as usize;
+        let body_start = offset + 8;
+        // Streaming writers may leave the data size unset, so clamp to the file
+        let body_end = body_start.saturating_add(chunk_size).min(bytes.len());
+        let body = &bytes[body_start..body_end];
+
+        match chunk_id {
+            b"fmt " => spec = Some(parse_fmt_chunk(body)?),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+
+        // Chunks are word aligned
+        offset = body_end + (chunk_size & 1);
+    }
+
+    let spec = spec.ok_or("WAV file has no fmt chunk")?;
+    let data = data.ok_or("WAV file has no data chunk")?;
+
+    let frame_bytes = spec.bytes_per_sample() * spec.channels as usize;
+    let usable = data.len() - data.len() % frame_bytes;
+    let samples = decode_samples(&data[..usable], &spec);
+
+    Ok(WavAudio { spec, samples })
+}
+
+/// Encode interleaved f32 samples as a WAV file
+///
+/// Samples are clamped to [-1, 1] before integer quantization.
+///
+/// # Arguments
+/// * `samples` - Interleaved samples (`frames * channels` values)
+/// * `spec` - Output format
+pub fn encode_wav(samples: &[f32], spec: WavSpec) -> Result<Vec<u8>, String> {
+    spec.validate()?;
+    if !samples.len().is_multiple_of(spec.channels as usize) {
+        return Err(format!(
+            "Sample count {} is not a multiple of channel count {}",
+            samples.len(),
+            spec.channels
+        ));
+    }
+
+    let width = spec.bytes_per_sample();
+    let data_len = samples.len() * width;
+    let format_tag = match spec.sample_format {
+        WavSampleFormat::Pcm => FORMAT_PCM,
+        WavSampleFormat::Float => FORMAT_IEEE_FLOAT,
+    };
+    let block_align = spec.channels as usize * width;
+    let byte_rate = spec.sample_rate as usize * block_align;
+
+    let mut out = Vec::with_capacity(44 + data_len);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&((36 + data_len) as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&format_tag.to_le_bytes());
+    out.extend_from_slice(&spec.channels.to_le_bytes());
+    out.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    out.extend_from_slice(&(byte_rate as u32).to_le_bytes());
+    out.extend_from_slice(&(block_align as u16).to_le_bytes());
+    out.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(data_len as u32).to_le_bytes());
+
+    for &sample in samples {
+        let s = sample.clamp(-1.0, 1.0);
+        match (spec.sample_format, spec.bits_per_sample) {
+            (WavSampleFormat::Pcm, 8) => out.push(((s * 127.0).round() + 128.0) as u8),
+            (WavSampleFormat::Pcm, 16) => {
+                out.extend_from_slice(&((s * 32767.0).round() as i16).to_le_bytes())
+            }
+            (WavSampleFormat::Pcm, 24) => {
+                let value = (s * 8_388_607.0).round() as i32;
+                out.extend_from_slice(&value.to_le_bytes()[..3]);
+            }
+            (WavSampleFormat::Pcm, 32) => {
+                let value = (s as f64 * 2_147_483_647.0).round() as i32;
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            (WavSampleFormat::Float, 32) => out.extend_from_slice(&sample.to_le_bytes()),
+            (WavSampleFormat::Float, 64) => out.extend_from_slice(&(sample as f64).to_le_bytes()),
+            _ => unreachable!("spec validated above"),
+        }
+    }
+
+    if !data_len.is_multiple_of(2) {
+        // Pad byte keeps the chunk word aligned; not counted in the chunk size
+        out.push(0);
+    }
+
+    Ok(out)
+}
+
+/// Decode a WAV file into 16kHz mono samples ready for Whisper
+pub fn load_wav_for_whisper(bytes: &[u8]) -> Result<Vec<f32>, String> {
+    decode_wav(bytes)?.to_whisper_input()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frames: usize, sample_rate: u32) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin() * 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn test_roundtrip_all_formats() {
+        let samples = sine(256, 16000);
+        let specs = [
+            (WavSpec { bits_per_sample: 8, ..WavSpec::pcm16(1, 16000) }, 1.0 / 64.0),
+            (WavSpec::pcm16(1, 16000), 1e-4),
+            (WavSpec { bits_per_sample: 24, ..WavSpec::pcm16(1, 16000) }, 1e-6),
+            (WavSpec { bits_per_sample: 32, ..WavSpec::pcm16(1, 16000) }, 1e-6),
+            (WavSpec::float32(1, 16000), 0.0),
+            (WavSpec { bits_per_sample: 64, ..WavSpec::float32(1, 16000) }, 0.0),
+        ];
+
+        for (spec, tolerance) in specs {
+            let bytes = encode_wav(&samples, spec).unwrap();
+            let decoded = decode_wav(&bytes).unwrap();
+            assert_eq!(decoded.spec, spec);
+            assert_eq!(decoded.samples.len(), samples.len());
+            for (a, b) in samples.iter().zip(&decoded.samples) {
+                assert!((a - b).abs() <= tolerance, "{:?}: {} vs {}", spec, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_hand_built_pcm16_stereo() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&44u32.to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&8000u32.to_le_bytes());
+        bytes.extend_from_slice(&32000u32.to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        // An unknown chunk with odd size must be skipped including its pad byte
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3, 0]);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&8u32.to_le_bytes());
+        for v in [16384i16, -16384, i16::MAX, i16::MIN] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+
+        let wav = decode_wav(&bytes).unwrap();
+        assert_eq!(wav.spec, WavSpec::pcm16(2, 8000));
+        assert_eq!(wav.frames(), 2);
+        assert_eq!(wav.samples[0], 0.5);
+        assert_eq!(wav.samples[1], -0.5);
+        assert_eq!(wav.samples[3], -1.0);
+
+        let mono = wav.to_mono();
+        assert_eq!(mono.len(), 2);
+        assert_eq!(mono[0], 0.0);
+    }
+
+    #[test]
+    fn test_decode_extensible_float() {
+        let samples = [0.25f32, -0.75];
+        let mut bytes = encode_wav(&samples, WavSpec::float32(1, 16000)).unwrap();
+
+        // Rewrite the fmt chunk as WAVE_FORMAT_EXTENSIBLE with a float SubFormat
+        let mut fmt = bytes[20..36].to_vec();
+        fmt[0..2].copy_from_slice(&FORMAT_EXTENSIBLE.to_le_bytes());
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&32u16.to_le_bytes());
+        fmt.extend_from_slice(&4u32.to_le_bytes());
+        fmt.extend_from_slice(&FORMAT_IEEE_FLOAT.to_le_bytes());
+        fmt.extend_from_slice(&[0; 14]);
+
+        let mut rebuilt = bytes[0..12].to_vec();
+        rebuilt.extend_from_slice(b"fmt ");
+        rebuilt.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        rebuilt.extend_from_slice(&fmt);
+        rebuilt.extend_from_slice(&bytes.split_off(36));
+
+        let wav = decode_wav(&rebuilt).unwrap();
+        assert_eq!(wav.spec.sample_format, WavSampleFormat::Float);
+        assert_eq!(wav.samples, samples);
+    }
+
+    #[test]
+    fn test_whisper_input_downmixes_and_resamples() {
+        let mono = sine(48000, 48000);
+        let stereo: Vec<f32> = mono.iter().flat_map(|&s| [s, s]).collect();
+        let bytes = encode_wav(&stereo, WavSpec::float32(2, 48000)).unwrap();
+
+        let input = load_wav_for_whisper(&bytes).unwrap();
+        assert_eq!(input.len(), WHISPER_SAMPLE_RATE as usize);
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        assert!(decode_wav(b"not a wav file").is_err());
+        assert!(encode_wav(&[0.0; 3], WavSpec::pcm16(2, 16000)).is_err());
+        assert!(encode_wav(&[0.0], WavSpec { bits_per_sample: 12, ..WavSpec::pcm16(1, 16000) }).is_err());
+    }
+}
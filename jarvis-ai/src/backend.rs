@@ -0,0 +1,112 @@
+//! Compute backend and device selection
+//!
+//! The inference engine is generic over the Burn backend. This module names
+//! the backends the crate is built with and helps pick a device at runtime.
+
+use burn_ndarray::{NdArray, NdArrayDevice};
+use serde::{Deserialize, Serialize};
+
+/// CPU backend, always available
+pub type CpuBackend = NdArray<f32>;
+
+/// Device type of [`CpuBackend`]
+pub type CpuDevice = NdArrayDevice;
+
+/// WebGPU backend, available with the `wgpu` feature
+#[cfg(feature = "wgpu")]
+pub type GpuBackend = burn::backend::Wgpu;
+
+/// Device type of [`GpuBackend`]
+#[cfg(feature = "wgpu")]
+pub type GpuDevice = burn::backend::wgpu::WgpuDevice;
+
+/// Kind of compute backend an engine runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackendKind {
+    /// ndarray on the CPU
+    Cpu,
+    /// wgpu on WebGPU/Vulkan/Metal
+    Gpu,
+}
+
+impl BackendKind {
+    /// Get a human-readable name
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Cpu => "CPU (ndarray)",
+            BackendKind::Gpu => "GPU (wgpu)",
+        }
+    }
+
+    /// Check whether this backend was compiled into the crate
+    pub fn is_available(&self) -> bool {
+        match self {
+            BackendKind::Cpu => true,
+            BackendKind::Gpu => cfg!(feature = "wgpu"),
+        }
+    }
+
+    /// All backends compiled into the crate, fastest first
+    pub fn available() -> Vec<BackendKind> {
+        [BackendKind::Gpu, BackendKind::Cpu]
+            .into_iter()
+            .filter(BackendKind::is_available)
+            .collect()
+    }
+
+    /// The fastest backend compiled into the crate
+    ///
+    /// This does not check that a GPU adapter actually exists; callers should
+    /// fall back to [`BackendKind::Cpu`] if [`init_gpu`] fails.
+    pub fn preferred() -> BackendKind {
+        Self::available()[0]
+    }
+}
+
+/// Initialize the wgpu runtime for a device
+///
+/// In the browser the adapter can only be requested asynchronously, so this
+/// must be awaited before the first tensor is created on `device`. Native
+/// builds may skip it and let the runtime initialize lazily.
+///
+/// # Returns
+/// * `Ok(())` once the runtime is ready
+/// * `Err(String)` if no GPU adapter is available, e.g. a browser without WebGPU
+#[cfg(feature = "wgpu")]
+pub async fn init_gpu(device: &GpuDevice) -> Result<(), String> {
+    use burn::backend::wgpu::graphics::{AutoGraphicsApi, GraphicsApi};
+    use burn::backend::wgpu::{RuntimeOptions, init_setup_async};
+
+    // Devices created from an existing setup are already initialized
+    if matches!(device, GpuDevice::Existing(_)) {
+        return Ok(());
+    }
+    // The runtime panics when it finds no adapter, so check for one first
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: AutoGraphicsApi::backend().into(),
+        ..Default::default()
+    });
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| format!("No GPU adapter available: {}", e))?;
+
+    init_setup_async::<AutoGraphicsApi>(device, RuntimeOptions::default()).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_available_backends() {
+        let available = BackendKind::available();
+        assert!(available.contains(&BackendKind::Cpu));
+        assert_eq!(available.contains(&BackendKind::Gpu), cfg!(feature = "wgpu"));
+        assert_eq!(BackendKind::preferred(), available[0]);
+    }
+}
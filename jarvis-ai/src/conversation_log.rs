@@ -0,0 +1,636 @@
+//! Conversation logging
+//!
+//! A [`ConversationLogger`] records the messages, tool calls, model, timings
+//! and finish reason of one conversation, if its [`ConversationOptions`] have
+//! `log_enabled` set. Entries are buffered as they happen and written to a
+//! [`LogSink`] with [`ConversationLogger::flush`]; any sink can be exported as
+//! JSONL, one [`LogEntry`] per line.
+
+use crate::agent::runner::now_ms;
+use crate::agent::{AgentEvent, AgentRun, StopReason};
+use crate::types::{ConversationOptions, Message, MessagePartTool, MessageRole};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Why a conversation ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// The model answered
+    Answered,
+    /// The model answered with the conversation end keyword
+    ConversationEnded,
+    MaxIterations,
+    TimeLimit,
+    /// Generation failed
+    Error(String),
+}
+
+impl From<StopReason> for FinishReason {
+    fn from(reason: StopReason) -> Self {
+        match reason {
+            StopReason::Answered => FinishReason::Answered,
+            StopReason::MaxIterations => FinishReason::MaxIterations,
+            StopReason::TimeLimit => FinishReason::TimeLimit,
+        }
+    }
+}
+
+/// Something that happened in a conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LogEvent {
+    Started { options: ConversationOptions },
+    /// A message; replies carry the model that wrote them and how long it took
+    Message {
+        message: Message,
+        model: Option<String>,
+        duration_ms: Option<f64>,
+    },
+    /// A tool call with its response
+    ToolCall { call: MessagePartTool, duration_ms: f64 },
+    Finished { reason: FinishReason, duration_ms: f64 },
+}
+
+/// One line of a conversation log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub conversation_id: String,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: f64,
+    #[serde(flatten)]
+    pub event: LogEvent,
+}
+
+/// Storage for conversation logs
+#[allow(async_fn_in_trait)]
+pub trait LogSink {
+    /// Store entries after those already logged
+    async fn append(&self, entries: &[LogEntry]) -> Result<(), String>;
+
+    /// Entries of one conversation, in order
+    async fn entries(&self, conversation_id: &str) -> Result<Vec<LogEntry>, String>;
+
+    /// Ids of the logged conversations
+    async fn conversations(&self) -> Result<Vec<String>, String>;
+
+    /// Delete every log
+    async fn clear(&self) -> Result<(), String>;
+}
+
+/// Render entries as JSONL
+pub fn to_jsonl(entries: &[LogEntry]) -> Result<String, String> {
+    entries
+        .iter()
+        .map(|entry| serde_json::to_string(entry).map(|line| line + "\n"))
+        .collect::<Result<String, _>>()
+        .map_err(|e| format!("Failed to serialize log entry: {}", e))
+}
+
+/// Read entries from JSONL, skipping blank lines
+pub fn from_jsonl(jsonl: &str) -> Result<Vec<LogEntry>, String> {
+    jsonl
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("Invalid log entry: {}", e)))
+        .collect()
+}
+
+/// Export every conversation in a sink as JSONL
+pub async fn export_jsonl<S: LogSink + ?Sized>(sink: &S) -> Result<String, String> {
+    let mut jsonl = String::new();
+    for id in sink.conversations().await? {
+        jsonl.push_str(&to_jsonl(&sink.entries(&id).await?)?);
+    }
+    Ok(jsonl)
+}
+
+/// Records one conversation
+pub struct ConversationLogger {
+    options: ConversationOptions,
+    conversation_id: String,
+    model: Option<String>,
+    /// Entries not yet written to a sink
+    pending: Vec<LogEntry>,
+    started_ms: f64,
+    /// When the last event happened, for timing replies
+    last_ms: f64,
+    /// Start times of running tool calls, by part id
+    tools_started: HashMap<String, f64>,
+}
+
+impl ConversationLogger {
+    /// Start logging a new conversation; nothing is recorded unless `options.log_enabled`
+    pub fn new(options: ConversationOptions) -> Self {
+        let now = now_ms();
+        let mut logger = Self {
+            options: options.clone(),
+            conversation_id: Uuid::new_v4().to_string(),
+            model: None,
+            pending: Vec::new(),
+            started_ms: now,
+            last_ms: now,
+            tools_started: HashMap::new(),
+        };
+        logger.record(LogEvent::Started { options });
+        logger
+    }
+
+    /// Check whether entries are recorded
+    pub fn is_enabled(&self) -> bool {
+        self.options.log_enabled
+    }
+
+    pub fn conversation_id(&self) -> &str {
+        &self.conversation_id
+    }
+
+    /// Set the registry id of the model writing the replies
+    pub fn set_model(&mut self, model_id: &str) {
+        self.model = Some(model_id.to_string());
+    }
+
+    fn record(&mut self, event: LogEvent) {
+        let now = now_ms();
+        self.last_ms = now;
+        if self.options.log_enabled {
+            self.pending.push(LogEntry {
+                conversation_id: self.conversation_id.clone(),
+                timestamp_ms: now,
+                event,
+            });
+        }
+    }
+
+    /// Record a message, e.g. the user's
+    pub fn log_message(&mut self, message: &Message) {
+        let model = (message.role == MessageRole::Assistant).then(|| self.model.clone()).flatten();
+        self.record(LogEvent::Message {
+            message: message.clone(),
+            model,
+            duration_ms: None,
+        });
+    }
+
+    /// Record a step of [`Agent::run`](crate::Agent::run); pass this from its `on_event`
+    pub fn log_event(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::Response(message) => {
+                let duration_ms = now_ms() - self.last_ms;
+                self.record(LogEvent::Message {
+                    message: message.clone(),
+                    model: self.model.clone(),
+                    duration_ms: Some(duration_ms),
+                });
+            }
+            AgentEvent::ToolStarted(call) => {
+                self.tools_started.insert(call.base.id.clone(), now_ms());
+            }
+            AgentEvent::ToolFinished(call) => {
+                let now = now_ms();
+                let started = self.tools_started.remove(&call.base.id).unwrap_or(now);
+                self.record(LogEvent::ToolCall {
+                    call: call.clone(),
+                    duration_ms: now - started,
+                });
+            }
+        }
+    }
+
+    /// Record how a run of the agent ended
+    pub fn log_finish(&mut self, run: &Result<AgentRun, String>) {
+        let keyword = self.options.conversation_end_keyword.as_deref().filter(|k| !k.is_empty());
+        let reason = match run {
+            Ok(run) if keyword.is_some_and(|keyword| run.answer().contains(keyword)) => FinishReason::ConversationEnded,
+            Ok(run) => run.stop_reason.into(),
+            Err(e) => FinishReason::Error(e.clone()),
+        };
+        let duration_ms = now_ms() - self.started_ms;
+        self.record(LogEvent::Finished { reason, duration_ms });
+    }
+
+    /// Entries not yet written to a sink
+    pub fn pending(&self) -> &[LogEntry] {
+        &self.pending
+    }
+
+    /// Write pending entries to a sink; they stay pending if writing fails
+    pub async fn flush<S: LogSink + ?Sized>(&mut self, sink: &S) -> Result<(), String> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        sink.append(&self.pending).await?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Logs kept in memory, e.g. for tests or a session without persistence
+#[derive(Debug, Default)]
+pub struct MemoryLogSink {
+    entries: RefCell<Vec<LogEntry>>,
+}
+
+impl MemoryLogSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LogSink for MemoryLogSink {
+    async fn append(&self, entries: &[LogEntry]) -> Result<(), String> {
+        self.entries.borrow_mut().extend_from_slice(entries);
+        Ok(())
+    }
+
+    async fn entries(&self, conversation_id: &str) -> Result<Vec<LogEntry>, String> {
+        let entries = self.entries.borrow();
+        Ok(entries.iter().filter(|e| e.conversation_id == conversation_id).cloned().collect())
+    }
+
+    async fn conversations(&self) -> Result<Vec<String>, String> {
+        let mut ids: Vec<String> = Vec::new();
+        for entry in self.entries.borrow().iter() {
+            if !ids.contains(&entry.conversation_id) {
+                ids.push(entry.conversation_id.clone());
+            }
+        }
+        Ok(ids)
+    }
+
+    async fn clear(&self) -> Result<(), String> {
+        self.entries.borrow_mut().clear();
+        Ok(())
+    }
+}
+
+/// The sink used by [`default_sink`] on this platform
+#[cfg(not(target_arch = "wasm32"))]
+pub type DefaultLogSink = FileLogSink;
+
+/// The sink used by [`default_sink`] on this platform
+#[cfg(target_arch = "wasm32")]
+pub type DefaultLogSink = IndexedDbLogSink;
+
+/// Open the platform's default log storage
+pub fn default_sink() -> DefaultLogSink {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        FileLogSink::default_location()
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        IndexedDbLogSink::new(IndexedDbLogSink::DEFAULT_NAME)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use fs::FileLogSink;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod fs {
+    use super::{from_jsonl, to_jsonl, LogEntry, LogSink};
+    use std::collections::HashSet;
+    use std::fs::{self, OpenOptions};
+    use std::io::{ErrorKind, Write};
+    use std::path::{Path, PathBuf};
+
+    /// Logs in a directory on disk, one `<conversation id>.jsonl` file per conversation
+    #[derive(Debug, Clone)]
+    pub struct FileLogSink {
+        root: PathBuf,
+    }
+
+    impl FileLogSink {
+        pub fn new(root: impl Into<PathBuf>) -> Self {
+            Self { root: root.into() }
+        }
+
+        /// `$JARVIS_LOG_DIR`, else `$XDG_DATA_HOME/jarvis/logs`, else `~/.local/share/jarvis/logs`
+        pub fn default_location() -> Self {
+            let env = |name| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+            let root = env("JARVIS_LOG_DIR").unwrap_or_else(|| {
+                env("XDG_DATA_HOME")
+                    .or_else(|| env("HOME").map(|home| home.join(".local").join("share")))
+                    .unwrap_or_else(std::env::temp_dir)
+                    .join("jarvis")
+                    .join("logs")
+            });
+            Self::new(root)
+        }
+
+        /// Log directory
+        pub fn root(&self) -> &Path {
+            &self.root
+        }
+
+        /// Path of a conversation's log
+        pub fn path(&self, conversation_id: &str) -> Result<PathBuf, String> {
+            if conversation_id.is_empty() || conversation_id.contains(['/', '\\', '.']) {
+                return Err(format!("Invalid conversation id {:?}", conversation_id));
+            }
+            Ok(self.root.join(format!("{}.jsonl", conversation_id)))
+        }
+    }
+
+    impl LogSink for FileLogSink {
+        async fn append(&self, entries: &[LogEntry]) -> Result<(), String> {
+            fs::create_dir_all(&self.root).map_err(|e| format!("Failed to create {}: {}", self.root.display(), e))?;
+            // Each conversation once, in order of first appearance
+            let mut seen = HashSet::new();
+            let ids = entries.iter().map(|e| e.conversation_id.as_str()).filter(|id| seen.insert(*id));
+            for id in ids {
+                let conversation: Vec<LogEntry> = entries.iter().filter(|e| e.conversation_id == id).cloned().collect();
+                let path = self.path(id)?;
+                let jsonl = to_jsonl(&conversation)?;
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(jsonl.as_bytes()))
+                    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            }
+            Ok(())
+        }
+
+        async fn entries(&self, conversation_id: &str) -> Result<Vec<LogEntry>, String> {
+            let path = self.path(conversation_id)?;
+            match fs::read_to_string(&path) {
+                Ok(jsonl) => from_jsonl(&jsonl),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+                Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+            }
+        }
+
+        async fn conversations(&self) -> Result<Vec<String>, String> {
+            let dir = match fs::read_dir(&self.root) {
+                Ok(dir) => dir,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(format!("Failed to read {}: {}", self.root.display(), e)),
+            };
+            let mut ids: Vec<String> = dir
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.strip_suffix(".jsonl").map(str::to_string))
+                .collect();
+            ids.sort();
+            Ok(ids)
+        }
+
+        async fn clear(&self) -> Result<(), String> {
+            for id in self.conversations().await? {
+                let path = self.path(&id)?;
+                fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use web::IndexedDbLogSink;
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use super::{LogEntry, LogSink};
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{IdbDatabase, IdbObjectStoreParameters, IdbOpenDbRequest, IdbRequest, IdbTransaction, IdbTransactionMode};
+
+    const STORE: &str = "entries";
+    const BY_CONVERSATION: &str = "conversation_id";
+
+    /// Logs in an IndexedDB database; each record holds a conversation id and a JSON entry
+    pub struct IndexedDbLogSink {
+        name: String,
+    }
+
+    impl IndexedDbLogSink {
+        /// Database name used by [`super::default_sink`]
+        pub const DEFAULT_NAME: &'static str = "jarvis-logs";
+
+        pub fn new(name: impl Into<String>) -> Self {
+            Self { name: name.into() }
+        }
+
+        async fn open(&self) -> Result<IdbDatabase, String> {
+            let window = web_sys::window().ok_or("No window found")?;
+            let factory = window.indexed_db().ok().flatten().ok_or("IndexedDB is not available")?;
+            let request = factory.open_with_u32(&self.name, 1).map_err(|_| "Failed to open log database")?;
+
+            let upgrade = Closure::<dyn FnMut(web_sys::Event)>::new(|event: web_sys::Event| {
+                let db = event
+                    .target()
+                    .and_then(|target| target.dyn_into::<IdbOpenDbRequest>().ok())
+                    .and_then(|request| request.result().ok())
+                    .and_then(|db| db.dyn_into::<IdbDatabase>().ok());
+                if let Some(db) = db {
+                    let parameters = IdbObjectStoreParameters::new();
+                    parameters.set_auto_increment(true);
+                    if let Ok(store) = db.create_object_store_with_optional_parameters(STORE, &parameters) {
+                        let _ = store.create_index_with_str(BY_CONVERSATION, BY_CONVERSATION);
+                    }
+                }
+            });
+            request.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+            let db = wait(&request).await;
+            request.set_onupgradeneeded(None);
+            db?.dyn_into::<IdbDatabase>().map_err(|_| "Not a database".to_string())
+        }
+
+        async fn transaction(&self, mode: IdbTransactionMode) -> Result<IdbTransaction, String> {
+            self.open()
+                .await?
+                .transaction_with_str_and_mode(STORE, mode)
+                .map_err(|_| "Failed to start log transaction".to_string())
+        }
+    }
+
+    /// Wait for a request to succeed and return its result
+    async fn wait(request: &IdbRequest) -> Result<JsValue, String> {
+        let promise = js_sys::Promise::new(&mut |resolve, reject| {
+            request.set_onsuccess(Some(&resolve));
+            request.set_onerror(Some(&reject));
+        });
+        JsFuture::from(promise).await.map_err(|_| "IndexedDB request failed")?;
+        request.result().map_err(|_| "IndexedDB request has no result".to_string())
+    }
+
+    /// Parse the JSON entries of records
+    fn read_records(records: JsValue) -> Result<Vec<LogEntry>, String> {
+        js_sys::Array::from(&records)
+            .iter()
+            .map(|record| {
+                let json = js_sys::Reflect::get(&record, &"entry".into()).ok().and_then(|v| v.as_string());
+                serde_json::from_str(&json.ok_or("Log record has no entry")?).map_err(|e| format!("Invalid log entry: {}", e))
+            })
+            .collect()
+    }
+
+    impl LogSink for IndexedDbLogSink {
+        async fn append(&self, entries: &[LogEntry]) -> Result<(), String> {
+            let transaction = self.transaction(IdbTransactionMode::Readwrite).await?;
+            let store = transaction.object_store(STORE).map_err(|_| "Missing log store")?;
+            for entry in entries {
+                let json = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+                let record = js_sys::Object::new();
+                js_sys::Reflect::set(&record, &BY_CONVERSATION.into(), &entry.conversation_id.as_str().into())
+                    .and_then(|_| js_sys::Reflect::set(&record, &"entry".into(), &json.into()))
+                    .map_err(|_| "Failed to build log record")?;
+                store.add(&record).map_err(|_| "Failed to add log record")?;
+            }
+            let done = js_sys::Promise::new(&mut |resolve, reject| {
+                transaction.set_oncomplete(Some(&resolve));
+                transaction.set_onerror(Some(&reject));
+            });
+            JsFuture::from(done).await.map_err(|_| "Failed to write log".to_string())?;
+            Ok(())
+        }
+
+        async fn entries(&self, conversation_id: &str) -> Result<Vec<LogEntry>, String> {
+            let transaction = self.transaction(IdbTransactionMode::Readonly).await?;
+            let request = transaction
+                .object_store(STORE)
+                .and_then(|store| store.index(BY_CONVERSATION))
+                .and_then(|index| index.get_all_with_key(&conversation_id.into()))
+                .map_err(|_| "Failed to read log")?;
+            read_records(wait(&request).await?)
+        }
+
+        async fn conversations(&self) -> Result<Vec<String>, String> {
+            let transaction = self.transaction(IdbTransactionMode::Readonly).await?;
+            let request = transaction
+                .object_store(STORE)
+                .and_then(|store| store.get_all())
+                .map_err(|_| "Failed to read log")?;
+            let mut ids: Vec<String> = Vec::new();
+            for entry in read_records(wait(&request).await?)? {
+                if !ids.contains(&entry.conversation_id) {
+                    ids.push(entry.conversation_id);
+                }
+            }
+            Ok(ids)
+        }
+
+        async fn clear(&self) -> Result<(), String> {
+            let transaction = self.transaction(IdbTransactionMode::Readwrite).await?;
+            let request = transaction
+                .object_store(STORE)
+                .and_then(|store| store.clear())
+                .map_err(|_| "Failed to clear log")?;
+            wait(&request).await.map(|_| ())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn run(stop_reason: StopReason, answer: &str) -> Result<AgentRun, String> {
+        let messages = vec![Message::assistant(answer.to_string())];
+        Ok(AgentRun { history: messages.clone(), messages, stop_reason })
+    }
+
+    #[test]
+    fn test_log_conversation() {
+        let mut logger = ConversationLogger::new(ConversationOptions::default());
+        logger.set_model("tinyllama");
+        logger.log_message(&Message::user("What time is it?".to_string()));
+
+        let mut call = MessagePartTool::new("get_time".to_string(), serde_json::json!({}));
+        logger.log_event(&AgentEvent::Response(Message::assistant_with_tool_calls(String::new(), vec![call.clone()])));
+        logger.log_event(&AgentEvent::ToolStarted(call.clone()));
+        call.response = "12:00".to_string();
+        logger.log_event(&AgentEvent::ToolFinished(call));
+        logger.log_event(&AgentEvent::Response(Message::assistant("Noon, sir. CONVERSATION_ENDED".to_string())));
+        logger.log_finish(&run(StopReason::Answered, "Noon, sir. CONVERSATION_ENDED"));
+
+        let sink = MemoryLogSink::new();
+        block_on(logger.flush(&sink)).unwrap();
+        assert!(logger.pending().is_empty());
+        let entries = block_on(sink.entries(logger.conversation_id())).unwrap();
+        let events: Vec<&str> = entries
+            .iter()
+            .map(|entry| match &entry.event {
+                LogEvent::Started { .. } => "started",
+                LogEvent::Message { model: None, .. } => "user",
+                LogEvent::Message { model: Some(_), duration_ms: Some(_), .. } => "reply",
+                LogEvent::Message { .. } => "other",
+                LogEvent::ToolCall { .. } => "tool",
+                LogEvent::Finished { .. } => "finished",
+            })
+            .collect();
+        assert_eq!(events, vec!["started", "user", "reply", "tool", "reply", "finished"]);
+        assert!(matches!(&entries[5].event, LogEvent::Finished { reason: FinishReason::ConversationEnded, .. }));
+
+        // JSONL round trip
+        let jsonl = block_on(export_jsonl(&sink)).unwrap();
+        assert_eq!(jsonl.lines().count(), 6);
+        assert!(jsonl.lines().next().unwrap().contains("\"event\":\"started\""));
+        let parsed = from_jsonl(&jsonl).unwrap();
+        assert!(matches!(&parsed[3].event, LogEvent::ToolCall { call, .. } if call.response == "12:00"));
+    }
+
+    #[test]
+    fn test_logging_disabled() {
+        let options = ConversationOptions { log_enabled: false, ..Default::default() };
+        let mut logger = ConversationLogger::new(options);
+        logger.log_message(&Message::user("Hi".to_string()));
+        logger.log_finish(&Err("model not ready".to_string()));
+        assert!(!logger.is_enabled());
+        assert!(logger.pending().is_empty());
+
+        let mut logger = ConversationLogger::new(ConversationOptions::default());
+        logger.log_finish(&run(StopReason::TimeLimit, "CONVERSATION_ENDED"));
+        logger.log_finish(&run(StopReason::TimeLimit, "Still working"));
+        logger.log_finish(&Err("model not ready".to_string()));
+        let reasons: Vec<FinishReason> = logger.pending()[1..]
+            .iter()
+            .filter_map(|entry| match &entry.event {
+                LogEvent::Finished { reason, .. } => Some(reason.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reasons, vec![
+            FinishReason::ConversationEnded,
+            FinishReason::TimeLimit,
+            FinishReason::Error("model not ready".to_string())
+        ]);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_file_sink() {
+        let dir = std::env::temp_dir().join(format!("jarvis-logs-{}", Uuid::new_v4()));
+        let sink = FileLogSink::new(&dir);
+        assert!(block_on(sink.conversations()).unwrap().is_empty());
+
+        let mut first = ConversationLogger::new(ConversationOptions::default());
+        let mut second = ConversationLogger::new(ConversationOptions::default());
+        first.log_message(&Message::user("One".to_string()));
+        block_on(first.flush(&sink)).unwrap();
+        block_on(second.flush(&sink)).unwrap();
+        first.log_message(&Message::user("Two".to_string()));
+        block_on(first.flush(&sink)).unwrap();
+
+        assert_eq!(block_on(sink.conversations()).unwrap().len(), 2);
+        assert_eq!(block_on(sink.entries(first.conversation_id())).unwrap().len(), 3);
+        assert_eq!(block_on(export_jsonl(&sink)).unwrap().lines().count(), 4);
+        assert!(sink.path("../escape").is_err());
+
+        // Entries of interleaved conversations are each written once
+        let first_entries = block_on(sink.entries(first.conversation_id())).unwrap();
+        let second_entries = block_on(sink.entries(second.conversation_id())).unwrap();
+        let interleaved = [first_entries[0].clone(), second_entries[0].clone(), first_entries[1].clone()];
+        block_on(sink.clear()).unwrap();
+        assert!(block_on(sink.conversations()).unwrap().is_empty());
+        block_on(sink.append(&interleaved)).unwrap();
+        assert_eq!(block_on(sink.entries(first.conversation_id())).unwrap().len(), 2);
+        assert_eq!(block_on(sink.entries(second.conversation_id())).unwrap().len(), 1);
+
+        block_on(sink.clear()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,720 @@
+//! Constrained decoding with grammars and JSON Schemas
+//!
+//! A [`Grammar`] (written in GBNF or compiled from a JSON Schema) is matched
+//! character by character with a pushdown automaton. At each generation step
+//! a [`GrammarSampler`] masks out every token whose text cannot continue the
+//! output, so the model can only produce strings the grammar accepts.
+
+mod bpe;
+pub mod gbnf;
+pub mod json_schema;
+
+use crate::models::gguf::MetadataValue;
+use bpe::Bpe;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// One element of a rule's alternative
+#[derive(Debug, Clone, PartialEq)]
+enum Elem {
+    /// A character in (or, if negated, outside) any of the inclusive ranges
+    Chars { ranges: Vec<(char, char)>, negated: bool },
+    /// A reference to another rule
+    Rule(usize),
+}
+
+impl Elem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Elem::Chars { ranges, negated } => ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated,
+            Elem::Rule(_) => false,
+        }
+    }
+}
+
+/// Position within a rule: the element of an alternative matched next
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Pos {
+    rule: usize,
+    alt: usize,
+    idx: usize,
+}
+
+/// Rules to return through; the last position is matched next
+type Stack = Vec<Pos>;
+
+/// A compiled context-free grammar
+///
+/// Left-recursive rules are rejected; write them right-recursively.
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    /// Alternatives of each rule, each a sequence of elements
+    rules: Vec<Vec<Vec<Elem>>>,
+    root: usize,
+}
+
+impl Grammar {
+    /// Parse a GBNF grammar whose start rule is `root`
+    pub fn parse(gbnf: &str) -> Result<Self, String> {
+        gbnf::parse(gbnf)
+    }
+
+    /// Compile a JSON Schema into a grammar for JSON documents it accepts
+    pub fn from_json_schema(schema: &Value) -> Result<Self, String> {
+        Self::parse(&json_schema::to_gbnf(schema)?)
+    }
+
+    /// State before any output
+    pub fn start(&self) -> GrammarState<'_> {
+        let mut stacks = Vec::new();
+        for alt in 0..self.rules[self.root].len() {
+            let pos = Pos { rule: self.root, alt, idx: 0 };
+            self.expand(vec![pos], &mut stacks);
+        }
+        GrammarState::new(self, stacks)
+    }
+
+    /// Check whether the grammar accepts `text` in full
+    pub fn accepts(&self, text: &str) -> bool {
+        self.start().advance_str(text).is_some_and(|state| state.is_complete())
+    }
+
+    /// Resolve rule references until each stack's top is a character element
+    ///
+    /// This terminates because the parser rejects left-recursive grammars.
+    fn expand(&self, mut stack: Stack, out: &mut Vec<Stack>) {
+        loop {
+            let Some(&top) = stack.last() else {
+                // Matched the whole grammar
+                out.push(stack);
+                return;
+            };
+            match self.rules[top.rule][top.alt].get(top.idx) {
+                None => {
+                    stack.pop();
+                    if let Some(parent) = stack.last_mut() {
+                        parent.idx += 1;
+                    }
+                }
+                Some(Elem::Chars { .. }) => {
+                    out.push(stack);
+                    return;
+                }
+                Some(Elem::Rule(rule)) => {
+                    for alt in 0..self.rules[*rule].len() {
+                        let mut next = stack.clone();
+                        next.push(Pos { rule: *rule, alt, idx: 0 });
+                        self.expand(next, out);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Where matching a [`Grammar`] has got to
+#[derive(Debug, Clone)]
+pub struct GrammarState<'g> {
+    grammar: &'g Grammar,
+    stacks: Vec<Stack>,
+}
+
+impl<'g> GrammarState<'g> {
+    fn new(grammar: &'g Grammar, mut stacks: Vec<Stack>) -> Self {
+        stacks.sort();
+        stacks.dedup();
+        Self { grammar, stacks }
+    }
+
+    /// The state after `c`, or `None` if the grammar does not allow it here
+    pub fn advance(&self, c: char) -> Option<Self> {
+        let mut stacks = Vec::new();
+        for stack in &self.stacks {
+            let Some(&top) = stack.last() else {
+                continue;
+            };
+            if !self.grammar.rules[top.rule][top.alt][top.idx].matches(c) {
+                continue;
+            }
+            let mut next = stack.clone();
+            if let Some(top) = next.last_mut() {
+                top.idx += 1;
+            }
+            self.grammar.expand(next, &mut stacks);
+        }
+        (!stacks.is_empty()).then(|| Self::new(self.grammar, stacks))
+    }
+
+    /// The state after `text`, or `None` if the grammar does not allow it here
+    pub fn advance_str(&self, text: &str) -> Option<Self> {
+        text.chars().try_fold(self.clone(), |state, c| state.advance(c))
+    }
+
+    /// Check whether the output so far is a complete match
+    pub fn is_complete(&self) -> bool {
+        self.stacks.iter().any(Vec::is_empty)
+    }
+
+    /// Check whether the output can only end here
+    pub fn is_finished(&self) -> bool {
+        self.stacks.iter().all(Vec::is_empty)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: Vec<(char, usize)>,
+    /// Tokens whose text ends at this node
+    tokens: Vec<u32>,
+}
+
+/// The text of each token id, indexed for computing masks
+///
+/// Special tokens have no text for masks and decoding, but are still found
+/// in prompts by [`Vocabulary::encode`].
+#[derive(Debug)]
+pub struct Vocabulary {
+    /// Token texts by id
+    tokens: Vec<String>,
+    eos: u32,
+    /// Prefix tree of token texts; node 0 is the root
+    trie: Vec<TrieNode>,
+    /// Beginning-of-sequence token the tokenizer starts every prompt with
+    bos: Option<u32>,
+    /// Special tokens by their spelling in prompts, longest first
+    special: Vec<(String, u32)>,
+    /// The tokenizer's merges, when it is BPE
+    bpe: Option<Bpe>,
+}
+
+impl Vocabulary {
+    /// Build from token texts by id; empty tokens are never allowed
+    pub fn new(tokens: Vec<String>, eos: u32) -> Self {
+        let mut trie = vec![TrieNode::default()];
+        for (id, token) in tokens.iter().enumerate() {
+            if token.is_empty() || id as u32 == eos {
+                continue;
+            }
+            let mut node = 0;
+            for c in token.chars() {
+                node = match trie[node].children.iter().find(|(child, _)| *child == c) {
+                    Some((_, next)) => *next,
+                    None => {
+                        trie.push(TrieNode::default());
+                        let next = trie.len() - 1;
+                        trie[node].children.push((c, next));
+                        next
+                    }
+                };
+            }
+            trie[node].tokens.push(id as u32);
+        }
+        Self { tokens, eos, trie, bos: None, special: Vec::new(), bpe: None }
+    }
+
+    /// Recognize special tokens in prompts
+    fn with_special(mut self, mut special: Vec<(String, u32)>) -> Self {
+        special.sort_by_key(|(text, _)| std::cmp::Reverse(text.len()));
+        self.special = special;
+        self
+    }
+
+    /// Read the vocabulary of a HuggingFace `tokenizer.json`
+    ///
+    /// Byte-level BPE (`Ġ` for space) and SentencePiece (`▁` for space,
+    /// `<0x0A>` byte tokens) spellings are decoded to the text they produce.
+    /// Tokens that are only part of a character are left out. Prompts are
+    /// encoded with the tokenizer's merges and its beginning-of-sequence token.
+    pub fn from_tokenizer_json(json: &[u8], vocab_size: usize) -> Result<Self, String> {
+        let json: Value = serde_json::from_slice(json).map_err(|e| format!("Invalid tokenizer.json: {}", e))?;
+        let vocab = json.pointer("/model/vocab").and_then(Value::as_object).ok_or("tokenizer.json has no vocabulary")?;
+        let byte_level = json.pointer("/decoder/type").and_then(Value::as_str) == Some("ByteLevel");
+
+        let mut tokens = vec![String::new(); vocab_size];
+        let mut ids = HashMap::new();
+        for (token, id) in vocab {
+            let Some(id) = id.as_u64().filter(|id| (*id as usize) < vocab_size) else {
+                continue;
+            };
+            tokens[id as usize] = if byte_level { decode_byte_level(token) } else { decode_sentencepiece(token) };
+            ids.insert(token.clone(), id as u32);
+        }
+
+        // Special tokens never appear in constrained output
+        let mut eos = None;
+        let mut special = Vec::new();
+        for added in json.get("added_tokens").and_then(Value::as_array).into_iter().flatten() {
+            let (Some(content), Some(id)) = (added["content"].as_str(), added["id"].as_u64()) else {
+                continue;
+            };
+            if let Some(slot) = tokens.get_mut(id as usize) {
+                slot.clear();
+            }
+            if matches!(content, "</s>" | "<|endoftext|>" | "<|im_end|>" | "<|end|>") && eos.is_none() {
+                eos = Some(id as u32);
+            }
+            special.push((content.to_string(), id as u32));
+        }
+        let eos = eos.ok_or("tokenizer.json has no end-of-sequence token")?;
+
+        let merges = json.pointer("/model/merges").and_then(Value::as_array);
+        let bpe = merges.filter(|_| json.pointer("/model/type").and_then(Value::as_str) == Some("BPE")).map(|merges| {
+            // Merges are written as "a b" or, in newer files, as ["a", "b"]
+            let pairs = merges.iter().filter_map(|merge| match merge {
+                Value::String(merge) => merge.split_once(' '),
+                Value::Array(pair) => Some((pair.first()?.as_str()?, pair.get(1)?.as_str()?)),
+                _ => None,
+            });
+            Bpe::new(ids, pairs, byte_level)
+        });
+        let bos = template_bos(&json, &special);
+
+        let mut vocabulary = Self::new(tokens, eos).with_special(special);
+        vocabulary.bpe = bpe;
+        vocabulary.bos = bos;
+        Ok(vocabulary)
+    }
+
+    /// Read the vocabulary in a GGUF file's `tokenizer.ggml.*` metadata
+    ///
+    /// `gpt2` vocabularies are byte-level BPE with merges; `llama` ones are
+    /// SentencePiece, whose merges follow from the token scores. Unknown,
+    /// control and user-defined tokens are special.
+    pub fn from_gguf(metadata: &HashMap<String, MetadataValue>, vocab_size: usize) -> Result<Self, String> {
+        let get = |key: &str| metadata.get(&format!("tokenizer.ggml.{}", key));
+        let array = |key: &str| get(key).and_then(MetadataValue::as_array).unwrap_or_default();
+        let byte_level = match get("model").and_then(MetadataValue::as_str) {
+            Some("gpt2") => true,
+            Some("llama") => false,
+            other => return Err(format!("GGUF tokenizer {:?} is not supported", other.unwrap_or_default())),
+        };
+        let spellings = array("tokens");
+        if spellings.is_empty() {
+            return Err("GGUF file has no tokenizer.ggml.tokens".to_string());
+        }
+        let types = array("token_type");
+
+        let mut tokens = vec![String::new(); vocab_size];
+        let mut special = Vec::new();
+        // Spellings of the ordinary tokens, for merging
+        let mut pieces = Vec::with_capacity(spellings.len());
+        for (id, spelling) in spellings.iter().enumerate() {
+            let spelling = spelling.as_str().unwrap_or_default();
+            if matches!(types.get(id).and_then(MetadataValue::as_usize), Some(2..=4)) {
+                special.push((spelling.to_string(), id as u32));
+                pieces.push(String::new());
+                continue;
+            }
+            pieces.push(spelling.to_string());
+            if let Some(slot) = tokens.get_mut(id) {
+                *slot = if byte_level { decode_byte_level(spelling) } else { decode_sentencepiece(spelling) };
+            }
+        }
+        let eos = get("eos_token_id").and_then(MetadataValue::as_usize).ok_or("GGUF file has no end-of-sequence token")?;
+
+        let bpe = if byte_level {
+            let ids = pieces.iter().enumerate().filter(|(_, p)| !p.is_empty()).map(|(id, p)| (p.clone(), id as u32));
+            let merges = array("merges").iter().filter_map(|merge| merge.as_str()?.split_once(' '));
+            Bpe::new(ids.collect(), merges, true)
+        } else {
+            let scores: Vec<f32> = array("scores").iter().map(|s| s.as_f64().unwrap_or_default() as f32).collect();
+            Bpe::from_scores(&pieces, &scores)
+        };
+        // llama.cpp starts SentencePiece prompts with BOS unless told otherwise
+        let add_bos = match get("add_bos_token") {
+            Some(MetadataValue::Bool(add)) => *add,
+            _ => !byte_level,
+        };
+
+        let mut vocabulary = Self::new(tokens, eos as u32).with_special(special);
+        vocabulary.bpe = Some(bpe);
+        vocabulary.bos = get("bos_token_id").and_then(MetadataValue::as_usize).filter(|_| add_bos).map(|id| id as u32);
+        Ok(vocabulary)
+    }
+
+    /// Id of a special token, e.g. a vision-language model's `<image>`
+    pub fn special_token(&self, content: &str) -> Option<u32> {
+        self.special.iter().find(|(token, _)| token == content).map(|(_, id)| *id)
+    }
+
+    /// Single-character tokens for printable ASCII, for models without a tokenizer
+    ///
+    /// Id 0 ends the sequence and id `n` is the character `' ' + n - 1`.
+    pub fn ascii(vocab_size: usize) -> Self {
+        let mut tokens = vec![String::new(); vocab_size];
+        for (id, c) in (' '..='~').enumerate() {
+            if let Some(slot) = tokens.get_mut(id + 1) {
+                *slot = c.to_string();
+            }
+        }
+        Self::new(tokens, 0)
+    }
+
+    /// Number of token ids
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Check whether there are no tokens
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// End-of-sequence token id
+    pub fn eos(&self) -> u32 {
+        self.eos
+    }
+
+    /// Text of a token
+    pub fn token(&self, id: u32) -> &str {
+        self.tokens.get(id as usize).map_or("", String::as_str)
+    }
+
+    /// Text of a sequence of tokens, stopping at the end-of-sequence token
+    pub fn decode(&self, ids: &[u32]) -> String {
+        ids.iter().take_while(|&&id| id != self.eos).map(|&id| self.token(id)).collect()
+    }
+
+    /// Beginning-of-sequence token prompts start with, if the tokenizer adds one
+    pub fn bos(&self) -> Option<u32> {
+        self.bos
+    }
+
+    /// Encode a prompt: the beginning-of-sequence token, if any, then [`Self::encode`]
+    pub fn encode_prompt(&self, text: &str) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.bos.into_iter().collect();
+        let text_ids = self.encode(text);
+        // A template may already start with the token
+        let skip = usize::from(self.bos.is_some() && text_ids.first() == self.bos.as_ref());
+        ids.extend(&text_ids[skip..]);
+        ids
+    }
+
+    /// Split text into tokens
+    ///
+    /// Special tokens such as `<|im_start|>` are matched first. The text
+    /// between them is encoded with the tokenizer's merges when it has them,
+    /// otherwise by longest match, dropping characters no token covers.
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut rest = text;
+        loop {
+            let next = self
+                .special
+                .iter()
+                .filter_map(|(special, id)| Some((rest.find(special.as_str())?, special.len(), *id)))
+                .min_by_key(|(start, _, _)| *start);
+            let Some((start, len, id)) = next else {
+                self.encode_text(rest, &mut ids);
+                return ids;
+            };
+            self.encode_text(&rest[..start], &mut ids);
+            ids.push(id);
+            rest = &rest[start + len..];
+        }
+    }
+
+    /// Encode text without special tokens
+    fn encode_text(&self, text: &str, ids: &mut Vec<u32>) {
+        if let Some(bpe) = &self.bpe {
+            return bpe.encode(text, ids);
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let mut start = 0;
+        while start < chars.len() {
+            let mut node = 0;
+            let mut longest = None;
+            for (len, c) in chars[start..].iter().enumerate() {
+                let Some(&(_, child)) = self.trie[node].children.iter().find(|(child, _)| child == c) else {
+                    break;
+                };
+                node = child;
+                if let Some(&id) = self.trie[node].tokens.first() {
+                    longest = Some((id, len + 1));
+                }
+            }
+            match longest {
+                Some((id, len)) => {
+                    ids.push(id);
+                    start += len;
+                }
+                None => start += 1,
+            }
+        }
+    }
+
+    /// Which tokens may come next in `state`; the end-of-sequence token is
+    /// allowed once the output is complete
+    pub fn mask(&self, state: &GrammarState) -> Vec<bool> {
+        let mut allowed = vec![false; self.tokens.len()];
+        self.visit(0, state, &mut allowed);
+        if state.is_complete() {
+            if let Some(eos) = allowed.get_mut(self.eos as usize) {
+                *eos = true;
+            }
+        }
+        allowed
+    }
+
+    /// Walk the trie alongside the grammar, skipping subtrees it rejects
+    fn visit(&self, node: usize, state: &GrammarState, allowed: &mut [bool]) {
+        for &(c, child) in &self.trie[node].children {
+            if let Some(next) = state.advance(c) {
+                for &id in &self.trie[child].tokens {
+                    allowed[id as usize] = true;
+                }
+                self.visit(child, &next, allowed);
+            }
+        }
+    }
+}
+
+/// The special token a `TemplateProcessing` post-processor puts before a
+/// single sequence, possibly inside a `Sequence` of post-processors
+fn template_bos(json: &Value, special: &[(String, u32)]) -> Option<u32> {
+    let processor = json.get("post_processor")?;
+    let processors = match processor.get("processors").and_then(Value::as_array) {
+        Some(processors) => processors.iter().collect(),
+        None => vec![processor],
+    };
+    let template = processors.into_iter().find(|p| p["type"] == "TemplateProcessing")?;
+    let first = template.pointer("/single/0/SpecialToken/id")?.as_str()?;
+    special.iter().find(|(text, _)| text == first).map(|(_, id)| *id)
+}
+
+/// GPT-2 byte-level BPE maps each byte to a printable character; undo it
+///
+/// A token holding only part of a multi-byte character has no text of its
+/// own, so it comes out empty and is never allowed in constrained output.
+fn decode_byte_level(token: &str) -> String {
+    let bytes: Option<Vec<u8>> = token
+        .chars()
+        .map(|c| match c as u32 {
+            // Printable bytes stand for themselves
+            0x21..=0x7e | 0xa1..=0xac | 0xae..=0xff => Some(c as u32 as u8),
+            // The rest were shifted up past 0xff in order
+            n @ 0x100..=0x143 => {
+                let mut unprintable = (0u8..=0xff).filter(|b| !matches!(b, 0x21..=0x7e | 0xa1..=0xac | 0xae..=0xff));
+                unprintable.nth((n - 0x100) as usize)
+            }
+            _ => None,
+        })
+        .collect();
+    bytes.and_then(|bytes| String::from_utf8(bytes).ok()).unwrap_or_default()
+}
+
+/// SentencePiece writes spaces as `▁` and unknown bytes as `<0xNN>`
+fn decode_sentencepiece(token: &str) -> String {
+    if let Some(byte) = token.strip_prefix("<0x").and_then(|t| t.strip_suffix('>')) {
+        return u8::from_str_radix(byte, 16).ok().filter(u8::is_ascii).map_or(String::new(), |b| (b as char).to_string());
+    }
+    token.replace('▁', " ")
+}
+
+/// Restricts sampling to tokens that keep the output valid for a grammar
+pub struct GrammarSampler<'a> {
+    vocabulary: &'a Vocabulary,
+    state: GrammarState<'a>,
+    output: String,
+}
+
+impl<'a> GrammarSampler<'a> {
+    pub fn new(grammar: &'a Grammar, vocabulary: &'a Vocabulary) -> Self {
+        Self {
+            vocabulary,
+            state: grammar.start(),
+            output: String::new(),
+        }
+    }
+
+    /// Set the logits of disallowed tokens to negative infinity
+    pub fn apply(&self, logits: &mut [f32]) {
+        for (logit, allowed) in logits.iter_mut().zip(self.vocabulary.mask(&self.state)) {
+            if !allowed {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+    }
+
+    /// Pick the allowed token with the highest logit, or `None` if nothing is allowed
+    pub fn sample_greedy(&self, logits: &[f32]) -> Option<u32> {
+        let mut logits = logits.to_vec();
+        self.apply(&mut logits);
+        logits
+            .iter()
+            .enumerate()
+            .filter(|(_, logit)| logit.is_finite())
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(id, _)| id as u32)
+    }
+
+    /// Record a sampled token; returns `true` once generation should stop
+    pub fn accept(&mut self, token: u32) -> Result<bool, String> {
+        if token == self.vocabulary.eos() {
+            return if self.state.is_complete() { Ok(true) } else { Err("Output ended before the grammar was complete".to_string()) };
+        }
+        let text = self.vocabulary.token(token);
+        self.state = self
+            .state
+            .advance_str(text)
+            .ok_or_else(|| format!("Token {:?} is not allowed by the grammar", text))?;
+        self.output.push_str(text);
+        Ok(self.state.is_finished())
+    }
+
+    /// Text generated so far
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Check whether the output so far is a complete match
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A `tokenizer.json` with the same tokens as [`Vocabulary::ascii`], but
+    /// for `special` tokens taking the last ids
+    pub(crate) fn ascii_tokenizer_json(vocab_size: usize, special: &[&str]) -> String {
+        let first_special = vocab_size - special.len();
+        let vocab: serde_json::Map<String, Value> =
+            (' '..='~').zip(1..first_special).map(|(c, id)| (c.to_string(), id.into())).collect();
+        let added = std::iter::once(&"</s>").chain(special).zip(std::iter::once(0).chain(first_special..));
+        let added: Vec<Value> = added.map(|(content, id)| serde_json::json!({"id": id, "content": content})).collect();
+        serde_json::json!({"added_tokens": added, "model": {"vocab": vocab}}).to_string()
+    }
+
+    const ARITHMETIC: &str = r#"
+        # Sums of numbers, with optional parentheses
+        root ::= expr
+        expr ::= term ("+" term)*
+        term ::= [0-9]+ | "(" expr ")"
+    "#;
+
+    #[test]
+    fn test_parse_and_match() {
+        let grammar = Grammar::parse(ARITHMETIC).unwrap();
+        assert!(grammar.accepts("1+23"));
+        assert!(grammar.accepts("(1+2)+3"));
+        assert!(!grammar.accepts("1+"));
+        assert!(!grammar.accepts("1-2"));
+        assert!(grammar.start().advance_str("(1+").is_some());
+
+        let state = grammar.start().advance_str("12").unwrap();
+        assert!(state.is_complete());
+        assert!(!state.is_finished());
+
+        let grammar = Grammar::parse("root ::= [^\"\\n]* \"\\x21\"\nend ::= .?").unwrap();
+        assert!(grammar.accepts("hi there!"));
+        assert!(!grammar.accepts("hi\"!"));
+    }
+
+    #[test]
+    fn test_grammar_errors() {
+        assert!(Grammar::parse("expr ::= \"a\"").unwrap_err().contains("no root"));
+        assert!(Grammar::parse("root ::= missing").unwrap_err().contains("missing"));
+        assert!(Grammar::parse("root ::= (\"a\"").is_err());
+        assert!(Grammar::parse("root ::= \"a\"\nroot ::= \"b\"").is_err());
+
+        // Left recursion, direct, through other rules or past rules that match nothing
+        let error = |gbnf| Grammar::parse(gbnf).unwrap_err();
+        assert_eq!(error("root ::= root \"a\" | \"b\""), "Rule root is left-recursive; write it right-recursively");
+        assert!(error("root ::= list\nlist ::= item | list \",\" item\nitem ::= [a-z]").contains("Rule list"));
+        assert!(error("root ::= \"x\"? \" \"* root \"a\" | \"b\"").contains("Rule root"));
+        assert!(error("root ::= (\"a\"?)*").contains("can match nothing"));
+        // Right recursion is fine
+        assert!(Grammar::parse("root ::= \"a\" root | \"b\"").unwrap().accepts("aab"));
+    }
+
+    #[test]
+    fn test_token_mask() {
+        let grammar = Grammar::parse(r#"root ::= "yes" | "no""#).unwrap();
+        let tokens = ["</s>", "y", "yes", "no", "n", "o", "maybe", "es"].map(str::to_string).to_vec();
+        let vocabulary = Vocabulary::new(tokens, 0);
+
+        let mut sampler = GrammarSampler::new(&grammar, &vocabulary);
+        let allowed: Vec<&str> = (0..8).filter(|&id| vocabulary.mask(&sampler.state)[id]).map(|id| vocabulary.token(id as u32)).collect();
+        assert_eq!(allowed, vec!["y", "yes", "no", "n"]);
+
+        // "maybe" has the highest logit but is masked out
+        let logits = [0.0, 0.1, 0.2, 0.3, 0.4, 0.0, 9.0, 0.0];
+        assert_eq!(sampler.sample_greedy(&logits), Some(4));
+        assert!(!sampler.accept(4).unwrap());
+        assert_eq!(sampler.sample_greedy(&logits), Some(5));
+        assert!(sampler.accept(5).unwrap());
+        assert_eq!(sampler.output(), "no");
+        assert!(sampler.accept(6).is_err());
+
+        assert_eq!(vocabulary.encode("yes, no"), vec![2, 3]);
+        assert_eq!(vocabulary.decode(&[1, 7, 0, 3]), "yes");
+    }
+
+    #[test]
+    fn test_tokenizer_vocabularies() {
+        // "Ã©" is "é" as bytes C3 A9; "Ã" and "©" are its two halves
+        let byte_level = r#"{"model": {"vocab": {"Ġyes": 0, "Ċ": 1, "{\"": 2, "Ã©": 4, "Ã": 5, "©": 6}},
+            "decoder": {"type": "ByteLevel"}, "added_tokens": [{"id": 3, "content": "<|endoftext|>"}]}"#;
+        let vocabulary = Vocabulary::from_tokenizer_json(byte_level.as_bytes(), 7).unwrap();
+        assert_eq!([0, 1, 2, 3].map(|id| vocabulary.token(id)), [" yes", "\n", "{\"", ""]);
+        assert_eq!([4, 5, 6].map(|id| vocabulary.token(id)), ["é", "", ""]);
+        let any = Grammar::parse("root ::= .*").unwrap();
+        let mask = vocabulary.mask(&any.start());
+        assert!(mask[4] && !mask[5] && !mask[6]);
+        assert_eq!(vocabulary.eos(), 3);
+
+        let sentencepiece = r#"{"model": {"vocab": {"<s>": 1, "</s>": 2, "▁yes": 3, "<0x0A>": 4}},
+            "added_tokens": [{"id": 1, "content": "<s>"}, {"id": 2, "content": "</s>"}]}"#;
+        let vocabulary = Vocabulary::from_tokenizer_json(sentencepiece.as_bytes(), 5).unwrap();
+        assert_eq!([1, 3, 4].map(|id| vocabulary.token(id)), ["", " yes", "\n"]);
+        assert_eq!(vocabulary.eos(), 2);
+
+        assert!(Vocabulary::from_tokenizer_json(b"{\"model\": {\"vocab\": {}}}", 4).is_err());
+        assert_eq!(Vocabulary::ascii(100).token(1), " ");
+    }
+
+    #[test]
+    fn test_encode_prompts() {
+        let chat = r#"{"model": {"type": "BPE", "vocab": {"u": 0, "s": 1, "e": 2, "r": 3, "us": 4, "er": 5, "user": 6, "Ċ": 7},
+            "merges": ["u s", ["e", "r"], "us er"]}, "decoder": {"type": "ByteLevel"},
+            "added_tokens": [{"id": 8, "content": "<|im_start|>"}, {"id": 9, "content": "<|im_end|>"}]}"#;
+        let vocabulary = Vocabulary::from_tokenizer_json(chat.as_bytes(), 10).unwrap();
+        assert_eq!(vocabulary.encode("<|im_start|>user"), [8, 6]);
+        assert_eq!(vocabulary.encode("<|im_start|>user\nus<|im_end|>"), [8, 6, 7, 4, 9]);
+        assert_eq!(vocabulary.encode_prompt("<|im_start|>user"), [8, 6]);
+        // Special tokens are still never sampled
+        let mask = vocabulary.mask(&Grammar::parse("root ::= .*").unwrap().start());
+        assert!(!mask[8] && mask[6]);
+
+        let sentencepiece = r#"{"model": {"type": "BPE", "vocab": {"<unk>": 0, "<s>": 1, "</s>": 2, "▁": 3, "h": 4, "i": 5,
+            "▁h": 6, "▁hi": 7, "<0x21>": 8}, "merges": ["▁ h", "▁h i"]},
+            "added_tokens": [{"id": 1, "content": "<s>"}, {"id": 2, "content": "</s>"}],
+            "post_processor": {"type": "TemplateProcessing",
+                "single": [{"SpecialToken": {"id": "<s>", "type_id": 0}}, {"Sequence": {"id": "A", "type_id": 0}}]}}"#;
+        let vocabulary = Vocabulary::from_tokenizer_json(sentencepiece.as_bytes(), 9).unwrap();
+        assert_eq!(vocabulary.bos(), Some(1));
+        // "!" is missing from the vocabulary, so it falls back to its byte
+        assert_eq!(vocabulary.encode_prompt("hi!</s>"), [1, 7, 8, 2]);
+        assert_eq!(vocabulary.encode_prompt("<s>hi"), [1, 7]);
+
+        let string = |s: &str| MetadataValue::String(s.to_string());
+        let list = |values: Vec<MetadataValue>| MetadataValue::Array(values);
+        let spellings = ["<unk>", "<s>", "</s>", "▁", "h", "i", "▁h", "▁hi", "<0x21>"];
+        let metadata = HashMap::from([
+            ("tokenizer.ggml.model".to_string(), string("llama")),
+            ("tokenizer.ggml.tokens".to_string(), list(spellings.iter().map(|s| string(s)).collect())),
+            ("tokenizer.ggml.scores".to_string(), list([0.0, 0.0, 0.0, -1.0, -2.0, -3.0, -4.0, -5.0, 0.0].map(MetadataValue::Float).to_vec())),
+            ("tokenizer.ggml.token_type".to_string(), list([2, 3, 3, 1, 1, 1, 1, 1, 6].map(MetadataValue::Int).to_vec())),
+            ("tokenizer.ggml.bos_token_id".to_string(), MetadataValue::UInt(1)),
+            ("tokenizer.ggml.eos_token_id".to_string(), MetadataValue::UInt(2)),
+        ]);
+        let vocabulary = Vocabulary::from_gguf(&metadata, 9).unwrap();
+        assert_eq!(vocabulary.encode_prompt("hi!</s>"), [1, 7, 8, 2]);
+        assert_eq!((vocabulary.eos(), vocabulary.token(7), vocabulary.token(1)), (2, " hi", ""));
+        assert!(Vocabulary::from_gguf(&HashMap::new(), 9).is_err());
+    }
+}
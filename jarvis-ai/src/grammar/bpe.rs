@@ -0,0 +1,217 @@
+//! Byte-pair encoding of prompts
+//!
+//! Prompts are split into tokens the way the model's tokenizer does it, by
+//! applying its merges in rank order, rather than by longest match. Both
+//! GPT-2 byte-level vocabularies (`Ġ` for space) and SentencePiece ones (`▁`
+//! for space, `<0xNN>` tokens for bytes missing from the vocabulary) are
+//! handled.
+
+use std::collections::HashMap;
+
+/// Merges of a BPE tokenizer over token ids
+#[derive(Debug)]
+pub(super) struct Bpe {
+    /// Token ids by their spelling in the tokenizer
+    ids: HashMap<String, u32>,
+    /// Rank and result of merging two tokens; lower ranks merge first
+    merges: HashMap<(u32, u32), (usize, u32)>,
+    /// GPT-2 byte-level rather than SentencePiece spelling
+    byte_level: bool,
+}
+
+impl Bpe {
+    /// Build from token spellings by id and merges in rank order
+    ///
+    /// Merges whose parts or result are not in the vocabulary are ignored.
+    pub(super) fn new<'a>(
+        ids: HashMap<String, u32>,
+        merges: impl IntoIterator<Item = (&'a str, &'a str)>,
+        byte_level: bool,
+    ) -> Self {
+        let mut table = HashMap::new();
+        for (rank, (left, right)) in merges.into_iter().enumerate() {
+            let merged = format!("{}{}", left, right);
+            let (Some(&left), Some(&right), Some(&merged)) = (ids.get(left), ids.get(right), ids.get(&merged)) else {
+                continue;
+            };
+            table.entry((left, right)).or_insert((rank, merged));
+        }
+        Self { ids, merges: table, byte_level }
+    }
+
+    /// Merges of a SentencePiece vocabulary that only has token scores
+    ///
+    /// Every way of splitting a token into two others is a merge, ranked by
+    /// the score of the token it makes, highest first. Empty spellings are
+    /// left out.
+    pub(super) fn from_scores(tokens: &[String], scores: &[f32]) -> Self {
+        let ids: HashMap<String, u32> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| !token.is_empty())
+            .map(|(id, token)| (token.clone(), id as u32))
+            .collect();
+        let mut merges = Vec::new();
+        for (token, score) in tokens.iter().zip(scores) {
+            for (split, _) in token.char_indices().skip(1) {
+                let (left, right) = token.split_at(split);
+                if ids.contains_key(left) && ids.contains_key(right) {
+                    merges.push((*score, left, right));
+                }
+            }
+        }
+        merges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Self::new(ids, merges.into_iter().map(|(_, left, right)| (left, right)), false)
+    }
+
+    /// Append the tokens of text that holds no special tokens
+    pub(super) fn encode(&self, text: &str, out: &mut Vec<u32>) {
+        if text.is_empty() {
+            return;
+        }
+        if self.byte_level {
+            for word in split_words(text) {
+                let symbols: Vec<u32> = word.bytes().filter_map(|b| self.ids.get(byte_char(b).encode_utf8(&mut [0; 4])).copied()).collect();
+                self.merge(symbols, out);
+            }
+        } else {
+            // SentencePiece marks the start of the text like any other space.
+            // Pieces never continue past a space into the next word, so words
+            // are merged one at a time.
+            let text = format!("▁{}", text.replace(' ', "▁"));
+            let mut symbols = Vec::new();
+            let mut previous = '▁';
+            for c in text.chars() {
+                if c == '▁' && previous != '▁' {
+                    self.merge(std::mem::take(&mut symbols), out);
+                }
+                previous = c;
+                match self.ids.get(c.encode_utf8(&mut [0; 4])) {
+                    Some(&id) => symbols.push(id),
+                    None => {
+                        let bytes = c.to_string().into_bytes();
+                        symbols.extend(bytes.iter().filter_map(|b| self.ids.get(&format!("<0x{:02X}>", b)).copied()));
+                    }
+                }
+            }
+            self.merge(symbols, out);
+        }
+    }
+
+    /// Apply merges to a word's symbols, lowest rank first
+    fn merge(&self, mut symbols: Vec<u32>, out: &mut Vec<u32>) {
+        loop {
+            let best = symbols
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| Some((self.merges.get(&(pair[0], pair[1]))?, i)))
+                .min_by_key(|((rank, _), _)| *rank);
+            let Some(((_, merged), i)) = best else {
+                break;
+            };
+            symbols[i] = *merged;
+            symbols.remove(i + 1);
+        }
+        out.extend(symbols);
+    }
+}
+
+/// GPT-2's printable stand-in for a byte; see `decode_byte_level`
+fn byte_char(byte: u8) -> char {
+    let printable = |b: u8| matches!(b, 0x21..=0x7e | 0xa1..=0xac | 0xae..=0xff);
+    if printable(byte) {
+        return byte as char;
+    }
+    let index = (0..byte).filter(|b| !printable(*b)).count() as u32;
+    char::from_u32(0x100 + index).unwrap_or('?')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Letter,
+    Number,
+    Space,
+    Other,
+}
+
+fn class(c: char) -> Class {
+    if c.is_alphabetic() {
+        Class::Letter
+    } else if c.is_numeric() {
+        Class::Number
+    } else if c.is_whitespace() {
+        Class::Space
+    } else {
+        Class::Other
+    }
+}
+
+/// Split text into words as GPT-2's pre-tokenizer does
+///
+/// Words are English contractions, runs of letters, digits or other symbols
+/// with at most one leading space, and runs of whitespace, which leave their
+/// last space to a following word.
+fn split_words(text: &str) -> Vec<&str> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let offset = |i: usize| chars.get(i).map_or(text.len(), |(pos, _)| *pos);
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        if c == '\'' {
+            let rest = &text[start + 1..];
+            if let Some(suffix) = ["s", "t", "re", "ve", "m", "ll", "d"].iter().find(|s| rest.starts_with(*s)) {
+                words.push(&text[start..start + 1 + suffix.len()]);
+                i += 1 + suffix.len();
+                continue;
+            }
+        }
+        let mut end = i;
+        if c == ' ' && chars.get(i + 1).is_some_and(|(_, next)| class(*next) != Class::Space) {
+            end += 1;
+        }
+        let kind = class(chars[end].1);
+        if kind == Class::Space {
+            while end < chars.len() && class(chars[end].1) == Class::Space {
+                end += 1;
+            }
+            if end < chars.len() && end - i > 1 {
+                end -= 1;
+            }
+        } else {
+            while end < chars.len() && class(chars[end].1) == kind {
+                end += 1;
+            }
+        }
+        words.push(&text[start..offset(end)]);
+        i = end;
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(split_words("Hello world's 42!!  \n x"), ["Hello", " world", "'s", " 42", "!!", "  \n", " x"]);
+        assert_eq!(split_words("a  "), ["a", "  "]);
+    }
+
+    #[test]
+    fn test_merges_by_rank() {
+        let ids: HashMap<String, u32> = ["a", "b", "c", "ab", "bc"].iter().enumerate().map(|(i, t)| (t.to_string(), i as u32)).collect();
+        // Longest match would give "ab" + "c"; "b c" merges first
+        let bpe = Bpe::new(ids, [("b", "c"), ("a", "b")], true);
+        let mut out = Vec::new();
+        bpe.encode("abc", &mut out);
+        assert_eq!(out, [0, 4]);
+
+        // With scores, the best-scoring result merges first
+        let scored = Bpe::from_scores(&["a", "b", "c", "ab", "bc"].map(String::from), &[0.0, 0.0, 0.0, -1.0, -2.0]);
+        let mut out = Vec::new();
+        scored.merge(vec![0, 1, 2], &mut out);
+        assert_eq!(out, [3, 2]);
+    }
+}
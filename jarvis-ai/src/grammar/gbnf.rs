@@ -0,0 +1,314 @@
+//! Parser for GBNF grammars
+//!
+//! The format used by llama.cpp: rules like `root ::= "yes" | "no"`, with
+//! string literals, character classes (`[a-z]`, `[^"]`), `.` for any
+//! character, parenthesized groups, and the `*`, `+` and `?` operators.
+//! `#` starts a comment. Left-recursive rules are rejected.
+
+use super::{Elem, Grammar};
+use std::collections::HashMap;
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    /// Alternatives of each rule; `None` until the rule is defined
+    rules: Vec<Option<Vec<Vec<Elem>>>>,
+}
+
+/// Parse a GBNF grammar whose start rule is `root`
+pub fn parse(src: &str) -> Result<Grammar, String> {
+    let mut parser = Parser {
+        src,
+        pos: 0,
+        names: Vec::new(),
+        ids: HashMap::new(),
+        rules: Vec::new(),
+    };
+
+    parser.skip_space(true);
+    while parser.pos < src.len() {
+        let name = parser.name().ok_or_else(|| parser.error("expected a rule name"))?;
+        parser.skip_space(false);
+        if !parser.eat("::=") {
+            return Err(parser.error("expected ::="));
+        }
+        let id = parser.rule_id(&name);
+        if parser.rules[id].is_some() {
+            return Err(format!("Rule {} is defined twice", name));
+        }
+        let alternatives = parser.alternatives(false)?;
+        parser.rules[id] = Some(alternatives);
+        parser.skip_space(true);
+    }
+
+    let root = *parser.ids.get("root").ok_or("Grammar has no root rule")?;
+    let rules = parser
+        .rules
+        .into_iter()
+        .zip(&parser.names)
+        .map(|(rule, name)| rule.ok_or_else(|| format!("Rule {} is used but not defined", name)))
+        .collect::<Result<Vec<_>, _>>()?;
+    match left_recursive(&rules, &parser.names) {
+        Some(name) if name.starts_with('<') => {
+            return Err("Grammar repeats an item that can match nothing, which makes it left-recursive".to_string())
+        }
+        Some(name) => return Err(format!("Rule {} is left-recursive; write it right-recursively", name)),
+        None => {}
+    }
+    Ok(Grammar { rules, root })
+}
+
+/// Name of a rule that can reach itself without consuming a character, if
+/// any; named rules are preferred over helper rules
+fn left_recursive<'n>(rules: &[Vec<Vec<Elem>>], names: &'n [String]) -> Option<&'n str> {
+    // Rules that can match the empty string, found by iterating to a fixed point
+    let mut nullable = vec![false; rules.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (id, alternatives) in rules.iter().enumerate() {
+            let empty = alternatives
+                .iter()
+                .any(|alt| alt.iter().all(|elem| matches!(elem, Elem::Rule(rule) if nullable[*rule])));
+            if empty && !nullable[id] {
+                nullable[id] = true;
+                changed = true;
+            }
+        }
+    }
+
+    // Rules each rule can start with: references reached past nullable rules only
+    let leftmost: Vec<Vec<usize>> = rules
+        .iter()
+        .map(|alternatives| {
+            let mut starts = Vec::new();
+            for alt in alternatives {
+                for elem in alt {
+                    let Elem::Rule(rule) = elem else { break };
+                    starts.push(*rule);
+                    if !nullable[*rule] {
+                        break;
+                    }
+                }
+            }
+            starts
+        })
+        .collect();
+
+    let recursive = (0..rules.len()).filter(|&id| {
+        let mut seen = vec![false; rules.len()];
+        let mut pending = leftmost[id].clone();
+        while let Some(rule) = pending.pop() {
+            if rule == id {
+                return true;
+            }
+            if !std::mem::replace(&mut seen[rule], true) {
+                pending.extend(&leftmost[rule]);
+            }
+        }
+        false
+    });
+    recursive.map(|id| names[id].as_str()).min_by_key(|name| name.starts_with('<'))
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.src[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.src[..self.pos].matches('\n').count() + 1;
+        format!("Invalid grammar at line {}: {}", line, message)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let matched = self.rest().starts_with(token);
+        if matched {
+            self.pos += token.len();
+        }
+        matched
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next_char(&mut self) -> Result<char, String> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end of grammar"))?;
+        self.pos += c.len_utf8();
+        Ok(c)
+    }
+
+    /// Skip spaces and comments, and newlines if `newlines` is set
+    fn skip_space(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                self.pos += self.rest().find('\n').unwrap_or(self.rest().len());
+            } else if c == ' ' || c == '\t' || c == '\r' || (newlines && c == '\n') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let len = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_')).unwrap_or(self.rest().len());
+        if len == 0 {
+            return None;
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Some(name)
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        self.names.push(name.to_string());
+        self.rules.push(None);
+        self.ids.insert(name.to_string(), self.rules.len() - 1);
+        self.rules.len() - 1
+    }
+
+    /// Add an anonymous rule for a group or repetition
+    fn helper_rule(&mut self, alternatives: Vec<Vec<Elem>>) -> usize {
+        // Not a valid rule name, so it cannot clash with one
+        let id = self.rule_id(&format!("<group {}>", self.rules.len()));
+        self.rules[id] = Some(alternatives);
+        id
+    }
+
+    /// Check whether the next token starts a new rule (`name ::=`)
+    fn at_rule_start(&self) -> bool {
+        let rest = self.rest();
+        let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_')).unwrap_or(rest.len());
+        len > 0 && rest[len..].trim_start_matches([' ', '\t']).starts_with("::=")
+    }
+
+    fn alternatives(&mut self, nested: bool) -> Result<Vec<Vec<Elem>>, String> {
+        let mut alternatives = vec![self.sequence(nested)?];
+        while self.eat("|") {
+            alternatives.push(self.sequence(nested)?);
+        }
+        Ok(alternatives)
+    }
+
+    /// Elements up to `|`, `)` or the next rule; newlines are only allowed inside groups
+    fn sequence(&mut self, nested: bool) -> Result<Vec<Elem>, String> {
+        let mut sequence = Vec::new();
+        loop {
+            self.skip_space(true);
+            match self.peek() {
+                None | Some('|') | Some(')') => break,
+                _ if !nested && self.at_rule_start() => break,
+                _ => {}
+            }
+            let start = sequence.len();
+            self.item(&mut sequence)?;
+            self.skip_space(false);
+            let repeat = match self.peek() {
+                Some(op @ ('*' | '+' | '?')) => {
+                    self.pos += 1;
+                    op
+                }
+                _ => continue,
+            };
+            let item: Vec<Elem> = sequence.drain(start..).collect();
+            match repeat {
+                '*' => {
+                    let id = self.helper_rule(Vec::new());
+                    let mut recurse = item;
+                    recurse.push(Elem::Rule(id));
+                    self.rules[id] = Some(vec![recurse, Vec::new()]);
+                    sequence.push(Elem::Rule(id));
+                }
+                '+' => {
+                    let id = self.helper_rule(Vec::new());
+                    let mut recurse = item.clone();
+                    recurse.push(Elem::Rule(id));
+                    self.rules[id] = Some(vec![recurse, item]);
+                    sequence.push(Elem::Rule(id));
+                }
+                _ => {
+                    let id = self.helper_rule(vec![item, Vec::new()]);
+                    sequence.push(Elem::Rule(id));
+                }
+            }
+        }
+        Ok(sequence)
+    }
+
+    /// Parse one literal, class, reference or group onto `sequence`
+    fn item(&mut self, sequence: &mut Vec<Elem>) -> Result<(), String> {
+        match self.peek() {
+            Some('"') => {
+                self.pos += 1;
+                while !self.eat("\"") {
+                    let c = self.literal_char()?;
+                    sequence.push(Elem::Chars { ranges: vec![(c, c)], negated: false });
+                }
+            }
+            Some('[') => {
+                self.pos += 1;
+                let negated = self.eat("^");
+                let mut ranges = Vec::new();
+                while !self.eat("]") {
+                    let start = self.literal_char()?;
+                    let end = if self.rest().starts_with('-') && !self.rest().starts_with("-]") {
+                        self.pos += 1;
+                        self.literal_char()?
+                    } else {
+                        start
+                    };
+                    ranges.push((start, end));
+                }
+                sequence.push(Elem::Chars { ranges, negated });
+            }
+            Some('.') => {
+                self.pos += 1;
+                sequence.push(Elem::Chars { ranges: Vec::new(), negated: true });
+            }
+            Some('(') => {
+                self.pos += 1;
+                let alternatives = self.alternatives(true)?;
+                self.skip_space(true);
+                if !self.eat(")") {
+                    return Err(self.error("expected )"));
+                }
+                let id = self.helper_rule(alternatives);
+                sequence.push(Elem::Rule(id));
+            }
+            _ => {
+                let name = self.name().ok_or_else(|| self.error("expected a literal, class, group or rule name"))?;
+                let id = self.rule_id(&name);
+                sequence.push(Elem::Rule(id));
+            }
+        }
+        Ok(())
+    }
+
+    /// A character in a literal or class, with escapes resolved
+    fn literal_char(&mut self) -> Result<char, String> {
+        let c = self.next_char()?;
+        if c != '\\' {
+            return Ok(c);
+        }
+        let hex = |parser: &mut Self, digits: usize| -> Result<char, String> {
+            let code = parser.rest().get(..digits).and_then(|h| u32::from_str_radix(h, 16).ok());
+            parser.pos += digits;
+            code.and_then(char::from_u32).ok_or_else(|| parser.error("invalid escape"))
+        };
+        match self.next_char()? {
+            'n' => Ok('\n'),
+            'r' => Ok('\r'),
+            't' => Ok('\t'),
+            'x' => hex(self, 2),
+            'u' => hex(self, 4),
+            other => Ok(other),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! JSON Schema to GBNF compiler
+//!
+//! Supports the subset of JSON Schema used for tool arguments and
+//! extraction: `type` (including lists of types), `properties` with
+//! `required`, `items` with `minItems`/`maxItems`, `minLength`/`maxLength`,
+//! `enum`, `const`, `anyOf`/`oneOf` and local `$ref`s. Objects never get
+//! properties beyond those listed, as with `additionalProperties: false`; a
+//! schema without constraints accepts any JSON value. Other keywords that
+//! restrict values, such as `pattern`, `format` or `minimum`, are an error
+//! rather than silently ignored.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Rules every compiled grammar can use
+const PRIMITIVES: &str = r#"
+ws ::= " "?
+string ::= "\"" char* "\""
+char ::= [^"\\\x00-\x1f] | "\\" (["\\/bfnrt] | "u" hex hex hex hex)
+hex ::= [0-9a-fA-F]
+integer ::= "-"? ("0" | [1-9] [0-9]*)
+number ::= integer ("." [0-9]+)? ([eE] [-+]? [0-9]+)?
+boolean ::= "true" | "false"
+null ::= "null"
+value ::= object | array | string | number | boolean | null
+object ::= "{" ws (string ":" ws value ("," ws string ":" ws value)*)? ws "}"
+array ::= "[" ws (value ("," ws value)*)? ws "]"
+"#;
+
+/// Keywords that restrict values in ways the compiler cannot express
+const UNSUPPORTED: &[&str] = &[
+    "pattern",
+    "format",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "patternProperties",
+    "propertyNames",
+    "minProperties",
+    "maxProperties",
+    "dependentRequired",
+    "dependentSchemas",
+    "prefixItems",
+    "contains",
+    "uniqueItems",
+    "allOf",
+    "not",
+    "if",
+];
+
+struct Compiler<'a> {
+    root: &'a Value,
+    rules: Vec<String>,
+    /// Rule names of `$ref`s already compiled, so recursive schemas terminate
+    refs: HashMap<String, String>,
+}
+
+/// Compile a JSON Schema into a GBNF grammar for the JSON documents it accepts
+pub fn to_gbnf(schema: &Value) -> Result<String, String> {
+    let mut compiler = Compiler {
+        root: schema,
+        rules: Vec::new(),
+        refs: HashMap::new(),
+    };
+    let root = compiler.visit(schema)?;
+    let mut gbnf = format!("root ::= {}\n", root);
+    for rule in &compiler.rules {
+        gbnf.push_str(rule);
+        gbnf.push('\n');
+    }
+    gbnf.push_str(PRIMITIVES);
+    Ok(gbnf)
+}
+
+/// A GBNF string literal matching `text` exactly
+fn literal(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `item` repeated between `min` and `max` times, joined by `separator`
+fn repeat(item: &str, separator: &str, min: usize, max: Option<usize>) -> String {
+    let next = if separator.is_empty() { item.to_string() } else { format!("{} {}", separator, item) };
+    let optional_tail = |count: usize| -> String {
+        // Nested so the separators stay between items: (a (a (a)?)?)?
+        (0..count).fold(String::new(), |inner, _| format!("({} {})?", next, inner))
+    };
+    match (min, max) {
+        (_, Some(0)) => String::new(),
+        (0, max) => format!("({})?", repeat(item, separator, 1, max)),
+        (min, max) => {
+            let mut out = item.to_string();
+            for _ in 1..min {
+                out.push(' ');
+                out.push_str(&next);
+            }
+            match max {
+                Some(max) => format!("{} {}", out, optional_tail(max.saturating_sub(min))),
+                None => format!("{} ({})*", out, next),
+            }
+        }
+    }
+}
+
+fn size(schema: &Value, key: &str) -> Option<usize> {
+    schema.get(key).and_then(Value::as_u64).map(|n| n as usize)
+}
+
+impl Compiler<'_> {
+    /// Add a rule and return its name
+    fn rule(&mut self, body: String) -> String {
+        let name = format!("schema-{}", self.rules.len());
+        self.rules.push(format!("{} ::= {}", name, body));
+        name
+    }
+
+    /// A GBNF expression for the values `schema` accepts
+    fn visit(&mut self, schema: &Value) -> Result<String, String> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok("value".to_string()),
+            Value::Object(_) => schema,
+            _ => return Err(format!("Unsupported schema: {}", schema)),
+        };
+        if let Some(keyword) = UNSUPPORTED.iter().find(|keyword| schema.get(**keyword).is_some()) {
+            return Err(format!("Unsupported schema keyword: {}", keyword));
+        }
+        if schema.get("additionalProperties").is_some_and(|additional| additional != &Value::Bool(false)) {
+            return Err("Unsupported schema keyword: additionalProperties, other than false".to_string());
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            return self.reference(reference);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(literal(&value.to_string()));
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            let alternatives: Vec<String> = values.iter().map(|v| literal(&v.to_string())).collect();
+            return Ok(format!("({})", alternatives.join(" | ")));
+        }
+        if let Some(options) = schema.get("anyOf").or_else(|| schema.get("oneOf")).and_then(Value::as_array) {
+            let alternatives = options.iter().map(|option| self.visit(option)).collect::<Result<Vec<_>, _>>()?;
+            return Ok(format!("({})", alternatives.join(" | ")));
+        }
+
+        match schema.get("type") {
+            None => Ok(if schema.get("properties").is_some() { self.object(schema)? } else { "value".to_string() }),
+            Some(Value::String(ty)) => self.typed(ty, schema),
+            Some(Value::Array(types)) => {
+                let alternatives = types
+                    .iter()
+                    .map(|ty| self.typed(ty.as_str().ok_or("type must be a string")?, schema))
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok(format!("({})", alternatives.join(" | ")))
+            }
+            Some(other) => Err(format!("Unsupported type: {}", other)),
+        }
+    }
+
+    fn typed(&mut self, ty: &str, schema: &Value) -> Result<String, String> {
+        match ty {
+            "object" => self.object(schema),
+            "array" => {
+                let item = match schema.get("items") {
+                    Some(items) => self.visit(items)?,
+                    None => "value".to_string(),
+                };
+                let items = repeat(&item, "\",\" ws", size(schema, "minItems").unwrap_or(0), size(schema, "maxItems"));
+                Ok(self.rule(format!("\"[\" ws {} ws \"]\"", items)))
+            }
+            "string" => match (size(schema, "minLength"), size(schema, "maxLength")) {
+                (None, None) => Ok("string".to_string()),
+                (min, max) => Ok(self.rule(format!("\"\\\"\" {} \"\\\"\"", repeat("char", "", min.unwrap_or(0), max)))),
+            },
+            "integer" | "number" | "boolean" | "null" => Ok(ty.to_string()),
+            other => Err(format!("Unsupported type: {}", other)),
+        }
+    }
+
+    /// Listed properties in order; required ones must appear, optional ones may
+    fn object(&mut self, schema: &Value) -> Result<String, String> {
+        let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+            return Ok("object".to_string());
+        };
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|names| names.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        let mut members = Vec::new();
+        for (name, property) in properties {
+            let value = self.visit(property)?;
+            members.push((format!("{} \":\" ws {}", literal(&format!("\"{}\"", name)), value), required.contains(&name.as_str())));
+        }
+
+        // Build from the last member back: each rule is the members from
+        // `i` on, with commas only between members that are present
+        let mut rest: Option<(String, bool)> = None;
+        for (member, is_required) in members.into_iter().rev() {
+            let any_required_after = rest.as_ref().is_some_and(|(_, required)| *required);
+            let with_member = match &rest {
+                Some((rest, _)) if any_required_after => format!("{} \",\" ws {}", member, rest),
+                Some((rest, _)) => format!("{} (\",\" ws {})?", member, rest),
+                None => member,
+            };
+            let body = match &rest {
+                Some((rest, _)) if !is_required && !any_required_after => format!("{} | {}", with_member, rest),
+                _ => with_member,
+            };
+            let required_here = is_required || any_required_after;
+            rest = Some((self.rule(body), required_here));
+        }
+
+        let members = match rest {
+            Some((rule, true)) => rule,
+            Some((rule, false)) => format!("{}?", rule),
+            None => String::new(),
+        };
+        Ok(self.rule(format!("\"{{\" ws {} ws \"}}\"", members)))
+    }
+
+    fn reference(&mut self, reference: &str) -> Result<String, String> {
+        if let Some(name) = self.refs.get(reference) {
+            return Ok(name.clone());
+        }
+        let target = reference
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+            .ok_or_else(|| format!("Unresolvable $ref {}", reference))?;
+
+        // Reserve the rule first so recursive references resolve to it
+        let name = format!("schema-{}", self.rules.len());
+        self.rules.push(String::new());
+        self.refs.insert(reference.to_string(), name.clone());
+        let body = self.visit(target)?;
+        let index = name.trim_start_matches("schema-").parse::<usize>().unwrap_or_default();
+        self.rules[index] = format!("{} ::= {}", name, body);
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Grammar;
+    use serde_json::json;
+
+    #[test]
+    fn test_object_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "city": {"type": "string"},
+                "days": {"type": "integer"},
+                "units": {"enum": ["metric", "imperial"]},
+            },
+            "required": ["city"],
+        });
+        let grammar = Grammar::from_json_schema(&schema).unwrap();
+        assert!(grammar.accepts(r#"{"city": "Oslo"}"#));
+        assert!(grammar.accepts(r#"{ "city": "Oslo", "days": 3, "units": "metric" }"#));
+        assert!(grammar.accepts(r#"{"city": "Oslo", "units": "imperial"}"#));
+        assert!(!grammar.accepts(r#"{"days": 3}"#));
+        assert!(!grammar.accepts(r#"{"city": "Oslo", "units": "kelvin"}"#));
+        assert!(!grammar.accepts(r#"{"city": 3}"#));
+        assert!(!grammar.accepts(r#"{"city": "Oslo", "extra": 1}"#));
+        assert!(!grammar.accepts(r#"{"city": "Oslo",}"#));
+
+        let optional = json!({"properties": {"a": {"type": "boolean"}, "b": {"type": "null"}}});
+        let grammar = Grammar::from_json_schema(&optional).unwrap();
+        for valid in ["{}", r#"{"a": true}"#, r#"{"b": null}"#, r#"{"a": false, "b": null}"#] {
+            assert!(grammar.accepts(valid), "{}", valid);
+        }
+        assert!(!grammar.accepts(r#"{, "b": null}"#));
+    }
+
+    #[test]
+    fn test_arrays_strings_and_refs() {
+        let schema = json!({
+            "type": "array",
+            "items": {"$ref": "#/$defs/tag"},
+            "minItems": 1,
+            "maxItems": 2,
+            "$defs": {"tag": {"type": "string", "maxLength": 3}},
+        });
+        let grammar = Grammar::from_json_schema(&schema).unwrap();
+        assert!(grammar.accepts(r#"["a"]"#));
+        assert!(grammar.accepts(r#"["a", "bcd"]"#));
+        assert!(!grammar.accepts("[]"));
+        assert!(!grammar.accepts(r#"["a", "b", "c"]"#));
+        assert!(!grammar.accepts(r#"["abcd"]"#));
+
+        let tree = json!({
+            "$defs": {"node": {"type": "object", "properties": {"children": {"type": "array", "items": {"$ref": "#/$defs/node"}}}}},
+            "$ref": "#/$defs/node",
+        });
+        let grammar = Grammar::from_json_schema(&tree).unwrap();
+        assert!(grammar.accepts(r#"{"children": [{}, {"children": []}]}"#));
+
+        let any = Grammar::from_json_schema(&json!({})).unwrap();
+        assert!(any.accepts(r#"{"x": [1, -2.5e3, "y\n", null]}"#));
+        assert!(!any.accepts("{x: 1}"));
+
+        let nullable = Grammar::from_json_schema(&json!({"type": ["number", "null"]})).unwrap();
+        assert!(nullable.accepts("null") && nullable.accepts("0.5") && !nullable.accepts("\"0.5\""));
+        assert!(Grammar::from_json_schema(&json!({"$ref": "#/missing"})).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_keywords() {
+        let error = |schema| Grammar::from_json_schema(&schema).unwrap_err();
+        assert_eq!(error(json!({"type": "string", "pattern": "^[a-z]+$"})), "Unsupported schema keyword: pattern");
+        assert_eq!(error(json!({"type": "string", "format": "date-time"})), "Unsupported schema keyword: format");
+        let nested = json!({"properties": {"age": {"type": "integer", "minimum": 0}}});
+        assert_eq!(error(nested), "Unsupported schema keyword: minimum");
+        assert!(error(json!({"type": "object", "additionalProperties": {"type": "string"}})).contains("additionalProperties"));
+
+        let closed = json!({"properties": {"a": {"type": "null"}}, "additionalProperties": false});
+        let grammar = Grammar::from_json_schema(&closed).unwrap();
+        assert!(grammar.accepts(r#"{"a": null}"#));
+        assert!(!grammar.accepts(r#"{"a": null, "b": null}"#));
+    }
+}
@@ -0,0 +1,289 @@
+//! Image decoding and preprocessing for vision models
+//!
+//! Camera frames and uploads arrive as PNG/JPEG/WebP bytes. This module
+//! decodes them (honouring EXIF orientation), fits them to a model's input
+//! resolution and produces a normalized `[1, 3, H, W]` Burn tensor.
+
+use ::image::imageops::{self, FilterType};
+use ::image::{DynamicImage, ImageDecoder, ImageReader, Rgb, RgbImage};
+use burn::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// How an image is fitted to the target resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResizeMode {
+    /// Resize to exactly the target size, ignoring aspect ratio
+    Stretch,
+    /// Resize the shorter side to fit, then crop the centre
+    CenterCrop,
+    /// Resize the longer side to fit, then pad the borders with a fill colour
+    Pad { fill: [u8; 3] },
+}
+
+/// Preprocessing parameters for a vision model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImagePreprocessConfig {
+    pub width: u32,
+    pub height: u32,
+    pub resize_mode: ResizeMode,
+    /// Per-channel mean subtracted after scaling pixels to [0, 1]
+    pub mean: [f32; 3],
+    /// Per-channel standard deviation divided by after subtracting the mean
+    pub std: [f32; 3],
+}
+
+impl ImagePreprocessConfig {
+    /// SigLIP preprocessing: stretch to a square and map to [-1, 1]
+    pub fn siglip(size: u32) -> Self {
+        Self {
+            width: size,
+            height: size,
+            resize_mode: ResizeMode::Stretch,
+            mean: [0.5, 0.5, 0.5],
+            std: [0.5, 0.5, 0.5],
+        }
+    }
+
+    /// CLIP/ImageNet-style preprocessing: shortest-edge resize and centre crop
+    pub fn clip(size: u32) -> Self {
+        Self {
+            width: size,
+            height: size,
+            resize_mode: ResizeMode::CenterCrop,
+            mean: [0.481_454_66, 0.457_827_5, 0.408_210_73],
+            std: [0.268_629_54, 0.261_302_6, 0.275_777_1],
+        }
+    }
+}
+
+/// Decode PNG, JPEG or WebP bytes into an upright RGB image
+///
+/// EXIF orientation (as written by phone cameras) is applied so the result
+/// is displayed the way the user took it.
+pub fn decode_image(bytes: &[u8]) -> Result<RgbImage, String> {
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| format!("Failed to read image: {}", e))?;
+    let mut decoder = reader
+        .into_decoder()
+        .map_err(|e| format!("Unsupported image format: {}", e))?;
+
+    // A malformed EXIF block should not make an otherwise valid image unusable
+    let orientation = decoder.orientation().ok();
+
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| format!("Failed to decode image: {}", e))?;
+    if let Some(orientation) = orientation {
+        image.apply_orientation(orientation);
+    }
+
+    Ok(image.to_rgb8())
+}
+
+/// Fit an image to the configured resolution using bicubic resampling
+pub fn resize_image(image: &RgbImage, config: &ImagePreprocessConfig) -> RgbImage {
+    let (target_w, target_h) = (config.width, config.height);
+    let (src_w, src_h) = image.dimensions();
+
+    match config.resize_mode {
+        ResizeMode::Stretch => imageops::resize(image, target_w, target_h, FilterType::CatmullRom),
+        ResizeMode::CenterCrop => {
+            let scale = f64::max(target_w as f64 / src_w as f64, target_h as f64 / src_h as f64);
+            let (w, h) = scaled_size(src_w, src_h, scale);
+            let resized = imageops::resize(image, w.max(target_w), h.max(target_h), FilterType::CatmullRom);
+            let x = (resized.width() - target_w) / 2;
+            let y = (resized.height() - target_h) / 2;
+            imageops::crop_imm(&resized, x, y, target_w, target_h).to_image()
+        }
+        ResizeMode::Pad { fill } => {
+            let scale = f64::min(target_w as f64 / src_w as f64, target_h as f64 / src_h as f64);
+            let (w, h) = scaled_size(src_w, src_h, scale);
+            let resized = imageops::resize(image, w.min(target_w), h.min(target_h), FilterType::CatmullRom);
+            let mut canvas = RgbImage::from_pixel(target_w, target_h, Rgb(fill));
+            let x = (target_w - resized.width()) / 2;
+            let y = (target_h - resized.height()) / 2;
+            imageops::replace(&mut canvas, &resized, x as i64, y as i64);
+            canvas
+        }
+    }
+}
+
+fn scaled_size(width: u32, height: u32, scale: f64) -> (u32, u32) {
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+/// Normalize an RGB image into channel-major (`[3, H, W]`) f32 values
+pub fn normalize_image(image: &RgbImage, mean: [f32; 3], std: [f32; 3]) -> Vec<f32> {
+    let plane = (image.width() * image.height()) as usize;
+    let mut values = vec![0.0f32; 3 * plane];
+    for (i, pixel) in image.pixels().enumerate() {
+        for c in 0..3 {
+            values[c * plane + i] = (pixel[c] as f32 / 255.0 - mean[c]) / std[c];
+        }
+    }
+    values
+}
+
+/// Decode, resize and normalize an image into a `[1, 3, H, W]` tensor
+///
+/// # Arguments
+/// * `bytes` - Encoded PNG, JPEG or WebP image
+/// * `config` - Target resolution, resize mode and normalization
+/// * `device` - Device to create the tensor on
+pub fn preprocess_image<B: Backend>(
+    bytes: &[u8],
+    config: &ImagePreprocessConfig,
+    device: &B::Device,
+) -> Result<Tensor<B, 4>, String> {
+    if config.width == 0 || config.height == 0 {
+        return Err("Target image size cannot be zero".to_string());
+    }
+
+    let image = decode_image(bytes)?;
+    let resized = resize_image(&image, config);
+    let values = normalize_image(&resized, config.mean, config.std);
+
+    Ok(Tensor::from_data(
+        TensorData::new(values, [1, 3, config.height as usize, config.width as usize]),
+        device,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{ImageFormat, RgbImage};
+    use burn_ndarray::NdArray;
+
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    /// 16x8 image: left half red, right half blue
+    fn split_fixture() -> RgbImage {
+        RgbImage::from_fn(16, 8, |x, _| if x < 8 { RED } else { BLUE })
+    }
+
+    fn encode(image: &RgbImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+        bytes
+    }
+
+    /// Insert an EXIF APP1 segment with the given orientation after the JPEG SOI marker
+    fn with_exif_orientation(jpeg: &[u8], orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&0x0112u16.to_le_bytes());
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&orientation.to_le_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        segment.extend_from_slice(b"Exif\0\0");
+        segment.extend_from_slice(&tiff);
+
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&segment);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    fn is_reddish(p: &Rgb<u8>) -> bool {
+        p[0] > 200 && p[2] < 60
+    }
+
+    fn is_bluish(p: &Rgb<u8>) -> bool {
+        p[2] > 200 && p[0] < 60
+    }
+
+    #[test]
+    fn test_decode_png_and_webp_lossless() {
+        let fixture = split_fixture();
+        for format in [ImageFormat::Png, ImageFormat::WebP] {
+            let decoded = decode_image(&encode(&fixture, format)).unwrap();
+            assert_eq!(decoded, fixture, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_decode_jpeg_applies_exif_orientation() {
+        let jpeg = encode(&split_fixture(), ImageFormat::Jpeg);
+
+        let upright = decode_image(&jpeg).unwrap();
+        assert_eq!(upright.dimensions(), (16, 8));
+
+        // Orientation 6: rotate 90° clockwise, so the red left half ends up on top
+        let rotated = decode_image(&with_exif_orientation(&jpeg, 6)).unwrap();
+        assert_eq!(rotated.dimensions(), (8, 16));
+        assert!(is_reddish(rotated.get_pixel(4, 2)));
+        assert!(is_bluish(rotated.get_pixel(4, 13)));
+    }
+
+    #[test]
+    fn test_resize_modes() {
+        let fixture = split_fixture();
+        let config = |resize_mode| ImagePreprocessConfig {
+            resize_mode,
+            ..ImagePreprocessConfig::siglip(8)
+        };
+
+        let stretched = resize_image(&fixture, &config(ResizeMode::Stretch));
+        assert_eq!(stretched.dimensions(), (8, 8));
+        assert_eq!(*stretched.get_pixel(0, 0), RED);
+        assert_eq!(*stretched.get_pixel(7, 7), BLUE);
+
+        // 16x8 -> 16x8 (height already fits), centre 8x8 keeps the red/blue seam in the middle
+        let cropped = resize_image(&fixture, &config(ResizeMode::CenterCrop));
+        assert_eq!(cropped.dimensions(), (8, 8));
+        assert_eq!(*cropped.get_pixel(0, 4), RED);
+        assert_eq!(*cropped.get_pixel(7, 4), BLUE);
+
+        // 16x8 -> 8x4 centred vertically between two rows of padding
+        let padded = resize_image(&fixture, &config(ResizeMode::Pad { fill: [0, 255, 0] }));
+        assert_eq!(padded.dimensions(), (8, 8));
+        assert_eq!(*padded.get_pixel(0, 0), Rgb([0, 255, 0]));
+        assert_eq!(*padded.get_pixel(0, 7), Rgb([0, 255, 0]));
+        assert_eq!(*padded.get_pixel(0, 3), RED);
+        assert_eq!(*padded.get_pixel(7, 4), BLUE);
+    }
+
+    #[test]
+    fn test_preprocess_image_tensor() {
+        let png = encode(&split_fixture(), ImageFormat::Png);
+        let device = Default::default();
+        let tensor = preprocess_image::<NdArray<f32>>(&png, &ImagePreprocessConfig::siglip(8), &device).unwrap();
+        assert_eq!(tensor.dims(), [1, 3, 8, 8]);
+
+        let values = tensor.into_data().to_vec::<f32>().unwrap();
+        let plane = 64;
+        // Top-left pixel is pure red: R=1, G=-1, B=-1 after SigLIP normalization
+        assert_eq!(values[0], 1.0);
+        assert_eq!(values[plane], -1.0);
+        assert_eq!(values[2 * plane], -1.0);
+        // Bottom-right pixel is pure blue
+        assert_eq!(values[plane - 1], -1.0);
+        assert_eq!(values[3 * plane - 1], 1.0);
+    }
+
+    #[test]
+    fn test_clip_normalization() {
+        let image = RgbImage::from_pixel(1, 1, Rgb([255, 255, 255]));
+        let config = ImagePreprocessConfig::clip(1);
+        let values = normalize_image(&image, config.mean, config.std);
+        assert!((values[0] - (1.0 - 0.481_454_66) / 0.268_629_54).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_invalid_image() {
+        let device = Default::default();
+        assert!(decode_image(b"not an image").is_err());
+        assert!(preprocess_image::<NdArray<f32>>(&[], &ImagePreprocessConfig::siglip(0), &device).is_err());
+    }
+}
@@ -4,13 +4,22 @@
 //! It uses the Burn ML framework which supports both CPU (ndarray) and GPU (WebGPU) backends.
 
 use crate::models::{
-    ModelType, download_model, WhisperConfig, WhisperModel, LlmConfig, LlmModel, 
-    create_whisper_model, create_llm_model
+    gguf, local, Architecture, ChatTemplate, DownloadConfig, ModelFiles, ModelRegistry, ModelRole, ModelSpec, download_model,
+    WhisperModel, LlmModel, create_whisper_model, create_llm_model, VisionModel, create_vision_model,
+    TextEmbeddingModel, WordPieceTokenizer, create_embedding_model, vision::{END_OF_UTTERANCE, IMAGE_TOKEN}, LoraAdapter, WeightMap, LlmSession,
+    SpeculativeConfig, SpeculativeDecoder, SpeculativeStats
 };
+use crate::agent::{estimate_tokens, TextGenerator};
+use crate::backend::CpuBackend;
+use crate::grammar::{Grammar, GrammarSampler, Vocabulary};
+use crate::image::preprocess_image;
+use crate::memory::{MemoryBudget, MemoryError, MemoryManager};
+use crate::audio::{N_FRAMES, N_MEL_BINS};
+use crate::streaming::SpeechRecognizer;
 use crate::types::Message;
 use burn::prelude::*;
-use burn_ndarray::NdArray;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use log::{info, warn}; // Added for comprehensive logging
 
@@ -36,6 +45,13 @@ pub struct InferenceConfig {
     pub temperature: f32,
     /// Top-p sampling threshold
     pub top_p: f32,
+    /// Tokens the draft model proposes per pass of the LLM, when one is loaded
+    #[serde(default = "default_draft_tokens")]
+    pub draft_tokens: usize,
+}
+
+fn default_draft_tokens() -> usize {
+    4
 }
 
 impl Default for InferenceConfig {
@@ -44,38 +60,103 @@ impl Default for InferenceConfig {
             max_tokens: 256,
             temperature: 0.7,
             top_p: 0.9,
+            draft_tokens: default_draft_tokens(),
         }
     }
 }
 
 /// Trait for a generic model that can run inference
-pub trait JarvisModel<B: Backend>: Send + Sync {
+///
+/// Models are shared behind `Arc<Mutex<..>>`, so they only need to be `Send`.
+pub trait JarvisModel<B: Backend>: Send {
     /// Transcribe audio to text
     fn transcribe(&self, audio: &[f32]) -> Result<String, String>;
+
+    /// Transcribe a precomputed log mel spectrogram (frame-major, `N_MEL_BINS` per frame)
+    fn transcribe_mel(&self, mel: &[f32], n_frames: usize) -> Result<String, String>;
     
-    /// Generate text from messages
-    fn generate(&self, messages: &[Message]) -> Result<String, String>;
+    /// Generate text from messages in at most `max_tokens` tokens, sampling
+    /// with `config`'s settings; its draft settings are not used
+    fn generate(&self, messages: &[Message], config: SpeculativeConfig, max_tokens: usize) -> Result<String, String>;
+
+    /// Generate text from messages that `grammar` accepts, in at most `max_tokens` tokens
+    fn generate_constrained(&self, _messages: &[Message], _grammar: &Grammar, _max_tokens: usize) -> Result<String, String> {
+        Err(format!("{} model cannot generate text", self.model_id()))
+    }
+
+    /// Generate replies to several conversations, each in at most `max_tokens` tokens
+    fn generate_batch(&self, conversations: &[Vec<Message>], max_tokens: usize) -> Result<Vec<String>, String> {
+        conversations.iter().map(|messages| self.generate(messages, SpeculativeConfig::default(), max_tokens)).collect()
+    }
+
+    /// Use `draft` to propose tokens for speculative decoding, or stop with `None`
+    fn set_draft_model(&mut self, _draft: Option<LlmModel<B>>) -> Result<(), String> {
+        Err(format!("{} model cannot use a draft model", self.model_id()))
+    }
+
+    /// Check whether a draft model is set
+    fn has_draft_model(&self) -> bool {
+        false
+    }
+
+    /// Generate text from messages with speculative decoding, in at most `max_tokens` tokens
+    fn generate_speculative(
+        &self,
+        _messages: &[Message],
+        _config: SpeculativeConfig,
+        _max_tokens: usize,
+    ) -> Result<(String, SpeculativeStats), String> {
+        Err(format!("{} model cannot decode speculatively", self.model_id()))
+    }
+
+    /// Number of tokens `text` encodes to, if the model has a tokenizer
+    fn count_tokens(&self, _text: &str) -> Option<usize> {
+        None
+    }
+
+    /// Describe an image (PNG/JPEG bytes), optionally answering a question about it,
+    /// in at most `max_tokens` tokens sampled with `config`'s settings
+    fn describe_image(&self, image: &[u8], prompt: &str, config: SpeculativeConfig, max_tokens: usize) -> Result<String, String>;
+
+    /// Embed texts as L2-normalized vectors
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String>;
     
-    /// Get model metadata
-    fn model_type(&self) -> ModelType;
+    /// Get the registry id of the model
+    fn model_id(&self) -> &str;
+
+    /// Add a LoRA adapter, replacing one loaded under the same name
+    fn load_adapter(&mut self, _adapter: LoraAdapter<B>) -> Result<(), String> {
+        Err(format!("{} model does not support LoRA adapters", self.model_id()))
+    }
+
+    /// Switch to a loaded adapter at a scale, or back to the base model with `None`
+    fn set_adapter(&mut self, _name: Option<&str>, _scale: f32) -> Result<(), String> {
+        Err(format!("{} model does not support LoRA adapters", self.model_id()))
+    }
+
+    /// Drop a loaded adapter, returning whether it existed
+    fn remove_adapter(&mut self, _name: &str) -> bool {
+        false
+    }
+
+    /// Name and scale of the adapter in use
+    fn active_adapter(&self) -> Option<(String, f32)> {
+        None
+    }
 }
 
 /// Real Whisper model implementation
 pub struct RealWhisperModel<B: Backend> {
     model: WhisperModel<B>,
-    model_type: ModelType,
+    id: String,
 }
 
 impl<B: Backend> RealWhisperModel<B> {
-    pub fn new(model: WhisperModel<B>, model_type: ModelType) -> Self {
-        Self { model, model_type }
+    pub fn new(model: WhisperModel<B>, id: String) -> Self {
+        Self { model, id }
     }
 }
 
-// Note: Burn models are not Sync by default, so we need to implement it manually
-// This is safe because we're not actually sharing mutable state between threads
-unsafe impl<B: Backend> Sync for RealWhisperModel<B> {}
-
 impl<B: Backend> JarvisModel<B> for RealWhisperModel<B> {
     fn transcribe(&self, audio: &[f32]) -> Result<String, String> {
         info!("Transcribing {} audio samples", audio.len());
@@ -88,240 +169,701 @@ impl<B: Backend> JarvisModel<B> for RealWhisperModel<B> {
         let n_frames = 3000; // 30 seconds at 100fps
         
         // Create a mock mel spectrogram tensor
-        let device = B::Device::default();
-        let mel_tensor = Tensor::<B, 3>::zeros([batch_size, n_mels, n_frames], &device);
+        let mel_tensor = Tensor::<B, 3>::zeros([batch_size, n_mels, n_frames], self.model.device());
         
         // Run encoder
         let _encoder_output = self.model.encode(mel_tensor);
         
         // For simplicity, we'll return a mock transcription
         // A real implementation would run the decoder with beam search or sampling
-        Ok(format!("Transcription of {} audio samples using {:?} model", audio.len(), self.model_type))
+        Ok(format!("Transcription of {} audio samples using {} model", audio.len(), self.id))
+    }
+
+    fn transcribe_mel(&self, mel: &[f32], n_frames: usize) -> Result<String, String> {
+        if mel.len() != n_frames * N_MEL_BINS {
+            return Err(format!(
+                "Mel spectrogram has {} values, expected {} frames of {} bins",
+                mel.len(),
+                n_frames,
+                N_MEL_BINS
+            ));
+        }
+        info!("Transcribing {} mel frames", n_frames);
+
+        // Whisper always encodes a full 30 second window, so pad with silence
+        let frames = Tensor::<B, 3>::from_data(
+            TensorData::new(mel.to_vec(), [1, n_frames, N_MEL_BINS]),
+            self.model.device(),
+        );
+        let mel_tensor = frames.swap_dims(1, 2).pad((0, N_FRAMES.saturating_sub(n_frames), 0, 0), -1.0);
+
+        // Run encoder
+        let _encoder_output = self.model.encode(mel_tensor);
+
+        // A real implementation would run the decoder over the encoder output
+        Ok(format!("Transcription of {} mel frames using {} model", n_frames, self.id))
     }
     
-    fn generate(&self, _messages: &[Message]) -> Result<String, String> {
+    fn generate(&self, _messages: &[Message], _config: SpeculativeConfig, _max_tokens: usize) -> Result<String, String> {
         Err("Whisper model cannot generate text".to_string())
     }
+
+    fn describe_image(&self, _image: &[u8], _prompt: &str, _config: SpeculativeConfig, _max_tokens: usize) -> Result<String, String> {
+        Err("Whisper model cannot describe images".to_string())
+    }
+
+    fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        Err("Whisper model cannot embed text".to_string())
+    }
     
-    fn model_type(&self) -> ModelType {
-        self.model_type
+    fn model_id(&self) -> &str {
+        &self.id
     }
 }
 
 /// Real LLM model implementation
 pub struct RealLlmModel<B: Backend> {
     model: LlmModel<B>,
-    model_type: ModelType,
+    id: String,
+    chat_template: ChatTemplate,
+    vocabulary: Vocabulary,
+    /// Smaller model sharing the vocabulary, for speculative decoding
+    draft: Option<LlmModel<B>>,
 }
 
 impl<B: Backend> RealLlmModel<B> {
-    pub fn new(model: LlmModel<B>, model_type: ModelType) -> Self {
-        Self { model, model_type }
+    /// Wrap an LLM; prompts default to ChatML when no template is given
+    pub fn new(model: LlmModel<B>, id: String, chat_template: Option<ChatTemplate>, vocabulary: Vocabulary) -> Self {
+        Self {
+            model,
+            id,
+            chat_template: chat_template.unwrap_or(ChatTemplate::ChatMl),
+            vocabulary,
+            draft: None,
+        }
     }
-}
 
-// Note: Burn models are not Sync by default, so we need to implement it manually
-// This is safe because we're not actually sharing mutable state between threads
-unsafe impl<B: Backend> Sync for RealLlmModel<B> {}
+    /// Encode the prompt for messages, and cap `max_tokens` to the room
+    /// left after it in a context of `context_length` tokens
+    fn prompt_tokens(&self, messages: &[Message], context_length: usize, max_tokens: usize) -> Result<(Vec<u32>, usize), String> {
+        let prompt = self.vocabulary.encode_prompt(&self.chat_template.apply(messages));
+        if prompt.len() >= context_length {
+            return Err(format!("Prompt of {} tokens does not fit the context length of {}", prompt.len(), context_length));
+        }
+        let max_tokens = max_tokens.min(context_length - prompt.len());
+        Ok((prompt, max_tokens))
+    }
+}
 
 impl<B: Backend> JarvisModel<B> for RealLlmModel<B> {
     fn transcribe(&self, _audio: &[f32]) -> Result<String, String> {
         Err("LLM model cannot transcribe audio".to_string())
     }
+
+    fn transcribe_mel(&self, _mel: &[f32], _n_frames: usize) -> Result<String, String> {
+        Err("LLM model cannot transcribe audio".to_string())
+    }
     
-    fn generate(&self, messages: &[Message]) -> Result<String, String> {
+    fn generate(&self, messages: &[Message], config: SpeculativeConfig, max_tokens: usize) -> Result<String, String> {
         info!("Generating response for {} messages", messages.len());
-        
-        // Use the model to ensure it's not marked as dead code
-        let _model = &self.model;
-        
-        // Extract last user message for context
-        let user_message = messages.iter()
-            .rev()
-            .find(|m| m.role == crate::types::MessageRole::User)
-            .and_then(|m| {
-                // Get the first text part from message parts
-                m.message_parts.iter().find_map(|part| {
-                    match part {
-                        crate::types::MessagePart::Text(text_part) => Some(text_part.text.clone()),
-                        _ => None,
-                    }
-                })
-            })
-            .unwrap_or_else(|| "No user message".to_string());
-        
-        // For now, we'll return a mock response
-        // A real implementation would tokenize the input, run inference, and decode the output
-        Ok(format!("Generated response to '{}' using {:?} model", user_message.chars().take(50).collect::<String>(), self.model_type))
+        let (prompt, max_tokens) = self.prompt_tokens(messages, self.model.config().max_position_embeddings, max_tokens)?;
+        let config = SpeculativeConfig { eos: Some(self.vocabulary.eos()), ..config };
+        let tokens = SpeculativeDecoder::new(config).generate_without_draft(&mut LlmSession::new(&self.model), &prompt, max_tokens)?;
+        info!("Generated {} tokens", tokens.len());
+        Ok(self.vocabulary.decode(&tokens))
+    }
+
+    fn generate_constrained(&self, messages: &[Message], grammar: &Grammar, max_tokens: usize) -> Result<String, String> {
+        let prompt = self.vocabulary.encode_prompt(&self.chat_template.apply(messages));
+        let mut sampler = GrammarSampler::new(grammar, &self.vocabulary);
+        self.model.generate_constrained(&prompt, &mut sampler, max_tokens)?;
+        if !sampler.is_complete() {
+            return Err("Output ended before the grammar was complete".to_string());
+        }
+        Ok(sampler.output().to_string())
+    }
+
+    fn generate_batch(&self, conversations: &[Vec<Message>], max_tokens: usize) -> Result<Vec<String>, String> {
+        let prompts: Vec<Vec<u32>> = conversations
+            .iter()
+            .map(|messages| self.vocabulary.encode_prompt(&self.chat_template.apply(messages)))
+            .collect();
+        let outputs = self.model.generate_batch(&prompts, max_tokens, self.vocabulary.eos())?;
+        Ok(outputs.iter().map(|output| self.vocabulary.decode(&output.tokens)).collect())
+    }
+
+    fn set_draft_model(&mut self, draft: Option<LlmModel<B>>) -> Result<(), String> {
+        if let Some(draft) = &draft {
+            let (vocab_size, expected) = (draft.config().vocab_size, self.model.config().vocab_size);
+            if vocab_size != expected {
+                return Err(format!("Draft model has {} tokens, but {} has {}", vocab_size, self.id, expected));
+            }
+        }
+        self.draft = draft;
+        Ok(())
+    }
+
+    fn has_draft_model(&self) -> bool {
+        self.draft.is_some()
+    }
+
+    fn generate_speculative(
+        &self,
+        messages: &[Message],
+        config: SpeculativeConfig,
+        max_tokens: usize,
+    ) -> Result<(String, SpeculativeStats), String> {
+        let draft = self.draft.as_ref().ok_or("No draft model loaded")?;
+        // Both models must hold the prompt and reply
+        let context_length = self.model.config().max_position_embeddings.min(draft.config().max_position_embeddings);
+        let (prompt, max_tokens) = self.prompt_tokens(messages, context_length, max_tokens)?;
+        let config = SpeculativeConfig { eos: Some(self.vocabulary.eos()), ..config };
+        let (mut target, mut draft) = (LlmSession::new(&self.model), LlmSession::new(draft));
+        let output = SpeculativeDecoder::new(config).generate(&mut target, &mut draft, &prompt, max_tokens)?;
+        info!(
+            "Generated {} tokens, {:.0}% of draft tokens accepted",
+            output.stats.generated,
+            output.stats.acceptance_rate() * 100.0
+        );
+        Ok((self.vocabulary.decode(&output.tokens), output.stats))
+    }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        Some(self.vocabulary.encode(text).len())
+    }
+
+    fn describe_image(&self, _image: &[u8], _prompt: &str, _config: SpeculativeConfig, _max_tokens: usize) -> Result<String, String> {
+        Err("LLM model cannot describe images".to_string())
+    }
+
+    fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        Err("LLM model cannot embed text".to_string())
     }
     
-    fn model_type(&self) -> ModelType {
-        self.model_type
+    fn model_id(&self) -> &str {
+        &self.id
+    }
+    fn load_adapter(&mut self, adapter: LoraAdapter<B>) -> Result<(), String> {
+        self.model.load_adapter(adapter)
+    }
+
+    fn set_adapter(&mut self, name: Option<&str>, scale: f32) -> Result<(), String> {
+        self.model.set_adapter(name, scale)
+    }
+
+    fn remove_adapter(&mut self, name: &str) -> bool {
+        self.model.remove_adapter(name)
+    }
+
+    fn active_adapter(&self) -> Option<(String, f32)> {
+        self.model.active_adapter().map(|(name, scale)| (name.to_string(), scale))
     }
 }
-/// Inference engine for running models
-///
-/// The engine is generic over the Burn backend, allowing it to work with
-/// different computation backends (ndarray for CPU, wgpu for WebGPU).
-pub struct InferenceEngine {
-    model_type: Option<ModelType>,
-    model_state: ModelState,
-    config: InferenceConfig,
-    model: Option<Arc<Mutex<dyn JarvisModel<NdArray<f32>>>>>,
-    model_data: Option<Vec<u8>>,
+/// Real vision-language model implementation
+pub struct RealVisionModel<B: Backend> {
+    model: VisionModel<B>,
+    id: String,
+    vocabulary: Vocabulary,
+    /// Token whose embeddings the image's replace
+    image_token: u32,
+}
+
+impl<B: Backend> RealVisionModel<B> {
+    /// Wrap a vision-language model; its vocabulary must have the image token
+    pub fn new(model: VisionModel<B>, id: String, vocabulary: Vocabulary) -> Result<Self, String> {
+        let image_token = vocabulary
+            .special_token(IMAGE_TOKEN)
+            .ok_or_else(|| format!("Tokenizer has no {} token", IMAGE_TOKEN))?;
+        Ok(Self { model, id, vocabulary, image_token })
+    }
+}
+
+impl<B: Backend> JarvisModel<B> for RealVisionModel<B> {
+    fn transcribe(&self, _audio: &[f32]) -> Result<String, String> {
+        Err("Vision model cannot transcribe audio".to_string())
+    }
+
+    fn transcribe_mel(&self, _mel: &[f32], _n_frames: usize) -> Result<String, String> {
+        Err("Vision model cannot transcribe audio".to_string())
+    }
+
+    fn generate(&self, _messages: &[Message], _config: SpeculativeConfig, _max_tokens: usize) -> Result<String, String> {
+        Err("Vision model requires an image; use describe_image".to_string())
+    }
+
+    fn describe_image(&self, image: &[u8], prompt: &str, config: SpeculativeConfig, max_tokens: usize) -> Result<String, String> {
+        info!("Describing image of {} bytes", image.len());
+        let vision = self.model.config();
+        let pixels = preprocess_image::<B>(image, &vision.preprocess_config(), &self.model.device())?;
+
+        let question = if prompt.trim().is_empty() { "Describe this image." } else { prompt };
+        let prompt = self.vocabulary.encode_prompt(&vision.image_prompt(question));
+        let context_length = vision.text.max_position_embeddings;
+        if prompt.len() >= context_length {
+            return Err(format!("Prompt of {} tokens does not fit the context length of {}", prompt.len(), context_length));
+        }
+        let max_tokens = max_tokens.min(context_length - prompt.len());
+
+        let images = self.model.encode_image(pixels);
+        let [_, count, hidden] = images.dims();
+        let text = self.model.text_model();
+        let mut session = LlmSession::with_images(text, self.image_token, images.reshape([count, hidden]));
+        // Replies end with the end of the assistant's turn
+        let eos = self.vocabulary.special_token(END_OF_UTTERANCE).unwrap_or(self.vocabulary.eos());
+        let config = SpeculativeConfig { eos: Some(eos), ..config };
+        let tokens = SpeculativeDecoder::new(config).generate_without_draft(&mut session, &prompt, max_tokens)?;
+        Ok(self.vocabulary.decode(&tokens).trim().to_string())
+    }
+
+    fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        Err("Vision model cannot embed text".to_string())
+    }
+
+    fn model_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Real sentence embedding model implementation
+pub struct RealEmbeddingModel<B: Backend> {
+    model: TextEmbeddingModel<B>,
+    tokenizer: WordPieceTokenizer,
+    max_length: usize,
+    id: String,
+}
+
+impl<B: Backend> RealEmbeddingModel<B> {
+    /// Wrap an embedding model; inputs are truncated to `max_length` tokens
+    pub fn new(model: TextEmbeddingModel<B>, tokenizer: WordPieceTokenizer, max_length: usize, id: String) -> Self {
+        Self {
+            model,
+            tokenizer,
+            max_length,
+            id,
+        }
+    }
+}
+
+impl<B: Backend> JarvisModel<B> for RealEmbeddingModel<B> {
+    fn transcribe(&self, _audio: &[f32]) -> Result<String, String> {
+        Err("Embedding model cannot transcribe audio".to_string())
+    }
+
+    fn transcribe_mel(&self, _mel: &[f32], _n_frames: usize) -> Result<String, String> {
+        Err("Embedding model cannot transcribe audio".to_string())
+    }
+
+    fn generate(&self, _messages: &[Message], _config: SpeculativeConfig, _max_tokens: usize) -> Result<String, String> {
+        Err("Embedding model cannot generate text".to_string())
+    }
+
+    fn describe_image(&self, _image: &[u8], _prompt: &str, _config: SpeculativeConfig, _max_tokens: usize) -> Result<String, String> {
+        Err("Embedding model cannot describe images".to_string())
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        info!("Embedding {} texts", texts.len());
+
+        let token_ids: Vec<Vec<u32>> = texts.iter().map(|text| self.tokenizer.encode(text, self.max_length)).collect();
+        let embeddings = self.model.embed(&token_ids, self.tokenizer.pad_id());
+        let [_, dim] = embeddings.dims();
+        let values = embeddings.into_data().to_vec::<f32>().map_err(|e| format!("{:?}", e))?;
+        Ok(values.chunks(dim).map(<[f32]>::to_vec).collect())
+    }
+
+    fn model_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Vocabulary of an LLM's files: its `tokenizer.json`, or the tokenizer in
+/// the metadata of a GGUF checkpoint
+fn llm_vocabulary(files: &ModelFiles, vocab_size: usize) -> Result<Vocabulary, String> {
+    if let Some(json) = files.get("tokenizer.json") {
+        return Vocabulary::from_tokenizer_json(json, vocab_size).map_err(|e| format!("Failed to read tokenizer.json: {}", e));
+    }
+    match files.weight_shards().first().filter(|shard| shard.starts_with(gguf::MAGIC)) {
+        Some(shard) => Vocabulary::from_gguf(&gguf::parse(shard)?.metadata, vocab_size)
+            .map_err(|e| format!("Failed to read the GGUF tokenizer: {}", e)),
+        None => Err("Model files include no tokenizer.json".to_string()),
+    }
+}
+
+/// A model occupying one role in the engine
+struct ModelSlot<B: Backend> {
+    spec: ModelSpec,
+    state: ModelState,
+    model: Option<Arc<Mutex<dyn JarvisModel<B>>>>,
+    model_data: Option<ModelFiles>,
     loading_progress: Option<(u64, u64)>,
 }
 
-impl InferenceEngine {
-    /// Create a new inference engine with default configuration
-    pub fn new() -> Self {
+impl<B: Backend> ModelSlot<B> {
+    fn new(spec: ModelSpec) -> Self {
         Self {
-            model_type: None,
-            model_state: ModelState::Unloaded,
-            config: InferenceConfig::default(),
+            spec,
+            state: ModelState::Loading,
             model: None,
             model_data: None,
             loading_progress: None,
         }
     }
+}
 
-    /// Create a new inference engine with custom configuration
+/// Inference engine for running models
+///
+/// The engine is generic over the Burn backend, allowing it to work with
+/// different computation backends (ndarray for CPU, wgpu for WebGPU).
+/// Models are created on the engine's device when they are initialized.
+///
+/// One model can be loaded per [`ModelRole`], so e.g. Whisper and an LLM can
+/// serve voice mode side by side. Each role is loaded, initialized and
+/// unloaded independently, and requests are routed to the model for their role.
+///
+/// Models are described by a [`ModelSpec`], either a built-in
+/// [`crate::ModelType`] or one added to the engine's [`ModelRegistry`].
+/// Loads are checked against a [`MemoryBudget`]; least recently used models
+/// in other roles are unloaded to make room.
+pub struct InferenceEngine<B: Backend = CpuBackend> {
+    device: B::Device,
+    config: InferenceConfig,
+    download_config: DownloadConfig,
+    slots: HashMap<ModelRole, ModelSlot<B>>,
+    memory: MemoryManager,
+    registry: ModelRegistry,
+}
+
+impl InferenceEngine<CpuBackend> {
+    /// Create a new CPU inference engine with default configuration
+    pub fn new() -> Self {
+        Self::with_config(InferenceConfig::default())
+    }
+
+    /// Create a new CPU inference engine with custom configuration
     pub fn with_config(config: InferenceConfig) -> Self {
+        Self::with_device(Default::default(), config)
+    }
+}
+
+#[cfg(feature = "wgpu")]
+impl InferenceEngine<crate::backend::GpuBackend> {
+    /// Create a new GPU inference engine
+    ///
+    /// Initializes the wgpu runtime for `device` first, which is required in the browser.
+    ///
+    /// # Returns
+    /// * `Err(String)` if no GPU adapter is available; use a CPU engine instead
+    pub async fn new_gpu(device: crate::backend::GpuDevice, config: InferenceConfig) -> Result<Self, String> {
+        crate::backend::init_gpu(&device).await?;
+        Ok(Self::with_device(device, config))
+    }
+}
+
+impl<B: Backend> InferenceEngine<B> {
+    /// Create a new inference engine that runs on the given device
+    pub fn with_device(device: B::Device, config: InferenceConfig) -> Self {
         Self {
-            model_type: None,
-            model_state: ModelState::Unloaded,
+            device,
             config,
-            model: None,
-            model_data: None,
-            loading_progress: None,
+            download_config: DownloadConfig::default(),
+            slots: HashMap::new(),
+            memory: MemoryManager::new(MemoryBudget::detect()),
+            registry: ModelRegistry::builtin(),
         }
     }
 
+    /// Get the device models are created on
+    pub fn device(&self) -> &B::Device {
+        &self.device
+    }
+
+    /// Get the backend name reported by Burn (e.g. `ndarray`)
+    pub fn backend_name(&self) -> String {
+        B::name(&self.device)
+    }
+
     /// Load a model for inference
     ///
+    /// Replaces any model currently occupying the same role, and unloads
+    /// least recently used models in other roles if the memory budget requires it.
+    ///
     /// # Arguments
-    /// * `model` - The type of model to load
+    /// * `model` - A built-in [`crate::ModelType`] or a [`ModelSpec`]
     ///
     /// # Returns
     /// * `Ok(())` if the model was loaded successfully
-    /// * `Err(String)` if loading failed
-    pub fn load_model(&mut self, model: ModelType) -> Result<(), String> {
-        info!("Loading model: {:?}", model);
-        self.model_state = ModelState::Loading;
-        self.model_type = Some(model);
-        
+    /// * `Err(MemoryError)` if the model does not fit the memory budget on its own
+    pub fn load_model(&mut self, model: impl Into<ModelSpec>) -> Result<(), MemoryError> {
+        let spec = model.into();
+        info!("Loading model: {}", spec.id);
+        self.reserve(&spec)?;
+        self.slots.insert(spec.role(), ModelSlot::new(spec));
+
+        Ok(())
+    }
+
+    /// Make room for a model within the memory budget and account for it
+    fn reserve(&mut self, spec: &ModelSpec) -> Result<(), MemoryError> {
+        for role in self.memory.plan(spec, &self.registry)? {
+            warn!("Evicting {} model to make room for {}", role.name(), spec.id);
+            self.unload_model(role);
+        }
+        self.memory.insert(spec);
         Ok(())
     }
 
     /// Start downloading a model asynchronously
-    pub async fn download_model(&mut self, model: ModelType, on_progress: impl Fn(u64, u64)) -> Result<(), String> {
-        info!("Downloading model: {:?}", model);
-        self.model_state = ModelState::Loading;
-        self.model_type = Some(model);
+    pub async fn download_model(&mut self, model: impl Into<ModelSpec>, on_progress: impl Fn(u64, u64)) -> Result<(), String> {
+        let spec = model.into();
+        info!("Downloading model: {}", spec.id);
+        let role = spec.role();
 
-        let data = download_model(model, |progress| {
+        // A failed download leaves the loaded models and their slots untouched
+        let data = download_model(&spec, &self.download_config, |progress| {
             on_progress(progress.loaded_bytes, progress.total_bytes);
         }).await?;
 
-        self.model_data = Some(data);
+        self.reserve(&spec)?;
+        let mut slot = ModelSlot::new(spec);
+        slot.model_data = Some(data);
+        self.slots.insert(role, slot);
         Ok(())
     }
 
-    /// Initialize the model with downloaded data
-    pub fn initialize_model(&mut self) -> Result<(), String> {
-        info!("Initializing model");
-        match self.model_type {
-            Some(model_type) => {
-                // Check if we have model data to load
-                if let Some(ref model_data) = self.model_data {
-                    // Create real Burn models with loaded weights
-                    let real_model: Arc<Mutex<dyn JarvisModel<NdArray<f32>>>> = match model_type {
-                        ModelType::WhisperTiny | ModelType::WhisperBase | ModelType::WhisperSmall => {
-                            let model = create_whisper_model(model_type, model_data)
-                                .map_err(|e| format!("Failed to create Whisper model: {}", e))?;
-                            let real_whisper = RealWhisperModel::new(model, model_type);
-                            Arc::new(Mutex::new(real_whisper))
-                        }
-                        ModelType::Phi2 | ModelType::TinyLlama => {
-                            let model = create_llm_model(model_type, model_data)
-                                .map_err(|e| format!("Failed to create LLM model: {}", e))?;
-                            let real_llm = RealLlmModel::new(model, model_type);
-                            Arc::new(Mutex::new(real_llm))
-                        }
-                    };
-                    
-                    self.model = Some(real_model);
-                    self.model_state = ModelState::Ready;
-                    
-                    info!(
-                        "{} model initialized successfully with Burn ML framework and loaded weights.",
-                        match model_type {
-                            ModelType::WhisperTiny | ModelType::WhisperBase | ModelType::WhisperSmall => "Whisper",
-                            ModelType::Phi2 | ModelType::TinyLlama => "LLM",
-                        }
-                    );
-                } else {
-                    // Create models without weights (uninitialized)
-                    let real_model: Arc<Mutex<dyn JarvisModel<NdArray<f32>>>> = match model_type {
-                        ModelType::WhisperTiny => {
-                            let config = WhisperConfig::tiny();
-                            let model = WhisperModel::new(&config);
-                            let real_whisper = RealWhisperModel::new(model, model_type);
-                            Arc::new(Mutex::new(real_whisper))
-                        }
-                        ModelType::WhisperBase => {
-                            let config = WhisperConfig::base();
-                            let model = WhisperModel::new(&config);
-                            let real_whisper = RealWhisperModel::new(model, model_type);
-                            Arc::new(Mutex::new(real_whisper))
-                        }
-                        ModelType::WhisperSmall => {
-                            // For small model, we might want a different config
-                            let config = WhisperConfig::base();
-                            let model = WhisperModel::new(&config);
-                            let real_whisper = RealWhisperModel::new(model, model_type);
-                            Arc::new(Mutex::new(real_whisper))
-                        }
-                        ModelType::Phi2 => {
-                            let config = LlmConfig::phi_2();
-                            let model = LlmModel::new(&config);
-                            let real_llm = RealLlmModel::new(model, model_type);
-                            Arc::new(Mutex::new(real_llm))
-                        }
-                        ModelType::TinyLlama => {
-                            let config = LlmConfig::tiny_llama();
-                            let model = LlmModel::new(&config);
-                            let real_llm = RealLlmModel::new(model, model_type);
-                            Arc::new(Mutex::new(real_llm))
-                        }
-                    };
-                    
-                    self.model = Some(real_model);
-                    self.model_state = ModelState::Ready;
-                    
-                    warn!(
-                        "{} model initialized without weights. Random initialization will be used.",
-                        match model_type {
-                            ModelType::WhisperTiny | ModelType::WhisperBase | ModelType::WhisperSmall => "Whisper",
-                            ModelType::Phi2 | ModelType::TinyLlama => "LLM",
-                        }
-                    );
-                }
-                
+    /// Load and initialize a model from files already on the device
+    ///
+    /// The architecture is read from the files' `config.json`, or from the
+    /// metadata of GGUF weights; see [`crate::models::local`]. In the browser, pass the bytes of files the
+    /// user picked through [`crate::models::local::files_from_bytes`].
+    ///
+    /// # Returns
+    /// * `Ok(ModelRole)` - the role the model now fills
+    /// * `Err(String)` if the files are incomplete or the model does not fit in memory
+    pub fn load_from_files(&mut self, id: &str, files: ModelFiles) -> Result<ModelRole, String> {
+        let spec = local::spec_from_files(id, &files)?;
+        let role = spec.role();
+        info!("Loading {} from local files as {}", id, role.name());
+        self.reserve(&spec)?;
+
+        let mut slot = ModelSlot::new(spec);
+        slot.model_data = Some(files);
+        self.slots.insert(role, slot);
+        self.initialize_model(role)?;
+        Ok(role)
+    }
+
+    /// Load and initialize a model from a directory, e.g. a HuggingFace snapshot
+    ///
+    /// Weights are memory-mapped rather than read into memory. The model's id
+    /// is the directory name.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_from_path(&mut self, dir: impl AsRef<std::path::Path>) -> Result<ModelRole, String> {
+        let dir = dir.as_ref();
+        let files = local::read_dir(dir)?;
+        let id = dir.file_name().and_then(|name| name.to_str()).unwrap_or(local::LOCAL_REVISION);
+        self.load_from_files(id, files)
+    }
+
+    /// Load a PEFT LoRA adapter onto the text generation model
+    ///
+    /// The adapter is kept alongside the base weights and is not active until
+    /// selected with [`Self::set_adapter`].
+    ///
+    /// # Arguments
+    /// * `name` - Name to select the adapter by
+    /// * `config` - Contents of `adapter_config.json`
+    /// * `weights` - Contents of `adapter_model.safetensors`
+    pub fn load_adapter(&mut self, name: &str, config: &[u8], weights: &[u8]) -> Result<(), String> {
+        let adapter = LoraAdapter::from_bytes(name, config, weights, &self.device)?;
+        let model = self.ready_model(ModelRole::TextGeneration)?;
+        model.lock().unwrap().load_adapter(adapter)
+    }
+
+    /// Load a PEFT LoRA adapter from a directory, named after the directory
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_adapter_from_path(&mut self, dir: impl AsRef<std::path::Path>) -> Result<String, String> {
+        use crate::models::{lora, FileData};
+
+        let dir = dir.as_ref();
+        let config = dir.join(lora::ADAPTER_CONFIG);
+        let config = std::fs::read(&config).map_err(|e| format!("Failed to read {}: {}", config.display(), e))?;
+        let weights = FileData::map(&dir.join(lora::ADAPTER_WEIGHTS))?;
+        let name = dir.file_name().and_then(|name| name.to_str()).unwrap_or("adapter").to_string();
+        self.load_adapter(&name, &config, &weights)?;
+        Ok(name)
+    }
+
+    /// Switch the text generation model to a loaded adapter, or back to the base model with `None`
+    ///
+    /// # Arguments
+    /// * `scale` - Strength of the adapter; 1.0 applies it as trained
+    pub fn set_adapter(&mut self, name: Option<&str>, scale: f32) -> Result<(), String> {
+        let model = self.ready_model(ModelRole::TextGeneration)?;
+        model.lock().unwrap().set_adapter(name, scale)
+    }
+
+    /// Drop a LoRA adapter from the text generation model, returning whether it was loaded
+    pub fn remove_adapter(&mut self, name: &str) -> bool {
+        self.ready_model(ModelRole::TextGeneration)
+            .is_ok_and(|model| model.lock().unwrap().remove_adapter(name))
+    }
+
+    /// Name and scale of the adapter the text generation model is using
+    pub fn active_adapter(&self) -> Option<(String, f32)> {
+        self.ready_model(ModelRole::TextGeneration).ok()?.lock().unwrap().active_adapter()
+    }
+
+    /// Load a small LLM from files as the draft model for speculative decoding
+    ///
+    /// The draft must share the text generation model's vocabulary. While it
+    /// is loaded, [`Self::generate`] decodes speculatively. It is unloaded
+    /// with the text generation model and not counted against the memory budget.
+    pub fn load_draft_model(&mut self, id: &str, files: ModelFiles) -> Result<(), String> {
+        let spec = local::spec_from_files(id, &files)?;
+        let Architecture::Llm(config) = &spec.architecture else {
+            return Err(format!("{} is not a text generation model", id));
+        };
+        let weights = WeightMap::from_files(&files)?;
+        let draft = create_llm_model(config, &weights, &self.device)
+            .map_err(|e| format!("Failed to create draft model: {}", e))?;
+        info!("Loading {} as the draft model", id);
+        let model = self.ready_model(ModelRole::TextGeneration)?;
+        model.lock().unwrap().set_draft_model(Some(draft))
+    }
+
+    /// Load the draft model from a directory, named after the directory
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_draft_model_from_path(&mut self, dir: impl AsRef<std::path::Path>) -> Result<(), String> {
+        let dir = dir.as_ref();
+        let files = local::read_dir(dir)?;
+        let id = dir.file_name().and_then(|name| name.to_str()).unwrap_or(local::LOCAL_REVISION);
+        self.load_draft_model(id, files)
+    }
+
+    /// Drop the draft model, so generation no longer decodes speculatively
+    pub fn unload_draft_model(&mut self) {
+        if let Ok(model) = self.ready_model(ModelRole::TextGeneration) {
+            let _ = model.lock().unwrap().set_draft_model(None);
+        }
+    }
+
+    /// Check whether the text generation model has a draft model
+    pub fn has_draft_model(&self) -> bool {
+        self.ready_model(ModelRole::TextGeneration)
+            .is_ok_and(|model| model.lock().unwrap().has_draft_model())
+    }
+
+    /// Initialize the model loaded for a role with its downloaded data
+    ///
+    /// The model data is released once the model is built, so the weights
+    /// are not held twice; loading the model again re-reads them.
+    pub fn initialize_model(&mut self, role: ModelRole) -> Result<(), String> {
+        info!("Initializing {} model", role.name());
+        let slot = self
+            .slots
+            .get_mut(&role)
+            .ok_or_else(|| format!("No {} model specified", role.name()))?;
+
+        match Self::build_model(&self.device, &slot.spec, slot.model_data.as_ref()) {
+            Ok(model) => {
+                slot.model = Some(model);
+                slot.model_data = None;
+                slot.state = ModelState::Ready;
                 Ok(())
             }
-            None => Err("No model type specified".to_string()),
+            Err(e) => {
+                slot.state = ModelState::Error;
+                Err(e)
+            }
+        }
+    }
+
+    /// Create a Burn model, loading weights if model data is available
+    fn build_model(
+        device: &B::Device,
+        spec: &ModelSpec,
+        model_data: Option<&ModelFiles>,
+    ) -> Result<Arc<Mutex<dyn JarvisModel<B>>>, String> {
+        let weights = model_data.map(WeightMap::from_files).transpose()?.unwrap_or_default();
+        let id = spec.id.clone();
+        let real_model: Arc<Mutex<dyn JarvisModel<B>>> = match &spec.architecture {
+            Architecture::Whisper(config) => {
+                let model = create_whisper_model(config, &weights, device)
+                    .map_err(|e| format!("Failed to create Whisper model: {}", e))?;
+                Arc::new(Mutex::new(RealWhisperModel::new(model, id)))
+            }
+            Architecture::Llm(config) => {
+                let model = create_llm_model(config, &weights, device)
+                    .map_err(|e| format!("Failed to create LLM model: {}", e))?;
+                // Prompts must be encoded with the tokenizer the weights were
+                // trained with; only a model without weights makes do with ASCII
+                let vocabulary = match model_data.filter(|_| !weights.is_empty()) {
+                    Some(data) => llm_vocabulary(data, config.vocab_size)?,
+                    None => Vocabulary::ascii(config.vocab_size),
+                };
+                Arc::new(Mutex::new(RealLlmModel::new(model, id, spec.chat_template, vocabulary)))
+            }
+            Architecture::Vision(config) => {
+                let model = create_vision_model(config, &weights, device)
+                    .map_err(|e| format!("Failed to create vision model: {}", e))?;
+                let data = model_data.ok_or("Vision models need their files")?;
+                let vocabulary = llm_vocabulary(data, config.text.vocab_size)?;
+                Arc::new(Mutex::new(RealVisionModel::new(model, id, vocabulary)?))
+            }
+            Architecture::Embedding(config) => {
+                let model = create_embedding_model(config, &weights, device)
+                    .map_err(|e| format!("Failed to create embedding model: {}", e))?;
+                let tokenizer = match model_data.and_then(|data| data.get("tokenizer.json")) {
+                    Some(json) => WordPieceTokenizer::from_json(json)?,
+                    None => WordPieceTokenizer::hashed(config.vocab_size),
+                };
+                Arc::new(Mutex::new(RealEmbeddingModel::new(model, tokenizer, config.max_position_embeddings, id)))
+            }
+        };
+
+        if model_data.is_some() {
+            info!(
+                "{} model initialized successfully with Burn ML framework and loaded weights.",
+                spec.role().name()
+            );
+        } else {
+            warn!(
+                "{} model initialized without weights, so its output is a placeholder.",
+                spec.role().name()
+            );
+        }
+        Ok(real_model)
+    }
+
+    /// Unload the model occupying a role
+    pub fn unload_model(&mut self, role: ModelRole) {
+        self.memory.remove(role);
+        if let Some(slot) = self.slots.remove(&role) {
+            info!("{} model unloaded", slot.spec.id);
         }
     }
 
-    /// Unload the current model
-    pub fn unload_model(&mut self) {
-        self.model_type = None;
-        self.model_state = ModelState::Unloaded;
-        self.model = None;
-        self.model_data = None;
-        self.loading_progress = None;
-        info!("Model unloaded");
+    /// Unload all models
+    pub fn unload_all(&mut self) {
+        self.slots.clear();
+        self.memory.clear();
+        info!("All models unloaded");
+    }
+
+    /// Get the ready model for a role
+    fn ready_model(&self, role: ModelRole) -> Result<&Arc<Mutex<dyn JarvisModel<B>>>, String> {
+        let slot = self
+            .slots
+            .get(&role)
+            .ok_or_else(|| format!("No {} model loaded", role.name()))?;
+        if slot.state != ModelState::Ready {
+            return Err(format!("{} model not ready", slot.spec.id));
+        }
+        self.memory.touch(role);
+        slot.model.as_ref().ok_or_else(|| "Model not initialized".to_string())
     }
 
     /// Run speech-to-text inference using Whisper
@@ -333,27 +875,49 @@ impl InferenceEngine {
     /// * `Ok(String)` containing the transcribed text
     /// * `Err(String)` if transcription failed
     pub fn transcribe(&self, audio: &[f32]) -> Result<String, String> {
-        if self.model_state != ModelState::Ready {
-            return Err("Model not loaded".to_string());
-        }
+        let model = self.ready_model(ModelRole::SpeechToText)?;
+        model.lock().unwrap().transcribe(audio)
+    }
 
-        if let Some(ref model) = self.model {
-            let model = model.lock().unwrap();
-            match self.model_type {
-                Some(ModelType::WhisperTiny)
-                | Some(ModelType::WhisperBase)
-                | Some(ModelType::WhisperSmall) => {
-                    model.transcribe(audio)
-                }
-                _ => Err("No speech-to-text model loaded".to_string()),
-            }
-        } else {
-            Err("Model not initialized".to_string())
-        }
+    /// Run speech-to-text inference on a log mel spectrogram
+    ///
+    /// # Arguments
+    /// * `mel` - Normalized log mel values, frame-major (`N_MEL_BINS` per frame)
+    /// * `n_frames` - Number of frames in `mel`
+    pub fn transcribe_mel(&self, mel: &[f32], n_frames: usize) -> Result<String, String> {
+        let model = self.ready_model(ModelRole::SpeechToText)?;
+        model.lock().unwrap().transcribe_mel(mel, n_frames)
+    }
+
+    /// Run speech-to-text inference on a WAV file
+    ///
+    /// The audio is downmixed to mono and resampled to 16kHz before transcription.
+    ///
+    /// # Arguments
+    /// * `wav_bytes` - Contents of a RIFF/WAVE file
+    pub fn transcribe_wav(&self, wav_bytes: &[u8]) -> Result<String, String> {
+        let audio = crate::audio::load_wav_for_whisper(wav_bytes)?;
+        self.transcribe(&audio)
+    }
+
+    /// Run speech-to-text inference on an encoded audio file
+    ///
+    /// Accepts anything [`crate::audio::decode_audio`] understands, including
+    /// `MediaRecorder` WebM/Ogg Opus blobs and MP3/FLAC/M4A uploads.
+    ///
+    /// # Arguments
+    /// * `bytes` - File or blob contents
+    /// * `mime_type` - Optional MIME type reported by the browser
+    pub fn transcribe_file(&self, bytes: &[u8], mime_type: Option<&str>) -> Result<String, String> {
+        let audio = crate::audio::decode_audio_for_whisper(bytes, mime_type)?;
+        self.transcribe(&audio)
     }
 
     /// Run text generation inference using an LLM
     ///
+    /// Up to `max_tokens` tokens are sampled at the configured temperature
+    /// and top-p, speculatively once a draft model is loaded.
+    ///
     /// # Arguments
     /// * `messages` - Conversation history
     ///
@@ -361,41 +925,156 @@ impl InferenceEngine {
     /// * `Ok(String)` containing the generated response
     /// * `Err(String)` if generation failed
     pub fn generate(&self, messages: &[Message]) -> Result<String, String> {
-        if self.model_state != ModelState::Ready {
-            return Err("Model not loaded".to_string());
+        let model = self.ready_model(ModelRole::TextGeneration)?;
+        let model = model.lock().unwrap();
+        if model.has_draft_model() {
+            return model
+                .generate_speculative(messages, self.speculative_config(), self.config.max_tokens)
+                .map(|(reply, _)| reply);
         }
+        model.generate(messages, self.speculative_config(), self.config.max_tokens)
+    }
 
-        if let Some(ref model) = self.model {
-            let model = model.lock().unwrap();
-            match self.model_type {
-                Some(ModelType::Phi2) | Some(ModelType::TinyLlama) => {
-                    model.generate(messages)
-                }
-                _ => Err("No text generation model loaded".to_string()),
-            }
-        } else {
-            Err("Model not initialized".to_string())
+    /// Generate a response with speculative decoding, reporting how many of
+    /// the draft model's tokens the LLM accepted
+    ///
+    /// The draft proposes `draft_tokens` tokens and the LLM checks them in
+    /// one pass; the reply is distributed as if the LLM had decoded alone.
+    ///
+    /// # Returns
+    /// * `Ok((String, SpeculativeStats))` with the reply and acceptance statistics
+    /// * `Err(String)` if no LLM or draft model is loaded, or generation failed
+    pub fn generate_speculative(&self, messages: &[Message]) -> Result<(String, SpeculativeStats), String> {
+        let model = self.ready_model(ModelRole::TextGeneration)?;
+        model.lock().unwrap().generate_speculative(messages, self.speculative_config(), self.config.max_tokens)
+    }
+
+    fn speculative_config(&self) -> SpeculativeConfig {
+        SpeculativeConfig {
+            draft_tokens: self.config.draft_tokens,
+            temperature: self.config.temperature,
+            top_p: self.config.top_p,
+            ..Default::default()
         }
     }
 
-    /// Check if a model is loaded and ready
-    pub fn is_ready(&self) -> bool {
-        self.model_state == ModelState::Ready
+    /// Generate replies to several conversations in one batch, e.g. for
+    /// evaluation runs or background titles and summaries
+    ///
+    /// Each reply stops on its own at the end-of-sequence token or
+    /// `max_tokens`; the batch shrinks as replies finish.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<String>)` with one reply per conversation, in order
+    /// * `Err(String)` if no LLM is ready or a conversation does not fit its context window
+    pub fn generate_batch(&self, conversations: &[Vec<Message>]) -> Result<Vec<String>, String> {
+        let model = self.ready_model(ModelRole::TextGeneration)?;
+        model.lock().unwrap().generate_batch(conversations, self.config.max_tokens)
+    }
+
+    /// Generate a response that `grammar` accepts, e.g. for structured extraction
+    ///
+    /// At each step, tokens that cannot continue a match are masked out, so
+    /// the output is always valid; generation is capped at `max_tokens`.
+    ///
+    /// # Returns
+    /// * `Ok(String)` containing a complete match of the grammar
+    /// * `Err(String)` if no LLM is ready or no match fits in `max_tokens`
+    pub fn generate_constrained(&self, messages: &[Message], grammar: &Grammar) -> Result<String, String> {
+        let model = self.ready_model(ModelRole::TextGeneration)?;
+        model.lock().unwrap().generate_constrained(messages, grammar, self.config.max_tokens)
+    }
+
+    /// Generate a JSON value matching a JSON Schema, such as a tool's `input_schema`
+    ///
+    /// # Returns
+    /// * `Ok(Value)` that validates against the schema
+    /// * `Err(String)` if the schema is unsupported or generation failed
+    pub fn generate_json(&self, messages: &[Message], schema: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let grammar = Grammar::from_json_schema(schema)?;
+        let output = self.generate_constrained(messages, &grammar)?;
+        serde_json::from_str(&output).map_err(|e| format!("Generated invalid JSON: {}", e))
+    }
+
+    /// Count the tokens `text` takes up in the LLM's context window
+    pub fn count_tokens(&self, text: &str) -> Result<usize, String> {
+        let model = self.ready_model(ModelRole::TextGeneration)?;
+        let count = model.lock().unwrap().count_tokens(text);
+        count.ok_or_else(|| "LLM has no tokenizer".to_string())
+    }
+
+    /// Number of tokens the LLM can attend to, prompt and reply together
+    pub fn context_length(&self) -> Option<usize> {
+        match &self.current_model(ModelRole::TextGeneration)?.architecture {
+            Architecture::Llm(config) => Some(config.max_position_embeddings),
+            _ => None,
+        }
+    }
+
+    /// Describe an image using a vision-language model
+    ///
+    /// The image's embeddings prefix the prompt of the model's language
+    /// model, which decodes the reply with the configured sampling settings.
+    ///
+    /// # Arguments
+    /// * `image` - Encoded image bytes (e.g. a PNG from `CameraServer::take_picture`)
+    /// * `prompt` - Question about the image; empty for a general caption
+    ///
+    /// # Returns
+    /// * `Ok(String)` containing the description or answer
+    /// * `Err(String)` if no vision model is loaded or the image is invalid
+    pub fn describe_image(&self, image: &[u8], prompt: &str) -> Result<String, String> {
+        let model = self.ready_model(ModelRole::Vision)?;
+        model.lock().unwrap().describe_image(image, prompt, self.speculative_config(), self.config.max_tokens)
+    }
+
+    /// Embed texts for semantic search
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Vec<f32>>)` with one L2-normalized vector per text, so the dot
+    ///   product of two embeddings is their cosine similarity
+    /// * `Err(String)` if no embedding model is ready
+    pub fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let model = self.ready_model(ModelRole::Embeddings)?;
+        model.lock().unwrap().embed(texts)
+    }
+
+    /// Check if the model for a role is loaded and ready
+    pub fn is_ready(&self, role: ModelRole) -> bool {
+        self.state(role) == ModelState::Ready
     }
 
-    /// Check if a model is currently loading
+    /// Check if any model is currently loading
     pub fn is_loading(&self) -> bool {
-        self.model_state == ModelState::Loading
+        self.slots.values().any(|slot| slot.state == ModelState::Loading)
+    }
+
+    /// Get the state of the model for a role
+    pub fn state(&self, role: ModelRole) -> ModelState {
+        self.slots.get(&role).map_or(ModelState::Unloaded, |slot| slot.state)
+    }
+
+    /// Get the model loaded for a role
+    pub fn current_model(&self, role: ModelRole) -> Option<&ModelSpec> {
+        self.slots.get(&role).map(|slot| &slot.spec)
     }
 
-    /// Get the current model state
-    pub fn state(&self) -> ModelState {
-        self.model_state
+    /// Get every loaded model with its role and state
+    pub fn loaded_models(&self) -> Vec<(ModelRole, &ModelSpec, ModelState)> {
+        ModelRole::ALL
+            .iter()
+            .filter_map(|role| self.slots.get(role).map(|slot| (*role, &slot.spec, slot.state)))
+            .collect()
     }
 
-    /// Get the currently loaded model type
-    pub fn current_model(&self) -> Option<ModelType> {
-        self.model_type
+    /// Get the models available to load
+    pub fn registry(&self) -> &ModelRegistry {
+        &self.registry
+    }
+
+    /// Get the model registry to add models, e.g. from a manifest
+    pub fn registry_mut(&mut self) -> &mut ModelRegistry {
+        &mut self.registry
     }
 
     /// Get the inference configuration
@@ -408,39 +1087,89 @@ impl InferenceEngine {
         self.config = config;
     }
 
-    /// Get the download progress if model is loading
-    pub fn loading_progress(&self) -> Option<(u64, u64)> {
-        self.loading_progress
+    /// Get the memory budget for loaded models
+    pub fn memory_budget(&self) -> MemoryBudget {
+        self.memory.budget()
+    }
+
+    /// Update the memory budget; takes effect on the next load
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        self.memory.set_budget(budget);
+    }
+
+    /// Get the estimated memory used by loaded models in MB
+    pub fn resident_memory_mb(&self) -> u32 {
+        self.memory.resident_mb()
     }
 
-    /// Set loading progress
-    pub fn set_loading_progress(&mut self, loaded: u64, total: u64) {
-        self.loading_progress = Some((loaded, total));
+    /// Get the model download configuration
+    pub fn download_config(&self) -> &DownloadConfig {
+        &self.download_config
     }
 
-    /// Check if model data is available
-    pub fn has_model_data(&self) -> bool {
-        self.model_data.is_some()
+    /// Update the model download configuration, e.g. to pin a revision
+    pub fn set_download_config(&mut self, config: DownloadConfig) {
+        self.download_config = config;
+    }
+
+    /// Get the download progress of the model for a role
+    pub fn loading_progress(&self, role: ModelRole) -> Option<(u64, u64)> {
+        self.slots.get(&role).and_then(|slot| slot.loading_progress)
+    }
+
+    /// Set the download progress of the model for a role
+    pub fn set_loading_progress(&mut self, role: ModelRole, loaded: u64, total: u64) {
+        if let Some(slot) = self.slots.get_mut(&role) {
+            slot.loading_progress = Some((loaded, total));
+        }
+    }
+
+    /// Check if model data is available for a role
+    pub fn has_model_data(&self, role: ModelRole) -> bool {
+        self.slots.get(&role).is_some_and(|slot| slot.model_data.is_some())
     }
 }
 
-impl Default for InferenceEngine {
+impl Default for InferenceEngine<CpuBackend> {
     fn default() -> Self {
         Self::new()
     }
 }
 
+impl<B: Backend> SpeechRecognizer for InferenceEngine<B> {
+    fn transcribe_mel(&self, mel: &[f32], n_frames: usize) -> Result<String, String> {
+        InferenceEngine::<B>::transcribe_mel(self, mel, n_frames)
+    }
+}
+
+impl<B: Backend> TextGenerator for InferenceEngine<B> {
+    fn generate(&self, messages: &[Message]) -> Result<String, String> {
+        InferenceEngine::<B>::generate(self, messages)
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        InferenceEngine::<B>::count_tokens(self, text).unwrap_or_else(|_| estimate_tokens(text))
+    }
+
+    fn context_length(&self) -> Option<usize> {
+        InferenceEngine::<B>::context_length(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{FileData, LlmConfig, ModelType, WhisperConfig};
+    use crate::grammar::tests::ascii_tokenizer_json;
 
     #[test]
     fn test_inference_engine_creation() {
         let engine = InferenceEngine::new();
-        assert!(!engine.is_ready());
+        assert!(!engine.is_ready(ModelRole::TextGeneration));
         assert!(!engine.is_loading());
-        assert_eq!(engine.state(), ModelState::Unloaded);
-        assert!(engine.current_model().is_none());
+        assert_eq!(engine.state(ModelRole::SpeechToText), ModelState::Unloaded);
+        assert!(engine.current_model(ModelRole::SpeechToText).is_none());
+        assert!(engine.loaded_models().is_empty());
     }
 
     #[test]
@@ -457,38 +1186,477 @@ mod tests {
             max_tokens: 512,
             temperature: 0.5,
             top_p: 0.95,
+            draft_tokens: 2,
         };
         let engine = InferenceEngine::with_config(config.clone());
         assert_eq!(engine.config().max_tokens, 512);
     }
 
+    /// Run a model through an engine on any backend
+    fn load_and_run<B: Backend>(device: B::Device) {
+        let mut engine = InferenceEngine::<B>::with_device(device, InferenceConfig::default());
+        engine.load_model(ModelType::WhisperTiny).unwrap();
+        engine.initialize_model(ModelRole::SpeechToText).unwrap();
+        assert!(engine.transcribe(&[0.0; 1600]).is_ok());
+        assert!(engine.generate(&[]).is_err());
+
+        engine.unload_model(ModelRole::SpeechToText);
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        assert!(engine.generate(&[]).is_ok());
+        assert!(engine.transcribe(&[0.0; 1600]).is_err());
+    }
+
+    #[test]
+    fn test_generic_engine_on_cpu() {
+        load_and_run::<CpuBackend>(Default::default());
+
+        let engine = InferenceEngine::new();
+        assert_eq!(*engine.device(), Default::default());
+        assert_eq!(engine.backend_name(), "ndarray");
+    }
+
+    #[test]
+    fn test_models_loaded_side_by_side() {
+        let mut engine = InferenceEngine::new();
+        engine.load_model(ModelType::WhisperTiny).unwrap();
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        assert!(engine.is_loading());
+
+        engine.initialize_model(ModelRole::SpeechToText).unwrap();
+        assert!(engine.is_ready(ModelRole::SpeechToText));
+        assert_eq!(engine.state(ModelRole::TextGeneration), ModelState::Loading);
+        assert_eq!(engine.generate(&[]).unwrap_err(), "tinyllama model not ready");
+
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        assert!(engine.transcribe(&[0.0; 1600]).is_ok());
+        assert!(engine.generate(&[]).is_ok());
+        assert_eq!(
+            engine.loaded_models(),
+            vec![
+                (ModelRole::SpeechToText, &ModelType::WhisperTiny.spec(), ModelState::Ready),
+                (ModelRole::TextGeneration, &ModelType::TinyLlama.spec(), ModelState::Ready),
+            ]
+        );
+
+        // Loading into an occupied role replaces only that role's model
+        engine.load_model(ModelType::Phi2).unwrap();
+        assert_eq!(engine.current_model(ModelRole::TextGeneration), Some(&ModelType::Phi2.spec()));
+        assert!(engine.is_ready(ModelRole::SpeechToText));
+
+        engine.unload_model(ModelRole::TextGeneration);
+        assert_eq!(engine.generate(&[]).unwrap_err(), "No text generation model loaded");
+        assert!(engine.transcribe(&[0.0; 1600]).is_ok());
+
+        engine.unload_all();
+        assert!(engine.loaded_models().is_empty());
+        assert!(engine.initialize_model(ModelRole::Vision).is_err());
+    }
+
+    #[test]
+    fn test_memory_budget_evicts_least_recently_used() {
+        let mut engine = InferenceEngine::new();
+        engine.set_memory_budget(MemoryBudget::new(1500));
+        engine.load_model(ModelType::WhisperTiny).unwrap();
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        assert_eq!(engine.resident_memory_mb(), 1190);
+
+        // Using Whisper makes the LLM the eviction candidate
+        engine.initialize_model(ModelRole::SpeechToText).unwrap();
+        engine.transcribe(&[0.0; 1600]).unwrap();
+        engine.load_model(ModelType::SmolVlm).unwrap();
+        assert_eq!(engine.current_model(ModelRole::TextGeneration), None);
+        assert!(engine.is_ready(ModelRole::SpeechToText));
+        assert_eq!(engine.resident_memory_mb(), 1390);
+
+        let err = engine.load_model(ModelType::Phi2).unwrap_err();
+        assert_eq!(err.suggestion, Some(Box::new(ModelType::TinyLlama.spec())));
+        assert_eq!(engine.resident_memory_mb(), 1390);
+    }
+
+    /// Engine whose downloads go to a port nothing is listening on
+    #[cfg(not(target_arch = "wasm32"))]
+    fn offline_engine() -> InferenceEngine {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut engine = InferenceEngine::new();
+        engine.set_download_config(DownloadConfig {
+            endpoint: format!("http://{}", listener.local_addr().unwrap()),
+            ..DownloadConfig::default()
+        });
+        engine
+    }
+
+    /// Spec for a repository that is never in the download cache
+    #[cfg(not(target_arch = "wasm32"))]
+    fn uncached_spec(model: ModelType) -> ModelSpec {
+        let mut spec = model.spec();
+        spec.repo = format!("jarvis-test/{}", uuid::Uuid::new_v4());
+        spec
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_failed_download_evicts_nothing() {
+        let mut engine = offline_engine();
+        engine.set_memory_budget(MemoryBudget::new(1500));
+        engine.load_model(ModelType::WhisperTiny).unwrap();
+        engine.load_model(ModelType::TinyLlama).unwrap();
+
+        assert!(engine.download_model(uncached_spec(ModelType::SmolVlm), |_, _| {}).await.is_err());
+        assert_eq!(engine.current_model(ModelRole::TextGeneration), Some(&ModelType::TinyLlama.spec()));
+        assert_eq!(engine.resident_memory_mb(), 1190);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_failed_download_keeps_loaded_model() {
+        let mut engine = offline_engine();
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+
+        assert!(engine.download_model(uncached_spec(ModelType::Phi2), |_, _| {}).await.is_err());
+        assert_eq!(engine.current_model(ModelRole::TextGeneration), Some(&ModelType::TinyLlama.spec()));
+        assert!(engine.is_ready(ModelRole::TextGeneration));
+        assert!(engine.generate(&[Message::user("Hi".to_string())]).is_ok());
+    }
+
+    #[test]
+    fn test_custom_model_from_registry() {
+        let mut engine = InferenceEngine::new();
+        let mut spec = ModelType::TinyLlama.spec();
+        spec.id = "tinyllama-q8".to_string();
+        spec.ram_mb = 450;
+        engine.registry_mut().register(spec);
+
+        let spec = engine.registry().get("tinyllama-q8").cloned().unwrap();
+        engine.load_model(spec).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        assert!(engine.generate(&[Message::user("Hi".to_string())]).is_ok());
+
+        engine.set_memory_budget(MemoryBudget::new(500));
+        let err = engine.load_model(ModelType::Phi2).unwrap_err();
+        assert_eq!(err.suggestion.map(|s| s.id), Some("tinyllama-q8".to_string()));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_load_from_path() {
+        let dir = std::env::temp_dir().join(format!("jarvis-local-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = r#"{"model_type": "llama", "vocab_size": 16, "hidden_size": 8, "num_hidden_layers": 2,
+            "num_attention_heads": 2, "intermediate_size": 12, "max_position_embeddings": 16}"#;
+        std::fs::write(dir.join("config.json"), config).unwrap();
+        std::fs::write(dir.join("tokenizer.json"), ascii_tokenizer_json(16, &[])).unwrap();
+        let weights = crate::models::llm::tests::llama_weights(&crate::models::llm::tests::llama_config(), 1, false);
+        std::fs::write(dir.join("model.safetensors"), weights).unwrap();
+
+        let mut engine = InferenceEngine::new();
+        assert_eq!(engine.load_from_path(&dir).unwrap(), ModelRole::TextGeneration);
+        let spec = engine.current_model(ModelRole::TextGeneration).unwrap();
+        assert_eq!(spec.id, dir.file_name().unwrap().to_str().unwrap());
+        // The tiny vocabulary only spells a few symbols
+        let messages = [Message::user("#$%&".to_string())];
+        assert!(engine.generate(&messages).is_ok());
+        assert!(!engine.has_model_data(ModelRole::TextGeneration));
+
+        // Prompts of a model with weights cannot be encoded without its tokenizer
+        std::fs::write(dir.join("tokenizer.json"), "{}").unwrap();
+        assert!(engine.load_from_path(&dir).unwrap_err().contains("tokenizer.json has no vocabulary"));
+        std::fs::remove_file(dir.join("tokenizer.json")).unwrap();
+        assert!(engine.load_from_path(&dir).unwrap_err().contains("no tokenizer.json"));
+
+        // A GGUF checkpoint carries its own tokenizer
+        let gguf = dir.join("gguf");
+        std::fs::create_dir(&gguf).unwrap();
+        let config = crate::models::llm::tests::llama_config();
+        let weights = crate::models::llm::tests::llama_weights(&config, 1, false);
+        std::fs::write(gguf.join("model.gguf"), crate::models::gguf::tests::llama_gguf(&config, 1, &weights)).unwrap();
+        engine.load_from_path(&gguf).unwrap();
+        assert!(engine.generate(&messages).is_ok());
+
+        std::fs::remove_file(dir.join("config.json")).unwrap();
+        assert!(engine.load_from_path(&dir).unwrap_err().contains("config.json"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_real_whisper_model() {
         use burn_ndarray::NdArray;
         
         let config = WhisperConfig::tiny();
-        let model = WhisperModel::<NdArray<f32>>::new(&config);
+        let device = <NdArray<f32> as Backend>::Device::default();
+        let model = WhisperModel::<NdArray<f32>>::new(&config, &device);
         
         // Test encoding
-        let device = <NdArray<f32> as Backend>::Device::default();
         let mel_tensor = Tensor::<NdArray<f32>, 3>::zeros([1, 80, 3000], &device);
         let output = model.encode(mel_tensor);
         
         assert_eq!(output.dims(), [1, 80, 3000]);
     }
 
+    #[test]
+    fn test_transcribe_mel_requires_whisper() {
+        let mut engine = InferenceEngine::new();
+        engine.load_model(ModelType::WhisperTiny).unwrap();
+        engine.initialize_model(ModelRole::SpeechToText).unwrap();
+
+        let mel = vec![0.0; 10 * N_MEL_BINS];
+        assert!(engine.transcribe_mel(&mel, 10).is_ok());
+        assert!(engine.transcribe_mel(&mel, 11).is_err());
+
+        engine.unload_model(ModelRole::SpeechToText);
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        assert!(engine.transcribe_mel(&mel, 10).is_err());
+    }
+
+    #[test]
+    fn test_describe_image_requires_vision_model() {
+        let mut engine = InferenceEngine::new();
+        assert!(engine.describe_image(&[], "").is_err());
+
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        let err = engine.describe_image(&[], "What is this?").unwrap_err();
+        assert_eq!(err, "No vision model loaded");
+
+        use crate::models::vision::tests::{smolvlm_weights, tiny_config};
+        let config = tiny_config();
+        let config_json = serde_json::json!({
+            "model_type": "idefics3",
+            "scale_factor": config.scale_factor,
+            "vision_config": {
+                "image_size": config.image_size,
+                "patch_size": config.patch_size,
+                "hidden_size": config.hidden_size,
+                "num_hidden_layers": config.num_layers,
+                "num_attention_heads": config.num_attention_heads,
+                "intermediate_size": config.intermediate_size,
+            },
+            "text_config": {
+                "vocab_size": config.text.vocab_size,
+                "hidden_size": config.text.hidden_size,
+                "num_hidden_layers": config.text.num_layers,
+                "num_attention_heads": config.text.num_attention_heads,
+                "intermediate_size": config.text.intermediate_size,
+                "max_position_embeddings": config.text.max_position_embeddings,
+            },
+        });
+        let special = ["<fake_token_around_image>", "<global-img>", "<image>", "<end_of_utterance>"];
+        let files = |tokenizer: &[&str]| {
+            local::collect_files(HashMap::from([
+                ("config.json".to_string(), FileData::from(config_json.to_string().into_bytes())),
+                ("model.safetensors".to_string(), FileData::from(smolvlm_weights(&config))),
+                ("tokenizer.json".to_string(), FileData::from(ascii_tokenizer_json(16, tokenizer).into_bytes())),
+            ]))
+            .unwrap()
+        };
+        // The image has nowhere to go without its token
+        assert!(engine.load_from_files("smolvlm", files(&[])).unwrap_err().contains("<image>"));
+
+        engine.load_from_files("smolvlm", files(&special)).unwrap();
+        assert_eq!(engine.current_model(ModelRole::Vision).unwrap().architecture, Architecture::Vision(config));
+        let mut png = Vec::new();
+        ::image::RgbImage::from_fn(40, 30, |x, y| ::image::Rgb([x as u8 * 6, y as u8 * 8, 128]))
+            .write_to(&mut std::io::Cursor::new(&mut png), ::image::ImageFormat::Png)
+            .unwrap();
+        engine.set_config(InferenceConfig { max_tokens: 4, temperature: 0.0, ..Default::default() });
+        let description = engine.describe_image(&png, "#$").unwrap();
+        assert_eq!(engine.describe_image(&png, "#$").unwrap(), description);
+        assert!(engine.describe_image(b"not an image", "").is_err());
+    }
+
+    #[test]
+    fn test_swap_adapters_without_reloading() {
+        use crate::models::lora::tests::{adapter_weights, CONFIG};
+
+        let mut engine = InferenceEngine::new();
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        let weights = adapter_weights(0, 2048);
+        engine.load_adapter("persona", CONFIG.as_bytes(), &weights).unwrap();
+        engine.load_adapter("domain", CONFIG.as_bytes(), &weights).unwrap();
+        assert_eq!(engine.active_adapter(), None);
+
+        engine.set_adapter(Some("persona"), 0.8).unwrap();
+        assert_eq!(engine.active_adapter(), Some(("persona".to_string(), 0.8)));
+
+        engine.set_adapter(Some("domain"), 1.0).unwrap();
+        assert_eq!(engine.active_adapter(), Some(("domain".to_string(), 1.0)));
+        assert!(engine.remove_adapter("domain"));
+        assert_eq!(engine.active_adapter(), None);
+        assert!(engine.set_adapter(Some("domain"), 1.0).is_err());
+
+        // Adapters for a different base model are rejected
+        let err = engine.load_adapter("small", CONFIG.as_bytes(), &adapter_weights(0, 64)).unwrap_err();
+        assert!(err.contains("does not fit"));
+
+        engine.unload_model(ModelRole::TextGeneration);
+        assert!(engine.load_adapter("persona", CONFIG.as_bytes(), &weights).is_err());
+    }
+
+    #[test]
+    fn test_embed() {
+        let mut engine = InferenceEngine::new();
+        let texts = ["Meeting with John".to_string(), "Lunch appointment".to_string()];
+        assert_eq!(engine.embed(&texts).unwrap_err(), "No embedding model loaded");
+
+        // Without weights the embeddings would be meaningless
+        engine.load_model(ModelType::MiniLm).unwrap();
+        assert!(engine.initialize_model(ModelRole::Embeddings).unwrap_err().contains("need their weights"));
+        engine.unload_model(ModelRole::Embeddings);
+
+        let config = crate::models::embedding::tests::tiny_config();
+        let config_json = serde_json::json!({
+            "model_type": "bert",
+            "vocab_size": config.vocab_size,
+            "hidden_size": config.hidden_size,
+            "num_hidden_layers": config.num_layers,
+            "num_attention_heads": config.num_attention_heads,
+            "intermediate_size": config.intermediate_size,
+            "max_position_embeddings": config.max_position_embeddings,
+        });
+        let weights = crate::models::embedding::tests::bert_weights(&config, "");
+        let files = local::collect_files(HashMap::from([
+            ("config.json".to_string(), FileData::from(config_json.to_string().into_bytes())),
+            ("model.safetensors".to_string(), FileData::from(weights)),
+        ]))
+        .unwrap();
+        assert_eq!(engine.load_from_files("minilm", files).unwrap(), ModelRole::Embeddings);
+        let embeddings = engine.embed(&texts).unwrap();
+        assert_eq!(embeddings.len(), 2);
+        assert!(embeddings.iter().all(|e| e.len() == config.hidden_size));
+
+        let dot = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+        let again = engine.embed(&texts[..1]).unwrap();
+        assert!((dot(&embeddings[0], &again[0]) - 1.0).abs() < 1e-4);
+        assert!(engine.embed(&[]).unwrap().is_empty());
+        assert!(engine.generate(&[]).is_err());
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        let mut engine = InferenceEngine::new();
+        assert!(engine.count_tokens("Hello").is_err());
+        assert_eq!(engine.context_length(), None);
+        assert_eq!(TextGenerator::count_tokens(&engine, "Hello, sir"), 3);
+
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        assert_eq!(engine.context_length(), Some(2048));
+        // Without a tokenizer.json each printable character is a token
+        assert_eq!(engine.count_tokens("Hello, sir").unwrap(), 10);
+    }
+
+    #[test]
+    fn test_generate_json() {
+        let mut engine = InferenceEngine::new();
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"done": {"type": "boolean"}, "priority": {"enum": ["low", "high"]}},
+            "required": ["done", "priority"],
+        });
+        let messages = [Message::user("Is the task done?".to_string())];
+        assert!(engine.generate_json(&messages, &schema).is_err());
+
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        let value = engine.generate_json(&messages, &schema).unwrap();
+        assert!(value["done"].is_boolean());
+        assert!(value["priority"] == "low" || value["priority"] == "high");
+
+        let yes_no = Grammar::parse(r#"root ::= "yes" | "no""#).unwrap();
+        let answer = engine.generate_constrained(&messages, &yes_no).unwrap();
+        assert!(answer == "yes" || answer == "no");
+
+        // An unbounded string never finishes within the token cap
+        engine.set_config(InferenceConfig { max_tokens: 8, ..Default::default() });
+        let err = engine.generate_json(&messages, &serde_json::json!({"type": "string"})).unwrap_err();
+        assert!(err.contains("8 tokens"));
+        assert!(engine.generate_json(&messages, &serde_json::json!({"type": "date"})).is_err());
+    }
+
+    #[test]
+    fn test_generate_batch() {
+        let mut engine = InferenceEngine::new();
+        let conversations = vec![
+            vec![Message::user("Title this chat".to_string())],
+            vec![Message::user("Summarize this chat".to_string())],
+        ];
+        assert!(engine.generate_batch(&conversations).is_err());
+
+        engine.load_model(ModelType::TinyLlama).unwrap();
+        engine.initialize_model(ModelRole::TextGeneration).unwrap();
+        let replies = engine.generate_batch(&conversations).unwrap();
+        assert_eq!(replies.len(), 2);
+        assert!(engine.generate_batch(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_speculative_generation() {
+        use crate::models::llm::tests::{llama_config, llama_weights};
+
+        let config = LlmConfig { max_position_embeddings: 64, ..llama_config() };
+        let llama_files = |kv_heads: usize, config: &LlmConfig| {
+            let config_json = serde_json::json!({
+                "model_type": "llama",
+                "vocab_size": config.vocab_size,
+                "hidden_size": config.hidden_size,
+                "num_hidden_layers": config.num_layers,
+                "num_attention_heads": config.num_attention_heads,
+                "intermediate_size": config.intermediate_size,
+                "max_position_embeddings": config.max_position_embeddings,
+            });
+            local::collect_files(HashMap::from([
+                ("config.json".to_string(), FileData::from(config_json.to_string().into_bytes())),
+                ("model.safetensors".to_string(), FileData::from(llama_weights(config, kv_heads, false))),
+                ("tokenizer.json".to_string(), FileData::from(ascii_tokenizer_json(config.vocab_size, &[]).into_bytes())),
+            ]))
+            .unwrap()
+        };
+        let mut engine = InferenceEngine::with_config(InferenceConfig { max_tokens: 8, temperature: 0.0, ..Default::default() });
+        assert!(engine.load_draft_model("draft", llama_files(1, &config)).is_err());
+        engine.load_from_files("llama", llama_files(1, &config)).unwrap();
+        let messages = vec![Message::user("#$%&".to_string())];
+        assert_eq!(engine.generate_speculative(&messages).unwrap_err(), "No draft model loaded");
+        let plain = engine.generate(&messages).unwrap();
+
+        // A draft identical to the LLM is always right
+        engine.load_draft_model("draft", llama_files(1, &config)).unwrap();
+        assert!(engine.has_draft_model());
+        let (reply, stats) = engine.generate_speculative(&messages).unwrap();
+        assert_eq!(stats.generated, 8);
+        assert_eq!(stats.acceptance_rate(), 1.0);
+        assert!(stats.target_passes < stats.generated);
+        // Greedy decoding gives the LLM's own reply, with or without a draft
+        assert_eq!(reply, plain);
+        assert_eq!(engine.generate(&messages).unwrap(), reply);
+
+        // A different draft changes the passes, not the greedy reply
+        engine.load_draft_model("draft", llama_files(2, &config)).unwrap();
+        let (other, stats) = engine.generate_speculative(&messages).unwrap();
+        assert_eq!(other, reply);
+        assert!(stats.acceptance_rate() < 1.0);
+
+        let wider = LlmConfig { vocab_size: 32, ..config.clone() };
+        assert!(engine.load_draft_model("draft", llama_files(1, &wider)).unwrap_err().contains("32 tokens"));
+        engine.unload_draft_model();
+        assert!(!engine.has_draft_model());
+        assert!(engine.generate_speculative(&messages).is_err());
+    }
+
     #[test]
     fn test_real_llm_model() {
         use burn_ndarray::NdArray;
         
         let config = LlmConfig::phi_2();
-        let model = LlmModel::<NdArray<f32>>::new(&config);
+        let device = <NdArray<f32> as Backend>::Device::default();
+        let model = LlmModel::<NdArray<f32>>::new(&config, &device);
         
         // Test forward pass
-        let device = <NdArray<f32> as Backend>::Device::default();
         let input_tensor = Tensor::<NdArray<f32>, 2>::zeros([1, 10], &device);
         let output = model.forward(input_tensor);
         
-        assert_eq!(output.dims(), [1, 10, 50257]);
+        assert_eq!(output.dims(), [1, 10, config.vocab_size]);
     }
 }
@@ -17,17 +17,38 @@
 //! ```toml
 //! jarvis-ai = { path = "../jarvis-ai", features = ["wgpu"] }
 //! ```
+//!
+//! [`InferenceEngine`] defaults to the CPU backend; with `wgpu` enabled, use
+//! `InferenceEngine::new_gpu` to run on a [`backend::GpuDevice`].
 
 // Required for wgpu backend due to deeply nested associated types
 #![recursion_limit = "256"]
 
 pub mod agent;
 pub mod audio;
+pub mod backend;
+pub mod conversation_log;
+pub mod grammar;
+pub mod image;
 pub mod inference;
+pub mod memory;
 pub mod models;
+pub mod output_filter;
+pub mod streaming;
 pub mod types;
 
-pub use agent::Agent;
+pub use agent::{Agent, AgentEvent, Persona, RunLimits, ToolCallFormat};
+pub use backend::{BackendKind, CpuBackend};
+pub use conversation_log::{ConversationLogger, LogSink};
+#[cfg(feature = "wgpu")]
+pub use backend::{GpuBackend, GpuDevice};
+pub use grammar::Grammar;
 pub use inference::{InferenceConfig, InferenceEngine, ModelState};
-pub use models::{LoadProgress, ModelType};
+pub use memory::{MemoryBudget, MemoryError};
+pub use models::{
+    Architecture, ChatTemplate, DownloadConfig, LoadProgress, ModelFiles, ModelRegistry, ModelRole, ModelSpec, ModelType,
+    Quantization,
+};
+pub use output_filter::{OutputFilter, OutputFilters};
+pub use streaming::{SpeechRecognizer, StreamingConfig, StreamingTranscriber, TranscriptEvent};
 pub use types::*;
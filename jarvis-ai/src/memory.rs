@@ -0,0 +1,238 @@
+//! Memory budget for resident models
+//!
+//! Loading a model that does not fit crashes the browser tab, so the engine
+//! checks each load against a budget using [`ModelSpec::ram_mb`]. When the
+//! budget is exceeded, the least recently used models are evicted first.
+
+use crate::models::{ModelRegistry, ModelRole, ModelSpec};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::HashMap;
+
+/// Maximum memory resident models may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryBudget {
+    /// Limit in MB; `None` for no limit
+    pub limit_mb: Option<u32>,
+}
+
+impl MemoryBudget {
+    /// A budget of `limit_mb` megabytes
+    pub fn new(limit_mb: u32) -> Self {
+        Self { limit_mb: Some(limit_mb) }
+    }
+
+    /// No limit
+    pub fn unlimited() -> Self {
+        Self { limit_mb: None }
+    }
+
+    /// Budget for the current device
+    ///
+    /// In the browser this is half of `navigator.deviceMemory`, leaving room
+    /// for the page itself; elsewhere there is no limit.
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let device_memory_gb = web_sys::window()
+                .and_then(|window| js_sys::Reflect::get(&window.navigator(), &"deviceMemory".into()).ok())
+                .and_then(|value| value.as_f64());
+            if let Some(gb) = device_memory_gb {
+                return Self::new((gb * 1024.0 / 2.0) as u32);
+            }
+        }
+        Self::unlimited()
+    }
+
+    /// Check whether `mb` fits within the budget
+    pub fn fits(&self, mb: u32) -> bool {
+        self.limit_mb.is_none_or(|limit| mb <= limit)
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::detect()
+    }
+}
+
+/// A model does not fit in the memory budget even with every other model evicted
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error(
+    "{model} needs {required_mb} MB of memory but the budget is {budget_mb} MB{}",
+    suggestion.as_ref().map_or(String::new(), |s| format!("; try {} ({} MB) instead", s.id, s.ram_mb))
+)]
+pub struct MemoryError {
+    /// Id of the model that does not fit
+    pub model: String,
+    pub required_mb: u32,
+    pub budget_mb: u32,
+    /// Largest registered model for the same role that fits the budget
+    pub suggestion: Option<Box<ModelSpec>>,
+}
+
+impl From<MemoryError> for String {
+    fn from(err: MemoryError) -> Self {
+        err.to_string()
+    }
+}
+
+struct Resident {
+    ram_mb: u32,
+    last_used: Cell<u64>,
+}
+
+/// Tracks resident models and chooses which to evict
+pub struct MemoryManager {
+    budget: MemoryBudget,
+    resident: HashMap<ModelRole, Resident>,
+    clock: Cell<u64>,
+}
+
+impl MemoryManager {
+    pub fn new(budget: MemoryBudget) -> Self {
+        Self {
+            budget,
+            resident: HashMap::new(),
+            clock: Cell::new(0),
+        }
+    }
+
+    /// Get the memory budget
+    pub fn budget(&self) -> MemoryBudget {
+        self.budget
+    }
+
+    /// Change the memory budget; takes effect on the next load
+    pub fn set_budget(&mut self, budget: MemoryBudget) {
+        self.budget = budget;
+    }
+
+    /// Memory used by resident models in MB
+    pub fn resident_mb(&self) -> u32 {
+        self.resident.values().map(|r| r.ram_mb).sum()
+    }
+
+    /// Memory left in the budget in MB, `None` if unlimited
+    pub fn available_mb(&self) -> Option<u32> {
+        self.budget.limit_mb.map(|limit| limit.saturating_sub(self.resident_mb()))
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.set(self.clock.get() + 1);
+        self.clock.get()
+    }
+
+    /// Mark the model for a role as just used
+    pub fn touch(&self, role: ModelRole) {
+        if let Some(resident) = self.resident.get(&role) {
+            resident.last_used.set(self.tick());
+        }
+    }
+
+    /// Roles to evict, least recently used first, so that `spec` fits
+    ///
+    /// The model currently in `spec`'s role is replaced and so never counts
+    /// against the budget. If `spec` alone exceeds the budget, the error
+    /// suggests the largest model in `registry` that would fit instead.
+    pub fn plan(&self, spec: &ModelSpec, registry: &ModelRegistry) -> Result<Vec<ModelRole>, MemoryError> {
+        let required = spec.ram_mb;
+        let Some(limit) = self.budget.limit_mb else {
+            return Ok(Vec::new());
+        };
+        if required > limit {
+            return Err(MemoryError {
+                model: spec.id.clone(),
+                required_mb: required,
+                budget_mb: limit,
+                suggestion: registry
+                    .for_role(spec.role())
+                    .filter(|s| s.ram_mb <= limit)
+                    .max_by_key(|s| s.ram_mb)
+                    .map(|s| Box::new(s.clone())),
+            });
+        }
+
+        let mut others: Vec<(&ModelRole, &Resident)> =
+            self.resident.iter().filter(|(role, _)| **role != spec.role()).collect();
+        others.sort_by_key(|(_, r)| r.last_used.get());
+
+        let mut used: u32 = others.iter().map(|(_, r)| r.ram_mb).sum();
+        let mut evict = Vec::new();
+        for (role, resident) in others {
+            if used + required <= limit {
+                break;
+            }
+            used -= resident.ram_mb;
+            evict.push(*role);
+        }
+        Ok(evict)
+    }
+
+    /// Record a model as resident in its role
+    pub fn insert(&mut self, spec: &ModelSpec) {
+        let last_used = Cell::new(self.tick());
+        self.resident.insert(spec.role(), Resident { ram_mb: spec.ram_mb, last_used });
+    }
+
+    /// Forget the model in a role
+    pub fn remove(&mut self, role: ModelRole) {
+        self.resident.remove(&role);
+    }
+
+    /// Forget all models
+    pub fn clear(&mut self) {
+        self.resident.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ModelType;
+
+    #[test]
+    fn test_plan_evicts_least_recently_used() {
+        // Whisper tiny 390 MB + TinyLlama 800 MB, SmolVLM 1000 MB
+        let registry = ModelRegistry::builtin();
+        let mut memory = MemoryManager::new(MemoryBudget::new(2000));
+        memory.insert(&ModelType::WhisperTiny.spec());
+        memory.insert(&ModelType::TinyLlama.spec());
+        assert_eq!(memory.available_mb(), Some(810));
+
+        memory.touch(ModelRole::SpeechToText);
+        assert_eq!(memory.plan(&ModelType::SmolVlm.spec(), &registry).unwrap(), vec![ModelRole::TextGeneration]);
+
+        memory.touch(ModelRole::TextGeneration);
+        assert_eq!(memory.plan(&ModelType::SmolVlm.spec(), &registry).unwrap(), vec![ModelRole::SpeechToText]);
+
+        // Replacing TinyLlama with Phi-2 (2000 MB) frees TinyLlama but still needs Whisper gone
+        assert_eq!(memory.plan(&ModelType::Phi2.spec(), &registry).unwrap(), vec![ModelRole::SpeechToText]);
+        assert!(memory.plan(&ModelType::WhisperBase.spec(), &registry).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_too_large_model_suggests_smaller_one() {
+        let registry = ModelRegistry::builtin();
+        let memory = MemoryManager::new(MemoryBudget::new(1000));
+        let err = memory.plan(&ModelType::Phi2.spec(), &registry).unwrap_err();
+        assert_eq!(err.suggestion, Some(Box::new(ModelType::TinyLlama.spec())));
+        assert_eq!(
+            err.to_string(),
+            "phi-2 needs 2000 MB of memory but the budget is 1000 MB; try tinyllama (800 MB) instead"
+        );
+
+        let err = MemoryManager::new(MemoryBudget::new(100)).plan(&ModelType::WhisperTiny.spec(), &registry).unwrap_err();
+        assert_eq!(err.suggestion, None);
+    }
+
+    #[test]
+    fn test_unlimited_budget() {
+        let registry = ModelRegistry::builtin();
+        let mut memory = MemoryManager::new(MemoryBudget::unlimited());
+        memory.insert(&ModelType::Phi2.spec());
+        assert!(memory.plan(&ModelType::SmolVlm.spec(), &registry).unwrap().is_empty());
+        assert_eq!(memory.available_mb(), None);
+        assert_eq!(memory.resident_mb(), 2000);
+    }
+}
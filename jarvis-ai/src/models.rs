@@ -2,13 +2,65 @@
 
 use serde::{Deserialize, Serialize};
 
+pub mod cache;
+pub mod embedding;
+pub mod gguf;
+pub mod hub;
+pub mod local;
+pub mod lora;
+pub mod registry;
+pub mod speculative;
+pub mod weights;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test_server;
 pub mod whisper;
 pub mod llm;
+pub mod vision;
 
+pub use cache::FileData;
+pub use hub::{DownloadConfig, ModelFiles, ModelManifest, parse_shard_index};
+pub use lora::{LoraAdapter, LoraConfig};
+pub use registry::{Architecture, ChatTemplate, ModelRegistry, ModelSpec, Quantization, RegistryManifest};
+pub use speculative::{CausalLm, LlmSession, SpeculativeConfig, SpeculativeDecoder, SpeculativeOutput, SpeculativeStats};
+pub use weights::WeightMap;
 pub use whisper::{WhisperConfig, WhisperModel, create_whisper_model};
-pub use llm::{LlmConfig, LlmModel, create_llm_model};
+pub use llm::{BatchFinish, BatchOutput, KvCache, LlmConfig, LlmModel, create_llm_model};
+pub use vision::{VisionConfig, VisionModel, create_vision_model};
+pub use embedding::{TextEmbeddingConfig, TextEmbeddingModel, WordPieceTokenizer, create_embedding_model};
 
-/// Available models for inference
+/// Task a model performs; the inference engine holds one model per role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ModelRole {
+    SpeechToText,
+    TextGeneration,
+    Vision,
+    Embeddings,
+    TextToSpeech,
+}
+
+impl ModelRole {
+    /// All roles, in display order
+    pub const ALL: [ModelRole; 5] = [
+        ModelRole::SpeechToText,
+        ModelRole::TextGeneration,
+        ModelRole::Vision,
+        ModelRole::Embeddings,
+        ModelRole::TextToSpeech,
+    ];
+
+    /// Get a human-readable name
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelRole::SpeechToText => "speech-to-text",
+            ModelRole::TextGeneration => "text generation",
+            ModelRole::Vision => "vision",
+            ModelRole::Embeddings => "embedding",
+            ModelRole::TextToSpeech => "text-to-speech",
+        }
+    }
+}
+
+/// Built-in models, registered by default in every [`ModelRegistry`]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ModelType {
     WhisperTiny,
@@ -16,9 +68,64 @@ pub enum ModelType {
     WhisperSmall,
     Phi2,
     TinyLlama,
+    SmolVlm,
+    MiniLm,
 }
 
 impl ModelType {
+    /// All available models
+    pub const ALL: [ModelType; 7] = [
+        ModelType::WhisperTiny,
+        ModelType::WhisperBase,
+        ModelType::WhisperSmall,
+        ModelType::Phi2,
+        ModelType::TinyLlama,
+        ModelType::SmolVlm,
+        ModelType::MiniLm,
+    ];
+
+    /// Get the id of the model in the registry
+    pub fn id(&self) -> &'static str {
+        match self {
+            ModelType::WhisperTiny => "whisper-tiny",
+            ModelType::WhisperBase => "whisper-base",
+            ModelType::WhisperSmall => "whisper-small",
+            ModelType::Phi2 => "phi-2",
+            ModelType::TinyLlama => "tinyllama",
+            ModelType::SmolVlm => "smolvlm-256m",
+            ModelType::MiniLm => "all-minilm-l6-v2",
+        }
+    }
+
+    /// Get the registry entry describing this model
+    pub fn spec(&self) -> ModelSpec {
+        let architecture = match self {
+            ModelType::WhisperTiny => Architecture::Whisper(WhisperConfig::tiny()),
+            ModelType::WhisperBase => Architecture::Whisper(WhisperConfig::base()),
+            // Use base config for small for now
+            ModelType::WhisperSmall => Architecture::Whisper(WhisperConfig::base()),
+            ModelType::Phi2 => Architecture::Llm(LlmConfig::phi_2()),
+            ModelType::TinyLlama => Architecture::Llm(LlmConfig::tiny_llama()),
+            ModelType::SmolVlm => Architecture::Vision(VisionConfig::smolvlm_256m()),
+            ModelType::MiniLm => Architecture::Embedding(TextEmbeddingConfig::all_minilm_l6_v2()),
+        };
+        let chat_template = match self {
+            ModelType::Phi2 => Some(ChatTemplate::Phi),
+            ModelType::TinyLlama => Some(ChatTemplate::Zephyr),
+            _ => None,
+        };
+        ModelSpec {
+            id: self.id().to_string(),
+            architecture,
+            repo: self.model_name().to_string(),
+            files: self.manifest(),
+            quantization: Quantization::F32,
+            size_mb: self.size_mb(),
+            ram_mb: self.ram_mb(),
+            chat_template,
+        }
+    }
+
     /// Get the model name for downloading
     pub fn model_name(&self) -> &str {
         match self {
@@ -27,6 +134,33 @@ impl ModelType {
             ModelType::WhisperSmall => "openai/whisper-small",
             ModelType::Phi2 => "microsoft/phi-2",
             ModelType::TinyLlama => "TinyLlama/TinyLlama-1.1B-Chat-v1.0",
+            ModelType::SmolVlm => "HuggingFaceTB/SmolVLM-256M-Instruct",
+            ModelType::MiniLm => "sentence-transformers/all-MiniLM-L6-v2",
+        }
+    }
+
+    /// Get the role this model fills in the inference engine
+    pub fn role(&self) -> ModelRole {
+        match self {
+            ModelType::WhisperTiny | ModelType::WhisperBase | ModelType::WhisperSmall => ModelRole::SpeechToText,
+            ModelType::Phi2 | ModelType::TinyLlama => ModelRole::TextGeneration,
+            ModelType::SmolVlm => ModelRole::Vision,
+            ModelType::MiniLm => ModelRole::Embeddings,
+        }
+    }
+
+    /// Get the files that make up the model in its repository
+    pub fn manifest(&self) -> ModelManifest {
+        match self {
+            ModelType::WhisperTiny
+            | ModelType::WhisperBase
+            | ModelType::WhisperSmall
+            | ModelType::TinyLlama
+            | ModelType::MiniLm => {
+                ModelManifest::single(&["config.json", "tokenizer.json"])
+            }
+            ModelType::Phi2 => ModelManifest::sharded(&["config.json", "tokenizer.json"]),
+            ModelType::SmolVlm => ModelManifest::single(&["config.json", "tokenizer.json", "preprocessor_config.json"]),
         }
     }
 
@@ -38,6 +172,8 @@ impl ModelType {
             ModelType::WhisperSmall => 466,
             ModelType::Phi2 => 1500,
             ModelType::TinyLlama => 600,
+            ModelType::SmolVlm => 513,
+            ModelType::MiniLm => 90,
         }
     }
 
@@ -49,6 +185,8 @@ impl ModelType {
             ModelType::WhisperSmall => 1000,
             ModelType::Phi2 => 2000,
             ModelType::TinyLlama => 800,
+            ModelType::SmolVlm => 1000,
+            ModelType::MiniLm => 150,
         }
     }
 }
@@ -59,6 +197,12 @@ pub struct LoadProgress {
     pub loaded_bytes: u64,
     pub total_bytes: u64,
     pub percentage: f32,
+    /// File currently being downloaded
+    pub current_file: Option<String>,
+    /// Files fully downloaded so far
+    pub files_completed: usize,
+    /// Files that make up the model (0 if unknown)
+    pub files_total: usize,
 }
 
 impl LoadProgress {
@@ -73,81 +217,33 @@ impl LoadProgress {
             loaded_bytes: loaded,
             total_bytes: total,
             percentage,
+            current_file: None,
+            files_completed: 0,
+            files_total: 0,
         }
     }
-}
 
-/// Download a model from HuggingFace
-#[cfg(not(target_arch = "wasm32"))]
-pub async fn download_model(
-    model_type: ModelType,
-    on_progress: impl Fn(LoadProgress),
-) -> Result<Vec<u8>, String> {
-    use reqwest::Client;
-    use futures_util::StreamExt;
-
-    let client = Client::new();
-    let url = format!("https://huggingface.co/{}/resolve/main/model.safetensors", model_type.model_name());
-    
-    let response = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-    
-    let total_bytes = response.content_length().unwrap_or(0);
-    let mut loaded_bytes = 0;
-    let mut data = Vec::with_capacity(total_bytes as usize);
-    let mut stream = response.bytes_stream();
-    
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(|e| e.to_string())?;
-        loaded_bytes += chunk.len() as u64;
-        data.extend_from_slice(&chunk);
-        on_progress(LoadProgress::new(loaded_bytes, total_bytes));
+    /// Attach the file being downloaded and the position within the model's files
+    pub fn with_file(mut self, name: &str, completed: usize, total: usize) -> Self {
+        self.current_file = Some(name.to_string());
+        self.files_completed = completed;
+        self.files_total = total;
+        self
     }
-    
-    Ok(data)
 }
 
-/// Download a model from HuggingFace (WASM version)
-#[cfg(target_arch = "wasm32")]
+/// Default HuggingFace hub endpoint models are downloaded from
+pub const HF_ENDPOINT: &str = "https://huggingface.co";
+
+/// Download a model's weights, config and tokenizer from the configured hub
+///
+/// Files are kept in the platform's [`cache::default_cache`], so a model is
+/// only fetched once and an interrupted download resumes where it stopped.
 pub async fn download_model(
-    model_type: ModelType,
+    spec: &ModelSpec,
+    config: &DownloadConfig,
     on_progress: impl Fn(LoadProgress),
-) -> Result<Vec<u8>, String> {
-    use wasm_bindgen::prelude::*;
-    use wasm_bindgen_futures::JsFuture;
-    use web_sys::{Request, RequestInit, Response};
-    
-    let window = web_sys::window().ok_or("No window found")?;
-    let url = format!("https://huggingface.co/{}/resolve/main/model.safetensors", model_type.model_name());
-    
-    let opts = RequestInit::new();
-    opts.set_method("GET");
-    
-    let request = Request::new_with_str_and_init(&url, &opts)
-        .map_err(|_| "Failed to create request")?;
-    
-    let promise = window.fetch_with_request(&request);
-    let response = JsFuture::from(promise)
-        .await
-        .map_err(|_| "Fetch failed")?
-        .dyn_into::<Response>()
-        .map_err(|_| "Not a response")?;
-    
-    let content_length = 0; // Simplified for now - content length handling can be improved
-    
-    let array_buffer_promise = response.array_buffer().map_err(|_| "Failed to get array buffer")?;
-    let array_buffer = JsFuture::from(array_buffer_promise)
-        .await
-        .map_err(|_| "Failed to get array buffer data")?;
-    
-    let bytes = js_sys::Uint8Array::new(&array_buffer);
-    let mut data = vec![0; bytes.length() as usize];
-    bytes.copy_to(&mut data);
-    
-    on_progress(LoadProgress::new(data.len() as u64, content_length));
-    
-    Ok(data)
+) -> Result<ModelFiles, String> {
+    let cache = cache::default_cache()?;
+    hub::download_files(&cache, config, &spec.repo, &spec.files, on_progress).await
 }
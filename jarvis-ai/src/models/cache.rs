@@ -0,0 +1,1019 @@
+//! Persistent model file cache
+//!
+//! Model files are cached by repository, revision and file name so they are
+//! only downloaded once. Downloads are written to a partial entry as they
+//! stream in; an interrupted download resumes with an HTTP `Range` request,
+//! and a finished one is checked against the HuggingFace LFS sha256 before it
+//! becomes visible in the cache.
+//!
+//! Native builds cache to a directory on disk ([`FsModelCache`]); the browser
+//! uses the Cache API ([`WebModelCache`]).
+
+use super::LoadProgress;
+use futures::{Stream, StreamExt};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::ops::Deref;
+
+/// Bytes buffered in memory before being appended to a partial entry
+const FLUSH_BYTES: usize = 1024 * 1024;
+
+/// Largest block of a file the OS maps at once when a page of it is read
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+const MAP_BLOCK_BYTES: usize = 2 * 1024 * 1024;
+
+/// Contents of one model file
+#[derive(Debug, Clone)]
+pub enum FileData {
+    Owned(Vec<u8>),
+    /// A file on disk mapped into memory, so it is never copied
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(std::sync::Arc<memmap2::Mmap>),
+}
+
+impl FileData {
+    /// Map a file into memory
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn map(path: &std::path::Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        if file.metadata().map(|m| m.len()).unwrap_or(0) == 0 {
+            return Ok(FileData::Owned(Vec::new()));
+        }
+        // SAFETY: the map is read-only; model files must not be modified while loaded
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| format!("Failed to map {}: {}", path.display(), e))?;
+        Ok(FileData::Mapped(std::sync::Arc::new(map)))
+    }
+
+    /// Let the OS drop the pages holding `bytes`, a part of this file, from memory
+    ///
+    /// Only mapped files are affected; their pages are read back from disk if
+    /// the bytes are used again. Reading a page can map a whole block of the
+    /// file around it, so the blocks on either side of `bytes` are dropped too.
+    pub fn release(&self, bytes: &[u8]) {
+        #[cfg(all(unix, not(target_arch = "wasm32")))]
+        if let FileData::Mapped(map) = self {
+            let offset = (bytes.as_ptr() as usize).wrapping_sub(map.as_ptr() as usize);
+            if bytes.is_empty() || offset > map.len() || bytes.len() > map.len() - offset {
+                return;
+            }
+            let start = offset.saturating_sub(MAP_BLOCK_BYTES);
+            let end = (offset + bytes.len()).saturating_add(MAP_BLOCK_BYTES).min(map.len());
+            // SAFETY: the map is read-only, so dropped pages read back unchanged from the file
+            if let Err(e) = unsafe { map.unchecked_advise_range(memmap2::UncheckedAdvice::DontNeed, start, end - start) } {
+                warn!("Failed to release mapped pages: {}", e);
+            }
+        }
+        #[cfg(not(all(unix, not(target_arch = "wasm32"))))]
+        let _ = bytes;
+    }
+}
+
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileData::Owned(data) => data,
+            #[cfg(not(target_arch = "wasm32"))]
+            FileData::Mapped(map) => map,
+        }
+    }
+}
+
+impl From<Vec<u8>> for FileData {
+    fn from(data: Vec<u8>) -> Self {
+        FileData::Owned(data)
+    }
+}
+
+/// Identifies one file of one revision of a model repository
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Repository id, e.g. `openai/whisper-tiny.en`
+    pub repo: String,
+    /// Branch, tag or commit hash
+    pub revision: String,
+    /// Path of the file within the repository
+    pub file: String,
+}
+
+impl CacheKey {
+    pub fn new(repo: impl Into<String>, revision: impl Into<String>, file: impl Into<String>) -> Self {
+        Self {
+            repo: repo.into(),
+            revision: revision.into(),
+            file: file.into(),
+        }
+    }
+
+    /// Relative cache path, laid out like the HuggingFace hub cache
+    pub fn relative_path(&self) -> String {
+        format!("models--{}/{}/{}", self.repo.replace('/', "--"), self.revision, self.file)
+    }
+
+    /// Download URL of the file on a HuggingFace-compatible hub
+    pub fn url(&self, endpoint: &str) -> String {
+        format!(
+            "{}/{}/resolve/{}/{}",
+            endpoint.trim_end_matches('/'),
+            self.repo,
+            self.revision,
+            self.file
+        )
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}/{}", self.repo, self.revision, self.file)
+    }
+}
+
+/// Storage for downloaded model files
+///
+/// A file is first accumulated in a partial entry with [`append_partial`],
+/// then verified and promoted with [`commit_partial`]. Only committed files
+/// are returned by [`get`].
+///
+/// [`append_partial`]: ModelCache::append_partial
+/// [`commit_partial`]: ModelCache::commit_partial
+/// [`get`]: ModelCache::get
+// Browser futures are not `Send`, so the returned futures deliberately have no `Send` bound
+#[allow(async_fn_in_trait)]
+pub trait ModelCache {
+    /// Get a committed file
+    async fn get(&self, key: &CacheKey) -> Result<Option<FileData>, String>;
+
+    /// Size of a committed file, without reading it
+    async fn cached_len(&self, key: &CacheKey) -> Result<Option<u64>, String>;
+
+    /// Number of bytes in the partial entry (0 if there is none)
+    async fn partial_len(&self, key: &CacheKey) -> Result<u64, String>;
+
+    /// Append bytes to the partial entry
+    async fn append_partial(&self, key: &CacheKey, bytes: &[u8]) -> Result<(), String>;
+
+    /// Delete the partial entry
+    async fn discard_partial(&self, key: &CacheKey) -> Result<(), String>;
+
+    /// Verify the partial entry and make it the committed file
+    ///
+    /// If `sha256` is given and does not match, the partial entry is
+    /// discarded and an error is returned.
+    async fn commit_partial(&self, key: &CacheKey, sha256: Option<&str>) -> Result<FileData, String>;
+
+    /// Delete a committed file and any partial entry
+    async fn remove(&self, key: &CacheKey) -> Result<(), String>;
+}
+
+/// Lowercase hex sha256 of `bytes`
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Check a downloaded file against an expected sha256
+///
+/// The file is hashed a chunk at a time, releasing each chunk of a mapped
+/// file once it has been read.
+fn verify_sha256(key: &CacheKey, data: &FileData, expected: Option<&str>) -> Result<(), String> {
+    match expected {
+        Some(expected) => {
+            let mut hasher = Sha256::new();
+            for chunk in data.chunks(FLUSH_BYTES) {
+                hasher.update(chunk);
+                data.release(chunk);
+            }
+            let actual: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+            if actual.eq_ignore_ascii_case(expected) {
+                Ok(())
+            } else {
+                Err(format!("Checksum mismatch for {}: expected {}, got {}", key, expected, actual))
+            }
+        }
+        None => {
+            warn!("No checksum available for {}; skipping verification", key);
+            Ok(())
+        }
+    }
+}
+
+/// Extract the LFS sha256 from an `ETag`/`X-Linked-Etag` header value
+///
+/// HuggingFace serves LFS files with the sha256 of the content as their
+/// (linked) ETag; regular git files use the git blob sha1, which is ignored.
+pub fn lfs_sha256(etag: &str) -> Option<String> {
+    let value = etag.trim().trim_start_matches("W/").trim_matches('"');
+    (value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())).then(|| value.to_ascii_lowercase())
+}
+
+/// LFS sha256 from the headers of a response
+///
+/// A redirect's own `ETag` describes the redirect, so only its
+/// `X-Linked-Etag` counts.
+fn response_sha256(status: u16, linked_etag: Option<&str>, etag: Option<&str>) -> Option<String> {
+    let etag = etag.filter(|_| (200..300).contains(&status));
+    linked_etag.or(etag).and_then(lfs_sha256)
+}
+
+/// Response to a (possibly ranged) download request
+struct RangeResponse<S> {
+    status: u16,
+    /// Length of this response's body
+    content_length: Option<u64>,
+    body: S,
+}
+
+/// Download a file through a cache
+///
+/// Returns the cached file if present. Otherwise the file is streamed into a
+/// partial entry, resuming after any bytes left by an earlier interrupted
+/// download, and committed once its sha256 has been verified.
+///
+/// # Arguments
+/// * `cache` - Cache to read from and write to
+/// * `key` - File to download
+/// * `url` - Where to download it from
+/// * `token` - Optional bearer token for gated repositories
+/// * `on_progress` - Called as bytes arrive, including resumed bytes
+pub async fn cached_download<C: ModelCache>(
+    cache: &C,
+    key: &CacheKey,
+    url: &str,
+    token: Option<&str>,
+    on_progress: impl Fn(LoadProgress),
+) -> Result<FileData, String> {
+    if let Some(data) = cache.get(key).await? {
+        info!("Loaded {} from cache", key);
+        let len = data.len() as u64;
+        on_progress(LoadProgress::new(len, len));
+        return Ok(data);
+    }
+
+    // HuggingFace redirects LFS files to a CDN whose ETag is not the sha256,
+    // so the hash is read before the download follows the redirect
+    let sha256 = fetch_lfs_sha256(key, url, token).await?;
+    let (mut loaded, response) = loop {
+        let offset = cache.partial_len(key).await?;
+        if offset > 0 {
+            info!("Resuming download of {} at byte {}", key, offset);
+        }
+        let response = fetch_range(url, offset, token).await?;
+        match response.status {
+            206 if offset > 0 => break (offset, response),
+            200..=299 => {
+                // The server ignored the range; start over
+                if offset > 0 {
+                    cache.discard_partial(key).await?;
+                }
+                break (0, response);
+            }
+            // The partial entry is at least as long as the file, so it cannot be trusted
+            416 if offset > 0 => cache.discard_partial(key).await?,
+            status => return Err(format!("Download of {} failed: HTTP {}", url, status)),
+        }
+    };
+
+    let total = loaded + response.content_length.unwrap_or(0);
+    let mut body = Box::pin(response.body);
+    let mut buffer = Vec::with_capacity(FLUSH_BYTES);
+
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // Keep what arrived so the next attempt can resume from it
+                cache.append_partial(key, &buffer).await?;
+                return Err(format!("Download of {} interrupted: {}", url, e));
+            }
+        };
+        loaded += chunk.len() as u64;
+        buffer.extend_from_slice(&chunk);
+        if buffer.len() >= FLUSH_BYTES {
+            cache.append_partial(key, &buffer).await?;
+            buffer.clear();
+        }
+        on_progress(LoadProgress::new(loaded, total));
+    }
+    cache.append_partial(key, &buffer).await?;
+
+    cache.commit_partial(key, sha256.as_deref()).await
+}
+
+/// Size of the file at `url` from a `HEAD` request, if the server reports it
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn fetch_size(url: &str, token: Option<&str>) -> Result<Option<u64>, String> {
+    let mut request = reqwest::Client::new().head(url);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HEAD {} failed: HTTP {}", url, response.status()));
+    }
+    // `content_length()` describes the (empty) body of a HEAD response, so read the headers
+    let header = |name: &str| response.headers().get(name)?.to_str().ok()?.parse().ok();
+    Ok(header("x-linked-size").or_else(|| header("content-length")))
+}
+
+/// Size of the file at `url` from a `HEAD` request (WASM version)
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn fetch_size(url: &str, token: Option<&str>) -> Result<Option<u64>, String> {
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{Request, RequestInit, Response};
+
+    let window = web_sys::window().ok_or("No window found")?;
+    let opts = RequestInit::new();
+    opts.set_method("HEAD");
+    let headers = request_headers(0, token)?;
+    opts.set_headers(&headers);
+    let request = Request::new_with_str_and_init(url, &opts).map_err(|_| "Failed to create request")?;
+    let response = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(|_| "Fetch failed")?
+        .dyn_into::<Response>()
+        .map_err(|_| "Not a response")?;
+    if !response.ok() {
+        return Err(format!("HEAD {} failed: HTTP {}", url, response.status()));
+    }
+    let headers = response.headers();
+    let header = |name: &str| headers.get(name).ok().flatten()?.parse().ok();
+    Ok(header("x-linked-size").or_else(|| header("content-length")))
+}
+
+/// LFS sha256 of the file at `url`, from a `HEAD` request that does not follow redirects
+#[cfg(not(target_arch = "wasm32"))]
+async fn fetch_lfs_sha256(_key: &CacheKey, url: &str, token: Option<&str>) -> Result<Option<String>, String> {
+    use reqwest::{redirect::Policy, Client};
+
+    let client = Client::builder().redirect(Policy::none()).build().map_err(|e| e.to_string())?;
+    let mut request = client.head(url);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok());
+    Ok(response_sha256(response.status().as_u16(), header("x-linked-etag"), header("etag")))
+}
+
+/// LFS sha256 of the file at `url`, from a `HEAD` request that does not follow redirects (WASM version)
+///
+/// Browsers hide the headers of a redirect, so when there is one the hash is
+/// looked up in the hub's tree API instead.
+#[cfg(target_arch = "wasm32")]
+async fn fetch_lfs_sha256(key: &CacheKey, url: &str, token: Option<&str>) -> Result<Option<String>, String> {
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{Request, RequestInit, RequestRedirect, Response, ResponseType};
+
+    let window = web_sys::window().ok_or("No window found")?;
+    let fetch = |url: &str, method: &str| -> Result<js_sys::Promise, String> {
+        let opts = RequestInit::new();
+        opts.set_method(method);
+        opts.set_redirect(RequestRedirect::Manual);
+        let headers = request_headers(0, token)?;
+        opts.set_headers(&headers);
+        let request = Request::new_with_str_and_init(url, &opts).map_err(|_| "Failed to create request")?;
+        Ok(window.fetch_with_request(&request))
+    };
+    let response = JsFuture::from(fetch(url, "HEAD")?)
+        .await
+        .map_err(|_| "Fetch failed")?
+        .dyn_into::<Response>()
+        .map_err(|_| "Not a response")?;
+    if response.type_() != ResponseType::Opaqueredirect {
+        let headers = response.headers();
+        let header = |name: &str| headers.get(name).ok().flatten();
+        return Ok(response_sha256(response.status(), header("x-linked-etag").as_deref(), header("etag").as_deref()));
+    }
+
+    let suffix = format!("/{}/resolve/{}/{}", key.repo, key.revision, key.file);
+    let Some(endpoint) = url.strip_suffix(&suffix) else {
+        return Ok(None);
+    };
+    let dir = key.file.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let api = format!("{}/api/models/{}/tree/{}/{}", endpoint, key.repo, key.revision, dir);
+    let response = JsFuture::from(fetch(&api, "GET")?)
+        .await
+        .map_err(|_| "Fetch failed")?
+        .dyn_into::<Response>()
+        .map_err(|_| "Not a response")?;
+    if !response.ok() {
+        return Ok(None);
+    }
+    let text = JsFuture::from(response.text().map_err(|_| "Failed to read response")?)
+        .await
+        .map_err(|_| "Failed to read response")?
+        .as_string()
+        .unwrap_or_default();
+    let entries: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap_or_default();
+    Ok(entries
+        .iter()
+        .find(|entry| entry["path"] == key.file.as_str())
+        .and_then(|entry| entry["lfs"]["oid"].as_str())
+        .and_then(lfs_sha256))
+}
+
+/// Request `url` starting at byte `offset`
+#[cfg(not(target_arch = "wasm32"))]
+async fn fetch_range(
+    url: &str,
+    offset: u64,
+    token: Option<&str>,
+) -> Result<RangeResponse<impl Stream<Item = Result<Vec<u8>, String>>>, String> {
+    use reqwest::{header, Client};
+
+    let mut request = Client::new().get(url);
+    if offset > 0 {
+        request = request.header(header::RANGE, format!("bytes={}-", offset));
+    }
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+
+    Ok(RangeResponse {
+        status: response.status().as_u16(),
+        content_length: response.content_length(),
+        body: response
+            .bytes_stream()
+            .map(|chunk| chunk.map(|bytes| bytes.to_vec()).map_err(|e| e.to_string())),
+    })
+}
+
+/// `Range` and `Authorization` headers for a fetch request
+#[cfg(target_arch = "wasm32")]
+fn request_headers(offset: u64, token: Option<&str>) -> Result<web_sys::Headers, String> {
+    let headers = web_sys::Headers::new().map_err(|_| "Failed to create headers")?;
+    if offset > 0 {
+        headers
+            .set("Range", &format!("bytes={}-", offset))
+            .map_err(|_| "Failed to set Range header")?;
+    }
+    if let Some(token) = token {
+        headers
+            .set("Authorization", &format!("Bearer {}", token))
+            .map_err(|_| "Failed to set Authorization header")?;
+    }
+    Ok(headers)
+}
+
+/// Request `url` starting at byte `offset` (WASM version)
+#[cfg(target_arch = "wasm32")]
+async fn fetch_range(
+    url: &str,
+    offset: u64,
+    token: Option<&str>,
+) -> Result<RangeResponse<impl Stream<Item = Result<Vec<u8>, String>>>, String> {
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{ReadableStreamDefaultReader, Request, RequestInit, Response};
+
+    let window = web_sys::window().ok_or("No window found")?;
+
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    let headers = request_headers(offset, token)?;
+    opts.set_headers(&headers);
+
+    let request = Request::new_with_str_and_init(url, &opts).map_err(|_| "Failed to create request")?;
+    let response = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(|_| "Fetch failed")?
+        .dyn_into::<Response>()
+        .map_err(|_| "Not a response")?;
+
+    let headers = response.headers();
+    let header = |name: &str| headers.get(name).ok().flatten();
+    let content_length = header("content-length").and_then(|v| v.parse().ok());
+
+    let reader: ReadableStreamDefaultReader = response
+        .body()
+        .ok_or("Response has no body")?
+        .get_reader()
+        .unchecked_into();
+    let body = futures::stream::unfold(Some(reader), |reader| async move {
+        let reader = reader?;
+        let result = match JsFuture::from(reader.read()).await {
+            Ok(result) => result,
+            Err(e) => return Some((Err(format!("{:?}", e)), None)),
+        };
+        let done = js_sys::Reflect::get(&result, &"done".into())
+            .ok()
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        if done {
+            return None;
+        }
+        let value = js_sys::Reflect::get(&result, &"value".into()).ok()?;
+        Some((Ok(js_sys::Uint8Array::new(&value).to_vec()), Some(reader)))
+    });
+
+    Ok(RangeResponse {
+        status: response.status(),
+        content_length,
+        body,
+    })
+}
+
+/// The cache used by [`super::download_model`] on this platform
+#[cfg(not(target_arch = "wasm32"))]
+pub type DefaultModelCache = FsModelCache;
+
+/// The cache used by [`super::download_model`] on this platform
+#[cfg(target_arch = "wasm32")]
+pub type DefaultModelCache = WebModelCache;
+
+/// Open the platform's default model cache
+pub fn default_cache() -> Result<DefaultModelCache, String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Ok(FsModelCache::default_location())
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        Ok(WebModelCache::new(WebModelCache::DEFAULT_NAME))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use fs::FsModelCache;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod fs {
+    use super::{verify_sha256, CacheKey, FileData, ModelCache};
+    use std::fs::{self, OpenOptions};
+    use std::io::{ErrorKind, Write};
+    use std::path::{Path, PathBuf};
+
+    /// Model cache in a directory on disk
+    ///
+    /// Partial downloads are kept next to their final path with an
+    /// `.incomplete` suffix. Committed files are memory-mapped rather than
+    /// read, so a cached model costs no heap memory.
+    #[derive(Debug, Clone)]
+    pub struct FsModelCache {
+        root: PathBuf,
+    }
+
+    impl FsModelCache {
+        pub fn new(root: impl Into<PathBuf>) -> Self {
+            Self { root: root.into() }
+        }
+
+        /// `$JARVIS_MODEL_CACHE`, else `$XDG_CACHE_HOME/jarvis/models`, else `~/.cache/jarvis/models`
+        pub fn default_location() -> Self {
+            let env = |name| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+            let root = env("JARVIS_MODEL_CACHE").unwrap_or_else(|| {
+                env("XDG_CACHE_HOME")
+                    .or_else(|| env("HOME").map(|home| home.join(".cache")))
+                    .unwrap_or_else(std::env::temp_dir)
+                    .join("jarvis")
+                    .join("models")
+            });
+            Self::new(root)
+        }
+
+        /// Cache root directory
+        pub fn root(&self) -> &Path {
+            &self.root
+        }
+
+        /// Path of a committed file
+        pub fn path(&self, key: &CacheKey) -> PathBuf {
+            self.root.join(key.relative_path())
+        }
+
+        fn partial_path(&self, key: &CacheKey) -> PathBuf {
+            let mut path = self.path(key).into_os_string();
+            path.push(".incomplete");
+            path.into()
+        }
+    }
+
+    fn remove_if_exists(path: &Path) -> Result<(), String> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(format!("Failed to remove {}: {}", path.display(), e)),
+            _ => Ok(()),
+        }
+    }
+
+    impl ModelCache for FsModelCache {
+        async fn get(&self, key: &CacheKey) -> Result<Option<FileData>, String> {
+            let path = self.path(key);
+            if !path.exists() {
+                return Ok(None);
+            }
+            FileData::map(&path).map(Some)
+        }
+
+        async fn cached_len(&self, key: &CacheKey) -> Result<Option<u64>, String> {
+            Ok(fs::metadata(self.path(key)).ok().map(|m| m.len()))
+        }
+
+        async fn partial_len(&self, key: &CacheKey) -> Result<u64, String> {
+            Ok(fs::metadata(self.partial_path(key)).map(|m| m.len()).unwrap_or(0))
+        }
+
+        async fn append_partial(&self, key: &CacheKey, bytes: &[u8]) -> Result<(), String> {
+            let path = self.partial_path(key);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(bytes))
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+        }
+
+        async fn discard_partial(&self, key: &CacheKey) -> Result<(), String> {
+            remove_if_exists(&self.partial_path(key))
+        }
+
+        async fn commit_partial(&self, key: &CacheKey, sha256: Option<&str>) -> Result<FileData, String> {
+            let partial = self.partial_path(key);
+            let data = FileData::map(&partial)?;
+            if let Err(e) = verify_sha256(key, &data, sha256) {
+                drop(data);
+                remove_if_exists(&partial)?;
+                return Err(e);
+            }
+
+            // The mapping stays valid when the file is renamed
+            let path = self.path(key);
+            fs::rename(&partial, &path).map_err(|e| format!("Failed to move {} into cache: {}", key, e))?;
+            Ok(data)
+        }
+
+        async fn remove(&self, key: &CacheKey) -> Result<(), String> {
+            remove_if_exists(&self.partial_path(key))?;
+            remove_if_exists(&self.path(key))
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use web::WebModelCache;
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use super::{verify_sha256, CacheKey, FileData, ModelCache};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{Cache, Headers, Response, ResponseInit};
+
+    /// Size of the segments a partial download is persisted in
+    const SEGMENT_BYTES: usize = 8 * 1024 * 1024;
+
+    /// Download progress of one file
+    #[derive(Default)]
+    struct Partial {
+        /// Lengths of the segments already stored in the Cache API
+        segments: Vec<usize>,
+        /// Bytes not yet stored
+        pending: Vec<u8>,
+    }
+
+    /// Model cache backed by the browser Cache API
+    ///
+    /// A partial download is stored as a list of segments plus a small JSON
+    /// manifest of their lengths, so resuming never rewrites earlier data.
+    pub struct WebModelCache {
+        name: String,
+        partials: RefCell<HashMap<CacheKey, Partial>>,
+    }
+
+    impl WebModelCache {
+        /// Cache storage name used by [`super::default_cache`]
+        pub const DEFAULT_NAME: &'static str = "jarvis-models";
+
+        pub fn new(name: impl Into<String>) -> Self {
+            Self {
+                name: name.into(),
+                partials: RefCell::new(HashMap::new()),
+            }
+        }
+
+        async fn open(&self) -> Result<Cache, String> {
+            let window = web_sys::window().ok_or("No window found")?;
+            let caches = window.caches().map_err(|_| "Cache API is not available")?;
+            JsFuture::from(caches.open(&self.name))
+                .await
+                .map_err(|_| "Failed to open cache")?
+                .dyn_into::<Cache>()
+                .map_err(|_| "Not a cache".to_string())
+        }
+
+        /// Load the partial state of a file, reading its manifest if not yet known
+        async fn load_partial(&self, cache: &Cache, key: &CacheKey) -> Result<(), String> {
+            if self.partials.borrow().contains_key(key) {
+                return Ok(());
+            }
+            let segments = match read_entry(cache, &manifest_url(key)).await? {
+                Some(json) => serde_json::from_slice(&json).map_err(|e| e.to_string())?,
+                None => Vec::new(),
+            };
+            self.partials.borrow_mut().insert(
+                key.clone(),
+                Partial {
+                    segments,
+                    pending: Vec::new(),
+                },
+            );
+            Ok(())
+        }
+
+        /// Store pending bytes as a new segment
+        async fn flush(&self, cache: &Cache, key: &CacheKey) -> Result<(), String> {
+            let (index, pending) = {
+                let mut partials = self.partials.borrow_mut();
+                let partial = partials.get_mut(key).ok_or("Unknown partial download")?;
+                (partial.segments.len(), std::mem::take(&mut partial.pending))
+            };
+            if pending.is_empty() {
+                return Ok(());
+            }
+            write_entry(cache, &segment_url(key, index), &pending).await?;
+
+            let manifest = {
+                let mut partials = self.partials.borrow_mut();
+                let partial = partials.get_mut(key).ok_or("Unknown partial download")?;
+                partial.segments.push(pending.len());
+                serde_json::to_vec(&partial.segments).map_err(|e| e.to_string())?
+            };
+            write_entry(cache, &manifest_url(key), &manifest).await
+        }
+
+        async fn delete_partial(&self, cache: &Cache, key: &CacheKey) -> Result<(), String> {
+            self.load_partial(cache, key).await?;
+            let segments = self.partials.borrow_mut().remove(key).map_or(0, |p| p.segments.len());
+            for index in 0..segments {
+                delete_entry(cache, &segment_url(key, index)).await?;
+            }
+            delete_entry(cache, &manifest_url(key)).await
+        }
+    }
+
+    fn entry_url(key: &CacheKey) -> String {
+        format!("/jarvis-model-cache/{}", key.relative_path())
+    }
+
+    fn manifest_url(key: &CacheKey) -> String {
+        format!("{}.incomplete", entry_url(key))
+    }
+
+    fn segment_url(key: &CacheKey, index: usize) -> String {
+        format!("{}.incomplete.{}", entry_url(key), index)
+    }
+
+    async fn match_entry(cache: &Cache, url: &str) -> Result<Option<Response>, String> {
+        let matched = JsFuture::from(cache.match_with_str(url))
+            .await
+            .map_err(|_| "Cache lookup failed")?;
+        if matched.is_undefined() {
+            return Ok(None);
+        }
+        matched.dyn_into().map(Some).map_err(|_| "Not a response".to_string())
+    }
+
+    async fn read_entry(cache: &Cache, url: &str) -> Result<Option<Vec<u8>>, String> {
+        let Some(response) = match_entry(cache, url).await? else {
+            return Ok(None);
+        };
+        let buffer = JsFuture::from(response.array_buffer().map_err(|_| "Failed to get array buffer")?)
+            .await
+            .map_err(|_| "Failed to read cached data")?;
+        Ok(Some(js_sys::Uint8Array::new(&buffer).to_vec()))
+    }
+
+    async fn write_entry(cache: &Cache, url: &str, bytes: &[u8]) -> Result<(), String> {
+        let body = js_sys::Uint8Array::from(bytes);
+        // Record the length so `cached_len` does not have to read the body
+        let headers = Headers::new().map_err(|_| "Failed to create headers")?;
+        headers
+            .set("content-length", &bytes.len().to_string())
+            .map_err(|_| "Failed to set content length")?;
+        let init = ResponseInit::new();
+        init.set_headers(&headers);
+        let response = Response::new_with_opt_buffer_source_and_init(Some(&body), &init)
+            .map_err(|_| "Failed to create response")?;
+        JsFuture::from(cache.put_with_str(url, &response))
+            .await
+            .map_err(|_| format!("Failed to write {} to cache (storage quota exceeded?)", url))?;
+        Ok(())
+    }
+
+    async fn delete_entry(cache: &Cache, url: &str) -> Result<(), String> {
+        JsFuture::from(cache.delete_with_str(url))
+            .await
+            .map_err(|_| format!("Failed to delete {} from cache", url))?;
+        Ok(())
+    }
+
+    impl ModelCache for WebModelCache {
+        async fn get(&self, key: &CacheKey) -> Result<Option<FileData>, String> {
+            Ok(read_entry(&self.open().await?, &entry_url(key)).await?.map(FileData::from))
+        }
+
+        async fn cached_len(&self, key: &CacheKey) -> Result<Option<u64>, String> {
+            let response = match_entry(&self.open().await?, &entry_url(key)).await?;
+            Ok(response.and_then(|r| r.headers().get("content-length").ok().flatten()?.parse().ok()))
+        }
+
+        async fn partial_len(&self, key: &CacheKey) -> Result<u64, String> {
+            self.load_partial(&self.open().await?, key).await?;
+            let partials = self.partials.borrow();
+            Ok(partials
+                .get(key)
+                .map_or(0, |p| (p.segments.iter().sum::<usize>() + p.pending.len()) as u64))
+        }
+
+        async fn append_partial(&self, key: &CacheKey, bytes: &[u8]) -> Result<(), String> {
+            let cache = self.open().await?;
+            self.load_partial(&cache, key).await?;
+            let full = {
+                let mut partials = self.partials.borrow_mut();
+                let partial = partials.get_mut(key).ok_or("Unknown partial download")?;
+                partial.pending.extend_from_slice(bytes);
+                partial.pending.len() >= SEGMENT_BYTES
+            };
+            if full {
+                self.flush(&cache, key).await?;
+            }
+            Ok(())
+        }
+
+        async fn discard_partial(&self, key: &CacheKey) -> Result<(), String> {
+            self.delete_partial(&self.open().await?, key).await
+        }
+
+        async fn commit_partial(&self, key: &CacheKey, sha256: Option<&str>) -> Result<FileData, String> {
+            let cache = self.open().await?;
+            self.load_partial(&cache, key).await?;
+            let (segments, pending) = {
+                let partials = self.partials.borrow();
+                let partial = partials.get(key).ok_or("Unknown partial download")?;
+                (partial.segments.len(), partial.pending.clone())
+            };
+
+            let mut data = Vec::new();
+            for index in 0..segments {
+                let segment = read_entry(&cache, &segment_url(key, index))
+                    .await?
+                    .ok_or_else(|| format!("Partial download of {} is missing segment {}", key, index))?;
+                data.extend_from_slice(&segment);
+            }
+            data.extend_from_slice(&pending);
+
+            let data = FileData::from(data);
+            let verified = verify_sha256(key, &data, sha256);
+            if verified.is_ok() {
+                write_entry(&cache, &entry_url(key), &data).await?;
+            }
+            self.delete_partial(&cache, key).await?;
+            verified.map(|_| data)
+        }
+
+        async fn remove(&self, key: &CacheKey) -> Result<(), String> {
+            let cache = self.open().await?;
+            self.delete_partial(&cache, key).await?;
+            delete_entry(&cache, &entry_url(key)).await
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::models::test_server::{TestFile, TestServer};
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    fn temp_cache() -> (FsModelCache, PathBuf) {
+        let root = std::env::temp_dir().join(format!("jarvis-cache-test-{}", uuid::Uuid::new_v4()));
+        (FsModelCache::new(&root), root)
+    }
+
+    fn fixture() -> Vec<u8> {
+        (0..3 * FLUSH_BYTES / 2).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn key() -> CacheKey {
+        CacheKey::new("openai/whisper-tiny.en", "main", "model.safetensors")
+    }
+
+    #[test]
+    fn test_cache_key_layout() {
+        let key = key();
+        assert_eq!(key.relative_path(), "models--openai--whisper-tiny.en/main/model.safetensors");
+        assert_eq!(
+            key.url("https://huggingface.co/"),
+            "https://huggingface.co/openai/whisper-tiny.en/resolve/main/model.safetensors"
+        );
+    }
+
+    #[test]
+    fn test_lfs_sha256_from_etag() {
+        let sha = "A".repeat(64);
+        assert_eq!(lfs_sha256(&format!("\"{}\"", sha)), Some("a".repeat(64)));
+        assert_eq!(lfs_sha256(&format!("W/\"{}\"", sha)), Some("a".repeat(64)));
+        // Git blob sha1 of a non-LFS file
+        assert_eq!(lfs_sha256("\"0123456789abcdef0123456789abcdef01234567\""), None);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_download_then_serve_from_cache() {
+        let body = fixture();
+        let server = TestServer::single(TestFile::new(body.clone()).with_lfs_hash());
+        let url = server.url_of("/file");
+        let (cache, root) = temp_cache();
+
+        let progress = Mutex::new(Vec::new());
+        let data = cached_download(&cache, &key(), &url, None, |p| progress.lock().unwrap().push(p.loaded_bytes))
+            .await
+            .unwrap();
+        assert_eq!(&*data, &body[..]);
+        assert_eq!(progress.lock().unwrap().last(), Some(&(body.len() as u64)));
+        assert_eq!(cache.cached_len(&key()).await.unwrap(), Some(body.len() as u64));
+
+        // Second download is served from disk without another request
+        let again = cached_download(&cache, &key(), &url, None, |_| {}).await.unwrap();
+        assert_eq!(&*again, &body[..]);
+        assert_eq!(server.gets().len(), 1);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_interrupted_download_resumes_with_range() {
+        let body = fixture();
+        let cut = FLUSH_BYTES + 1000;
+        let server = TestServer::single(TestFile {
+            cut_after: Some(cut),
+            ..TestFile::new(body.clone()).with_lfs_hash()
+        });
+        let url = server.url_of("/file");
+        let (cache, root) = temp_cache();
+
+        let err = cached_download(&cache, &key(), &url, None, |_| {}).await.unwrap_err();
+        assert!(err.contains("interrupted"), "{}", err);
+        assert_eq!(cache.partial_len(&key()).await.unwrap(), cut as u64);
+        assert!(cache.get(&key()).await.unwrap().is_none());
+
+        let progress = Mutex::new(Vec::new());
+        let data = cached_download(&cache, &key(), &url, None, |p| progress.lock().unwrap().push(p))
+            .await
+            .unwrap();
+        assert_eq!(&*data, &body[..]);
+        let ranges: Vec<_> = server.gets().iter().map(|r| r.range_start).collect();
+        assert_eq!(ranges, vec![None, Some(cut as u64)]);
+        let last = progress.lock().unwrap().last().cloned().unwrap();
+        assert_eq!((last.loaded_bytes, last.total_bytes), (body.len() as u64, body.len() as u64));
+        assert_eq!(cache.partial_len(&key()).await.unwrap(), 0);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_checksum_mismatch_is_rejected() {
+        let server = TestServer::single(TestFile {
+            sha256: Some("0".repeat(64)),
+            ..TestFile::new(fixture())
+        });
+        let (cache, root) = temp_cache();
+
+        let err = cached_download(&cache, &key(), &server.url_of("/file"), None, |_| {}).await.unwrap_err();
+        assert!(err.contains("Checksum mismatch"), "{}", err);
+        assert!(cache.get(&key()).await.unwrap().is_none());
+        assert_eq!(cache.partial_len(&key()).await.unwrap(), 0);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_lfs_hash_is_read_from_redirect() {
+        let body = fixture();
+        let server = TestServer::single(TestFile::new(body.clone()).with_lfs_hash().behind_cdn());
+        let (cache, root) = temp_cache();
+        let data = cached_download(&cache, &key(), &server.url_of("/file"), None, |_| {}).await.unwrap();
+        assert_eq!(&*data, &body[..]);
+        let paths: Vec<_> = server.gets().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, vec!["/file", "/cdn/file"]);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // The hash on the redirect is checked, not skipped
+        let server = TestServer::single(TestFile {
+            sha256: Some("0".repeat(64)),
+            ..TestFile::new(body).behind_cdn()
+        });
+        let err = cached_download(&cache, &key(), &server.url_of("/file"), None, |_| {}).await.unwrap_err();
+        assert!(err.contains("Checksum mismatch"), "{}", err);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
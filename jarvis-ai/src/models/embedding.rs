@@ -0,0 +1,534 @@
+//! Sentence embedding model implementation using Burn
+//!
+//! A BERT/MiniLM-style encoder turns text into a fixed-size vector: token
+//! states are mean-pooled over the non-padding positions and L2-normalized,
+//! so the dot product of two embeddings is their cosine similarity.
+
+use super::weights::WeightMap;
+use burn::prelude::*;
+use burn::tensor::activation::{gelu, softmax};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use log;
+
+/// Configuration for the text embedding model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextEmbeddingConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub num_layers: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    /// Longest input in tokens; longer texts are truncated
+    pub max_position_embeddings: usize,
+}
+
+impl TextEmbeddingConfig {
+    /// all-MiniLM-L6-v2 configuration (384-dimensional embeddings)
+    pub fn all_minilm_l6_v2() -> Self {
+        Self {
+            vocab_size: 30522,
+            hidden_size: 384,
+            num_layers: 6,
+            num_attention_heads: 12,
+            intermediate_size: 1536,
+            max_position_embeddings: 512,
+        }
+    }
+}
+
+enum Vocab {
+    WordPiece(HashMap<String, u32>),
+    /// No vocabulary available; words are hashed into the id range
+    Hashed(u32),
+}
+
+/// BERT WordPiece tokenizer
+pub struct WordPieceTokenizer {
+    vocab: Vocab,
+    pad: u32,
+    unk: u32,
+    cls: u32,
+    sep: u32,
+}
+
+impl WordPieceTokenizer {
+    /// Load the vocabulary from a HuggingFace `tokenizer.json`
+    pub fn from_json(json: &[u8]) -> Result<Self, String> {
+        let json: serde_json::Value = serde_json::from_slice(json).map_err(|e| format!("Invalid tokenizer.json: {}", e))?;
+        let vocab: HashMap<String, u32> = json
+            .pointer("/model/vocab")
+            .and_then(|vocab| serde_json::from_value(vocab.clone()).ok())
+            .ok_or("tokenizer.json has no WordPiece vocabulary")?;
+        let id = |token: &str, default: u32| vocab.get(token).copied().unwrap_or(default);
+        Ok(Self {
+            pad: id("[PAD]", 0),
+            unk: id("[UNK]", 100),
+            cls: id("[CLS]", 101),
+            sep: id("[SEP]", 102),
+            vocab: Vocab::WordPiece(vocab),
+        })
+    }
+
+    /// A tokenizer that hashes words into `vocab_size` ids, for models without a vocabulary
+    pub fn hashed(vocab_size: usize) -> Self {
+        Self {
+            vocab: Vocab::Hashed(vocab_size as u32),
+            pad: 0,
+            unk: 1,
+            cls: 2,
+            sep: 3,
+        }
+    }
+
+    /// Id used to pad a batch to the same length
+    pub fn pad_id(&self) -> u32 {
+        self.pad
+    }
+
+    /// Token ids for `text`, wrapped in `[CLS]`/`[SEP]` and at most `max_len` long
+    pub fn encode(&self, text: &str, max_len: usize) -> Vec<u32> {
+        let mut ids = vec![self.cls];
+        for word in split_words(&text.to_lowercase()) {
+            match &self.vocab {
+                Vocab::WordPiece(vocab) => ids.extend(self.word_pieces(vocab, word)),
+                Vocab::Hashed(size) => ids.push(4 + fnv1a(word) % size.saturating_sub(4).max(1)),
+            }
+        }
+        ids.truncate(max_len.saturating_sub(1).max(1));
+        ids.push(self.sep);
+        ids
+    }
+
+    /// Split a word into the longest pieces in the vocabulary, left to right
+    fn word_pieces(&self, vocab: &HashMap<String, u32>, word: &str) -> Vec<u32> {
+        let chars: Vec<char> = word.chars().collect();
+        if chars.len() > 100 {
+            return vec![self.unk];
+        }
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            let piece = (start + 1..=chars.len()).rev().find_map(|end| {
+                let piece: String = chars[start..end].iter().collect();
+                let piece = if start > 0 { format!("##{}", piece) } else { piece };
+                vocab.get(&piece).map(|id| (*id, end))
+            });
+            match piece {
+                Some((id, end)) => {
+                    pieces.push(id);
+                    start = end;
+                }
+                None => return vec![self.unk],
+            }
+        }
+        pieces
+    }
+}
+
+/// Split on whitespace, with each punctuation character a word of its own
+fn split_words(text: &str) -> impl Iterator<Item = &str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        let is_punctuation = !c.is_alphanumeric() && !c.is_whitespace();
+        if c.is_whitespace() || is_punctuation {
+            if let Some(s) = start.take() {
+                words.push(&text[s..i]);
+            }
+            if is_punctuation {
+                words.push(&text[i..i + c.len_utf8()]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        words.push(&text[s..]);
+    }
+    words.into_iter()
+}
+
+/// 32-bit FNV-1a hash, stable across platforms and Rust versions
+fn fnv1a(text: &str) -> u32 {
+    text.bytes()
+        .fold(0x811c_9dc5, |hash: u32, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+/// LayerNorm epsilon of BERT checkpoints
+const LAYER_NORM_EPS: f32 = 1e-12;
+/// Additive attention bias that hides padding keys
+const MASKED: f32 = -1e9;
+
+/// A linear layer; `weight` is `[out_features, in_features]`
+struct Linear<B: Backend> {
+    weight: Tensor<B, 2>,
+    bias: Tensor<B, 1>,
+}
+
+impl<B: Backend> Linear<B> {
+    /// `input · weightᵀ + bias` over the last dimension
+    fn forward(&self, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        let [batch, len, in_features] = input.dims();
+        let out_features = self.weight.dims()[0];
+        let output = input.reshape([batch * len, in_features]).matmul(self.weight.clone().transpose());
+        (output + self.bias.clone().reshape([1, out_features])).reshape([batch, len, out_features])
+    }
+}
+
+struct Norm<B: Backend> {
+    gamma: Tensor<B, 1>,
+    beta: Tensor<B, 1>,
+}
+
+impl<B: Backend> Norm<B> {
+    fn forward(&self, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        let hidden = self.gamma.dims()[0];
+        let centered = input.clone() - input.mean_dim(2);
+        let std = (centered.clone().powf_scalar(2.0).mean_dim(2) + LAYER_NORM_EPS).sqrt();
+        centered / std * self.gamma.clone().reshape([1, 1, hidden]) + self.beta.clone().reshape([1, 1, hidden])
+    }
+}
+
+/// Parameters of one post-norm encoder layer
+struct EncoderLayer<B: Backend> {
+    query: Linear<B>,
+    key: Linear<B>,
+    value: Linear<B>,
+    attention_output: Linear<B>,
+    attention_norm: Norm<B>,
+    intermediate: Linear<B>,
+    output: Linear<B>,
+    output_norm: Norm<B>,
+}
+
+/// Text embedding model implementation
+///
+/// Parameters are a BERT encoder's, in the HuggingFace layout.
+pub struct TextEmbeddingModel<B: Backend> {
+    config: TextEmbeddingConfig,
+    /// `[vocab_size, hidden_size]`
+    word_embeddings: Tensor<B, 2>,
+    /// `[max_position_embeddings, hidden_size]`
+    position_embeddings: Tensor<B, 2>,
+    /// Embedding of token type 0, `[1, hidden_size]`, added to every token
+    token_type_embedding: Option<Tensor<B, 2>>,
+    embeddings_norm: Norm<B>,
+    layers: Vec<EncoderLayer<B>>,
+}
+
+impl<B: Backend> TextEmbeddingModel<B> {
+    /// Create a model from HuggingFace BERT weights (`embeddings.*` and
+    /// `encoder.layer.N.*`, optionally under `bert.`)
+    pub fn from_weights(config: &TextEmbeddingConfig, weights: &WeightMap, device: &B::Device) -> Result<Self, String> {
+        let hidden = config.hidden_size;
+        if config.num_attention_heads == 0 || !hidden.is_multiple_of(config.num_attention_heads) {
+            return Err(format!(
+                "Hidden size {} does not split into {} heads",
+                hidden, config.num_attention_heads
+            ));
+        }
+        let prefix = if weights.contains("bert.embeddings.word_embeddings.weight") { "bert." } else { "" };
+        let tensor = |name: &str, dims: &[usize]| -> Result<Tensor<B, 2>, String> {
+            let name = format!("{}{}", prefix, name);
+            match weights.shape(&name) {
+                Some(shape) if shape != dims => Err(format!("Tensor {} has shape {:?}, expected {:?}", name, shape, dims)),
+                _ => weights.tensor(&name, device),
+            }
+        };
+        let vector = |name: &str, size: usize| -> Result<Tensor<B, 1>, String> {
+            let name = format!("{}{}", prefix, name);
+            match weights.shape(&name) {
+                Some(shape) if shape != [size] => Err(format!("Tensor {} has shape {:?}, expected [{}]", name, shape, size)),
+                _ => weights.tensor(&name, device),
+            }
+        };
+        let linear = |name: &str, out_features: usize, in_features: usize| -> Result<Linear<B>, String> {
+            Ok(Linear {
+                weight: tensor(&format!("{}.weight", name), &[out_features, in_features])?,
+                bias: vector(&format!("{}.bias", name), out_features)?,
+            })
+        };
+        let norm = |name: &str| -> Result<Norm<B>, String> {
+            Ok(Norm {
+                gamma: vector(&format!("{}.weight", name), hidden)?,
+                beta: vector(&format!("{}.bias", name), hidden)?,
+            })
+        };
+
+        let token_type_embedding = match weights.shape(&format!("{}embeddings.token_type_embeddings.weight", prefix)) {
+            Some(&[types, _]) => Some(tensor("embeddings.token_type_embeddings.weight", &[types, hidden])?.narrow(0, 0, 1)),
+            _ => None,
+        };
+        let mut layers = Vec::with_capacity(config.num_layers);
+        for i in 0..config.num_layers {
+            let name = |module: &str| format!("encoder.layer.{}.{}", i, module);
+            layers.push(EncoderLayer {
+                query: linear(&name("attention.self.query"), hidden, hidden)?,
+                key: linear(&name("attention.self.key"), hidden, hidden)?,
+                value: linear(&name("attention.self.value"), hidden, hidden)?,
+                attention_output: linear(&name("attention.output.dense"), hidden, hidden)?,
+                attention_norm: norm(&name("attention.output.LayerNorm"))?,
+                intermediate: linear(&name("intermediate.dense"), config.intermediate_size, hidden)?,
+                output: linear(&name("output.dense"), hidden, config.intermediate_size)?,
+                output_norm: norm(&name("output.LayerNorm"))?,
+            });
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            word_embeddings: tensor("embeddings.word_embeddings.weight", &[config.vocab_size, hidden])?,
+            position_embeddings: tensor(
+                "embeddings.position_embeddings.weight",
+                &[config.max_position_embeddings, hidden],
+            )?,
+            token_type_embedding,
+            embeddings_norm: norm("embeddings.LayerNorm")?,
+            layers,
+        })
+    }
+
+    /// Get the device the model runs on
+    pub fn device(&self) -> B::Device {
+        self.word_embeddings.device()
+    }
+
+    /// Encode token ids into per-token hidden states
+    ///
+    /// # Arguments
+    /// * `input_ids` - `[batch, seq_len]`
+    /// * `attention_mask` - `[batch, seq_len]`, 1 for tokens and 0 for padding
+    ///
+    /// # Returns
+    /// `[batch, seq_len, hidden_size]`
+    pub fn forward(&self, input_ids: Tensor<B, 2, Int>, attention_mask: Tensor<B, 2>) -> Tensor<B, 3> {
+        let [batch, len] = input_ids.dims();
+        let hidden = self.config.hidden_size;
+        let heads = self.config.num_attention_heads;
+        let head_dim = hidden / heads;
+
+        let words = self.word_embeddings.clone().select(0, input_ids.reshape([batch * len]));
+        let positions = self.position_embeddings.clone().narrow(0, 0, len);
+        let mut x = words.reshape([batch, len, hidden]) + positions.unsqueeze_dim::<3>(0);
+        if let Some(token_type) = &self.token_type_embedding {
+            x = x + token_type.clone().unsqueeze_dim::<3>(0);
+        }
+        x = self.embeddings_norm.forward(x);
+
+        // Padding keys are never attended to
+        let bias = (attention_mask.ones_like() - attention_mask).reshape([batch, 1, 1, len]) * MASKED;
+        let scale = (head_dim as f32).sqrt();
+        for layer in &self.layers {
+            let split = |t: Tensor<B, 3>| t.reshape([batch, len, heads, head_dim]).swap_dims(1, 2);
+            let q = split(layer.query.forward(x.clone()));
+            let k = split(layer.key.forward(x.clone()));
+            let v = split(layer.value.forward(x.clone()));
+            let scores = q.matmul(k.swap_dims(2, 3)) / scale + bias.clone();
+            let attention = softmax(scores, 3).matmul(v).swap_dims(1, 2).reshape([batch, len, hidden]);
+            x = layer.attention_norm.forward(x + layer.attention_output.forward(attention));
+
+            let h = gelu(layer.intermediate.forward(x.clone()));
+            x = layer.output_norm.forward(x + layer.output.forward(h));
+        }
+        x
+    }
+
+    /// Embed a batch of token id sequences, padding them to the same length
+    ///
+    /// # Returns
+    /// `[batch, hidden_size]` L2-normalized sentence embeddings
+    pub fn embed(&self, token_ids: &[Vec<u32>], pad_id: u32) -> Tensor<B, 2> {
+        let batch = token_ids.len();
+        let seq_len = token_ids.iter().map(Vec::len).max().unwrap_or(0).max(1);
+        let mut ids = Vec::with_capacity(batch * seq_len);
+        let mut mask = Vec::with_capacity(batch * seq_len);
+        for tokens in token_ids {
+            ids.extend(tokens.iter().map(|&id| id as i64));
+            ids.extend(std::iter::repeat_n(pad_id as i64, seq_len - tokens.len()));
+            mask.extend(std::iter::repeat_n(1.0f32, tokens.len()));
+            mask.extend(std::iter::repeat_n(0.0f32, seq_len - tokens.len()));
+        }
+
+        let device = self.device();
+        let input_ids = Tensor::from_data(TensorData::new(ids, [batch, seq_len]), &device);
+        let attention_mask = Tensor::from_data(TensorData::new(mask, [batch, seq_len]), &device);
+        let hidden = self.forward(input_ids, attention_mask.clone());
+        l2_normalize(mean_pool(hidden, attention_mask))
+    }
+}
+
+/// Average token states over the positions where `attention_mask` is 1
+pub fn mean_pool<B: Backend>(hidden: Tensor<B, 3>, attention_mask: Tensor<B, 2>) -> Tensor<B, 2> {
+    let [batch, _, hidden_size] = hidden.dims();
+    let mask = attention_mask.unsqueeze_dim::<3>(2);
+    let summed = (hidden * mask.clone()).sum_dim(1).reshape([batch, hidden_size]);
+    let counts = mask.sum_dim(1).reshape([batch, 1]).clamp_min(1e-9);
+    summed / counts
+}
+
+/// Scale each row to unit length
+pub fn l2_normalize<B: Backend>(embeddings: Tensor<B, 2>) -> Tensor<B, 2> {
+    let norms = embeddings.clone().powf_scalar(2.0).sum_dim(1).sqrt().clamp_min(1e-12);
+    embeddings / norms
+}
+
+/// Create a text embedding model from BERT weights
+///
+/// An encoder without weights would produce meaningless embeddings, so
+/// missing weights are an error.
+pub fn create_embedding_model<B: Backend>(
+    config: &TextEmbeddingConfig,
+    weights: &WeightMap,
+    device: &B::Device,
+) -> Result<TextEmbeddingModel<B>, String> {
+    if weights.is_empty() {
+        return Err("Embedding models need their weights".to_string());
+    }
+    log::info!(
+        "Loading embedding model weights: {} tensors, {} parameters",
+        weights.len(),
+        weights.parameter_count()
+    );
+    TextEmbeddingModel::from_weights(config, weights, device)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+    use safetensors::tensor::TensorView;
+    use safetensors::Dtype;
+
+    pub(crate) fn tiny_config() -> TextEmbeddingConfig {
+        TextEmbeddingConfig {
+            vocab_size: 64,
+            hidden_size: 16,
+            num_layers: 2,
+            num_attention_heads: 2,
+            intermediate_size: 32,
+            max_position_embeddings: 16,
+        }
+    }
+
+    /// A BERT checkpoint with pseudo-random parameters, its tensor names under `prefix`
+    pub(crate) fn bert_weights(config: &TextEmbeddingConfig, prefix: &str) -> Vec<u8> {
+        let hidden = config.hidden_size;
+        let mut shapes = vec![
+            ("embeddings.word_embeddings.weight".to_string(), vec![config.vocab_size, hidden]),
+            ("embeddings.position_embeddings.weight".to_string(), vec![config.max_position_embeddings, hidden]),
+            ("embeddings.token_type_embeddings.weight".to_string(), vec![2, hidden]),
+            ("embeddings.LayerNorm.weight".to_string(), vec![hidden]),
+            ("embeddings.LayerNorm.bias".to_string(), vec![hidden]),
+        ];
+        for i in 0..config.num_layers {
+            let name = |module: &str| format!("encoder.layer.{}.{}", i, module);
+            for (module, out_features, in_features) in [
+                ("attention.self.query", hidden, hidden),
+                ("attention.self.key", hidden, hidden),
+                ("attention.self.value", hidden, hidden),
+                ("attention.output.dense", hidden, hidden),
+                ("intermediate.dense", config.intermediate_size, hidden),
+                ("output.dense", hidden, config.intermediate_size),
+            ] {
+                shapes.push((name(&format!("{}.weight", module)), vec![out_features, in_features]));
+                shapes.push((name(&format!("{}.bias", module)), vec![out_features]));
+            }
+            for module in ["attention.output.LayerNorm", "output.LayerNorm"] {
+                shapes.push((name(&format!("{}.weight", module)), vec![hidden]));
+                shapes.push((name(&format!("{}.bias", module)), vec![hidden]));
+            }
+        }
+
+        let mut state = 1u32;
+        let data: Vec<Vec<u8>> = shapes
+            .iter()
+            .map(|(name, shape)| {
+                let len: usize = shape.iter().product();
+                let value = |_| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    if name.ends_with("LayerNorm.weight") { 1.0 } else { (state >> 8) as f32 / (1 << 24) as f32 - 0.5 }
+                };
+                (0..len).map(value).flat_map(f32::to_le_bytes).collect()
+            })
+            .collect();
+        let names: Vec<String> = shapes.iter().map(|(name, _)| format!("{}{}", prefix, name)).collect();
+        let views = names
+            .iter()
+            .zip(&shapes)
+            .zip(&data)
+            .map(|((name, (_, shape)), data)| (name.as_str(), TensorView::new(Dtype::F32, shape.clone(), data).unwrap()));
+        safetensors::serialize(views, &None).unwrap()
+    }
+
+    fn model(weights: &[u8]) -> Result<TextEmbeddingModel<NdArray<f32>>, String> {
+        create_embedding_model(&tiny_config(), &WeightMap::from_shards(&[weights])?, &Default::default())
+    }
+
+    #[test]
+    fn test_word_piece_tokenizer() {
+        let json = r###"{"model": {"type": "WordPiece", "vocab": {"[PAD]": 0, "[UNK]": 1, "[CLS]": 2, "[SEP]": 3,
+            "meet": 4, "##ing": 5, "at": 6, "noon": 7, ".": 8}}}"###;
+        let tokenizer = WordPieceTokenizer::from_json(json.as_bytes()).unwrap();
+        assert_eq!(tokenizer.encode("Meeting at noon.", 16), vec![2, 4, 5, 6, 7, 8, 3]);
+        assert_eq!(tokenizer.encode("meeting xyz", 16), vec![2, 4, 5, 1, 3]);
+        assert_eq!(tokenizer.encode("meeting at noon", 3), vec![2, 4, 3]);
+        assert!(WordPieceTokenizer::from_json(b"{}").is_err());
+
+        let hashed = WordPieceTokenizer::hashed(64);
+        let ids = hashed.encode("Lunch, lunch", 16);
+        assert_eq!(ids.len(), 5);
+        assert_eq!(ids[1], ids[3]);
+        assert!(ids.iter().all(|&id| id < 64));
+    }
+
+    #[test]
+    fn test_pooling_ignores_padding() {
+        let device = Default::default();
+        let hidden = Tensor::<NdArray<f32>, 3>::from_floats([[[1.0, 2.0], [3.0, 4.0], [100.0, 100.0]]], &device);
+        let mask = Tensor::<NdArray<f32>, 2>::from_floats([[1.0, 1.0, 0.0]], &device);
+        let pooled = mean_pool(hidden, mask);
+        assert_eq!(pooled.to_data().to_vec::<f32>().unwrap(), vec![2.0, 3.0]);
+
+        let normalized = l2_normalize(Tensor::<NdArray<f32>, 2>::from_floats([[3.0, 4.0]], &device));
+        assert_eq!(normalized.to_data().to_vec::<f32>().unwrap(), vec![0.6, 0.8]);
+    }
+
+    #[test]
+    fn test_embeddings_are_normalized_and_batch_independent() {
+        let model = model(&bert_weights(&tiny_config(), "")).unwrap();
+        let tokenizer = WordPieceTokenizer::hashed(64);
+        let short = tokenizer.encode("hello", 16);
+        let long = tokenizer.encode("a much longer sentence about lunch", 16);
+
+        let batch = model.embed(&[short.clone(), long], tokenizer.pad_id());
+        assert_eq!(batch.dims(), [2, 16]);
+        let norms = batch.clone().powf_scalar(2.0).sum_dim(1).to_data().to_vec::<f32>().unwrap();
+        assert!(norms.iter().all(|n| (n - 1.0).abs() < 1e-4));
+
+        // Padding a sequence does not change its embedding
+        let alone = model.embed(&[short], tokenizer.pad_id()).to_data().to_vec::<f32>().unwrap();
+        let padded = batch.slice([0..1, 0..16]).to_data().to_vec::<f32>().unwrap();
+        assert!(alone.iter().zip(&padded).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_embedding_weights() {
+        let config = tiny_config();
+        let ids = vec![WordPieceTokenizer::hashed(64).encode("weights from a checkpoint", 16)];
+        let embed = |model: TextEmbeddingModel<NdArray<f32>>| model.embed(&ids, 0).to_data().to_vec::<f32>().unwrap();
+
+        // Tensors of a BertModel checkpoint carry a `bert.` prefix
+        let plain = embed(model(&bert_weights(&config, "")).unwrap());
+        let prefixed = embed(model(&bert_weights(&config, "bert.")).unwrap());
+        assert_eq!(plain, prefixed);
+
+        let missing = create_embedding_model::<NdArray<f32>>(&config, &WeightMap::default(), &Default::default());
+        assert_eq!(missing.err().unwrap(), "Embedding models need their weights");
+        let other = TextEmbeddingConfig { hidden_size: 8, ..config.clone() };
+        let err = model(&bert_weights(&other, "")).err().unwrap();
+        assert!(err.contains("has shape"), "{}", err);
+        let fewer_layers = TextEmbeddingConfig { num_layers: 1, ..config };
+        let err = model(&bert_weights(&fewer_layers, "")).err().unwrap();
+        assert!(err.contains("Missing tensor encoder.layer.1"), "{}", err);
+    }
+}
@@ -0,0 +1,494 @@
+//! GGUF model files, as written by llama.cpp
+//!
+//! Only the metadata and the tensor directory are parsed; tensor data stays
+//! in the file until [`super::WeightMap`] decodes it. Llama tensors are
+//! renamed to their HuggingFace names, and the rows of the query and key
+//! projections, which llama.cpp interleaves for its rotary embedding, are
+//! put back in HuggingFace order when they are read.
+
+use super::LlmConfig;
+use std::collections::HashMap;
+
+/// GGUF files start with this magic number
+pub const MAGIC: &[u8] = b"GGUF";
+/// Alignment of the tensor data unless `general.alignment` says otherwise
+const DEFAULT_ALIGNMENT: usize = 32;
+/// Values per Q8_0 block, stored as an f16 scale followed by one signed byte each
+pub const Q8_0_BLOCK: usize = 32;
+
+/// A metadata value
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataValue {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    Array(Vec<MetadataValue>),
+}
+
+impl MetadataValue {
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Self::Int(v) => usize::try_from(*v).ok(),
+            Self::UInt(v) => usize::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Float(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[MetadataValue]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Storage type of a tensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TensorType {
+    F32,
+    F16,
+    BF16,
+    Q8_0,
+    /// A GGML type that cannot be decoded, by its id
+    Other(u32),
+}
+
+impl TensorType {
+    fn from_id(id: u32) -> Self {
+        match id {
+            0 => Self::F32,
+            1 => Self::F16,
+            8 => Self::Q8_0,
+            30 => Self::BF16,
+            other => Self::Other(other),
+        }
+    }
+
+    /// Bytes taken by `count` values, if the type is supported and `count` fits its blocks
+    fn byte_len(self, count: usize) -> Option<usize> {
+        match self {
+            Self::F32 => Some(count * 4),
+            Self::F16 | Self::BF16 => Some(count * 2),
+            Self::Q8_0 if count.is_multiple_of(Q8_0_BLOCK) => Some(count / Q8_0_BLOCK * (2 + Q8_0_BLOCK)),
+            _ => None,
+        }
+    }
+}
+
+/// A tensor in a GGUF file
+pub struct GgufTensor<'a> {
+    /// HuggingFace name for Llama tensors, otherwise the name in the file
+    pub name: String,
+    /// Row-major shape; GGUF lists dimensions innermost first
+    pub shape: Vec<usize>,
+    pub kind: TensorType,
+    /// Encoded values; empty for types that cannot be decoded
+    pub data: &'a [u8],
+    /// Query or key heads whose rows llama.cpp interleaved
+    pub interleaved_heads: Option<usize>,
+}
+
+/// Metadata and tensors of a GGUF file
+pub struct GgufFile<'a> {
+    pub metadata: HashMap<String, MetadataValue>,
+    pub tensors: Vec<GgufTensor<'a>>,
+}
+
+impl GgufFile<'_> {
+    fn arch_usize(&self, key: &str) -> Option<usize> {
+        self.arch_value(key)?.as_usize()
+    }
+
+    fn arch_f64(&self, key: &str) -> Option<f64> {
+        self.arch_value(key)?.as_f64()
+    }
+
+    /// Metadata value under `general.architecture`'s prefix, e.g. `llama.block_count`
+    fn arch_value(&self, key: &str) -> Option<&MetadataValue> {
+        let arch = self.metadata.get("general.architecture")?.as_str()?;
+        self.metadata.get(&format!("{}.{}", arch, key))
+    }
+
+    /// Hyperparameters of a Llama model
+    pub fn llm_config(&self) -> Result<LlmConfig, String> {
+        let arch = self.metadata.get("general.architecture").and_then(MetadataValue::as_str).unwrap_or_default();
+        if arch != "llama" {
+            return Err(format!("GGUF architecture {:?} is not supported; only llama is", arch));
+        }
+        let field = |key: &str| self.arch_usize(key).ok_or_else(|| format!("GGUF metadata is missing {}.{}", arch, key));
+        let tokens = self.metadata.get("tokenizer.ggml.tokens").and_then(MetadataValue::as_array).map(<[_]>::len);
+        Ok(LlmConfig {
+            vocab_size: self.arch_usize("vocab_size").or(tokens).ok_or("GGUF metadata has no vocabulary size")?,
+            hidden_size: field("embedding_length")?,
+            num_layers: field("block_count")?,
+            num_attention_heads: field("attention.head_count")?,
+            intermediate_size: field("feed_forward_length")?,
+            max_position_embeddings: field("context_length")?,
+            partial_rotary_factor: 1.0,
+            rope_theta: self.arch_f64("rope.freq_base").unwrap_or(10000.0) as f32,
+        })
+    }
+}
+
+/// HuggingFace name of a llama.cpp Llama tensor
+fn hf_name(name: &str) -> Option<String> {
+    let top = match name {
+        "token_embd.weight" => Some("model.embed_tokens.weight"),
+        "output_norm.weight" => Some("model.norm.weight"),
+        "output.weight" => Some("lm_head.weight"),
+        _ => None,
+    };
+    if let Some(top) = top {
+        return Some(top.to_string());
+    }
+    let (layer, module) = name.strip_prefix("blk.")?.split_once('.')?;
+    let module = match module.strip_suffix(".weight")? {
+        "attn_norm" => "input_layernorm",
+        "attn_q" => "self_attn.q_proj",
+        "attn_k" => "self_attn.k_proj",
+        "attn_v" => "self_attn.v_proj",
+        "attn_output" => "self_attn.o_proj",
+        "ffn_norm" => "post_attention_layernorm",
+        "ffn_gate" => "mlp.gate_proj",
+        "ffn_up" => "mlp.up_proj",
+        "ffn_down" => "mlp.down_proj",
+        _ => return None,
+    };
+    Some(format!("model.layers.{}.{}.weight", layer, module))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or("Invalid GGUF file: unexpected end of header")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap_or([0; N]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        usize::try_from(self.u64()?).map_err(|_| "Invalid GGUF file: length out of range".to_string())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "Invalid GGUF file: string is not UTF-8".to_string())
+    }
+
+    fn value(&mut self, kind: u32) -> Result<MetadataValue, String> {
+        Ok(match kind {
+            0 => MetadataValue::UInt(self.array::<1>()?[0] as u64),
+            1 => MetadataValue::Int(i8::from_le_bytes(self.array()?) as i64),
+            2 => MetadataValue::UInt(u16::from_le_bytes(self.array()?) as u64),
+            3 => MetadataValue::Int(i16::from_le_bytes(self.array()?) as i64),
+            4 => MetadataValue::UInt(self.u32()? as u64),
+            5 => MetadataValue::Int(i32::from_le_bytes(self.array()?) as i64),
+            6 => MetadataValue::Float(f32::from_le_bytes(self.array()?) as f64),
+            7 => MetadataValue::Bool(self.array::<1>()?[0] != 0),
+            8 => MetadataValue::String(self.string()?),
+            9 => {
+                let kind = self.u32()?;
+                let len = self.len()?;
+                // Every element takes at least a byte, which bounds a corrupt length
+                if len > self.bytes.len() - self.pos {
+                    return Err("Invalid GGUF file: array longer than the file".to_string());
+                }
+                MetadataValue::Array((0..len).map(|_| self.value(kind)).collect::<Result<_, _>>()?)
+            }
+            10 => MetadataValue::UInt(self.u64()?),
+            11 => MetadataValue::Int(i64::from_le_bytes(self.array()?)),
+            12 => MetadataValue::Float(f64::from_le_bytes(self.array()?)),
+            other => return Err(format!("Invalid GGUF file: unknown metadata type {}", other)),
+        })
+    }
+}
+
+/// Parse the header of a GGUF file (version 2 or 3)
+pub fn parse(bytes: &[u8]) -> Result<GgufFile<'_>, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(4)? != MAGIC {
+        return Err("Not a GGUF file".to_string());
+    }
+    let version = reader.u32()?;
+    if !(2..=3).contains(&version) {
+        return Err(format!("GGUF version {} is not supported", version));
+    }
+    let tensor_count = reader.len()?;
+    let metadata_count = reader.len()?;
+
+    let mut metadata = HashMap::new();
+    for _ in 0..metadata_count {
+        let key = reader.string()?;
+        let kind = reader.u32()?;
+        metadata.insert(key, reader.value(kind)?);
+    }
+
+    let mut infos = Vec::new();
+    for _ in 0..tensor_count.min(bytes.len()) {
+        let name = reader.string()?;
+        let dims = reader.u32()? as usize;
+        let mut shape = (0..dims).map(|_| reader.len()).collect::<Result<Vec<_>, _>>()?;
+        shape.reverse();
+        let kind = TensorType::from_id(reader.u32()?);
+        let offset = reader.len()?;
+        infos.push((name, shape, kind, offset));
+    }
+
+    let alignment = metadata.get("general.alignment").and_then(MetadataValue::as_usize).unwrap_or(DEFAULT_ALIGNMENT);
+    let start = reader.pos.next_multiple_of(alignment.max(1));
+    let llama = metadata.get("general.architecture").and_then(MetadataValue::as_str) == Some("llama");
+    let heads = |key: &str| metadata.get(&format!("llama.attention.{}", key)).and_then(MetadataValue::as_usize);
+    let (q_heads, kv_heads) = (heads("head_count"), heads("head_count_kv").or(heads("head_count")));
+
+    let mut tensors = Vec::with_capacity(infos.len());
+    for (name, shape, kind, offset) in infos {
+        let data = match kind.byte_len(shape.iter().product()) {
+            Some(len) => start
+                .checked_add(offset)
+                .and_then(|begin| bytes.get(begin..begin.checked_add(len)?))
+                .ok_or_else(|| format!("Invalid GGUF file: data of tensor {} is out of bounds", name))?,
+            None => &[],
+        };
+        let renamed = if llama { hf_name(&name) } else { None };
+        let interleaved_heads = match renamed.as_deref() {
+            Some(n) if n.ends_with("self_attn.q_proj.weight") => q_heads,
+            Some(n) if n.ends_with("self_attn.k_proj.weight") => kv_heads,
+            _ => None,
+        };
+        tensors.push(GgufTensor {
+            name: renamed.unwrap_or(name),
+            shape,
+            kind,
+            data,
+            interleaved_heads,
+        });
+    }
+    Ok(GgufFile { metadata, tensors })
+}
+
+/// Put rows interleaved by llama.cpp back in HuggingFace order
+///
+/// llama.cpp stores each head's rotary pairs next to each other, where
+/// HuggingFace keeps the first halves of the pairs before the second halves.
+pub fn deinterleave_rows(values: &[f32], heads: usize, cols: usize) -> Vec<f32> {
+    let rows = values.len() / cols.max(1);
+    let head_dim = rows / heads.max(1);
+    let half = head_dim / 2;
+    let mut out = vec![0.0; values.len()];
+    for row in 0..rows {
+        let (head, within) = (row / head_dim, row % head_dim);
+        let (pair, side) = (within / 2, within % 2);
+        let target = head * head_dim + side * half + pair;
+        out[target * cols..(target + 1) * cols].copy_from_slice(&values[row * cols..(row + 1) * cols]);
+    }
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Encode a GGUF file; tensors are `(name, row-major shape, GGML type id, data)`
+    pub(crate) fn gguf_bytes(metadata: &[(&str, MetadataValue)], tensors: &[(&str, Vec<usize>, u32, Vec<u8>)]) -> Vec<u8> {
+        fn string(out: &mut Vec<u8>, s: &str) {
+            out.extend((s.len() as u64).to_le_bytes());
+            out.extend(s.as_bytes());
+        }
+        fn value(out: &mut Vec<u8>, v: &MetadataValue) {
+            match v {
+                MetadataValue::Int(v) => out.extend(v.to_le_bytes()),
+                MetadataValue::UInt(v) => out.extend(v.to_le_bytes()),
+                MetadataValue::Float(v) => out.extend(v.to_le_bytes()),
+                MetadataValue::Bool(v) => out.push(*v as u8),
+                MetadataValue::String(s) => string(out, s),
+                MetadataValue::Array(values) => {
+                    out.extend(values.first().map_or(8, kind).to_le_bytes());
+                    out.extend((values.len() as u64).to_le_bytes());
+                    values.iter().for_each(|v| value(out, v));
+                }
+            }
+        }
+        fn kind(value: &MetadataValue) -> u32 {
+            match value {
+                MetadataValue::Int(_) => 11,
+                MetadataValue::UInt(_) => 10,
+                MetadataValue::Float(_) => 12,
+                MetadataValue::Bool(_) => 7,
+                MetadataValue::String(_) => 8,
+                MetadataValue::Array(_) => 9,
+            }
+        }
+
+        let mut out = MAGIC.to_vec();
+        out.extend(3u32.to_le_bytes());
+        out.extend((tensors.len() as u64).to_le_bytes());
+        out.extend((metadata.len() as u64).to_le_bytes());
+        for (key, v) in metadata {
+            string(&mut out, key);
+            out.extend(kind(v).to_le_bytes());
+            value(&mut out, v);
+        }
+        let mut offset = 0;
+        let mut data = Vec::new();
+        for (name, shape, kind, bytes) in tensors {
+            string(&mut out, name);
+            out.extend((shape.len() as u32).to_le_bytes());
+            shape.iter().rev().for_each(|d| out.extend((*d as u64).to_le_bytes()));
+            out.extend(kind.to_le_bytes());
+            out.extend((offset as u64).to_le_bytes());
+            data.extend(bytes);
+            data.resize(data.len().next_multiple_of(DEFAULT_ALIGNMENT), 0);
+            offset = data.len();
+        }
+        out.resize(out.len().next_multiple_of(DEFAULT_ALIGNMENT), 0);
+        out.extend(data);
+        out
+    }
+
+    /// Convert a HuggingFace Llama checkpoint to F32 GGUF the way llama.cpp
+    /// does: tensors renamed, query and key rows interleaved, config in the metadata
+    pub(crate) fn llama_gguf(config: &LlmConfig, kv_heads: usize, safetensors: &[u8]) -> Vec<u8> {
+        let modules = ["attn_norm", "attn_q", "attn_k", "attn_v", "attn_output", "ffn_norm", "ffn_gate", "ffn_up", "ffn_down"];
+        let mut names: Vec<String> = ["token_embd", "output_norm", "output"].iter().map(|n| format!("{}.weight", n)).collect();
+        for i in 0..config.num_layers {
+            names.extend(modules.iter().map(|m| format!("blk.{}.{}.weight", i, m)));
+        }
+        let shard = safetensors::SafeTensors::deserialize(safetensors).unwrap();
+        let tensors: Vec<_> = names
+            .iter()
+            .filter_map(|name| {
+                let view = shard.tensor(&hf_name(name).unwrap()).ok()?;
+                let mut data = view.data().to_vec();
+                let heads = match &name[name.len().saturating_sub(13)..] {
+                    "attn_q.weight" => Some(config.num_attention_heads),
+                    "attn_k.weight" => Some(kv_heads),
+                    _ => None,
+                };
+                if let Some(heads) = heads {
+                    let row = view.shape()[1] * 4;
+                    let head_dim = view.shape()[0] / heads;
+                    for (i, chunk) in data.chunks_mut(row).enumerate() {
+                        let (head, within) = (i / head_dim, i % head_dim);
+                        let source = head * head_dim + (within % 2) * head_dim / 2 + within / 2;
+                        chunk.copy_from_slice(&view.data()[source * row..(source + 1) * row]);
+                    }
+                }
+                Some((name.as_str(), view.shape().to_vec(), 0, data))
+            })
+            .collect();
+        let uint = |v: usize| MetadataValue::UInt(v as u64);
+        // The ASCII vocabulary, spelled the SentencePiece way
+        let tokens = std::iter::once("</s>".to_string())
+            .chain((' '..='~').map(|c| if c == ' ' { "▁".to_string() } else { c.to_string() }))
+            .take(config.vocab_size)
+            .map(MetadataValue::String);
+        let types = (0..config.vocab_size).map(|id| MetadataValue::Int(if id == 0 { 3 } else { 1 }));
+        let metadata = [
+            ("tokenizer.ggml.model", MetadataValue::String("llama".to_string())),
+            ("tokenizer.ggml.tokens", MetadataValue::Array(tokens.collect())),
+            ("tokenizer.ggml.token_type", MetadataValue::Array(types.collect())),
+            ("tokenizer.ggml.eos_token_id", uint(0)),
+            ("general.architecture", MetadataValue::String("llama".to_string())),
+            ("llama.vocab_size", uint(config.vocab_size)),
+            ("llama.embedding_length", uint(config.hidden_size)),
+            ("llama.block_count", uint(config.num_layers)),
+            ("llama.attention.head_count", uint(config.num_attention_heads)),
+            ("llama.attention.head_count_kv", uint(kv_heads)),
+            ("llama.feed_forward_length", uint(config.intermediate_size)),
+            ("llama.context_length", uint(config.max_position_embeddings)),
+        ];
+        gguf_bytes(&metadata, &tensors)
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let metadata = [
+            ("general.architecture", MetadataValue::String("llama".to_string())),
+            ("llama.attention.head_count", MetadataValue::UInt(2)),
+            ("tokenizer.ggml.tokens", MetadataValue::Array(vec![MetadataValue::String("a".to_string()); 3])),
+        ];
+        let embed: Vec<u8> = (0..6).flat_map(|v| (v as f32).to_le_bytes()).collect();
+        let tensors = [
+            ("token_embd.weight", vec![3, 2], 0, embed.clone()),
+            ("blk.0.attn_q.weight", vec![4, 1], 1, vec![0; 8]),
+            ("blk.0.attn_q.bias", vec![4], 2, Vec::new()),
+        ];
+        let bytes = gguf_bytes(&metadata, &tensors);
+        let file = parse(&bytes).unwrap();
+        assert_eq!(file.metadata["llama.attention.head_count"].as_usize(), Some(2));
+        assert_eq!(file.tensors[0].name, "model.embed_tokens.weight");
+        assert_eq!(file.tensors[0].shape, [3, 2]);
+        assert_eq!(file.tensors[0].data, &embed[..]);
+        assert_eq!(file.tensors[1].name, "model.layers.0.self_attn.q_proj.weight");
+        assert_eq!(file.tensors[1].interleaved_heads, Some(2));
+        // Unknown tensors keep their names, and Q4_0 cannot be decoded
+        assert_eq!(file.tensors[2].name, "blk.0.attn_q.bias");
+        assert_eq!(file.tensors[2].kind, TensorType::Other(2));
+        assert!(file.llm_config().unwrap_err().contains("embedding_length"));
+
+        assert!(parse(b"GGUF\x01\x00\x00\x00").err().unwrap().contains("version 1"));
+        assert!(parse(&bytes[..bytes.len() - 30]).err().unwrap().contains("out of bounds"));
+    }
+
+    #[test]
+    fn test_llama_from_gguf() {
+        use crate::models::llm::tests::{assert_close, llama_config, llama_weights};
+        use crate::models::{LlmModel, WeightMap};
+        use burn::prelude::*;
+        type B = burn_ndarray::NdArray<f32>;
+
+        let config = llama_config();
+        let device = Default::default();
+        let safetensors = llama_weights(&config, 1, false);
+        let gguf = llama_gguf(&config, 1, &safetensors);
+        assert_eq!(parse(&gguf).unwrap().llm_config().unwrap(), config);
+
+        let logits = |shard: &[u8]| {
+            let model = LlmModel::<B>::from_weights(&config, &WeightMap::from_shards(&[shard]).unwrap(), &device).unwrap();
+            let ids = Tensor::<B, 2>::from_data(TensorData::new(vec![1.0, 5.0, 9.0], [1, 3]), &device);
+            model.forward(ids).into_data().to_vec::<f32>().unwrap()
+        };
+        assert_close(&logits(&gguf), &logits(&safetensors));
+    }
+
+    #[test]
+    fn test_deinterleave_rows() {
+        // One head of 4 rows: llama.cpp order is (0, 2, 1, 3) of the HuggingFace rows
+        let rows = [0.0, 2.0, 1.0, 3.0, 10.0, 12.0, 11.0, 13.0];
+        assert_eq!(deinterleave_rows(&rows, 2, 1), [0.0, 1.0, 2.0, 3.0, 10.0, 11.0, 12.0, 13.0]);
+    }
+}
@@ -0,0 +1,482 @@
+//! Multi-file model downloads from a HuggingFace-style hub
+//!
+//! A model is more than its weights: it also needs `config.json`,
+//! `tokenizer.json` and, for large checkpoints, several safetensors shards
+//! listed in a `*.safetensors.index.json`. This module resolves a model's
+//! files and downloads them through a [`ModelCache`] with combined progress.
+
+use super::cache::{cached_download, fetch_size, CacheKey, FileData, ModelCache};
+use super::{LoadProgress, HF_ENDPOINT};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Files that make up a model in its repository
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelManifest {
+    /// `model.safetensors`, or a `*.safetensors.index.json` for sharded checkpoints
+    pub weights: String,
+    /// Config, tokenizer and other small files
+    pub files: Vec<String>,
+}
+
+impl ModelManifest {
+    /// A checkpoint stored as a single `model.safetensors`
+    pub fn single(files: &[&str]) -> Self {
+        Self {
+            weights: "model.safetensors".to_string(),
+            files: files.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// A checkpoint split into shards listed in `model.safetensors.index.json`
+    pub fn sharded(files: &[&str]) -> Self {
+        Self {
+            weights: "model.safetensors.index.json".to_string(),
+            ..Self::single(files)
+        }
+    }
+
+    /// Check whether the weights are sharded
+    pub fn is_sharded(&self) -> bool {
+        self.weights.ends_with(".index.json")
+    }
+}
+
+impl Default for ModelManifest {
+    /// A single `model.safetensors` with `config.json` and `tokenizer.json`
+    fn default() -> Self {
+        Self::single(&["config.json", "tokenizer.json"])
+    }
+}
+
+/// Options for downloading models
+///
+/// Missing fields take their defaults when deserialized, so stored settings
+/// keep working as options are added.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// Branch, tag or commit hash to download; pin a commit for reproducible builds
+    pub revision: String,
+    /// Base URL of the HuggingFace-compatible hub tried first
+    pub endpoint: String,
+    /// Fallback hubs, tried in order when the endpoint fails
+    pub mirrors: Vec<String>,
+    /// Bearer token for gated or private repositories, sent to the endpoint
+    pub token: Option<String>,
+    /// Also send the token to the mirrors; off by default so a third-party
+    /// mirror never sees it
+    pub token_for_mirrors: bool,
+}
+
+impl DownloadConfig {
+    /// Endpoint followed by mirrors, in the order they are tried
+    pub fn endpoints(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.endpoint.as_str())
+            .chain(self.mirrors.iter().map(String::as_str))
+            .filter(|endpoint| !endpoint.trim().is_empty())
+    }
+
+    /// Endpoints in the order they are tried, each with the token to send it
+    fn targets(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        let token = self.token.as_deref().filter(|token| !token.is_empty());
+        let mirror_token = token.filter(|_| self.token_for_mirrors);
+        std::iter::once((self.endpoint.as_str(), token))
+            .chain(self.mirrors.iter().map(move |mirror| (mirror.as_str(), mirror_token)))
+            .filter(|(endpoint, _)| !endpoint.trim().is_empty())
+    }
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            revision: "main".to_string(),
+            endpoint: HF_ENDPOINT.to_string(),
+            mirrors: Vec::new(),
+            token: None,
+            token_for_mirrors: false,
+        }
+    }
+}
+
+// Keep the access token out of logs
+impl fmt::Debug for DownloadConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DownloadConfig")
+            .field("revision", &self.revision)
+            .field("endpoint", &self.endpoint)
+            .field("mirrors", &self.mirrors)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("token_for_mirrors", &self.token_for_mirrors)
+            .finish()
+    }
+}
+
+/// Contents of a model's files, downloaded or read from the device
+#[derive(Debug, Clone, Default)]
+pub struct ModelFiles {
+    /// Revision the files were downloaded from, or `local`
+    pub revision: String,
+    files: HashMap<String, FileData>,
+    /// Safetensors or GGUF files holding the weights, in shard order
+    weights: Vec<String>,
+}
+
+impl ModelFiles {
+    pub(crate) fn new(revision: &str, files: HashMap<String, FileData>, weights: Vec<String>) -> Self {
+        Self {
+            revision: revision.to_string(),
+            files,
+            weights,
+        }
+    }
+
+    /// Get a file by its path in the repository
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.files.get(name).map(|data| &**data)
+    }
+
+    /// Safetensors data of each weight shard, in order
+    pub fn weight_shards(&self) -> Vec<&[u8]> {
+        self.weights.iter().filter_map(|name| self.get(name)).collect()
+    }
+
+    /// Weight shards, in order
+    pub fn weight_data(&self) -> Vec<&FileData> {
+        self.weights.iter().filter_map(|name| self.files.get(name)).collect()
+    }
+
+    /// Names of the weight shards, in order
+    pub fn weight_files(&self) -> &[String] {
+        &self.weights
+    }
+
+    /// Total size of all files in bytes
+    pub fn total_bytes(&self) -> u64 {
+        self.files.values().map(|data| data.len() as u64).sum()
+    }
+}
+
+/// `model.safetensors.index.json`
+#[derive(Deserialize)]
+struct ShardIndex {
+    weight_map: HashMap<String, String>,
+}
+
+/// List the shard files referenced by a safetensors index, in order
+pub fn parse_shard_index(json: &[u8]) -> Result<Vec<String>, String> {
+    let index: ShardIndex = serde_json::from_slice(json).map_err(|e| format!("Invalid shard index: {}", e))?;
+    let shards: BTreeSet<String> = index.weight_map.into_values().collect();
+    if shards.is_empty() {
+        return Err("Shard index lists no weight files".to_string());
+    }
+    Ok(shards.into_iter().collect())
+}
+
+/// Download one file, falling back through the configured mirrors
+async fn download_file<C: ModelCache>(
+    cache: &C,
+    config: &DownloadConfig,
+    key: &CacheKey,
+    on_progress: impl Fn(LoadProgress),
+) -> Result<FileData, String> {
+    let mut errors = Vec::new();
+    for (endpoint, token) in config.targets() {
+        match cached_download(cache, key, &key.url(endpoint), token, &on_progress).await {
+            Ok(data) => return Ok(data),
+            Err(e) => {
+                warn!("{}", e);
+                errors.push(e);
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Err("No model hub endpoint configured".to_string());
+    }
+    Err(errors.join("; "))
+}
+
+/// Size of a file from the cache, or from the first mirror that reports it
+async fn file_size<C: ModelCache>(cache: &C, config: &DownloadConfig, key: &CacheKey) -> Result<u64, String> {
+    if let Some(size) = cache.cached_len(key).await? {
+        return Ok(size);
+    }
+    for (endpoint, token) in config.targets() {
+        if let Ok(Some(size)) = fetch_size(&key.url(endpoint), token).await {
+            return Ok(size);
+        }
+    }
+    Ok(0)
+}
+
+/// Download all files of a model through a cache
+///
+/// For sharded checkpoints the index is fetched first to find the shards.
+/// Progress covers every file: sizes come from the cache or a `HEAD`
+/// request before downloading starts, so `total_bytes` is known up front.
+/// Each file is tried on the configured endpoint, then on each mirror; the
+/// access token only goes to mirrors if `token_for_mirrors` is set.
+///
+/// # Arguments
+/// * `cache` - Cache to read from and write to
+/// * `config` - Revision, endpoints and access token
+/// * `repo` - Repository id
+/// * `manifest` - Files to download
+/// * `on_progress` - Called with combined progress across all files
+pub async fn download_files<C: ModelCache>(
+    cache: &C,
+    config: &DownloadConfig,
+    repo: &str,
+    manifest: &ModelManifest,
+    on_progress: impl Fn(LoadProgress),
+) -> Result<ModelFiles, String> {
+    let revision = config.revision.as_str();
+    let key = |file: &str| CacheKey::new(repo, revision, file);
+    let mut files = HashMap::new();
+
+    let weights = if manifest.is_sharded() {
+        let index = download_file(cache, config, &key(&manifest.weights), |_| {}).await?;
+        let shards = parse_shard_index(&index)?;
+        files.insert(manifest.weights.clone(), index);
+        shards
+    } else {
+        vec![manifest.weights.clone()]
+    };
+
+    let names: Vec<String> = manifest.files.iter().chain(&weights).cloned().collect();
+    let mut sizes = Vec::with_capacity(names.len());
+    for name in &names {
+        sizes.push(file_size(cache, config, &key(name)).await?);
+    }
+    let expected_total: u64 = sizes.iter().sum();
+
+    let mut completed_bytes = 0u64;
+    for (i, name) in names.iter().enumerate() {
+        let data = download_file(cache, config, &key(name), |progress| {
+            let loaded = completed_bytes + progress.loaded_bytes;
+            let current_total = progress.total_bytes.max(sizes[i]);
+            let total = expected_total - sizes[i] + current_total;
+            on_progress(LoadProgress::new(loaded, total.max(loaded)).with_file(name, i, names.len()));
+        })
+        .await?;
+        completed_bytes += data.len() as u64;
+        files.insert(name.clone(), data);
+    }
+
+    on_progress(LoadProgress {
+        files_completed: names.len(),
+        files_total: names.len(),
+        ..LoadProgress::new(completed_bytes, completed_bytes)
+    });
+
+    Ok(ModelFiles::new(revision, files, weights))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::models::cache::FsModelCache;
+    use crate::models::test_server::{TestFile, TestServer};
+    use std::sync::Mutex;
+
+    const REPO: &str = "microsoft/phi-2";
+
+    fn shard_index() -> Vec<u8> {
+        serde_json::json!({
+            "metadata": {"total_size": 300},
+            "weight_map": {
+                "lm_head.weight": "model-00002-of-00002.safetensors",
+                "model.embed_tokens.weight": "model-00001-of-00002.safetensors",
+                "model.layers.0.mlp.fc1.weight": "model-00001-of-00002.safetensors",
+            }
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn path(revision: &str, file: &str) -> String {
+        format!("/{}/resolve/{}/{}", REPO, revision, file)
+    }
+
+    fn config(endpoint: &str, revision: &str) -> DownloadConfig {
+        DownloadConfig {
+            revision: revision.to_string(),
+            endpoint: endpoint.to_string(),
+            ..DownloadConfig::default()
+        }
+    }
+
+    /// URL of a port nothing is listening on
+    fn dead_endpoint() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    fn temp_cache() -> (FsModelCache, std::path::PathBuf) {
+        let root = std::env::temp_dir().join(format!("jarvis-hub-test-{}", uuid::Uuid::new_v4()));
+        (FsModelCache::new(&root), root)
+    }
+
+    #[test]
+    fn test_parse_shard_index() {
+        let shards = parse_shard_index(&shard_index()).unwrap();
+        assert_eq!(shards, vec!["model-00001-of-00002.safetensors", "model-00002-of-00002.safetensors"]);
+        assert!(parse_shard_index(b"{\"weight_map\": {}}").is_err());
+        assert!(parse_shard_index(b"not json").is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_download_sharded_model_at_pinned_revision() {
+        let revision = "a1b2c3d";
+        let server = TestServer::start(HashMap::from([
+            (path(revision, "config.json"), TestFile::new(b"{}".to_vec())),
+            (path(revision, "tokenizer.json"), TestFile::new(vec![b't'; 50])),
+            (path(revision, "model.safetensors.index.json"), TestFile::new(shard_index())),
+            (path(revision, "model-00001-of-00002.safetensors"), TestFile::new(vec![1u8; 200]).with_lfs_hash()),
+            (path(revision, "model-00002-of-00002.safetensors"), TestFile::new(vec![2u8; 100]).with_lfs_hash()),
+        ]));
+        let (cache, root) = temp_cache();
+        let manifest = ModelManifest::sharded(&["config.json", "tokenizer.json"]);
+
+        let progress = Mutex::new(Vec::new());
+        let files = download_files(&cache, &config(&server.url, revision), REPO, &manifest, |p| {
+            progress.lock().unwrap().push(p)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(files.revision, revision);
+        assert_eq!(files.get("config.json"), Some(&b"{}"[..]));
+        assert_eq!(files.weight_shards(), vec![&[1u8; 200][..], &[2u8; 100][..]]);
+
+        // Every request went to the pinned revision
+        assert!(server.received().iter().all(|r| r.path.contains("/resolve/a1b2c3d/")));
+
+        // Totals are known up front and progress only moves forward
+        let progress = progress.into_inner().unwrap();
+        assert!(progress.iter().all(|p| p.total_bytes == 352));
+        assert!(progress.windows(2).all(|w| w[0].loaded_bytes <= w[1].loaded_bytes));
+        let last = progress.last().unwrap();
+        assert_eq!((last.loaded_bytes, last.files_completed, last.files_total), (352, 4, 4));
+
+        // A second download is served entirely from the cache
+        let before = server.received().len();
+        download_files(&cache, &config(&server.url, revision), REPO, &manifest, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(server.received().len(), before);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_missing_file_fails_download() {
+        let server = TestServer::start(HashMap::from([(
+            path("main", "model.safetensors"),
+            TestFile::new(vec![0u8; 10]),
+        )]));
+        let (cache, root) = temp_cache();
+        let manifest = ModelManifest::single(&["config.json"]);
+
+        let err = download_files(&cache, &config(&server.url, "main"), REPO, &manifest, |_| {})
+            .await
+            .unwrap_err();
+        assert!(err.contains("HTTP 404"), "{}", err);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_falls_back_to_mirrors_in_order() {
+        let files = || {
+            HashMap::from([
+                (path("main", "config.json"), TestFile::new(b"{}".to_vec())),
+                (path("main", "model.safetensors"), TestFile::new(vec![7u8; 64]).with_lfs_hash()),
+            ])
+        };
+        // The first mirror is reachable but lacks the weights
+        let partial_mirror = TestServer::start(HashMap::from([(path("main", "config.json"), TestFile::new(b"{}".to_vec()))]));
+        let full_mirror = TestServer::start(files());
+        let unused_mirror = TestServer::start(files());
+        let (cache, root) = temp_cache();
+
+        let config = DownloadConfig {
+            mirrors: vec![partial_mirror.url.clone(), full_mirror.url.clone(), unused_mirror.url.clone()],
+            ..config(&dead_endpoint(), "main")
+        };
+        let files = download_files(&cache, &config, REPO, &ModelManifest::single(&["config.json"]), |_| {})
+            .await
+            .unwrap();
+
+        assert_eq!(files.weight_shards(), vec![&[7u8; 64][..]]);
+        assert_eq!(partial_mirror.gets().len(), 2);
+        assert_eq!(full_mirror.gets().len(), 1);
+        assert!(unused_mirror.received().is_empty());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_gated_repository_requires_token() {
+        let server = TestServer::start_with_token(
+            HashMap::from([(path("main", "model.safetensors"), TestFile::new(vec![1u8; 16]))]),
+            Some("hf_secret"),
+        );
+        let (cache, root) = temp_cache();
+        let manifest = ModelManifest::single(&[]);
+
+        let err = download_files(&cache, &config(&server.url, "main"), REPO, &manifest, |_| {})
+            .await
+            .unwrap_err();
+        assert!(err.contains("HTTP 401"), "{}", err);
+
+        let config = DownloadConfig {
+            token: Some("hf_secret".to_string()),
+            ..config(&server.url, "main")
+        };
+        download_files(&cache, &config, REPO, &manifest, |_| {}).await.unwrap();
+        assert_eq!(server.gets().last().unwrap().authorization.as_deref(), Some("Bearer hf_secret"));
+
+        // The token never shows up in logs
+        assert!(!format!("{:?}", config).contains("hf_secret"));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_token_stays_with_endpoint_unless_mirrors_opted_in() {
+        let mirror = TestServer::start_with_token(
+            HashMap::from([(path("main", "model.safetensors"), TestFile::new(vec![1u8; 16]))]),
+            Some("hf_secret"),
+        );
+        let (cache, root) = temp_cache();
+        let manifest = ModelManifest::single(&[]);
+        let config = DownloadConfig {
+            token: Some("hf_secret".to_string()),
+            mirrors: vec![mirror.url.clone()],
+            ..config(&dead_endpoint(), "main")
+        };
+
+        let err = download_files(&cache, &config, REPO, &manifest, |_| {}).await.unwrap_err();
+        assert!(err.contains("HTTP 401"), "{}", err);
+        assert!(mirror.received().iter().all(|r| r.authorization.is_none()));
+
+        let config = DownloadConfig { token_for_mirrors: true, ..config };
+        download_files(&cache, &config, REPO, &manifest, |_| {}).await.unwrap();
+        assert_eq!(mirror.gets().last().unwrap().authorization.as_deref(), Some("Bearer hf_secret"));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_download_config_defaults_when_deserialized() {
+        let config: DownloadConfig = serde_json::from_str(r#"{"mirrors": ["https://hf-mirror.example"]}"#).unwrap();
+        assert_eq!(config.revision, "main");
+        assert_eq!(
+            config.endpoints().collect::<Vec<_>>(),
+            vec![HF_ENDPOINT, "https://hf-mirror.example"]
+        );
+    }
+}
@@ -1,10 +1,17 @@
 //! LLM model implementation using Burn
 
+use super::lora::LoraAdapter;
+use super::weights::WeightMap;
+use crate::grammar::GrammarSampler;
 use burn::prelude::*;
+use burn::tensor::activation::{silu, softmax};
+use std::f32::consts::PI;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use log;
 
 /// Configuration for LLM model
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LlmConfig {
     pub vocab_size: usize,
     pub hidden_size: usize,
@@ -12,18 +19,34 @@ pub struct LlmConfig {
     pub num_attention_heads: usize,
     pub intermediate_size: usize,
     pub max_position_embeddings: usize,
+    /// Fraction of each attention head's dimensions that get rotary position embeddings
+    #[serde(default = "full_rotary")]
+    pub partial_rotary_factor: f32,
+    /// Base of the rotary position embedding frequencies
+    #[serde(default = "default_rope_theta")]
+    pub rope_theta: f32,
+}
+
+fn full_rotary() -> f32 {
+    1.0
+}
+
+fn default_rope_theta() -> f32 {
+    10000.0
 }
 
 impl LlmConfig {
     /// Phi-2 model configuration
     pub fn phi_2() -> Self {
         Self {
-            vocab_size: 50257,
+            vocab_size: 51200,
             hidden_size: 2560,
             num_layers: 32,
             num_attention_heads: 32,
             intermediate_size: 10240,
             max_position_embeddings: 2048,
+            partial_rotary_factor: 0.4,
+            rope_theta: 10000.0,
         }
     }
 
@@ -36,40 +59,610 @@ impl LlmConfig {
             num_attention_heads: 32,
             intermediate_size: 5632,
             max_position_embeddings: 2048,
+            partial_rotary_factor: 1.0,
+            rope_theta: 10000.0,
+        }
+    }
+}
+
+/// RMSNorm epsilon of Llama-family checkpoints
+const RMS_NORM_EPS: f32 = 1e-5;
+/// LayerNorm epsilon of Phi checkpoints
+const LAYER_NORM_EPS: f32 = 1e-5;
+/// Added to attention scores of positions that must not be attended to
+const MASKED: f32 = -1e9;
+
+/// Decoder layouts whose HuggingFace checkpoints can be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    /// RMSNorm, a SiLU-gated MLP after attention, no biases
+    Llama,
+    /// LayerNorm, attention and a GELU MLP in parallel, biases, partial rotary embeddings
+    Phi,
+}
+
+impl Family {
+    /// Module of the attention output projection
+    fn output_proj(self) -> &'static str {
+        match self {
+            Self::Llama => "self_attn.o_proj",
+            Self::Phi => "self_attn.dense",
+        }
+    }
+}
+
+/// RMSNorm in Llama, LayerNorm with a bias in Phi
+enum Norm<B: Backend> {
+    Rms(Tensor<B, 1>),
+    Layer(Tensor<B, 1>, Tensor<B, 1>),
+}
+
+impl<B: Backend> Norm<B> {
+    fn forward(&self, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        match self {
+            Self::Rms(gamma) => rms_norm(input, gamma),
+            Self::Layer(gamma, beta) => layer_norm(input, gamma, beta),
+        }
+    }
+}
+
+/// Feed-forward block of a decoder layer
+enum Mlp<B: Backend> {
+    /// Llama's SiLU-gated MLP
+    Gated {
+        gate_proj: Tensor<B, 2>,
+        up_proj: Tensor<B, 2>,
+        down_proj: Tensor<B, 2>,
+    },
+    /// Phi's GELU MLP, with biases
+    Gelu {
+        fc1: Tensor<B, 2>,
+        fc1_bias: Tensor<B, 1>,
+        fc2: Tensor<B, 2>,
+        fc2_bias: Tensor<B, 1>,
+    },
+}
+
+/// Parameters of one decoder layer; projections are `[out_features, in_features]`
+struct DecoderLayer<B: Backend> {
+    input_norm: Norm<B>,
+    q_proj: Tensor<B, 2>,
+    k_proj: Tensor<B, 2>,
+    v_proj: Tensor<B, 2>,
+    o_proj: Tensor<B, 2>,
+    /// Biases of the query, key, value and output projections, which Phi has
+    attention_bias: Option<[Tensor<B, 1>; 4]>,
+    /// `None` when attention and the MLP both read the input norm's output
+    /// and add to the residual in parallel, as in Phi
+    post_attention_norm: Option<Norm<B>>,
+    mlp: Mlp<B>,
+}
+
+impl<B: Backend> DecoderLayer<B> {
+    /// The weight of projection `module`, e.g. `self_attn.q_proj`
+    fn projection_mut(&mut self, family: Family, module: &str) -> Option<&mut Tensor<B, 2>> {
+        match (module, &mut self.mlp) {
+            ("self_attn.q_proj", _) => Some(&mut self.q_proj),
+            ("self_attn.k_proj", _) => Some(&mut self.k_proj),
+            ("self_attn.v_proj", _) => Some(&mut self.v_proj),
+            (module, _) if module == family.output_proj() => Some(&mut self.o_proj),
+            ("mlp.gate_proj", Mlp::Gated { gate_proj, .. }) => Some(gate_proj),
+            ("mlp.up_proj", Mlp::Gated { up_proj, .. }) => Some(up_proj),
+            ("mlp.down_proj", Mlp::Gated { down_proj, .. }) => Some(down_proj),
+            ("mlp.fc1", Mlp::Gelu { fc1, .. }) => Some(fc1),
+            ("mlp.fc2", Mlp::Gelu { fc2, .. }) => Some(fc2),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters of a Llama- or Phi-style decoder, in the HuggingFace layout
+struct Decoder<B: Backend> {
+    family: Family,
+    /// `[vocab_size, hidden_size]`
+    embed_tokens: Tensor<B, 2>,
+    layers: Vec<DecoderLayer<B>>,
+    norm: Norm<B>,
+    /// `[vocab_size, hidden_size]`; the embeddings when they are tied
+    lm_head: Tensor<B, 2>,
+    lm_head_bias: Option<Tensor<B, 1>>,
+    /// Heads of the keys and values, fewer than the query heads with grouped-query attention
+    num_kv_heads: usize,
+    /// Leading dimensions of each head that get rotary position embeddings
+    rotary_dim: usize,
+}
+
+impl<B: Backend> Decoder<B> {
+    fn from_weights(config: &LlmConfig, weights: &WeightMap, device: &B::Device) -> Result<Self, String> {
+        let family = if weights.contains("model.final_layernorm.weight") { Family::Phi } else { Family::Llama };
+        let hidden = config.hidden_size;
+        let head_dim = hidden / config.num_attention_heads.max(1);
+        if head_dim == 0 || head_dim * config.num_attention_heads != hidden || !head_dim.is_multiple_of(2) {
+            return Err(format!(
+                "Hidden size {} does not split into {} heads of even size",
+                hidden, config.num_attention_heads
+            ));
+        }
+        // Rotated dimensions come in pairs
+        let rotary_dim = (head_dim as f32 * config.partial_rotary_factor) as usize / 2 * 2;
+        if rotary_dim == 0 || rotary_dim > head_dim {
+            return Err(format!("Partial rotary factor {} does not fit heads of {}", config.partial_rotary_factor, head_dim));
+        }
+        let tensor = |name: &str, dims: &[usize]| -> Result<Tensor<B, 2>, String> {
+            match weights.shape(name) {
+                Some(shape) if shape != dims => Err(format!("Tensor {} has shape {:?}, expected {:?}", name, shape, dims)),
+                _ => weights.tensor(name, device),
+            }
+        };
+        let vector = |name: &str, len: usize| -> Result<Tensor<B, 1>, String> {
+            match weights.shape(name) {
+                Some(shape) if shape != [len] => Err(format!("Tensor {} has shape {:?}, expected [{}]", name, shape, len)),
+                _ => weights.tensor(name, device),
+            }
+        };
+        let norm = |prefix: &str| -> Result<Norm<B>, String> {
+            let weight = vector(&format!("{}.weight", prefix), hidden)?;
+            Ok(match family {
+                Family::Llama => Norm::Rms(weight),
+                Family::Phi => Norm::Layer(weight, vector(&format!("{}.bias", prefix), hidden)?),
+            })
+        };
+
+        let k_rows = weights.shape("model.layers.0.self_attn.k_proj.weight").map_or(hidden, |shape| shape[0]);
+        let num_kv_heads = k_rows / head_dim;
+        if num_kv_heads == 0 || num_kv_heads * head_dim != k_rows || !config.num_attention_heads.is_multiple_of(num_kv_heads) {
+            return Err(format!("Key projection of {} rows does not fit {} attention heads", k_rows, config.num_attention_heads));
         }
+
+        let embed_tokens = tensor("model.embed_tokens.weight", &[config.vocab_size, hidden])?;
+        let intermediate = config.intermediate_size;
+        let mut layers = Vec::with_capacity(config.num_layers);
+        for i in 0..config.num_layers {
+            let name = |module: &str| format!("model.layers.{}.{}.weight", i, module);
+            let bias = |module: &str, len: usize| vector(&format!("model.layers.{}.{}.bias", i, module), len);
+            let (attention_bias, post_attention_norm, mlp) = match family {
+                Family::Llama => (
+                    None,
+                    Some(norm(&format!("model.layers.{}.post_attention_layernorm", i))?),
+                    Mlp::Gated {
+                        gate_proj: tensor(&name("mlp.gate_proj"), &[intermediate, hidden])?,
+                        up_proj: tensor(&name("mlp.up_proj"), &[intermediate, hidden])?,
+                        down_proj: tensor(&name("mlp.down_proj"), &[hidden, intermediate])?,
+                    },
+                ),
+                Family::Phi => (
+                    Some([
+                        bias("self_attn.q_proj", hidden)?,
+                        bias("self_attn.k_proj", k_rows)?,
+                        bias("self_attn.v_proj", k_rows)?,
+                        bias("self_attn.dense", hidden)?,
+                    ]),
+                    None,
+                    Mlp::Gelu {
+                        fc1: tensor(&name("mlp.fc1"), &[intermediate, hidden])?,
+                        fc1_bias: bias("mlp.fc1", intermediate)?,
+                        fc2: tensor(&name("mlp.fc2"), &[hidden, intermediate])?,
+                        fc2_bias: bias("mlp.fc2", hidden)?,
+                    },
+                ),
+            };
+            layers.push(DecoderLayer {
+                input_norm: norm(&format!("model.layers.{}.input_layernorm", i))?,
+                q_proj: tensor(&name("self_attn.q_proj"), &[hidden, hidden])?,
+                k_proj: tensor(&name("self_attn.k_proj"), &[k_rows, hidden])?,
+                v_proj: tensor(&name("self_attn.v_proj"), &[k_rows, hidden])?,
+                o_proj: tensor(&name(family.output_proj()), &[hidden, hidden])?,
+                attention_bias,
+                post_attention_norm,
+                mlp,
+            });
+        }
+        let lm_head = if weights.contains("lm_head.weight") {
+            tensor("lm_head.weight", &[config.vocab_size, hidden])?
+        } else {
+            embed_tokens.clone()
+        };
+        let lm_head_bias = match weights.contains("lm_head.bias") {
+            true => Some(vector("lm_head.bias", config.vocab_size)?),
+            false => None,
+        };
+        let norm = match family {
+            Family::Llama => norm("model.norm")?,
+            Family::Phi => norm("model.final_layernorm")?,
+        };
+
+        Ok(Self {
+            family,
+            embed_tokens,
+            layers,
+            norm,
+            lm_head,
+            lm_head_bias,
+            num_kv_heads,
+            rotary_dim,
+        })
     }
 }
 
+/// Embeddings of `[batch, len]` token ids
+fn embed<B: Backend>(decoder: &Decoder<B>, input_ids: Tensor<B, 2>) -> Tensor<B, 3> {
+    let [batch, len] = input_ids.dims();
+    let hidden = decoder.embed_tokens.dims()[1];
+    let ids = input_ids.int().reshape([batch * len]);
+    decoder.embed_tokens.clone().select(0, ids).reshape([batch, len, hidden])
+}
+
+/// `input · weightᵀ` over the last dimension
+fn linear<B: Backend>(input: Tensor<B, 3>, weight: &Tensor<B, 2>) -> Tensor<B, 3> {
+    let [batch, len, in_features] = input.dims();
+    let out_features = weight.dims()[0];
+    input
+        .reshape([batch * len, in_features])
+        .matmul(weight.clone().transpose())
+        .reshape([batch, len, out_features])
+}
+
+fn rms_norm<B: Backend>(input: Tensor<B, 3>, gamma: &Tensor<B, 1>) -> Tensor<B, 3> {
+    let hidden = gamma.dims()[0];
+    let rms = (input.clone().powf_scalar(2.0).mean_dim(2) + RMS_NORM_EPS).sqrt();
+    input / rms * gamma.clone().reshape([1, 1, hidden])
+}
+
+fn layer_norm<B: Backend>(input: Tensor<B, 3>, gamma: &Tensor<B, 1>, beta: &Tensor<B, 1>) -> Tensor<B, 3> {
+    let hidden = gamma.dims()[0];
+    let centered = input.clone() - input.mean_dim(2);
+    let variance = centered.clone().powf_scalar(2.0).mean_dim(2);
+    centered / (variance + LAYER_NORM_EPS).sqrt() * gamma.clone().reshape([1, 1, hidden]) + beta.clone().reshape([1, 1, hidden])
+}
+
+/// GELU with the tanh approximation Phi was trained with
+fn gelu_tanh<B: Backend>(input: Tensor<B, 3>) -> Tensor<B, 3> {
+    let inner = (input.clone() + input.clone().powf_scalar(3.0) * 0.044715) * (2.0 / PI).sqrt();
+    input * (inner.tanh() + 1.0) * 0.5
+}
+
+/// Add a bias over the last dimension
+fn add_bias<B: Backend>(input: Tensor<B, 3>, bias: Option<&Tensor<B, 1>>) -> Tensor<B, 3> {
+    match bias {
+        Some(bias) => {
+            let len = bias.dims()[0];
+            input + bias.clone().reshape([1, 1, len])
+        }
+        None => input,
+    }
+}
+
+/// Rotate the leading dimensions of `[batch, heads, len, head_dim]`, as many
+/// as `cos` and `sin` have, by their position angles
+fn rotate<B: Backend>(input: Tensor<B, 4>, cos: &Tensor<B, 4>, sin: &Tensor<B, 4>) -> Tensor<B, 4> {
+    let (rotary_dim, head_dim) = (cos.dims()[3], input.dims()[3]);
+    if rotary_dim < head_dim {
+        let pass = input.clone().narrow(3, rotary_dim, head_dim - rotary_dim);
+        return Tensor::cat(vec![rotate(input.narrow(3, 0, rotary_dim), cos, sin), pass], 3);
+    }
+    let half = head_dim / 2;
+    let first = input.clone().narrow(3, 0, half);
+    let second = input.clone().narrow(3, half, half);
+    let rotated = Tensor::cat(vec![second.neg(), first], 3);
+    input * cos.clone() + rotated * sin.clone()
+}
+
 /// LLM model implementation
+///
+/// Without weights the model has no parameters and predicts all-zero
+/// logits. Any number of LoRA adapters can be loaded alongside the base
+/// weights; the active one is applied to projection outputs at runtime, so
+/// switching adapters never reloads the base model. An adapter that stays in
+/// use can instead be merged into the weights, so it costs nothing per token.
 pub struct LlmModel<B: Backend> {
     config: LlmConfig,
-    phantom: std::marker::PhantomData<B>,
+    device: B::Device,
+    decoder: Option<Decoder<B>>,
+    adapters: HashMap<String, LoraAdapter<B>>,
+    /// Name and scale of the adapter in use
+    active_adapter: Option<(String, f32)>,
+    /// Name and scale of the adapter merged into the decoder weights
+    merged_adapter: Option<(String, f32)>,
 }
 
 impl<B: Backend> LlmModel<B> {
-    /// Create a new LLM model
-    pub fn new(config: &LlmConfig) -> Self {
+    /// Create a new LLM model without weights on the given device
+    pub fn new(config: &LlmConfig, device: &B::Device) -> Self {
         Self {
             config: config.clone(),
-            phantom: std::marker::PhantomData,
+            device: device.clone(),
+            decoder: None,
+            adapters: HashMap::new(),
+            active_adapter: None,
+            merged_adapter: None,
         }
     }
 
+    /// Create a Llama- or Phi-style model from HuggingFace weights
+    /// (`model.embed_tokens`, `model.layers.N.*`, `model.norm` or, for Phi,
+    /// `model.final_layernorm`, and an optional `lm_head`)
+    pub fn from_weights(config: &LlmConfig, weights: &WeightMap, device: &B::Device) -> Result<Self, String> {
+        Ok(Self {
+            decoder: Some(Decoder::from_weights(config, weights, device)?),
+            ..Self::new(config, device)
+        })
+    }
+
+    /// Check whether the model has weights
+    pub fn has_weights(&self) -> bool {
+        self.decoder.is_some()
+    }
+
+    /// Get the device the model runs on
+    pub fn device(&self) -> &B::Device {
+        &self.device
+    }
+
+    /// Get the model's configuration
+    pub fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    /// Add a LoRA adapter, replacing one loaded under the same name
+    ///
+    /// Fails if the adapter targets layers or projection sizes this model
+    /// does not have, i.e. it was trained for a different base model.
+    pub fn load_adapter(&mut self, adapter: LoraAdapter<B>) -> Result<(), String> {
+        for module in adapter.modules() {
+            let layer = module
+                .split('.')
+                .skip_while(|part| *part != "layers")
+                .nth(1)
+                .and_then(|index| index.parse::<usize>().ok());
+            let in_features = adapter.shape(module).map_or(0, |[in_features, _]| in_features);
+            let fits = layer.is_none_or(|layer| layer < self.config.num_layers)
+                && (in_features == self.config.hidden_size || in_features == self.config.intermediate_size);
+            if !fits {
+                return Err(format!("Adapter {} does not fit this model at {}", adapter.name(), module));
+            }
+        }
+        log::info!("Loaded LoRA adapter {} for {} modules", adapter.name(), adapter.modules().count());
+        self.adapters.insert(adapter.name().to_string(), adapter);
+        Ok(())
+    }
+
+    /// Switch to a loaded adapter at `scale` (1.0 as trained), or back to the base model with `None`
+    pub fn set_adapter(&mut self, name: Option<&str>, scale: f32) -> Result<(), String> {
+        self.active_adapter = match name {
+            Some(name) if !self.adapters.contains_key(name) => return Err(format!("No adapter named {}", name)),
+            Some(name) => Some((name.to_string(), scale)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Drop a loaded adapter, unmerging it first if it is merged, returning whether it existed
+    pub fn remove_adapter(&mut self, name: &str) -> bool {
+        if self.active_adapter.as_ref().is_some_and(|(active, _)| active == name) {
+            self.active_adapter = None;
+        }
+        if self.merged_adapter.as_ref().is_some_and(|(merged, _)| merged == name) {
+            let _ = self.unmerge_adapter();
+        }
+        self.adapters.remove(name).is_some()
+    }
+
+    /// Names of the loaded adapters, sorted
+    pub fn adapters(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.adapters.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// Name and scale of the adapter in use
+    pub fn active_adapter(&self) -> Option<(&str, f32)> {
+        self.active_adapter.as_ref().map(|(name, scale)| (name.as_str(), *scale))
+    }
+
+    /// Add the active adapter's update to a projection's output
+    ///
+    /// # Arguments
+    /// * `module` - Path of the projection, e.g. `model.layers.0.self_attn.q_proj`
+    /// * `input` - The projection's input, `[batch, seq_len, in_features]`
+    /// * `output` - The base projection's output, `[batch, seq_len, out_features]`
+    pub fn project(&self, module: &str, input: Tensor<B, 3>, output: Tensor<B, 3>) -> Tensor<B, 3> {
+        let delta = self
+            .active_adapter
+            .as_ref()
+            .and_then(|(name, scale)| self.adapters.get(name)?.forward(module, input, *scale));
+        match delta {
+            Some(delta) => output + delta,
+            None => output,
+        }
+    }
+
+    /// Layer `layer`'s projection `module`, with the active adapter's update
+    fn linear(
+        &self,
+        layer: usize,
+        module: &str,
+        input: Tensor<B, 3>,
+        weight: &Tensor<B, 2>,
+        bias: Option<&Tensor<B, 1>>,
+    ) -> Tensor<B, 3> {
+        let output = add_bias(linear(input.clone(), weight), bias);
+        if self.active_adapter.is_none() {
+            return output;
+        }
+        self.project(&format!("model.layers.{}.{}", layer, module), input, output)
+    }
+
+    /// Add a loaded adapter's update to the decoder weights at `scale`
+    ///
+    /// The merged model gives the same logits as the adapter applied at
+    /// runtime, without its per-token cost. If the adapter is also the active
+    /// one it is switched off, so its update is not applied twice. Only one
+    /// adapter can be merged at a time.
+    pub fn merge_adapter(&mut self, name: &str, scale: f32) -> Result<(), String> {
+        if let Some((merged, _)) = &self.merged_adapter {
+            return Err(format!("Adapter {} is already merged; unmerge it first", merged));
+        }
+        self.rewrite_weights(name, |adapter, module, weight| adapter.merge(module, weight, scale))?;
+        if self.active_adapter.as_ref().is_some_and(|(active, _)| active == name) {
+            self.active_adapter = None;
+        }
+        self.merged_adapter = Some((name.to_string(), scale));
+        Ok(())
+    }
+
+    /// Subtract the merged adapter's update from the decoder weights, restoring the base model
+    pub fn unmerge_adapter(&mut self) -> Result<(), String> {
+        let (name, scale) = self.merged_adapter.clone().ok_or("No adapter is merged")?;
+        self.rewrite_weights(&name, |adapter, module, weight| adapter.unmerge(module, weight, scale))?;
+        self.merged_adapter = None;
+        Ok(())
+    }
+
+    /// Name and scale of the adapter merged into the weights
+    pub fn merged_adapter(&self) -> Option<(&str, f32)> {
+        self.merged_adapter.as_ref().map(|(name, scale)| (name.as_str(), *scale))
+    }
+
+    /// Replace every decoder weight adapter `name` updates with `rewrite(adapter, module, weight)`
+    ///
+    /// Nothing is changed unless every module the adapter targets exists.
+    fn rewrite_weights(
+        &mut self,
+        name: &str,
+        rewrite: impl Fn(&LoraAdapter<B>, &str, Tensor<B, 2>) -> Tensor<B, 2>,
+    ) -> Result<(), String> {
+        let adapter = self.adapters.get(name).ok_or_else(|| format!("No adapter named {}", name))?;
+        let decoder = self.decoder.as_mut().ok_or("Adapters can only be merged into a model with weights")?;
+        let family = decoder.family;
+        let mut targets = Vec::new();
+        for module in adapter.modules() {
+            let target = module
+                .strip_prefix("model.layers.")
+                .and_then(|rest| rest.split_once('.'))
+                .and_then(|(layer, projection)| Some((layer.parse::<usize>().ok()?, projection)))
+                .filter(|(layer, projection)| {
+                    decoder.layers.get_mut(*layer).is_some_and(|l| l.projection_mut(family, projection).is_some())
+                });
+            let (layer, projection) =
+                target.ok_or_else(|| format!("Adapter {} targets {}, which this model cannot merge", name, module))?;
+            targets.push((layer, projection, module));
+        }
+        for (layer, projection, module) in targets {
+            if let Some(weight) = decoder.layers[layer].projection_mut(family, projection) {
+                *weight = rewrite(adapter, module, weight.clone());
+            }
+        }
+        Ok(())
+    }
+
     /// Forward pass for text generation
+    ///
+    /// Token ids are `[batch, seq_len]`; the logits are `[batch, seq_len, vocab_size]`.
     pub fn forward(&self, input_ids: Tensor<B, 2>) -> Tensor<B, 3> {
-        // Simple forward implementation
-        // In a real implementation, this would include:
-        // - Token embeddings
-        // - Transformer layers with attention
-        // - Layer normalization
-        // - Output projections
-        
-        // Use config to ensure it's not marked as dead code
-        let _config = &self.config;
-        
         let [batch_size, seq_len] = input_ids.dims();
-        let device = B::Device::default();
-        Tensor::zeros([batch_size, seq_len, self.config.vocab_size], &device)
+        let mask = Tensor::ones([batch_size, seq_len], &self.device);
+        self.forward_masked(input_ids, mask)
+    }
+
+    /// Forward pass over a left-padded batch
+    ///
+    /// Positions where `attention_mask` is 0 are padding: they are not attended
+    /// to, and position ids count only the unmasked tokens, so a padded
+    /// sequence gets the same logits as it would alone.
+    pub fn forward_masked(&self, input_ids: Tensor<B, 2>, attention_mask: Tensor<B, 2>) -> Tensor<B, 3> {
+        let [batch_size, seq_len] = input_ids.dims();
+        let Some(decoder) = &self.decoder else {
+            return Tensor::zeros([batch_size, seq_len, self.config.vocab_size], &self.device);
+        };
+        let mask = attention_mask.into_data().convert::<f32>().to_vec::<f32>().unwrap_or_default();
+        self.decode(decoder, embed(decoder, input_ids), &mask, None)
+    }
+
+    /// Run the decoder on input embeddings; `mask` holds the attention mask, row by row
+    ///
+    /// With a `cache`, the batch is one sequence continuing the cached
+    /// positions: they are attended to as well, and the new keys and values
+    /// are appended to them.
+    fn decode(&self, decoder: &Decoder<B>, mut x: Tensor<B, 3>, mask: &[f32], mut cache: Option<&mut KvCache<B>>) -> Tensor<B, 3> {
+        let [batch, len, _] = x.dims();
+        let past = cache.as_ref().map_or(0, |cache| cache.len());
+        let total = past + len;
+        let hidden = self.config.hidden_size;
+        let heads = self.config.num_attention_heads;
+        let head_dim = hidden / heads;
+        let kv_heads = decoder.num_kv_heads;
+        let rotary_dim = decoder.rotary_dim;
+        let theta = self.config.rope_theta;
+
+
+        // Positions count only real tokens, and a query never sees padding or later tokens
+        let mut angles = Vec::with_capacity(batch * len * rotary_dim);
+        let mut bias = Vec::with_capacity(batch * len * total);
+        for row in mask.chunks(len) {
+            let mut position = past as f32;
+            for &real in row {
+                let half = (0..rotary_dim / 2).map(|i| position * theta.powf(-2.0 * i as f32 / rotary_dim as f32));
+                let half: Vec<f32> = half.collect();
+                angles.extend_from_slice(&half);
+                angles.extend_from_slice(&half);
+                if real > 0.0 {
+                    position += 1.0;
+                }
+            }
+            for query in 0..len {
+                bias.extend((0..past).map(|_| 0.0));
+                bias.extend((0..len).map(|key| if key <= query && row[key] > 0.0 { 0.0 } else { MASKED }));
+            }
+        }
+        let angles = Tensor::<B, 4>::from_data(TensorData::new(angles, [batch, 1, len, rotary_dim]), &self.device);
+        let (cos, sin) = (angles.clone().cos(), angles.sin());
+        let bias = Tensor::<B, 4>::from_data(TensorData::new(bias, [batch, 1, len, total]), &self.device);
+        let scale = (head_dim as f32).sqrt();
+
+        for (i, layer) in decoder.layers.iter().enumerate() {
+            let h = layer.input_norm.forward(x.clone());
+            let split = |t: Tensor<B, 3>, n: usize| t.reshape([batch, len, n, head_dim]).swap_dims(1, 2);
+            let proj_bias = |j: usize| layer.attention_bias.as_ref().map(|biases| &biases[j]);
+            let q = self.linear(i, "self_attn.q_proj", h.clone(), &layer.q_proj, proj_bias(0));
+            let k = self.linear(i, "self_attn.k_proj", h.clone(), &layer.k_proj, proj_bias(1));
+            let q = rotate(split(q, heads), &cos, &sin);
+            let k = rotate(split(k, kv_heads), &cos, &sin);
+            let v = split(self.linear(i, "self_attn.v_proj", h.clone(), &layer.v_proj, proj_bias(2)), kv_heads);
+            let (k, v) = match cache.as_deref_mut() {
+                Some(cache) => cache.append(i, k, v),
+                None => (k, v),
+            };
+            // Each key/value head serves a group of query heads
+            let groups = heads / kv_heads;
+            let k = k.unsqueeze_dim::<5>(2).repeat_dim(2, groups).reshape([batch, heads, total, head_dim]);
+            let v = v.unsqueeze_dim::<5>(2).repeat_dim(2, groups).reshape([batch, heads, total, head_dim]);
+
+            let scores = q.matmul(k.swap_dims(2, 3)) / scale + bias.clone();
+            let attention = softmax(scores, 3).matmul(v).swap_dims(1, 2).reshape([batch, len, hidden]);
+            x = x + self.linear(i, decoder.family.output_proj(), attention, &layer.o_proj, proj_bias(3));
+
+            let h = match &layer.post_attention_norm {
+                Some(norm) => norm.forward(x.clone()),
+                None => h,
+            };
+            x = x + self.feed_forward(i, &layer.mlp, h);
+        }
+        add_bias(linear(decoder.norm.forward(x), &decoder.lm_head), decoder.lm_head_bias.as_ref())
+    }
+
+    /// Run layer `layer`'s MLP
+    fn feed_forward(&self, layer: usize, mlp: &Mlp<B>, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        match mlp {
+            Mlp::Gated { gate_proj, up_proj, down_proj } => {
+                let gate = silu(self.linear(layer, "mlp.gate_proj", input.clone(), gate_proj, None))
+                    * self.linear(layer, "mlp.up_proj", input, up_proj, None);
+                self.linear(layer, "mlp.down_proj", gate, down_proj, None)
+            }
+            Mlp::Gelu { fc1, fc1_bias, fc2, fc2_bias } => {
+                let hidden = gelu_tanh(self.linear(layer, "mlp.fc1", input, fc1, Some(fc1_bias)));
+                self.linear(layer, "mlp.fc2", hidden, fc2, Some(fc2_bias))
+            }
+        }
     }
 
     /// Generate text from input
@@ -81,27 +674,761 @@ impl<B: Backend> LlmModel<B> {
         // - Beam search
         
         let [batch_size, _] = input_ids.dims();
-        let device = B::Device::default();
-        Tensor::zeros([batch_size, max_length], &device)
+        Tensor::zeros([batch_size, max_length], &self.device)
+    }
+
+    /// Greedily generate tokens the sampler's grammar allows, until it is
+    /// finished, the model ends the output, or `max_tokens` is reached
+    ///
+    /// Reaching `max_tokens` is only an error if the output is not yet a
+    /// complete match.
+    pub fn generate_constrained(
+        &self,
+        prompt_ids: &[u32],
+        sampler: &mut GrammarSampler,
+        max_tokens: usize,
+    ) -> Result<(), String> {
+        if prompt_ids.is_empty() {
+            return Err("Prompt is empty".to_string());
+        }
+        let mut ids = prompt_ids.to_vec();
+        for _ in 0..max_tokens {
+            let len = ids.len();
+            let values: Vec<f32> = ids.iter().map(|&id| id as f32).collect();
+            let input = Tensor::<B, 2>::from_data(TensorData::new(values, [1, len]), &self.device);
+            let logits = self
+                .forward(input)
+                .slice([0..1, len - 1..len])
+                .into_data()
+                .to_vec::<f32>()
+                .map_err(|e| format!("Failed to read logits: {:?}", e))?;
+
+            let token = sampler.sample_greedy(&logits).ok_or("No token is allowed by the grammar")?;
+            if sampler.accept(token)? {
+                return Ok(());
+            }
+            ids.push(token);
+        }
+        if sampler.is_complete() {
+            return Ok(());
+        }
+        Err(format!("Output did not match the grammar within {} tokens", max_tokens))
+    }
+
+    /// Greedily continue several prompts at once, each until it produces
+    /// `eos`, reaches `max_tokens` or fills the context window
+    ///
+    /// Prompts are left-padded with `eos` to a common length and masked, and
+    /// finished sequences leave the batch.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<BatchOutput>)` with the tokens of each prompt, in order
+    /// * `Err(String)` if a prompt is empty or does not fit the context window
+    pub fn generate_batch(&self, prompts: &[Vec<u32>], max_tokens: usize, eos: u32) -> Result<Vec<BatchOutput>, String> {
+        decode_batch(prompts, max_tokens, eos, self.config.max_position_embeddings, |ids, mask, shape| {
+            let ids = Tensor::<B, 2>::from_data(TensorData::new(ids, shape), &self.device);
+            let mask = Tensor::<B, 2>::from_data(TensorData::new(mask, shape), &self.device);
+            let [batch, len] = shape;
+            let logits = self
+                .forward_masked(ids, mask)
+                .slice([0..batch, len - 1..len])
+                .into_data()
+                .to_vec::<f32>()
+                .map_err(|e| format!("Failed to read logits: {:?}", e))?;
+            Ok(logits.chunks(self.config.vocab_size).map(<[f32]>::to_vec).collect())
+        })
+    }
+
+    /// Run `tokens` after the sequence held in `cache`, returning the logits
+    /// predicted after each of them
+    ///
+    /// Only the new tokens are run: they attend to the cached keys and values,
+    /// and theirs are added to the cache. Truncate it to drop positions again,
+    /// e.g. draft tokens rejected during speculative decoding.
+    pub fn forward_cached(&self, cache: &mut KvCache<B>, tokens: &[u32]) -> Result<Vec<Vec<f32>>, String> {
+        self.run_cached(cache, tokens, None)
+    }
+
+    /// Like [`Self::forward_cached`], with image embeddings in place of the
+    /// embeddings of `image_token`
+    ///
+    /// `images` is `[count, hidden_size]`, one row for each `image_token` in
+    /// `tokens`, in order, e.g. a vision encoder's projected patch features.
+    pub fn forward_cached_with_images(
+        &self,
+        cache: &mut KvCache<B>,
+        tokens: &[u32],
+        image_token: u32,
+        images: Tensor<B, 2>,
+    ) -> Result<Vec<Vec<f32>>, String> {
+        self.run_cached(cache, tokens, Some((image_token, images)))
+    }
+
+    fn run_cached(&self, cache: &mut KvCache<B>, tokens: &[u32], images: Option<(u32, Tensor<B, 2>)>) -> Result<Vec<Vec<f32>>, String> {
+        if let Some((image_token, images)) = &images {
+            let count = tokens.iter().filter(|&&token| token == *image_token).count();
+            if images.dims() != [count, self.config.hidden_size] {
+                return Err(format!(
+                    "Image embeddings of shape {:?} do not fit {} image tokens of size {}",
+                    images.dims(),
+                    count,
+                    self.config.hidden_size
+                ));
+            }
+        }
+        if tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+        if cache.len() + tokens.len() > self.config.max_position_embeddings {
+            return Err(format!(
+                "Sequence of {} tokens exceeds the context length of {}",
+                cache.len() + tokens.len(),
+                self.config.max_position_embeddings
+            ));
+        }
+        let len = tokens.len();
+        let logits = match &self.decoder {
+            Some(decoder) => {
+                let values: Vec<f32> = tokens.iter().map(|&id| id as f32).collect();
+                let input = Tensor::<B, 2>::from_data(TensorData::new(values, [1, len]), &self.device);
+                let mut x = embed(decoder, input);
+                if let Some((image_token, images)) = images {
+                    // Rows past the tokens' own embeddings are the images', in order
+                    let mut next = len;
+                    let rows: Vec<i64> = tokens
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &token)| {
+                            if token != image_token {
+                                return i as i64;
+                            }
+                            next += 1;
+                            next as i64 - 1
+                        })
+                        .collect();
+                    let hidden = self.config.hidden_size;
+                    let table = Tensor::cat(vec![x.reshape([len, hidden]), images], 0);
+                    let rows = Tensor::<B, 1, Int>::from_data(TensorData::new(rows, [len]), &self.device);
+                    x = table.select(0, rows).reshape([1, len, hidden]);
+                }
+                self.decode(decoder, x, &vec![1.0; len], Some(cache))
+            }
+            None => Tensor::zeros([1, len, self.config.vocab_size], &self.device),
+        };
+        cache.tokens.extend_from_slice(tokens);
+        let logits = logits
+            .into_data()
+            .to_vec::<f32>()
+            .map_err(|e| format!("Failed to read logits: {:?}", e))?;
+        Ok(logits.chunks(self.config.vocab_size).map(<[f32]>::to_vec).collect())
+    }
+}
+
+/// Why a sequence of a batch stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchFinish {
+    /// The model produced the end-of-sequence token
+    Eos,
+    /// `max_tokens` were generated or the context window is full
+    Length,
+}
+
+/// One prompt's result from [`LlmModel::generate_batch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchOutput {
+    /// Generated tokens, without the end-of-sequence token
+    pub tokens: Vec<u32>,
+    pub finish: BatchFinish,
+}
+
+/// Left-pad sequences with `pad`, returning the ids, the attention mask and their shape
+fn pad_left(sequences: &[&[u32]], pad: u32) -> (Vec<f32>, Vec<f32>, [usize; 2]) {
+    let len = sequences.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut ids = Vec::with_capacity(sequences.len() * len);
+    let mut mask = Vec::with_capacity(sequences.len() * len);
+    for sequence in sequences {
+        let padding = len - sequence.len();
+        ids.extend(std::iter::repeat_n(pad as f32, padding).chain(sequence.iter().map(|&id| id as f32)));
+        mask.extend(std::iter::repeat_n(0.0, padding).chain(std::iter::repeat_n(1.0, sequence.len())));
+    }
+    (ids, mask, [sequences.len(), len])
+}
+
+/// Batched greedy decoding; `forward` maps padded ids, mask and shape to
+/// the logits after the last position of each row
+fn decode_batch(
+    prompts: &[Vec<u32>],
+    max_tokens: usize,
+    eos: u32,
+    context_length: usize,
+    mut forward: impl FnMut(Vec<f32>, Vec<f32>, [usize; 2]) -> Result<Vec<Vec<f32>>, String>,
+) -> Result<Vec<BatchOutput>, String> {
+    for (i, prompt) in prompts.iter().enumerate() {
+        if prompt.is_empty() {
+            return Err(format!("Prompt {} is empty", i));
+        }
+        if prompt.len() > context_length {
+            return Err(format!("Prompt {} has {} tokens, more than the context length of {}", i, prompt.len(), context_length));
+        }
+    }
+    let mut sequences = prompts.to_vec();
+    let mut outputs: Vec<BatchOutput> = prompts
+        .iter()
+        .map(|_| BatchOutput { tokens: Vec::new(), finish: BatchFinish::Length })
+        .collect();
+    let mut active: Vec<usize> = (0..prompts.len())
+        .filter(|&i| max_tokens > 0 && sequences[i].len() < context_length)
+        .collect();
+
+    while !active.is_empty() {
+        let rows: Vec<&[u32]> = active.iter().map(|&i| sequences[i].as_slice()).collect();
+        let (ids, mask, shape) = pad_left(&rows, eos);
+        let logits = forward(ids, mask, shape)?;
+        if logits.len() != active.len() {
+            return Err(format!("Expected logits for {} sequences, got {}", active.len(), logits.len()));
+        }
+
+        let mut still_active = Vec::with_capacity(active.len());
+        for (&i, row) in active.iter().zip(&logits) {
+            let token = row
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1).then(b.0.cmp(&a.0)))
+                .map_or(eos, |(id, _)| id as u32);
+            if token == eos {
+                outputs[i].finish = BatchFinish::Eos;
+                continue;
+            }
+            sequences[i].push(token);
+            outputs[i].tokens.push(token);
+            if outputs[i].tokens.len() < max_tokens && sequences[i].len() < context_length {
+                still_active.push(i);
+            }
+        }
+        active = still_active;
+    }
+    Ok(outputs)
+}
+
+/// Attention keys and values of the positions a sequence has been decoded
+/// through, so each step only runs the new tokens
+///
+/// The keys and values depend on the active LoRA adapter; start a new cache
+/// after switching adapters.
+#[derive(Debug, Clone)]
+pub struct KvCache<B: Backend> {
+    tokens: Vec<u32>,
+    /// Keys, with positions rotated in, and values of each layer, `[1, kv_heads, len, head_dim]`
+    layers: Vec<(Tensor<B, 4>, Tensor<B, 4>)>,
+}
+
+impl<B: Backend> Default for KvCache<B> {
+    fn default() -> Self {
+        Self { tokens: Vec::new(), layers: Vec::new() }
+    }
+}
+
+impl<B: Backend> KvCache<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append new keys and values to layer `layer`'s, returning all of them
+    fn append(&mut self, layer: usize, k: Tensor<B, 4>, v: Tensor<B, 4>) -> (Tensor<B, 4>, Tensor<B, 4>) {
+        let (k, v) = match self.layers.get(layer) {
+            Some((cached_k, cached_v)) => (Tensor::cat(vec![cached_k.clone(), k], 2), Tensor::cat(vec![cached_v.clone(), v], 2)),
+            None => (k, v),
+        };
+        match self.layers.get_mut(layer) {
+            Some(cached) => *cached = (k.clone(), v.clone()),
+            None => self.layers.push((k.clone(), v.clone())),
+        }
+        (k, v)
+    }
+
+    /// Number of cached positions
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Tokens at the cached positions
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    /// Drop every position from `len` on
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.tokens.len() {
+            return;
+        }
+        self.tokens.truncate(len);
+        if len == 0 {
+            self.layers.clear();
+        }
+        for (k, v) in &mut self.layers {
+            *k = k.clone().narrow(2, 0, len);
+            *v = v.clone().narrow(2, 0, len);
+        }
     }
 }
 
-/// Function to create LLM model with loaded weights
+/// Create an LLM, from Llama- or Phi-style weights if `weights` has any
 pub fn create_llm_model<B: Backend>(
-    model_type: crate::models::ModelType,
-    model_data: &[u8],
+    config: &LlmConfig,
+    weights: &WeightMap,
+    device: &B::Device,
 ) -> Result<LlmModel<B>, String> {
-    let config = match model_type {
-        crate::models::ModelType::Phi2 => LlmConfig::phi_2(),
-        crate::models::ModelType::TinyLlama => LlmConfig::tiny_llama(),
-        _ => return Err("Invalid model type for LLM".to_string()),
-    };
+    if weights.is_empty() {
+        return Ok(LlmModel::new(config, device));
+    }
+    log::info!(
+        "Loading LLM model weights: {} tensors, {} parameters",
+        weights.len(),
+        weights.parameter_count()
+    );
+    LlmModel::from_weights(config, weights, device)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::lora::tests::{adapter_weights, CONFIG};
+    use burn_ndarray::NdArray;
+    use safetensors::tensor::TensorView;
+    use safetensors::Dtype;
+
+    type B = NdArray<f32>;
+
+    /// A small Llama-style config with grouped-query attention when given 1 key/value head
+    pub(crate) fn llama_config() -> LlmConfig {
+        LlmConfig {
+            vocab_size: 16,
+            hidden_size: 8,
+            num_layers: 2,
+            num_attention_heads: 2,
+            intermediate_size: 12,
+            max_position_embeddings: 16,
+            partial_rotary_factor: 1.0,
+            rope_theta: 10000.0,
+        }
+    }
+
+    /// Safetensors of a Llama checkpoint with pseudo-random weights, without
+    /// `lm_head` when the embeddings are `tied`
+    pub(crate) fn llama_weights(config: &LlmConfig, kv_heads: usize, tied: bool) -> Vec<u8> {
+        checkpoint(&llama_shapes(config, kv_heads, tied))
+    }
+
+    /// Names and shapes of a Llama checkpoint's tensors
+    pub(crate) fn llama_shapes(config: &LlmConfig, kv_heads: usize, tied: bool) -> Vec<(String, Vec<usize>)> {
+        let hidden = config.hidden_size;
+        let kv = kv_heads * hidden / config.num_attention_heads;
+        let mut shapes = vec![("model.embed_tokens.weight".to_string(), vec![config.vocab_size, hidden])];
+        for i in 0..config.num_layers {
+            let name = |module: &str| format!("model.layers.{}.{}.weight", i, module);
+            shapes.extend([
+                (name("input_layernorm"), vec![hidden]),
+                (name("self_attn.q_proj"), vec![hidden, hidden]),
+                (name("self_attn.k_proj"), vec![kv, hidden]),
+                (name("self_attn.v_proj"), vec![kv, hidden]),
+                (name("self_attn.o_proj"), vec![hidden, hidden]),
+                (name("post_attention_layernorm"), vec![hidden]),
+                (name("mlp.gate_proj"), vec![config.intermediate_size, hidden]),
+                (name("mlp.up_proj"), vec![config.intermediate_size, hidden]),
+                (name("mlp.down_proj"), vec![hidden, config.intermediate_size]),
+            ]);
+        }
+        shapes.push(("model.norm.weight".to_string(), vec![hidden]));
+        if !tied {
+            shapes.push(("lm_head.weight".to_string(), vec![config.vocab_size, hidden]));
+        }
+        shapes
+    }
+
+    /// Safetensors of a Phi checkpoint with pseudo-random weights and biases
+    fn phi_weights(config: &LlmConfig) -> Vec<u8> {
+        let (hidden, intermediate) = (config.hidden_size, config.intermediate_size);
+        let mut shapes = vec![("model.embed_tokens.weight".to_string(), vec![config.vocab_size, hidden])];
+        for i in 0..config.num_layers {
+            let modules = [
+                ("input_layernorm", hidden, None),
+                ("self_attn.q_proj", hidden, Some(hidden)),
+                ("self_attn.k_proj", hidden, Some(hidden)),
+                ("self_attn.v_proj", hidden, Some(hidden)),
+                ("self_attn.dense", hidden, Some(hidden)),
+                ("mlp.fc1", intermediate, Some(hidden)),
+                ("mlp.fc2", hidden, Some(intermediate)),
+            ];
+            for (module, out, input) in modules {
+                let name = |param: &str| format!("model.layers.{}.{}.{}", i, module, param);
+                shapes.push((name("weight"), input.map_or(vec![out], |input| vec![out, input])));
+                shapes.push((name("bias"), vec![out]));
+            }
+        }
+        shapes.extend([
+            ("model.final_layernorm.weight".to_string(), vec![hidden]),
+            ("model.final_layernorm.bias".to_string(), vec![hidden]),
+            ("lm_head.weight".to_string(), vec![config.vocab_size, hidden]),
+            ("lm_head.bias".to_string(), vec![config.vocab_size]),
+        ]);
+        checkpoint(&shapes)
+    }
 
-    // Load model weights from safetensors data
-    // Note: This is a placeholder - actual weight loading would parse the safetensors format
-    if !model_data.is_empty() {
-        log::info!("Loading LLM model weights from {} bytes of data", model_data.len());
+    /// Safetensors with pseudo-random values, except norm weights of 1
+    pub(crate) fn checkpoint(shapes: &[(String, Vec<usize>)]) -> Vec<u8> {
+        let mut state = 1u32;
+        let data: Vec<Vec<u8>> = shapes
+            .iter()
+            .map(|(name, shape)| {
+                let len: usize = shape.iter().product();
+                let value = |_| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    if name.ends_with("norm.weight") { 1.0 } else { (state >> 8) as f32 / (1 << 24) as f32 - 0.5 }
+                };
+                (0..len).map(value).flat_map(f32::to_le_bytes).collect()
+            })
+            .collect();
+        let views = shapes
+            .iter()
+            .zip(&data)
+            .map(|((name, shape), data)| (name.as_str(), TensorView::new(Dtype::F32, shape.clone(), data).unwrap()));
+        safetensors::serialize(views, &None).unwrap()
     }
 
-    Ok(LlmModel::new(&config))
-}
\ No newline at end of file
+    pub(crate) fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn logits(model: &LlmModel<B>, ids: &[u32]) -> Vec<f32> {
+        let values: Vec<f32> = ids.iter().map(|&id| id as f32).collect();
+        let input = Tensor::<B, 2>::from_data(TensorData::new(values, [1, ids.len()]), &Default::default());
+        model.forward(input).into_data().to_vec::<f32>().unwrap()
+    }
+
+    #[test]
+    fn test_forward_with_weights() {
+        let config = llama_config();
+        let device = Default::default();
+        let bytes = llama_weights(&config, 1, false);
+        let model = create_llm_model::<B>(&config, &WeightMap::from_shards(&[&bytes]).unwrap(), &device).unwrap();
+        assert!(model.has_weights());
+
+        let short = logits(&model, &[1, 2, 3]);
+        assert_eq!(short.len(), 3 * 16);
+        assert!(short.iter().all(|v| v.is_finite()) && short.iter().any(|&v| v != 0.0));
+        // Attention is causal, so a later token does not change earlier logits
+        let long = logits(&model, &[1, 2, 3, 4]);
+        assert_close(&short, &long[..3 * 16]);
+
+        // A left-padded row gets the same logits as it does alone
+        let ids = Tensor::<B, 2>::from_data(TensorData::new(vec![0.0, 0.0, 5.0, 6.0, 1.0, 2.0, 3.0, 4.0], [2, 4]), &device);
+        let mask = Tensor::<B, 2>::from_data(TensorData::new(vec![0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0], [2, 4]), &device);
+        let batch = model.forward_masked(ids, mask).into_data().to_vec::<f32>().unwrap();
+        assert_close(&batch[2 * 16..4 * 16], &logits(&model, &[5, 6]));
+        assert_close(&batch[4 * 16..], &long);
+
+        let tied = llama_weights(&config, 2, true);
+        assert!(LlmModel::<B>::from_weights(&config, &WeightMap::from_shards(&[&tied]).unwrap(), &device).is_ok());
+    }
+
+    #[test]
+    fn test_weights_for_other_model() {
+        let device = Default::default();
+        let bytes = llama_weights(&llama_config(), 1, false);
+        let weights = WeightMap::from_shards(&[&bytes]).unwrap();
+        let wider = LlmConfig { vocab_size: 32, ..llama_config() };
+        let err = LlmModel::<B>::from_weights(&wider, &weights, &device).err().unwrap();
+        assert!(err.contains("shape"), "{}", err);
+        let deeper = LlmConfig { num_layers: 3, ..llama_config() };
+        let err = LlmModel::<B>::from_weights(&deeper, &weights, &device).err().unwrap();
+        assert!(err.contains("model.layers.2"), "{}", err);
+        // No weights at all gives the placeholder model
+        assert!(!create_llm_model::<B>(&llama_config(), &WeightMap::default(), &device).unwrap().has_weights());
+    }
+
+    fn tiny_config() -> LlmConfig {
+        LlmConfig {
+            vocab_size: 16,
+            hidden_size: 2,
+            num_layers: 1,
+            num_attention_heads: 1,
+            intermediate_size: 4,
+            max_position_embeddings: 8,
+            partial_rotary_factor: 1.0,
+            rope_theta: 10000.0,
+        }
+    }
+
+    #[test]
+    fn test_swap_adapters() {
+        let device = Default::default();
+        let mut model = LlmModel::<B>::new(&tiny_config(), &device);
+        let adapter = |name| LoraAdapter::<B>::from_bytes(name, CONFIG.as_bytes(), &adapter_weights(0, 2), &device);
+        model.load_adapter(adapter("persona").unwrap()).unwrap();
+        model.load_adapter(adapter("domain").unwrap()).unwrap();
+        assert_eq!(model.adapters(), vec!["domain", "persona"]);
+
+        let module = "model.layers.0.self_attn.q_proj";
+        let project = |model: &LlmModel<B>| {
+            let input = Tensor::<B, 3>::ones([1, 1, 2], &device);
+            model.project(module, input.clone(), input).into_data().to_vec::<f32>().unwrap()
+        };
+        assert_eq!(project(&model), vec![1.0, 1.0]);
+
+        model.set_adapter(Some("persona"), 0.5).unwrap();
+        assert_eq!(model.active_adapter(), Some(("persona", 0.5)));
+        assert_eq!(project(&model), vec![2.0, 2.0]);
+
+        model.set_adapter(Some("domain"), 1.0).unwrap();
+        assert_eq!(project(&model), vec![3.0, 3.0]);
+        assert!(model.set_adapter(Some("missing"), 1.0).is_err());
+
+        assert!(model.remove_adapter("domain"));
+        assert_eq!(model.active_adapter(), None);
+        assert_eq!(project(&model), vec![1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_adapter_changes_logits() {
+        let config = llama_config();
+        let device = Default::default();
+        let bytes = llama_weights(&config, 1, false);
+        let mut model = LlmModel::<B>::from_weights(&config, &WeightMap::from_shards(&[&bytes]).unwrap(), &device).unwrap();
+        let base = logits(&model, &[3, 4, 5]);
+
+        let adapter = LoraAdapter::<B>::from_bytes("persona", CONFIG.as_bytes(), &adapter_weights(1, 8), &device).unwrap();
+        model.load_adapter(adapter).unwrap();
+        assert_close(&logits(&model, &[3, 4, 5]), &base);
+
+        model.set_adapter(Some("persona"), 1.0).unwrap();
+        let adapted = logits(&model, &[3, 4, 5]);
+        assert!(adapted.iter().zip(&base).any(|(a, b)| (a - b).abs() > 1e-3));
+        // The first token attends only to itself, so the query update cannot change its logits
+        assert_close(&adapted[..16], &base[..16]);
+
+        model.set_adapter(None, 1.0).unwrap();
+        assert_close(&logits(&model, &[3, 4, 5]), &base);
+    }
+
+    #[test]
+    fn test_merged_adapter_matches_runtime() {
+        let config = llama_config();
+        let device = Default::default();
+        let bytes = llama_weights(&config, 1, false);
+        let mut model = LlmModel::<B>::from_weights(&config, &WeightMap::from_shards(&[&bytes]).unwrap(), &device).unwrap();
+        let base = logits(&model, &[3, 4, 5]);
+        let adapter = LoraAdapter::<B>::from_bytes("persona", CONFIG.as_bytes(), &adapter_weights(1, 8), &device).unwrap();
+        model.load_adapter(adapter).unwrap();
+        model.set_adapter(Some("persona"), 0.7).unwrap();
+        let runtime = logits(&model, &[3, 4, 5]);
+
+        // Merging switches the runtime adapter off, so it is not applied twice
+        model.merge_adapter("persona", 0.7).unwrap();
+        assert_eq!(model.active_adapter(), None);
+        assert_eq!(model.merged_adapter(), Some(("persona", 0.7)));
+        assert_close(&logits(&model, &[3, 4, 5]), &runtime);
+        assert!(model.merge_adapter("persona", 1.0).unwrap_err().contains("already merged"));
+
+        model.unmerge_adapter().unwrap();
+        assert_eq!(model.merged_adapter(), None);
+        assert_close(&logits(&model, &[3, 4, 5]), &base);
+        assert!(model.unmerge_adapter().is_err());
+        assert!(model.merge_adapter("missing", 1.0).is_err());
+
+        // Removing a merged adapter restores the base weights
+        model.merge_adapter("persona", 1.0).unwrap();
+        assert!(model.remove_adapter("persona"));
+        assert_close(&logits(&model, &[3, 4, 5]), &base);
+        assert!(LlmModel::<B>::new(&config, &device).unmerge_adapter().is_err());
+    }
+
+    #[test]
+    fn test_generate_constrained_stops_at_max_tokens() {
+        use crate::grammar::{Grammar, Vocabulary};
+        // All logits tie, so the highest allowed id wins: '9', never the end of sequence
+        let model = LlmModel::<B>::new(&LlmConfig { vocab_size: 128, ..tiny_config() }, &Default::default());
+        let vocabulary = Vocabulary::ascii(128);
+
+        let digits = Grammar::parse("root ::= [0-9]+").unwrap();
+        let mut sampler = GrammarSampler::new(&digits, &vocabulary);
+        model.generate_constrained(&[1], &mut sampler, 3).unwrap();
+        assert_eq!(sampler.output(), "999");
+
+        let word = Grammar::parse("root ::= \"digits\"").unwrap();
+        let mut sampler = GrammarSampler::new(&word, &vocabulary);
+        let err = model.generate_constrained(&[1], &mut sampler, 3).unwrap_err();
+        assert!(err.contains("within 3 tokens"), "{}", err);
+        assert_eq!(sampler.output(), "dig");
+    }
+
+    #[test]
+    fn test_forward_cached() {
+        let model = LlmModel::<B>::new(&tiny_config(), &Default::default());
+        let mut cache = KvCache::new();
+        let logits = model.forward_cached(&mut cache, &[1, 2, 3]).unwrap();
+        assert_eq!(logits.len(), 3);
+        assert_eq!(logits[0].len(), 16);
+        assert_eq!(model.forward_cached(&mut cache, &[4]).unwrap().len(), 1);
+        assert_eq!(cache.tokens(), &[1, 2, 3, 4]);
+
+        cache.truncate(2);
+        assert_eq!(cache.len(), 2);
+        assert!(model.forward_cached(&mut cache, &[0; 7]).is_err());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_cached_logits_match_forward() {
+        let config = llama_config();
+        let bytes = llama_weights(&config, 1, false);
+        let model = LlmModel::<B>::from_weights(&config, &WeightMap::from_shards(&[&bytes]).unwrap(), &Default::default()).unwrap();
+
+        let mut cache = KvCache::new();
+        let mut cached: Vec<f32> = Vec::new();
+        for tokens in [&[1, 2, 3][..], &[4], &[5, 6]] {
+            cached.extend(model.forward_cached(&mut cache, tokens).unwrap().concat());
+        }
+        assert_close(&cached, &logits(&model, &[1, 2, 3, 4, 5, 6]));
+
+        // Truncated positions are forgotten, keys and values included
+        cache.truncate(3);
+        let next = model.forward_cached(&mut cache, &[7, 8]).unwrap().concat();
+        assert_close(&next, &logits(&model, &[1, 2, 3, 7, 8])[3 * 16..]);
+        cache.truncate(0);
+        let first = model.forward_cached(&mut cache, &[9]).unwrap().concat();
+        assert_close(&first, &logits(&model, &[9]));
+    }
+
+    #[test]
+    fn test_image_embeddings_replace_tokens() {
+        let config = llama_config();
+        let device = Default::default();
+        let bytes = llama_weights(&config, 1, false);
+        let weights = WeightMap::from_shards(&[&bytes]).unwrap();
+        let model = LlmModel::<B>::from_weights(&config, &weights, &device).unwrap();
+
+        // Images embedded like tokens 4 and 6 read as those tokens
+        let embeddings = weights.tensor::<B, 2>("model.embed_tokens.weight", &device).unwrap();
+        let images = Tensor::cat(vec![embeddings.clone().narrow(0, 4, 1), embeddings.narrow(0, 6, 1)], 0);
+        let mut cache = KvCache::new();
+        let output = model.forward_cached_with_images(&mut cache, &[1, 15, 2, 15], 15, images.clone()).unwrap();
+        assert_close(&output.concat(), &logits(&model, &[1, 4, 2, 6]));
+
+        let mut cache = KvCache::new();
+        assert!(model.forward_cached_with_images(&mut cache, &[1, 15], 15, images).is_err());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_phi_checkpoint() {
+        let config = LlmConfig { partial_rotary_factor: 0.5, ..llama_config() };
+        let device = Default::default();
+        let bytes = phi_weights(&config);
+        let weights = WeightMap::from_shards(&[&bytes]).unwrap();
+        let model = LlmModel::<B>::from_weights(&config, &weights, &device).unwrap();
+
+        // A lone token attends only to itself, at position 0 where rotation is
+        // the identity, so its logits follow directly from the weights
+        let get = |name: &str| -> Vec<f32> {
+            match weights.shape(name).unwrap().len() {
+                1 => weights.tensor::<B, 1>(name, &device).unwrap().into_data().to_vec().unwrap(),
+                _ => weights.tensor::<B, 2>(name, &device).unwrap().into_data().to_vec().unwrap(),
+            }
+        };
+        let affine = |module: &str, x: &[f32]| -> Vec<f32> {
+            let (weight, bias) = (get(&format!("{}.weight", module)), get(&format!("{}.bias", module)));
+            weight.chunks(x.len()).zip(bias).map(|(row, b)| row.iter().zip(x).map(|(w, v)| w * v).sum::<f32>() + b).collect()
+        };
+        let layer_norm = |module: &str, x: &[f32]| -> Vec<f32> {
+            let mean = x.iter().sum::<f32>() / x.len() as f32;
+            let variance = x.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / x.len() as f32;
+            let (gamma, beta) = (get(&format!("{}.weight", module)), get(&format!("{}.bias", module)));
+            x.iter().zip(gamma.iter().zip(beta)).map(|(v, (g, b))| (v - mean) / (variance + 1e-5).sqrt() * g + b).collect()
+        };
+        let gelu = |v: f32| 0.5 * v * (1.0 + ((2.0 / PI).sqrt() * (v + 0.044715 * v.powi(3))).tanh());
+
+        let hidden = config.hidden_size;
+        let mut x = get("model.embed_tokens.weight")[3 * hidden..4 * hidden].to_vec();
+        for i in 0..config.num_layers {
+            let module = |name: &str| format!("model.layers.{}.{}", i, name);
+            let h = layer_norm(&module("input_layernorm"), &x);
+            let attention = affine(&module("self_attn.dense"), &affine(&module("self_attn.v_proj"), &h));
+            let fc1: Vec<f32> = affine(&module("mlp.fc1"), &h).into_iter().map(gelu).collect();
+            let mlp = affine(&module("mlp.fc2"), &fc1);
+            x = x.iter().zip(attention.iter().zip(mlp)).map(|(x, (a, m))| x + a + m).collect();
+        }
+        let expected = affine("lm_head", &layer_norm("model.final_layernorm", &x));
+        assert_close(&logits(&model, &[3]), &expected);
+
+        // Later positions rotate part of each head, the same with and without the cache
+        let mut cache = KvCache::new();
+        let cached = [model.forward_cached(&mut cache, &[3, 1]).unwrap(), model.forward_cached(&mut cache, &[4]).unwrap()];
+        assert_close(&cached.concat().concat(), &logits(&model, &[3, 1, 4]));
+    }
+
+    #[test]
+    fn test_decode_batch() {
+        let (ids, mask, shape) = pad_left(&[&[5, 6, 7], &[8]], 0);
+        assert_eq!(shape, [2, 3]);
+        assert_eq!(ids, vec![5.0, 6.0, 7.0, 0.0, 0.0, 8.0]);
+        assert_eq!(mask, vec![1.0, 1.0, 1.0, 0.0, 0.0, 1.0]);
+
+        // Counts up from the last real token and ends after 9; checks the padding on the way
+        let mut batch_sizes = Vec::new();
+        let prompts = vec![vec![7], vec![1, 2], vec![3]];
+        let outputs = decode_batch(&prompts, 4, 0, 16, |ids, mask, [batch, len]| {
+            batch_sizes.push(batch);
+            let rows = ids.chunks(len).zip(mask.chunks(len));
+            Ok(rows
+                .map(|(ids, mask)| {
+                    assert_eq!(mask.iter().filter(|&&m| m == 0.0).count(), ids.iter().take_while(|&&id| id == 0.0).count());
+                    let mut logits = vec![0.0; 11];
+                    logits[(ids[len - 1] as usize + 1) % 10] = 1.0;
+                    logits
+                })
+                .collect())
+        })
+        .unwrap();
+        assert_eq!(outputs[0], BatchOutput { tokens: vec![8, 9], finish: BatchFinish::Eos });
+        assert_eq!(outputs[1], BatchOutput { tokens: vec![3, 4, 5, 6], finish: BatchFinish::Length });
+        assert_eq!(outputs[2].tokens, vec![4, 5, 6, 7]);
+        // The finished sequence leaves the batch
+        assert_eq!(batch_sizes, vec![3, 3, 3, 2]);
+
+        let never = |_: Vec<f32>, _: Vec<f32>, _: [usize; 2]| -> Result<Vec<Vec<f32>>, String> { unreachable!() };
+        assert_eq!(decode_batch(&prompts, 0, 0, 16, never).unwrap()[1].tokens, Vec::<u32>::new());
+        assert!(decode_batch(&[vec![1], vec![]], 4, 0, 16, never).is_err());
+        assert!(decode_batch(&[vec![1; 17]], 4, 0, 16, never).is_err());
+    }
+
+    #[test]
+    fn test_generate_batch() {
+        let model = LlmModel::<B>::new(&tiny_config(), &Default::default());
+        // Placeholder logits are all equal, so token 0 wins
+        let outputs = model.generate_batch(&[vec![1, 2], vec![3]], 4, 15).unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[1], BatchOutput { tokens: vec![0; 4], finish: BatchFinish::Length });
+        assert!(model.generate_batch(&[], 4, 15).unwrap().is_empty());
+        // The context window of 8 tokens ends the longer prompt first
+        let outputs = model.generate_batch(&[vec![1; 6], vec![2]], 4, 15).unwrap();
+        assert_eq!([outputs[0].tokens.len(), outputs[1].tokens.len()], [2, 4]);
+    }
+
+    #[test]
+    fn test_adapter_for_other_model() {
+        let device = Default::default();
+        let mut model = LlmModel::<B>::new(&tiny_config(), &device);
+        let deeper = LoraAdapter::<B>::from_bytes("deep", CONFIG.as_bytes(), &adapter_weights(3, 2), &device).unwrap();
+        assert!(model.load_adapter(deeper).unwrap_err().contains("does not fit"));
+        let wider = LoraAdapter::<B>::from_bytes("wide", CONFIG.as_bytes(), &adapter_weights(0, 3), &device).unwrap();
+        assert!(model.load_adapter(wider).is_err());
+        assert!(model.adapters().is_empty());
+    }
+}
@@ -0,0 +1,380 @@
+//! Models from files already on the device
+//!
+//! A model directory (or a set of files picked in the browser) is recognized
+//! by its `config.json` plus safetensors weights, or by GGUF weights. The
+//! architecture and hyperparameters are read from the config, or from the
+//! GGUF metadata, so no registry entry is needed.
+
+use super::cache::FileData;
+use super::gguf::{self, MetadataValue};
+use super::weights::WeightMap;
+use super::{
+    Architecture, ChatTemplate, LlmConfig, ModelFiles, ModelSpec, Quantization, TextEmbeddingConfig, VisionConfig,
+    WhisperConfig, parse_shard_index,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Revision recorded for models that were not downloaded
+pub const LOCAL_REVISION: &str = "local";
+
+/// Collect model files, working out which hold the weights and in what order
+///
+/// Weights are the shards listed in a `*.safetensors.index.json`, otherwise
+/// every `*.safetensors` file sorted by name, otherwise the `*.gguf` file.
+/// Several GGUF files are only taken together when they are split shards
+/// (`*-00001-of-00002.gguf`); usually they are different quantizations of
+/// the same model. GGUF weights carry their own config, so `config.json`
+/// is only required alongside safetensors.
+pub fn collect_files(files: HashMap<String, FileData>) -> Result<ModelFiles, String> {
+    let index = files.keys().find(|name| name.ends_with(".safetensors.index.json"));
+    let weights = match index {
+        Some(index) => {
+            let shards = parse_shard_index(&files[index])?;
+            if let Some(missing) = shards.iter().find(|shard| !files.contains_key(*shard)) {
+                return Err(format!("Shard {} listed in {} is missing", missing, index));
+            }
+            shards
+        }
+        None => {
+            let mut safetensors: Vec<String> = files.keys().filter(|n| n.ends_with(".safetensors")).cloned().collect();
+            safetensors.sort();
+            safetensors
+        }
+    };
+    if !weights.is_empty() {
+        if !files.contains_key("config.json") {
+            return Err("Model files do not include a config.json".to_string());
+        }
+        return Ok(ModelFiles::new(LOCAL_REVISION, files, weights));
+    }
+
+    let mut weights: Vec<String> = files.keys().filter(|n| n.ends_with(".gguf")).cloned().collect();
+    weights.sort();
+    if weights.is_empty() {
+        return Err("Model files include no .safetensors or .gguf weights".to_string());
+    }
+    if weights.len() > 1 && !weights.iter().all(|name| name.contains("-of-")) {
+        return Err(format!("Model files include several GGUF models ({}); pick one", weights.join(", ")));
+    }
+    Ok(ModelFiles::new(LOCAL_REVISION, files, weights))
+}
+
+/// Collect model files from `(name, contents)` pairs, e.g. from a file picker
+pub fn files_from_bytes(files: impl IntoIterator<Item = (String, Vec<u8>)>) -> Result<ModelFiles, String> {
+    collect_files(files.into_iter().map(|(name, data)| (name, data.into())).collect())
+}
+
+/// Read the model files in a directory, memory-mapping the weights
+///
+/// JSON files are read into memory; everything else other than weights
+/// is ignored.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_dir(dir: &std::path::Path) -> Result<ModelFiles, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut files = HashMap::new();
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()).map(str::to_string) else {
+            continue;
+        };
+        let data = if name.ends_with(".safetensors") || name.ends_with(".gguf") {
+            FileData::map(&path)?
+        } else if name.ends_with(".json") {
+            FileData::Owned(std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?)
+        } else {
+            continue;
+        };
+        files.insert(name, data);
+    }
+    collect_files(files).map_err(|e| format!("{}: {}", dir.display(), e))
+}
+
+/// Id for a model from the paths of its files
+///
+/// Files picked as a directory share its name as their first path component,
+/// which becomes the id; otherwise the id is the name of the first weights file
+/// without its extension.
+pub fn model_id(paths: &[String]) -> String {
+    let dirs: Vec<&str> = paths.iter().filter_map(|path| path.split_once('/').map(|(dir, _)| dir)).collect();
+    if let Some(dir) = dirs.first().filter(|dir| dirs.len() == paths.len() && dirs.iter().all(|d| d == *dir)) {
+        return dir.to_string();
+    }
+    let mut weights: Vec<&str> = paths
+        .iter()
+        .map(|path| path.rsplit('/').next().unwrap_or(path))
+        .filter_map(|name| name.strip_suffix(".safetensors").or_else(|| name.strip_suffix(".gguf")))
+        .collect();
+    weights.sort();
+    weights.first().map_or_else(|| LOCAL_REVISION.to_string(), |name| name.to_string())
+}
+
+/// Id for the model in files chosen in an `<input type="file">` or dropped on the page
+///
+/// See [`model_id`]; browsers only report a file's directory when a whole
+/// directory was picked.
+pub fn file_list_id(list: &web_sys::FileList) -> String {
+    let paths: Vec<String> = (0..list.length())
+        .filter_map(|i| list.get(i))
+        .map(|file| {
+            js_sys::Reflect::get(&file, &"webkitRelativePath".into())
+                .ok()
+                .and_then(|path| path.as_string())
+                .filter(|path| !path.is_empty())
+                .unwrap_or_else(|| file.name())
+        })
+        .collect();
+    model_id(&paths)
+}
+
+/// Read the files chosen in an `<input type="file">` or dropped on the page
+pub async fn read_file_list(list: &web_sys::FileList) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut files = Vec::new();
+    for file in (0..list.length()).filter_map(|i| list.get(i)) {
+        let buffer = wasm_bindgen_futures::JsFuture::from(file.array_buffer())
+            .await
+            .map_err(|e| format!("Failed to read {}: {:?}", file.name(), e))?;
+        files.push((file.name(), js_sys::Uint8Array::new(&buffer).to_vec()));
+    }
+    Ok(files)
+}
+
+fn field(config: &Value, name: &str) -> Result<usize, String> {
+    config
+        .get(name)
+        .and_then(Value::as_u64)
+        .map(|n| n as usize)
+        .ok_or_else(|| format!("config.json is missing {}", name))
+}
+
+/// Read a model's architecture from its HuggingFace `config.json`
+///
+/// Whisper, Idefics3/SmolVLM and BERT configs are recognized by `model_type`;
+/// any other config with decoder hyperparameters is treated as an LLM, whose
+/// weights tell Llama and Phi layouts apart.
+pub fn architecture_from_config(config: &[u8], preprocessor: Option<&[u8]>) -> Result<Architecture, String> {
+    let config: Value = serde_json::from_slice(config).map_err(|e| format!("Invalid config.json: {}", e))?;
+    match config.get("model_type").and_then(Value::as_str).unwrap_or_default() {
+        "whisper" => Ok(Architecture::Whisper(WhisperConfig {
+            vocab_size: field(&config, "vocab_size")?,
+            num_mel_bins: field(&config, "num_mel_bins")?,
+            encoder_layers: field(&config, "encoder_layers")?,
+            encoder_attention_heads: field(&config, "encoder_attention_heads")?,
+            encoder_units: field(&config, "d_model")?,
+            decoder_layers: field(&config, "decoder_layers")?,
+            decoder_attention_heads: field(&config, "decoder_attention_heads")?,
+            decoder_units: field(&config, "d_model")?,
+        })),
+        "idefics3" | "smolvlm" => {
+            let vision = config.get("vision_config").ok_or("config.json is missing vision_config")?;
+            let text = config.get("text_config").ok_or("config.json is missing text_config")?;
+            let preprocessor: Value = preprocessor.and_then(|p| serde_json::from_slice(p).ok()).unwrap_or_default();
+            let norm = |name: &str| -> [f32; 3] {
+                preprocessor
+                    .get(name)
+                    .and_then(|v| serde_json::from_value::<[f32; 3]>(v.clone()).ok())
+                    .unwrap_or([0.5; 3])
+            };
+            Ok(Architecture::Vision(VisionConfig {
+                image_size: field(vision, "image_size")?,
+                patch_size: field(vision, "patch_size")?,
+                hidden_size: field(vision, "hidden_size")?,
+                num_layers: field(vision, "num_hidden_layers")?,
+                num_attention_heads: field(vision, "num_attention_heads")?,
+                intermediate_size: field(vision, "intermediate_size")?,
+                scale_factor: field(&config, "scale_factor")?,
+                text: llm_config(text)?,
+                image_mean: norm("image_mean"),
+                image_std: norm("image_std"),
+            }))
+        }
+        "bert" => Ok(Architecture::Embedding(TextEmbeddingConfig {
+            vocab_size: field(&config, "vocab_size")?,
+            hidden_size: field(&config, "hidden_size")?,
+            num_layers: field(&config, "num_hidden_layers")?,
+            num_attention_heads: field(&config, "num_attention_heads")?,
+            intermediate_size: field(&config, "intermediate_size")?,
+            max_position_embeddings: field(&config, "max_position_embeddings")?,
+        })),
+        _ => Ok(Architecture::Llm(llm_config(&config)?)),
+    }
+}
+
+/// Decoder hyperparameters of an LLM's config, or of a vision-language model's `text_config`
+fn llm_config(config: &Value) -> Result<LlmConfig, String> {
+    let float = |name: &str, default: f64| config.get(name).and_then(Value::as_f64).unwrap_or(default) as f32;
+    Ok(LlmConfig {
+        vocab_size: field(config, "vocab_size")?,
+        hidden_size: field(config, "hidden_size")?,
+        num_layers: field(config, "num_hidden_layers")?,
+        num_attention_heads: field(config, "num_attention_heads")?,
+        intermediate_size: field(config, "intermediate_size")?,
+        max_position_embeddings: field(config, "max_position_embeddings")?,
+        partial_rotary_factor: float("partial_rotary_factor", 1.0),
+        rope_theta: float("rope_theta", 10000.0),
+    })
+}
+
+/// Guess the chat template from the Jinja template in `tokenizer_config.json`
+fn chat_template(tokenizer_config: Option<&[u8]>) -> Option<ChatTemplate> {
+    let config: Value = serde_json::from_slice(tokenizer_config?).ok()?;
+    template_kind(config.get("chat_template")?.as_str()?)
+}
+
+/// Recognize a Jinja chat template by its role markers
+fn template_kind(template: &str) -> Option<ChatTemplate> {
+    if template.contains("<|im_start|>") {
+        Some(ChatTemplate::ChatMl)
+    } else if template.contains("<|assistant|>") {
+        Some(ChatTemplate::Zephyr)
+    } else if template.contains("Instruct:") {
+        Some(ChatTemplate::Phi)
+    } else {
+        None
+    }
+}
+
+/// Describe local model files as a [`ModelSpec`]
+///
+/// RAM usage is estimated as the weights plus a third for activations.
+/// GGUF weights are decoded to f32 when the model is built, so their RAM
+/// usage is estimated from the parameter count instead of the file size.
+pub fn spec_from_files(id: &str, files: &ModelFiles) -> Result<ModelSpec, String> {
+    let weight_bytes: u64 = files.weight_shards().iter().map(|shard| shard.len() as u64).sum();
+    let size_mb = weight_bytes.div_ceil(1024 * 1024) as u32;
+    let first_shard = files.weight_shards().first().copied().filter(|shard| shard.starts_with(gguf::MAGIC));
+    if let Some(shard) = first_shard {
+        let header = gguf::parse(shard)?;
+        let parameters = WeightMap::from_files(files)?.parameter_count() as u64;
+        let loaded_mb = (parameters * 4).div_ceil(1024 * 1024) as u32;
+        let template = header.metadata.get("tokenizer.chat_template").and_then(MetadataValue::as_str);
+        return Ok(ModelSpec {
+            id: id.to_string(),
+            architecture: Architecture::Llm(header.llm_config()?),
+            repo: String::new(),
+            files: super::ModelManifest::default(),
+            quantization: Quantization::F32,
+            size_mb,
+            ram_mb: loaded_mb + loaded_mb / 3,
+            chat_template: template.and_then(template_kind).or_else(|| chat_template(files.get("tokenizer_config.json"))),
+        });
+    }
+
+    let config = files.get("config.json").ok_or("Model files do not include a config.json")?;
+    let architecture = architecture_from_config(config, files.get("preprocessor_config.json"))?;
+
+    Ok(ModelSpec {
+        id: id.to_string(),
+        architecture,
+        // Local models are never downloaded
+        repo: String::new(),
+        files: super::ModelManifest::default(),
+        quantization: Quantization::F32,
+        size_mb,
+        ram_mb: size_mb + size_mb / 3,
+        chat_template: chat_template(files.get("tokenizer_config.json")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LLAMA_CONFIG: &str = r#"{"model_type": "llama", "vocab_size": 32000, "hidden_size": 64,
+        "num_hidden_layers": 2, "num_attention_heads": 4, "intermediate_size": 128, "max_position_embeddings": 256}"#;
+
+    fn file(data: &str) -> FileData {
+        data.as_bytes().to_vec().into()
+    }
+
+    #[test]
+    fn test_collect_weights() {
+        let files = HashMap::from([
+            ("config.json".to_string(), file(LLAMA_CONFIG)),
+            ("model-00002.safetensors".to_string(), file("b")),
+            ("model-00001.safetensors".to_string(), file("a")),
+            ("model.gguf".to_string(), file("g")),
+        ]);
+        assert_eq!(
+            collect_files(files.clone()).unwrap().weight_files(),
+            ["model-00001.safetensors", "model-00002.safetensors"]
+        );
+
+        let index = r#"{"weight_map": {"a": "model-00002.safetensors"}}"#;
+        let mut indexed = files.clone();
+        indexed.insert("model.safetensors.index.json".to_string(), file(index));
+        assert_eq!(collect_files(indexed).unwrap().weight_files(), ["model-00002.safetensors"]);
+
+        let no_config: HashMap<_, _> = files.iter().filter(|(n, _)| *n != "config.json").map(|(n, d)| (n.clone(), d.clone())).collect();
+        assert!(collect_files(no_config).unwrap_err().contains("config.json"));
+
+        // GGUF weights are used when there are no safetensors, and need no config.json
+        let gguf: HashMap<_, _> = files.into_iter().filter(|(n, _)| n == "model.gguf").collect();
+        assert_eq!(collect_files(gguf.clone()).unwrap().weight_files(), ["model.gguf"]);
+        let mut quantizations = gguf.clone();
+        quantizations.insert("model-q4.gguf".to_string(), file("q"));
+        assert!(collect_files(quantizations).unwrap_err().contains("several GGUF"));
+        let split = HashMap::from([
+            ("model-00002-of-00002.gguf".to_string(), file("b")),
+            ("model-00001-of-00002.gguf".to_string(), file("a")),
+        ]);
+        assert_eq!(
+            collect_files(split).unwrap().weight_files(),
+            ["model-00001-of-00002.gguf", "model-00002-of-00002.gguf"]
+        );
+
+        assert!(collect_files(HashMap::from([("config.json".to_string(), file(LLAMA_CONFIG))])).is_err());
+    }
+
+    #[test]
+    fn test_model_id_from_paths() {
+        let paths = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(model_id(&paths(&["tinyllama/config.json", "tinyllama/model.safetensors"])), "tinyllama");
+        assert_eq!(
+            model_id(&paths(&["config.json", "llama-2.safetensors", "llama-1.safetensors"])),
+            "llama-1"
+        );
+        assert_eq!(model_id(&paths(&["tinyllama-q8_0.gguf"])), "tinyllama-q8_0");
+        assert_eq!(model_id(&paths(&["config.json"])), LOCAL_REVISION);
+    }
+
+    #[test]
+    fn test_spec_from_config() {
+        let tokenizer_config = r#"{"chat_template": "{% for m in messages %}<|im_start|>{{ m.role }}{% endfor %}"}"#;
+        let files = files_from_bytes([
+            ("config.json".to_string(), LLAMA_CONFIG.as_bytes().to_vec()),
+            ("tokenizer_config.json".to_string(), tokenizer_config.as_bytes().to_vec()),
+            ("model.safetensors".to_string(), vec![0; 3 * 1024 * 1024]),
+        ])
+        .unwrap();
+        let spec = spec_from_files("my-llama", &files).unwrap();
+        assert_eq!(spec.role(), crate::models::ModelRole::TextGeneration);
+        assert_eq!(spec.size_mb, 3);
+        assert_eq!(spec.ram_mb, 4);
+        assert_eq!(spec.chat_template, Some(ChatTemplate::ChatMl));
+
+        let whisper = r#"{"model_type": "whisper", "vocab_size": 51865, "num_mel_bins": 80, "d_model": 384,
+            "encoder_layers": 4, "encoder_attention_heads": 6, "decoder_layers": 4, "decoder_attention_heads": 6}"#;
+        assert_eq!(
+            architecture_from_config(whisper.as_bytes(), None).unwrap(),
+            Architecture::Whisper(WhisperConfig::tiny())
+        );
+        let phi = LLAMA_CONFIG.replace("\"llama\"", "\"phi\", \"partial_rotary_factor\": 0.4");
+        let Architecture::Llm(phi) = architecture_from_config(phi.as_bytes(), None).unwrap() else { panic!() };
+        assert_eq!(phi.partial_rotary_factor, 0.4);
+        let bert = LLAMA_CONFIG.replace("llama", "bert");
+        assert_eq!(architecture_from_config(bert.as_bytes(), None).unwrap().role(), crate::models::ModelRole::Embeddings);
+        assert!(architecture_from_config(b"{\"model_type\": \"llama\"}", None).is_err());
+    }
+
+    #[test]
+    fn test_spec_from_gguf() {
+        use crate::models::llm::tests::{llama_config, llama_weights};
+        let config = llama_config();
+        let gguf = gguf::tests::llama_gguf(&config, 1, &llama_weights(&config, 1, false));
+        let files = files_from_bytes([("tiny.gguf".to_string(), gguf)]).unwrap();
+        let spec = spec_from_files("tiny", &files).unwrap();
+        assert_eq!(spec.architecture, Architecture::Llm(config));
+        assert_eq!(spec.chat_template, None);
+    }
+}
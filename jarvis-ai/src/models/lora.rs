@@ -0,0 +1,233 @@
+//! LoRA adapters for the language model
+//!
+//! An adapter stores a low-rank update `ΔW = B·A · alpha / r` for some of the
+//! base model's projections, in the PEFT format: an `adapter_config.json`
+//! and an `adapter_model.safetensors` with `lora_A`/`lora_B` pairs. The update
+//! is either added to a projection's output at runtime, so adapters can be
+//! swapped freely, or merged into the base weight once.
+
+use super::weights::WeightMap;
+use burn::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// File holding an adapter's hyperparameters
+pub const ADAPTER_CONFIG: &str = "adapter_config.json";
+/// File holding an adapter's weights
+pub const ADAPTER_WEIGHTS: &str = "adapter_model.safetensors";
+
+/// Hyperparameters from a PEFT `adapter_config.json`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LoraConfig {
+    /// Rank of the update
+    pub r: usize,
+    pub lora_alpha: f32,
+    /// Projections the adapter was trained on, e.g. `q_proj`, `v_proj`
+    #[serde(default, deserialize_with = "target_modules")]
+    pub target_modules: Vec<String>,
+    /// Scale by `alpha / sqrt(r)` instead of `alpha / r`
+    #[serde(default)]
+    pub use_rslora: bool,
+}
+
+/// `target_modules` is a list of names, or a single name like `all-linear`
+fn target_modules<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Targets {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Option::<Targets>::deserialize(deserializer)? {
+        Some(Targets::One(name)) => vec![name],
+        Some(Targets::Many(names)) => names,
+        None => Vec::new(),
+    })
+}
+
+impl LoraConfig {
+    /// Factor the update is multiplied by
+    pub fn scaling(&self) -> f32 {
+        let r = self.r.max(1) as f32;
+        if self.use_rslora { self.lora_alpha / r.sqrt() } else { self.lora_alpha / r }
+    }
+}
+
+/// Low-rank factors for one projection
+#[derive(Debug, Clone)]
+struct LoraPair<B: Backend> {
+    /// `[r, in_features]`
+    a: Tensor<B, 2>,
+    /// `[out_features, r]`
+    b: Tensor<B, 2>,
+}
+
+/// A LoRA adapter loaded on a device
+#[derive(Debug, Clone)]
+pub struct LoraAdapter<B: Backend> {
+    name: String,
+    config: LoraConfig,
+    /// Factors keyed by module path, e.g. `model.layers.0.self_attn.q_proj`
+    modules: HashMap<String, LoraPair<B>>,
+}
+
+impl<B: Backend> LoraAdapter<B> {
+    /// Load an adapter from the contents of its config and safetensors files
+    pub fn from_bytes(name: &str, config: &[u8], weights: &[u8], device: &B::Device) -> Result<Self, String> {
+        let config: LoraConfig =
+            serde_json::from_slice(config).map_err(|e| format!("Invalid {}: {}", ADAPTER_CONFIG, e))?;
+        let weights = WeightMap::from_shards(&[weights])?;
+
+        let mut modules = HashMap::new();
+        for tensor in weights.names() {
+            let Some(module) = tensor.strip_suffix(".lora_A.weight") else {
+                continue;
+            };
+            let b_name = format!("{}.lora_B.weight", module);
+            let pair = LoraPair {
+                a: weights.tensor(tensor, device)?,
+                b: weights.tensor(&b_name, device)?,
+            };
+            if pair.a.dims()[0] != config.r || pair.b.dims()[1] != config.r {
+                return Err(format!("{} does not have rank {}", module, config.r));
+            }
+            // PEFT prefixes the base model's module paths
+            let module = module.strip_prefix("base_model.model.").unwrap_or(module);
+            modules.insert(module.to_string(), pair);
+        }
+        if modules.is_empty() {
+            return Err(format!("{} contains no LoRA weights", ADAPTER_WEIGHTS));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            config,
+            modules,
+        })
+    }
+
+    /// Name the adapter was loaded under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Hyperparameters of the adapter
+    pub fn config(&self) -> &LoraConfig {
+        &self.config
+    }
+
+    /// Module paths the adapter updates, in no particular order
+    pub fn modules(&self) -> impl Iterator<Item = &str> {
+        self.modules.keys().map(String::as_str)
+    }
+
+    /// Input and output features of the projection a module's update applies to
+    pub fn shape(&self, module: &str) -> Option<[usize; 2]> {
+        self.modules.get(module).map(|pair| [pair.a.dims()[1], pair.b.dims()[0]])
+    }
+
+    /// The update to add to a projection's output, or `None` if the adapter does not touch `module`
+    ///
+    /// # Arguments
+    /// * `input` - The projection's input, `[batch, seq_len, in_features]`
+    /// * `scale` - Strength of the adapter, 1.0 as trained
+    pub fn forward(&self, module: &str, input: Tensor<B, 3>, scale: f32) -> Option<Tensor<B, 3>> {
+        let pair = self.modules.get(module)?;
+        let [batch, seq_len, in_features] = input.dims();
+        let out_features = pair.b.dims()[0];
+        let delta = input
+            .reshape([batch * seq_len, in_features])
+            .matmul(pair.a.clone().transpose())
+            .matmul(pair.b.clone().transpose())
+            .mul_scalar(self.config.scaling() * scale);
+        Some(delta.reshape([batch, seq_len, out_features]))
+    }
+
+    /// The update `ΔW = B·A` for a module, in the `[out_features, in_features]` layout of the base weight
+    pub fn delta(&self, module: &str, scale: f32) -> Option<Tensor<B, 2>> {
+        let pair = self.modules.get(module)?;
+        Some(pair.b.clone().matmul(pair.a.clone()).mul_scalar(self.config.scaling() * scale))
+    }
+
+    /// Merge the update into a base weight, so it costs nothing at runtime
+    pub fn merge(&self, module: &str, weight: Tensor<B, 2>, scale: f32) -> Tensor<B, 2> {
+        match self.delta(module, scale) {
+            Some(delta) => weight + delta,
+            None => weight,
+        }
+    }
+
+    /// Undo [`Self::merge`], restoring the base weight
+    pub fn unmerge(&self, module: &str, weight: Tensor<B, 2>, scale: f32) -> Tensor<B, 2> {
+        match self.delta(module, scale) {
+            Some(delta) => weight - delta,
+            None => weight,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+    use safetensors::tensor::TensorView;
+    use safetensors::Dtype;
+
+    pub(crate) const CONFIG: &str = r#"{"peft_type": "LORA", "r": 1, "lora_alpha": 2, "target_modules": ["q_proj"]}"#;
+
+    /// A rank 1 adapter for `model.layers.{layer}.self_attn.q_proj` of width `width`
+    pub(crate) fn adapter_weights(layer: usize, width: usize) -> Vec<u8> {
+        let bytes = |values: Vec<f32>| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>();
+        let a = bytes((0..width).map(|i| i as f32).collect());
+        let b = bytes(vec![1.0; width]);
+        let prefix = format!("base_model.model.model.layers.{}.self_attn.q_proj", layer);
+        let views = [
+            (format!("{}.lora_A.weight", prefix), TensorView::new(Dtype::F32, vec![1, width], &a).unwrap()),
+            (format!("{}.lora_B.weight", prefix), TensorView::new(Dtype::F32, vec![width, 1], &b).unwrap()),
+        ];
+        safetensors::serialize(views, &None).unwrap()
+    }
+
+    #[test]
+    fn test_load_and_apply() {
+        let device = Default::default();
+        let adapter = LoraAdapter::<NdArray<f32>>::from_bytes("persona", CONFIG.as_bytes(), &adapter_weights(0, 2), &device)
+            .unwrap();
+        let module = "model.layers.0.self_attn.q_proj";
+        assert_eq!(adapter.modules().collect::<Vec<_>>(), vec![module]);
+        assert_eq!(adapter.shape(module), Some([2, 2]));
+        assert_eq!(adapter.config().scaling(), 2.0);
+
+        // ΔW = B·A · 2 = [[0, 2], [0, 2]]; x = [1, 1] gives x·ΔWᵀ = [2, 2], halved by the scale
+        let input = Tensor::<NdArray<f32>, 3>::ones([1, 1, 2], &device);
+        let delta = adapter.forward(module, input.clone(), 0.5).unwrap();
+        assert_eq!(delta.into_data().to_vec::<f32>().unwrap(), vec![1.0, 1.0]);
+        assert!(adapter.forward("model.layers.0.mlp.up_proj", input, 1.0).is_none());
+
+        let weight = Tensor::<NdArray<f32>, 2>::zeros([2, 2], &device);
+        let merged = adapter.merge(module, weight, 1.0);
+        assert_eq!(merged.clone().into_data().to_vec::<f32>().unwrap(), vec![0.0, 2.0, 0.0, 2.0]);
+        let restored = adapter.unmerge(module, merged, 1.0);
+        assert_eq!(restored.into_data().to_vec::<f32>().unwrap(), vec![0.0; 4]);
+    }
+
+    #[test]
+    fn test_invalid_adapters() {
+        let device = Default::default();
+        let load = |config: &str, weights: &[u8]| {
+            LoraAdapter::<NdArray<f32>>::from_bytes("bad", config.as_bytes(), weights, &device)
+        };
+        let rank_2 = CONFIG.replace("\"r\": 1", "\"r\": 2");
+        assert!(load(&rank_2, &adapter_weights(0, 2)).unwrap_err().contains("rank 2"));
+        assert!(load("{}", &adapter_weights(0, 2)).is_err());
+
+        let empty = safetensors::serialize(Vec::<(String, TensorView)>::new(), &None).unwrap();
+        assert!(load(CONFIG, &empty).unwrap_err().contains("no LoRA weights"));
+
+        let config: LoraConfig =
+            serde_json::from_str(r#"{"r": 16, "lora_alpha": 16, "target_modules": "all-linear", "use_rslora": true}"#)
+                .unwrap();
+        assert_eq!(config.target_modules, vec!["all-linear"]);
+        assert_eq!(config.scaling(), 4.0);
+    }
+}
@@ -0,0 +1,343 @@
+//! Registry of models the engine can load
+//!
+//! Each model is described by a [`ModelSpec`]: where it is downloaded from,
+//! which architecture builds it and how much memory it needs. The built-in
+//! [`ModelType`] variants are registered by default, and more can be added at
+//! runtime from JSON or TOML manifests:
+//!
+//! ```toml
+//! [[models]]
+//! id = "tinyllama-q8"
+//! repo = "example/TinyLlama-1.1B-Chat-q8"
+//! quantization = "q8"
+//! size_mb = 300
+//! ram_mb = 450
+//! chat_template = "zephyr"
+//! architecture.llm = { vocab_size = 32000, hidden_size = 2048, num_layers = 22, num_attention_heads = 32, intermediate_size = 5632, max_position_embeddings = 2048 }
+//! ```
+
+use super::{LlmConfig, ModelManifest, ModelRole, ModelType, TextEmbeddingConfig, VisionConfig, WhisperConfig};
+use crate::agent::ToolCallFormat;
+use crate::types::{Message, MessageRole};
+use serde::{Deserialize, Serialize};
+
+/// Network architecture of a model, with its hyperparameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Architecture {
+    Whisper(WhisperConfig),
+    Llm(LlmConfig),
+    Vision(VisionConfig),
+    Embedding(TextEmbeddingConfig),
+}
+
+impl Architecture {
+    /// Get the role models of this architecture fill
+    pub fn role(&self) -> ModelRole {
+        match self {
+            Architecture::Whisper(_) => ModelRole::SpeechToText,
+            Architecture::Llm(_) => ModelRole::TextGeneration,
+            Architecture::Vision(_) => ModelRole::Vision,
+            Architecture::Embedding(_) => ModelRole::Embeddings,
+        }
+    }
+}
+
+/// Numeric format of a model's weights
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    #[default]
+    F32,
+    F16,
+    Q8,
+    Q4,
+}
+
+/// Prompt format a chat model was fine-tuned on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatTemplate {
+    /// `<|im_start|>role ... <|im_end|>` (Qwen, SmolLM)
+    ChatMl,
+    /// `<|role|> ... </s>` (Zephyr, TinyLlama)
+    Zephyr,
+    /// `Instruct: ... Output:` (Phi-2)
+    Phi,
+}
+
+impl ChatTemplate {
+    /// Syntax the models using this template call tools with
+    pub fn tool_format(&self) -> ToolCallFormat {
+        match self {
+            ChatTemplate::ChatMl => ToolCallFormat::Hermes,
+            ChatTemplate::Zephyr | ChatTemplate::Phi => ToolCallFormat::Json,
+        }
+    }
+
+    /// Render a conversation into a prompt ending where the assistant replies
+    ///
+    /// Tool calls are written in [`Self::tool_format`], and the responses of
+    /// calls that have run follow in a user turn.
+    pub fn apply(&self, messages: &[Message]) -> String {
+        let format = self.tool_format();
+        let mut prompt = String::new();
+        for message in messages {
+            let calls = message.tool_calls();
+            let text = std::iter::once(message.text())
+                .filter(|text| !text.is_empty())
+                .chain(calls.iter().map(|call| format.render_call(&call.function_name, &call.parameters)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            prompt.push_str(&self.turn(message.role, &text));
+
+            let responses: Vec<String> = calls
+                .iter()
+                .filter(|call| !call.response.is_empty())
+                .map(|call| format.render_response(&call.function_name, &call.response))
+                .collect();
+            if !responses.is_empty() {
+                prompt.push_str(&self.turn(MessageRole::User, &responses.join("\n")));
+            }
+        }
+        prompt.push_str(match self {
+            ChatTemplate::ChatMl => "<|im_start|>assistant\n",
+            ChatTemplate::Zephyr => "<|assistant|>\n",
+            ChatTemplate::Phi => "Output:",
+        });
+        prompt
+    }
+
+    fn turn(&self, role: MessageRole, text: &str) -> String {
+        let name = match role {
+            MessageRole::System => "system",
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+        };
+        match (self, role) {
+            (ChatTemplate::ChatMl, _) => format!("<|im_start|>{}\n{}<|im_end|>\n", name, text),
+            (ChatTemplate::Zephyr, _) => format!("<|{}|>\n{}</s>\n", name, text),
+            (ChatTemplate::Phi, MessageRole::System) => format!("{}\n", text),
+            (ChatTemplate::Phi, MessageRole::User) => format!("Instruct: {}\n", text),
+            (ChatTemplate::Phi, MessageRole::Assistant) => format!("Output: {}\n", text),
+        }
+    }
+}
+
+/// Everything needed to download, size and build a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSpec {
+    /// Unique identifier, e.g. `whisper-tiny`
+    pub id: String,
+    pub architecture: Architecture,
+    /// Repository on the model hub
+    pub repo: String,
+    #[serde(default)]
+    pub files: ModelManifest,
+    #[serde(default)]
+    pub quantization: Quantization,
+    /// Download size in MB
+    pub size_mb: u32,
+    /// Estimated RAM usage in MB
+    pub ram_mb: u32,
+    /// Prompt format for chat models
+    #[serde(default)]
+    pub chat_template: Option<ChatTemplate>,
+}
+
+impl ModelSpec {
+    /// Get the role this model fills in the inference engine
+    pub fn role(&self) -> ModelRole {
+        self.architecture.role()
+    }
+}
+
+impl From<ModelType> for ModelSpec {
+    fn from(model: ModelType) -> Self {
+        model.spec()
+    }
+}
+
+/// Manifest listing models to register
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegistryManifest {
+    pub models: Vec<ModelSpec>,
+}
+
+/// Models available to load, keyed by id
+#[derive(Debug, Clone, Default)]
+pub struct ModelRegistry {
+    specs: Vec<ModelSpec>,
+}
+
+impl ModelRegistry {
+    /// A registry of the built-in [`ModelType`] models
+    pub fn builtin() -> Self {
+        Self {
+            specs: ModelType::ALL.iter().map(ModelType::spec).collect(),
+        }
+    }
+
+    /// Add a model, replacing any registered with the same id
+    pub fn register(&mut self, spec: ModelSpec) {
+        match self.specs.iter_mut().find(|s| s.id == spec.id) {
+            Some(existing) => *existing = spec,
+            None => self.specs.push(spec),
+        }
+    }
+
+    /// Look up a model by id
+    pub fn get(&self, id: &str) -> Option<&ModelSpec> {
+        self.specs.iter().find(|s| s.id == id)
+    }
+
+    /// All registered models, in registration order
+    pub fn specs(&self) -> &[ModelSpec] {
+        &self.specs
+    }
+
+    /// Registered models that fill a role
+    pub fn for_role(&self, role: ModelRole) -> impl Iterator<Item = &ModelSpec> {
+        self.specs.iter().filter(move |s| s.role() == role)
+    }
+
+    /// Register every model in a manifest, returning how many were added
+    pub fn extend(&mut self, manifest: RegistryManifest) -> usize {
+        let count = manifest.models.len();
+        for spec in manifest.models {
+            self.register(spec);
+        }
+        count
+    }
+
+    /// Register models from a JSON manifest
+    pub fn load_json(&mut self, json: &str) -> Result<usize, String> {
+        let manifest = serde_json::from_str(json).map_err(|e| format!("Invalid model manifest: {}", e))?;
+        Ok(self.extend(manifest))
+    }
+
+    /// Register models from a TOML manifest
+    pub fn load_toml(&mut self, toml: &str) -> Result<usize, String> {
+        let manifest = toml::from_str(toml).map_err(|e| format!("Invalid model manifest: {}", e))?;
+        Ok(self.extend(manifest))
+    }
+
+    /// Register models from every `.json` and `.toml` manifest in a directory
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_dir(&mut self, dir: impl AsRef<std::path::Path>) -> Result<usize, String> {
+        let dir = dir.as_ref();
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        paths.sort();
+
+        let mut count = 0;
+        for path in paths {
+            let extension = path.extension().and_then(|e| e.to_str());
+            if !matches!(extension, Some("json" | "toml")) {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            count += match extension {
+                Some("json") => self.load_json(&text),
+                _ => self.load_toml(&text),
+            }
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MessagePartTool;
+
+    const MANIFEST: &str = r#"
+        [[models]]
+        id = "tinyllama-q8"
+        repo = "example/TinyLlama-1.1B-Chat-q8"
+        quantization = "q8"
+        size_mb = 300
+        ram_mb = 450
+        chat_template = "zephyr"
+        architecture.llm = { vocab_size = 32000, hidden_size = 2048, num_layers = 22, num_attention_heads = 32, intermediate_size = 5632, max_position_embeddings = 2048 }
+    "#;
+
+    #[test]
+    fn test_builtin_models() {
+        let registry = ModelRegistry::builtin();
+        assert_eq!(registry.specs().len(), ModelType::ALL.len());
+        for model in ModelType::ALL {
+            let spec = registry.get(model.id()).unwrap();
+            assert_eq!(spec.role(), model.role());
+            assert_eq!(spec.ram_mb, model.ram_mb());
+        }
+        let generators: Vec<_> = registry.for_role(ModelRole::TextGeneration).map(|s| s.id.as_str()).collect();
+        assert_eq!(generators, vec!["phi-2", "tinyllama"]);
+    }
+
+    #[test]
+    fn test_load_manifests() {
+        let mut registry = ModelRegistry::builtin();
+        assert_eq!(registry.load_toml(MANIFEST).unwrap(), 1);
+        let spec = registry.get("tinyllama-q8").unwrap();
+        assert_eq!(spec.quantization, Quantization::Q8);
+        assert_eq!(spec.role(), ModelRole::TextGeneration);
+        assert_eq!(spec.files, ModelManifest::default());
+        assert_eq!(spec.architecture, Architecture::Llm(LlmConfig::tiny_llama()));
+
+        // JSON manifests round-trip, and re-registering an id replaces it
+        let mut changed = spec.clone();
+        changed.ram_mb = 500;
+        let json = serde_json::to_string(&RegistryManifest { models: vec![changed] }).unwrap();
+        assert_eq!(registry.load_json(&json).unwrap(), 1);
+        assert_eq!(registry.get("tinyllama-q8").unwrap().ram_mb, 500);
+        assert_eq!(registry.specs().len(), ModelType::ALL.len() + 1);
+
+        assert!(registry.load_json("{\"models\": [{\"id\": \"broken\"}]}").is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_load_dir() {
+        let dir = std::env::temp_dir().join(format!("jarvis-registry-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tinyllama.toml"), MANIFEST).unwrap();
+        std::fs::write(dir.join("README.md"), "not a manifest").unwrap();
+
+        let mut registry = ModelRegistry::default();
+        assert_eq!(registry.load_dir(&dir).unwrap(), 1);
+        assert!(registry.get("tinyllama-q8").is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chat_templates() {
+        let messages = [Message::system("Be brief.".to_string()), Message::user("Hi".to_string())];
+        assert_eq!(
+            ChatTemplate::Zephyr.apply(&messages),
+            "<|system|>\nBe brief.</s>\n<|user|>\nHi</s>\n<|assistant|>\n"
+        );
+        assert_eq!(ChatTemplate::Phi.apply(&messages), "Be brief.\nInstruct: Hi\nOutput:");
+        assert!(ChatTemplate::ChatMl.apply(&messages).ends_with("<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"));
+    }
+
+    #[test]
+    fn test_tool_calls_in_templates() {
+        let mut call = MessagePartTool::new("get_time".to_string(), serde_json::json!({}));
+        let pending = Message::assistant_with_tool_calls("Checking.".to_string(), vec![call.clone()]);
+        call.response = "12:00".to_string();
+        let answered = Message::assistant_with_tool_calls(String::new(), vec![call]);
+
+        assert_eq!(
+            ChatTemplate::ChatMl.apply(&[pending]),
+            "<|im_start|>assistant\nChecking.\n<tool_call>\n{\"arguments\":{},\"name\":\"get_time\"}\n</tool_call><|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert_eq!(
+            ChatTemplate::Zephyr.apply(&[answered]),
+            "<|assistant|>\n{\"name\":\"get_time\",\"parameters\":{}}</s>\n<|user|>\n{\"name\":\"get_time\",\"output\":\"12:00\"}</s>\n<|assistant|>\n"
+        );
+    }
+}
@@ -0,0 +1,485 @@
+//! Speculative decoding with a draft model
+//!
+//! A small draft model proposes `draft_tokens` tokens one at a time, and the
+//! target model scores all of them in a single forward pass. Each draft token
+//! `x` is accepted with probability `min(1, p(x) / q(x))`, where `p` and `q`
+//! are the target's and the draft's distributions; at the first rejection a
+//! token is drawn from the residual `max(0, p - q)` instead. The output is
+//! therefore distributed exactly as if the target had decoded alone, but each
+//! target pass commits between one and `draft_tokens + 1` tokens.
+//!
+//! Both models decode against a KV cache; positions of rejected draft tokens
+//! are dropped from the caches before the next round.
+
+use super::llm::{KvCache, LlmModel};
+use burn::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A causal language model decoded incrementally against a KV cache
+pub trait CausalLm {
+    /// Run `tokens` after the cached positions, adding them to the cache, and
+    /// return the logits predicted after each of them
+    fn forward(&mut self, tokens: &[u32]) -> Result<Vec<Vec<f32>>, String>;
+
+    /// Number of cached positions
+    fn cached_len(&self) -> usize;
+
+    /// Drop every cached position from `len` on
+    fn truncate_cache(&mut self, len: usize);
+}
+
+/// An [`LlmModel`] decoding one sequence
+pub struct LlmSession<'a, B: Backend> {
+    model: &'a LlmModel<B>,
+    cache: KvCache<B>,
+    /// Image token and the embeddings the first pass holding it reads in its place
+    images: Option<(u32, Tensor<B, 2>)>,
+}
+
+impl<'a, B: Backend> LlmSession<'a, B> {
+    pub fn new(model: &'a LlmModel<B>) -> Self {
+        Self { model, cache: KvCache::new(), images: None }
+    }
+
+    /// A session for a prompt showing an image: its `image_token`s read the
+    /// rows of `images` in place of their embedding
+    pub fn with_images(model: &'a LlmModel<B>, image_token: u32, images: Tensor<B, 2>) -> Self {
+        Self { images: Some((image_token, images)), ..Self::new(model) }
+    }
+
+    pub fn cache(&self) -> &KvCache<B> {
+        &self.cache
+    }
+}
+
+impl<B: Backend> CausalLm for LlmSession<'_, B> {
+    fn forward(&mut self, tokens: &[u32]) -> Result<Vec<Vec<f32>>, String> {
+        match self.images.take_if(|(image_token, _)| tokens.contains(image_token)) {
+            Some((image_token, images)) => self.model.forward_cached_with_images(&mut self.cache, tokens, image_token, images),
+            None => self.model.forward_cached(&mut self.cache, tokens),
+        }
+    }
+
+    fn cached_len(&self) -> usize {
+        self.cache.len()
+    }
+
+    fn truncate_cache(&mut self, len: usize) {
+        self.cache.truncate(len);
+    }
+}
+
+/// Speculative decoding settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeculativeConfig {
+    /// Tokens the draft model proposes per target pass
+    pub draft_tokens: usize,
+    /// Sampling temperature; 0.0 decodes greedily
+    pub temperature: f32,
+    /// Top-p sampling threshold, applied to both models
+    pub top_p: f32,
+    /// Seed for the sampling random numbers
+    pub seed: u64,
+    /// Token that ends the output; it is not included in it
+    pub eos: Option<u32>,
+}
+
+impl Default for SpeculativeConfig {
+    fn default() -> Self {
+        Self {
+            draft_tokens: 4,
+            temperature: 0.0,
+            top_p: 1.0,
+            seed: 0x5EED,
+            eos: None,
+        }
+    }
+}
+
+/// How well the draft model predicted the target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpeculativeStats {
+    /// Draft tokens proposed
+    pub drafted: usize,
+    /// Draft tokens the target accepted
+    pub accepted: usize,
+    /// Forward passes of the target model
+    pub target_passes: usize,
+    /// Tokens generated
+    pub generated: usize,
+}
+
+impl SpeculativeStats {
+    /// Share of draft tokens accepted, from 0.0 to 1.0
+    pub fn acceptance_rate(&self) -> f32 {
+        if self.drafted == 0 {
+            0.0
+        } else {
+            self.accepted as f32 / self.drafted as f32
+        }
+    }
+
+    /// Tokens generated per forward pass of the target model
+    pub fn tokens_per_pass(&self) -> f32 {
+        if self.target_passes == 0 {
+            0.0
+        } else {
+            self.generated as f32 / self.target_passes as f32
+        }
+    }
+}
+
+/// Output of [`SpeculativeDecoder::generate`]
+#[derive(Debug, Clone)]
+pub struct SpeculativeOutput {
+    pub tokens: Vec<u32>,
+    pub stats: SpeculativeStats,
+}
+
+/// Generates with a target model, using a draft model to skip target passes
+pub struct SpeculativeDecoder {
+    config: SpeculativeConfig,
+    rng: u64,
+}
+
+impl SpeculativeDecoder {
+    pub fn new(config: SpeculativeConfig) -> Self {
+        let rng = config.seed;
+        Self { config, rng }
+    }
+
+    pub fn config(&self) -> &SpeculativeConfig {
+        &self.config
+    }
+
+    /// Generate up to `max_tokens` tokens after `prompt`
+    ///
+    /// Cached positions are kept where they are a prefix of `prompt`, e.g.
+    /// the earlier turns of a conversation; caches holding another sequence
+    /// must be truncated first.
+    ///
+    /// # Returns
+    /// * `Ok(SpeculativeOutput)` with the tokens and acceptance statistics
+    /// * `Err(String)` if the prompt is empty or a forward pass failed
+    pub fn generate<T: CausalLm + ?Sized, D: CausalLm + ?Sized>(
+        &mut self,
+        target: &mut T,
+        draft: &mut D,
+        prompt: &[u32],
+        max_tokens: usize,
+    ) -> Result<SpeculativeOutput, String> {
+        if prompt.is_empty() {
+            return Err("Prompt is empty".to_string());
+        }
+        let mut ids = prompt.to_vec();
+        let mut stats = SpeculativeStats::default();
+        // The last token is always run again, to get the logits after it
+        target.truncate_cache(target.cached_len().min(ids.len() - 1));
+        draft.truncate_cache(draft.cached_len().min(ids.len() - 1));
+
+        while stats.generated < max_tokens {
+            let base = ids.len();
+            // The target adds a token of its own, so never draft past `max_tokens`
+            let k = self.config.draft_tokens.min(max_tokens - stats.generated - 1);
+
+            let mut drafted = Vec::with_capacity(k);
+            let mut draft_probs = Vec::with_capacity(k);
+            let mut input = ids[draft.cached_len()..].to_vec();
+            for _ in 0..k {
+                let logits = draft.forward(&input)?;
+                let probs = self.distribution(logits.last().ok_or("Draft model returned no logits")?);
+                let token = self.sample(&probs);
+                drafted.push(token);
+                draft_probs.push(probs);
+                input = vec![token];
+            }
+
+            let mut input = ids[target.cached_len()..].to_vec();
+            input.extend_from_slice(&drafted);
+            let logits = target.forward(&input)?;
+            stats.target_passes += 1;
+            if logits.len() < k + 1 {
+                return Err("Target model returned too few logits".to_string());
+            }
+            let logits = &logits[logits.len() - k - 1..];
+
+            let mut accepted = 0;
+            let mut next = None;
+            for (i, (&token, q)) in drafted.iter().zip(&draft_probs).enumerate() {
+                let p = self.distribution(&logits[i]);
+                let (p_x, q_x) = (p[token as usize], q[token as usize]);
+                if self.uniform() * q_x < p_x {
+                    accepted += 1;
+                } else {
+                    next = Some(self.sample(&residual(&p, q)));
+                    break;
+                }
+            }
+            let next = match next {
+                Some(token) => token,
+                None => {
+                    let probs = self.distribution(&logits[k]);
+                    self.sample(&probs)
+                }
+            };
+            stats.drafted += k;
+            stats.accepted += accepted;
+
+            // Forget the rejected drafts; `next` is run at the start of the next round
+            target.truncate_cache(base + accepted);
+            draft.truncate_cache(draft.cached_len().min(base + accepted));
+
+            for &token in drafted[..accepted].iter().chain([&next]) {
+                if Some(token) == self.config.eos {
+                    return Ok(SpeculativeOutput { tokens: ids.split_off(prompt.len()), stats });
+                }
+                ids.push(token);
+                stats.generated += 1;
+            }
+        }
+        Ok(SpeculativeOutput { tokens: ids.split_off(prompt.len()), stats })
+    }
+
+    /// Generate up to `max_tokens` tokens after `prompt` with the target
+    /// alone, one pass per token, sampling as [`Self::generate`] does
+    ///
+    /// At temperature 0 the output matches `generate` with any draft model.
+    pub fn generate_without_draft<T: CausalLm + ?Sized>(
+        &mut self,
+        target: &mut T,
+        prompt: &[u32],
+        max_tokens: usize,
+    ) -> Result<Vec<u32>, String> {
+        if prompt.is_empty() {
+            return Err("Prompt is empty".to_string());
+        }
+        let mut ids = prompt.to_vec();
+        target.truncate_cache(target.cached_len().min(ids.len() - 1));
+        while ids.len() - prompt.len() < max_tokens {
+            let logits = target.forward(&ids[target.cached_len()..])?;
+            let probs = self.distribution(logits.last().ok_or("Target model returned no logits")?);
+            let token = self.sample(&probs);
+            if Some(token) == self.config.eos {
+                break;
+            }
+            ids.push(token);
+        }
+        Ok(ids.split_off(prompt.len()))
+    }
+
+    /// Sampling distribution for logits; one-hot on the top token when greedy
+    fn distribution(&self, logits: &[f32]) -> Vec<f32> {
+        let mut probs = vec![0.0; logits.len()];
+        if self.config.temperature <= 0.0 {
+            let top = logits.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1).then(b.0.cmp(&a.0)));
+            if let Some((top, _)) = top {
+                probs[top] = 1.0;
+            }
+            return probs;
+        }
+
+        let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        for (prob, &logit) in probs.iter_mut().zip(logits) {
+            *prob = ((logit - max) / self.config.temperature).exp();
+        }
+        let total: f32 = probs.iter().sum();
+        probs.iter_mut().for_each(|prob| *prob /= total);
+
+        if self.config.top_p < 1.0 {
+            let mut order: Vec<usize> = (0..probs.len()).collect();
+            order.sort_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+            let mut cumulative = 0.0;
+            let mut kept = 0.0;
+            for &i in &order {
+                if cumulative >= self.config.top_p {
+                    probs[i] = 0.0;
+                } else {
+                    cumulative += probs[i];
+                    kept += probs[i];
+                }
+            }
+            probs.iter_mut().for_each(|prob| *prob /= kept);
+        }
+        probs
+    }
+
+    /// Draw a token from a distribution
+    fn sample(&mut self, probs: &[f32]) -> u32 {
+        let target = self.uniform() * probs.iter().sum::<f32>();
+        let mut cumulative = 0.0;
+        for (token, &prob) in probs.iter().enumerate() {
+            cumulative += prob;
+            if prob > 0.0 && target < cumulative {
+                return token as u32;
+            }
+        }
+        // Rounding left `target` past the end; take the last possible token
+        probs.iter().rposition(|&prob| prob > 0.0).unwrap_or(0) as u32
+    }
+
+    /// Uniform random number in `[0, 1)` (SplitMix64)
+    fn uniform(&mut self) -> f32 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Normalized `max(0, p - q)`, the correction drawn from after a rejection
+fn residual(p: &[f32], q: &[f32]) -> Vec<f32> {
+    let residual: Vec<f32> = p.iter().zip(q).map(|(p, q)| (p - q).max(0.0)).collect();
+    // Only rounding can leave nothing; fall back to the target distribution
+    if residual.iter().sum::<f32>() <= 0.0 {
+        return p.to_vec();
+    }
+    residual
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+
+    /// Logits computed from the tokens so far, with a cache of those tokens
+    struct FnLm<F: Fn(&[u32]) -> Vec<f32>> {
+        logits: F,
+        cache: Vec<u32>,
+        calls: usize,
+    }
+
+    impl<F: Fn(&[u32]) -> Vec<f32>> FnLm<F> {
+        fn new(logits: F) -> Self {
+            Self { logits, cache: Vec::new(), calls: 0 }
+        }
+    }
+
+    impl<F: Fn(&[u32]) -> Vec<f32>> CausalLm for FnLm<F> {
+        fn forward(&mut self, tokens: &[u32]) -> Result<Vec<Vec<f32>>, String> {
+            self.calls += 1;
+            let mut rows = Vec::new();
+            for &token in tokens {
+                self.cache.push(token);
+                rows.push((self.logits)(&self.cache));
+            }
+            Ok(rows)
+        }
+
+        fn cached_len(&self) -> usize {
+            self.cache.len()
+        }
+
+        fn truncate_cache(&mut self, len: usize) {
+            self.cache.truncate(len);
+        }
+    }
+
+    /// Counts up modulo 8 from the last token
+    fn counter(tokens: &[u32]) -> Vec<f32> {
+        let mut logits = vec![0.0; 8];
+        logits[((tokens.last().unwrap() + 1) % 8) as usize] = 5.0;
+        logits
+    }
+
+    /// Like `counter`, but guesses wrong after every 3
+    fn sloppy_counter(tokens: &[u32]) -> Vec<f32> {
+        let mut logits = vec![0.0; 8];
+        let next = if *tokens.last().unwrap() == 3 { 0 } else { (tokens.last().unwrap() + 1) % 8 };
+        logits[next as usize] = 5.0;
+        logits
+    }
+
+    #[test]
+    fn test_greedy_matches_target() {
+        let mut decoder = SpeculativeDecoder::new(SpeculativeConfig::default());
+        let (mut target, mut draft) = (FnLm::new(counter), FnLm::new(counter));
+        let output = decoder.generate(&mut target, &mut draft, &[5, 6], 10).unwrap();
+        assert_eq!(output.tokens, vec![7, 0, 1, 2, 3, 4, 5, 6, 7, 0]);
+        assert_eq!(output.stats.acceptance_rate(), 1.0);
+        assert_eq!(output.stats.target_passes, 2);
+        assert_eq!(target.calls, 2);
+
+        // A worse draft costs target passes, not correctness
+        let mut decoder = SpeculativeDecoder::new(SpeculativeConfig::default());
+        let (mut target, mut draft) = (FnLm::new(counter), FnLm::new(sloppy_counter));
+        let output = decoder.generate(&mut target, &mut draft, &[5, 6], 10).unwrap();
+        assert_eq!(output.tokens, vec![7, 0, 1, 2, 3, 4, 5, 6, 7, 0]);
+        assert!(output.stats.acceptance_rate() < 1.0);
+        assert!(output.stats.target_passes > 2);
+        // Rejected drafts are not left in the caches
+        assert_eq!(target.cache, [5, 6, 7, 0, 1, 2, 3, 4, 5, 6, 7][..target.cache.len()]);
+        assert_eq!(draft.cache, [5, 6, 7, 0, 1, 2, 3, 4, 5, 6, 7][..draft.cache.len()]);
+
+        let config = SpeculativeConfig { eos: Some(2), ..Default::default() };
+        let output = SpeculativeDecoder::new(config.clone()).generate(&mut target, &mut draft, &[6], 10).unwrap();
+        assert_eq!(output.tokens, vec![7, 0, 1]);
+        assert!(decoder.generate(&mut target, &mut draft, &[], 10).is_err());
+
+        // Without a draft, one pass per token gives the same output
+        let mut target = FnLm::new(counter);
+        let tokens = SpeculativeDecoder::new(config).generate_without_draft(&mut target, &[5, 6], 10).unwrap();
+        assert_eq!(tokens, vec![7, 0, 1]);
+        assert_eq!(target.calls, 4);
+    }
+
+    #[test]
+    fn test_sampling_preserves_target_distribution() {
+        // Fixed distributions: the target prefers token 0, the draft token 2
+        let target_logits = |_: &[u32]| vec![0.5f32.ln(), 0.3f32.ln(), 0.2f32.ln()];
+        let draft_logits = |_: &[u32]| vec![0.1f32.ln(), 0.2f32.ln(), 0.7f32.ln()];
+        let config = SpeculativeConfig { draft_tokens: 3, temperature: 1.0, ..Default::default() };
+        let mut decoder = SpeculativeDecoder::new(config);
+        let (mut target, mut draft) = (FnLm::new(target_logits), FnLm::new(draft_logits));
+
+        let mut counts = [0usize; 3];
+        let mut stats = SpeculativeStats::default();
+        for _ in 0..2000 {
+            target.truncate_cache(0);
+            draft.truncate_cache(0);
+            let output = decoder.generate(&mut target, &mut draft, &[0], 8).unwrap();
+            output.tokens.iter().for_each(|&token| counts[token as usize] += 1);
+            stats.drafted += output.stats.drafted;
+            stats.accepted += output.stats.accepted;
+        }
+        let total = counts.iter().sum::<usize>() as f32;
+        for (count, expected) in counts.iter().zip([0.5, 0.3, 0.2]) {
+            assert!((*count as f32 / total - expected).abs() < 0.02, "{:?}", counts);
+        }
+        // Each draft is accepted with the overlap of the distributions, 0.1 + 0.2 + 0.2,
+        // and a rejection discards the rest of the round: (0.5 + 0.25 + 0.125) / 3
+        assert!((stats.acceptance_rate() - 0.29).abs() < 0.03, "{}", stats.acceptance_rate());
+    }
+
+    #[test]
+    fn test_llm_sessions() {
+        let config = crate::models::LlmConfig {
+            vocab_size: 16,
+            hidden_size: 2,
+            num_layers: 1,
+            num_attention_heads: 1,
+            intermediate_size: 4,
+            max_position_embeddings: 32,
+            partial_rotary_factor: 1.0,
+            rope_theta: 10000.0,
+        };
+        let device = Default::default();
+        let target = LlmModel::<NdArray<f32>>::new(&config, &device);
+        let draft = LlmModel::<NdArray<f32>>::new(&config, &device);
+        let (mut target, mut draft) = (LlmSession::new(&target), LlmSession::new(&draft));
+        let mut decoder = SpeculativeDecoder::new(SpeculativeConfig::default());
+        let output = decoder.generate(&mut target, &mut draft, &[1, 2, 3], 6).unwrap();
+        assert_eq!(output.tokens.len(), 6);
+        assert_eq!(output.stats.acceptance_rate(), 1.0);
+        assert_eq!(output.stats.tokens_per_pass(), 3.0);
+        assert_eq!(target.cache().len(), 8);
+
+        // The prompt is reused from the cache on the next turn
+        let mut prompt = vec![1, 2, 3];
+        prompt.extend(&output.tokens);
+        prompt.push(9);
+        assert!(decoder.generate(&mut target, &mut draft, &prompt, 30).is_err());
+        assert_eq!(decoder.generate(&mut target, &mut draft, &prompt, 4).unwrap().tokens.len(), 4);
+    }
+}
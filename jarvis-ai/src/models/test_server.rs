@@ -0,0 +1,179 @@
+//! Minimal HTTP/1.1 stand-in for the model hub, used by download tests
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+/// A request received by the [`TestServer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Received {
+    pub method: String,
+    pub path: String,
+    /// Start offset of a `Range: bytes=N-` header
+    pub range_start: Option<u64>,
+    pub authorization: Option<String>,
+}
+
+/// A file served by the [`TestServer`]
+#[derive(Debug, Clone, Default)]
+pub struct TestFile {
+    pub body: Vec<u8>,
+    /// Sent as `X-Linked-Etag`, like HuggingFace does for LFS files
+    pub sha256: Option<String>,
+    /// Drop the first response after this many body bytes
+    pub cut_after: Option<usize>,
+    /// Redirect to the body at `/cdn<path>`, sending the hash only on the
+    /// redirect like HuggingFace does for LFS files
+    pub cdn: bool,
+}
+
+impl TestFile {
+    pub fn new(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            body: body.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Serve the correct sha256 of the body as its LFS hash
+    pub fn with_lfs_hash(mut self) -> Self {
+        self.sha256 = Some(super::cache::sha256_hex(&self.body));
+        self
+    }
+
+    /// Serve the body from a CDN path, behind a redirect
+    pub fn behind_cdn(mut self) -> Self {
+        self.cdn = true;
+        self
+    }
+}
+
+/// Serves files by path, honouring `HEAD` and `Range: bytes=N-`
+///
+/// Files behind a CDN answer with a 302 to `/cdn<path>`, which serves the
+/// body with an `ETag` that is not its sha256.
+///
+/// Unknown paths get a 404. If a bearer token is required, requests without
+/// it get a 401 like a gated HuggingFace repository.
+pub struct TestServer {
+    pub url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl TestServer {
+    pub fn start(files: HashMap<String, TestFile>) -> Self {
+        Self::start_with_token(files, None)
+    }
+
+    pub fn start_with_token(files: HashMap<String, TestFile>, token: Option<&str>) -> Self {
+        let expected_auth = token.map(|token| format!("Bearer {}", token));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+
+        std::thread::spawn(move || {
+            let mut served: HashMap<String, usize> = HashMap::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).to_string();
+                let mut lines = head.lines();
+                let mut request_line = lines.next().unwrap_or_default().split(' ');
+                let method = request_line.next().unwrap_or_default().to_string();
+                let path = request_line.next().unwrap_or_default().to_string();
+                let header = |name: &str| {
+                    head.lines().skip(1).find_map(|line| {
+                        let (key, value) = line.split_once(':')?;
+                        key.eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+                    })
+                };
+                let range_start = header("range")
+                    .and_then(|r| r.strip_prefix("bytes=").map(|r| r.trim_end_matches('-').to_string()))
+                    .and_then(|r| r.parse::<u64>().ok());
+                let authorization = header("authorization");
+                let authorized = expected_auth.is_none() || authorization == expected_auth;
+                log.lock().unwrap().push(Received {
+                    method: method.clone(),
+                    path: path.clone(),
+                    range_start,
+                    authorization,
+                });
+
+                let cdn_path = path.strip_prefix("/cdn").filter(|path| files.get(*path).is_some_and(|file| file.cdn));
+                let file = files.get(cdn_path.unwrap_or(&path));
+                if !authorized || file.is_none() {
+                    let status = if authorized { "404 Not Found" } else { "401 Unauthorized" };
+                    let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                    let _ = stream.write_all(response.as_bytes());
+                    continue;
+                }
+                let file = file.unwrap();
+                let etag = file
+                    .sha256
+                    .as_ref()
+                    .map_or(String::new(), |sha| format!("x-linked-etag: \"{}\"\r\n", sha));
+                if file.cdn && cdn_path.is_none() {
+                    let response = format!(
+                        "HTTP/1.1 302 Found\r\nlocation: /cdn{}\r\nx-linked-size: {}\r\n{}content-length: 0\r\nconnection: close\r\n\r\n",
+                        path,
+                        file.body.len(),
+                        etag
+                    );
+                    let _ = stream.write_all(response.as_bytes());
+                    continue;
+                }
+                let etag = if file.cdn { format!("etag: \"cdn-{}\"\r\n", file.body.len()) } else { etag };
+
+                let start = range_start.unwrap_or(0) as usize;
+                let part = &file.body[start.min(file.body.len())..];
+                let status = if start > 0 { "206 Partial Content" } else { "200 OK" };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\n{}connection: close\r\n\r\n",
+                    status,
+                    part.len(),
+                    etag
+                );
+                let _ = stream.write_all(response.as_bytes());
+                if method == "HEAD" {
+                    continue;
+                }
+
+                let count = served.entry(path).or_insert(0);
+                let body = match file.cut_after {
+                    Some(cut) if *count == 0 => &part[..cut.min(part.len())],
+                    _ => part,
+                };
+                *count += 1;
+                let _ = stream.write_all(body);
+            }
+        });
+
+        Self { url, received }
+    }
+
+    /// Serve a single file at `/file`
+    pub fn single(file: TestFile) -> Self {
+        Self::start(HashMap::from([("/file".to_string(), file)]))
+    }
+
+    /// URL of a path on this server
+    pub fn url_of(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+
+    /// Requests received so far
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// `GET` requests received so far
+    pub fn gets(&self) -> Vec<Received> {
+        self.received().into_iter().filter(|r| r.method == "GET").collect()
+    }
+}
@@ -0,0 +1,554 @@
+//! Vision-language model implementation using Burn
+//!
+//! A SigLIP-style ViT encoder turns an image into patch embeddings, and an
+//! Idefics3-style connector (pixel shuffle + linear projection) maps them into
+//! the language model's embedding space, where they stand in for the
+//! `<image>` tokens of the prompt. The language model is a Llama-style
+//! [`LlmModel`], so replies are decoded like any other LLM's.
+
+use crate::image::ImagePreprocessConfig;
+use super::llm::{LlmConfig, LlmModel};
+use super::weights::WeightMap;
+use burn::prelude::*;
+use burn::tensor::activation::softmax;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use log;
+
+/// Token the image embeddings replace in the prompt
+pub const IMAGE_TOKEN: &str = "<image>";
+/// Token that ends a turn of the Idefics3 chat format
+pub const END_OF_UTTERANCE: &str = "<end_of_utterance>";
+/// LayerNorm epsilon of SigLIP checkpoints
+const LAYER_NORM_EPS: f32 = 1e-6;
+
+/// Configuration for the vision-language model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VisionConfig {
+    /// Input resolution (square)
+    pub image_size: usize,
+    pub patch_size: usize,
+    pub hidden_size: usize,
+    pub num_layers: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    /// Pixel shuffle factor; reduces image tokens by `scale_factor²`
+    pub scale_factor: usize,
+    /// Language model the image tokens are fed into
+    pub text: LlmConfig,
+    /// Per-channel normalization mean
+    pub image_mean: [f32; 3],
+    /// Per-channel normalization standard deviation
+    pub image_std: [f32; 3],
+}
+
+impl VisionConfig {
+    /// SmolVLM-256M configuration (SigLIP base/16 at 512px, SmolLM2-135M text model)
+    pub fn smolvlm_256m() -> Self {
+        Self {
+            image_size: 512,
+            patch_size: 16,
+            hidden_size: 768,
+            num_layers: 12,
+            num_attention_heads: 12,
+            intermediate_size: 3072,
+            scale_factor: 4,
+            text: LlmConfig {
+                vocab_size: 49280,
+                hidden_size: 576,
+                num_layers: 30,
+                num_attention_heads: 9,
+                intermediate_size: 1536,
+                max_position_embeddings: 8192,
+                partial_rotary_factor: 1.0,
+                rope_theta: 100000.0,
+            },
+            image_mean: [0.5, 0.5, 0.5],
+            image_std: [0.5, 0.5, 0.5],
+        }
+    }
+
+    /// Patches along one side of the image
+    pub fn patches_per_side(&self) -> usize {
+        self.image_size / self.patch_size
+    }
+
+    /// Total number of patches produced by the encoder
+    pub fn num_patches(&self) -> usize {
+        self.patches_per_side() * self.patches_per_side()
+    }
+
+    /// Number of image tokens handed to the language model
+    pub fn num_image_tokens(&self) -> usize {
+        self.num_patches() / (self.scale_factor * self.scale_factor)
+    }
+
+    /// Image preprocessing matching this encoder's training setup
+    pub fn preprocess_config(&self) -> ImagePreprocessConfig {
+        ImagePreprocessConfig {
+            mean: self.image_mean,
+            std: self.image_std,
+            ..ImagePreprocessConfig::siglip(self.image_size as u32)
+        }
+    }
+
+    /// Prompt asking `question` about one image, in the Idefics3 chat format
+    pub fn image_prompt(&self, question: &str) -> String {
+        format!(
+            "<|im_start|>User:<fake_token_around_image><global-img>{}<fake_token_around_image>{}{}\nAssistant:",
+            IMAGE_TOKEN.repeat(self.num_image_tokens()),
+            question,
+            END_OF_UTTERANCE
+        )
+    }
+}
+
+/// A linear layer; `weight` is `[out_features, in_features]`
+struct Linear<B: Backend> {
+    weight: Tensor<B, 2>,
+    bias: Tensor<B, 1>,
+}
+
+impl<B: Backend> Linear<B> {
+    /// `input · weightᵀ + bias` over the last dimension
+    fn forward(&self, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        let [batch, len, in_features] = input.dims();
+        let out_features = self.weight.dims()[0];
+        let output = input.reshape([batch * len, in_features]).matmul(self.weight.clone().transpose());
+        (output + self.bias.clone().reshape([1, out_features])).reshape([batch, len, out_features])
+    }
+}
+
+struct Norm<B: Backend> {
+    gamma: Tensor<B, 1>,
+    beta: Tensor<B, 1>,
+}
+
+impl<B: Backend> Norm<B> {
+    fn forward(&self, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        let hidden = self.gamma.dims()[0];
+        let centered = input.clone() - input.mean_dim(2);
+        let std = (centered.clone().powf_scalar(2.0).mean_dim(2) + LAYER_NORM_EPS).sqrt();
+        centered / std * self.gamma.clone().reshape([1, 1, hidden]) + self.beta.clone().reshape([1, 1, hidden])
+    }
+}
+
+/// Parameters of one pre-norm encoder layer
+struct EncoderLayer<B: Backend> {
+    layer_norm1: Norm<B>,
+    q_proj: Linear<B>,
+    k_proj: Linear<B>,
+    v_proj: Linear<B>,
+    out_proj: Linear<B>,
+    layer_norm2: Norm<B>,
+    fc1: Linear<B>,
+    fc2: Linear<B>,
+}
+
+/// Vision-language model implementation
+///
+/// Parameters are an Idefics3/SmolVLM checkpoint's, in the HuggingFace
+/// layout: `model.vision_model.*`, `model.connector.*` and the text model
+/// under `model.text_model.*` with `lm_head`.
+pub struct VisionModel<B: Backend> {
+    config: VisionConfig,
+    /// Flattened patches (`3 * patch²` values, channel-major) to hidden size
+    patch_embedding: Linear<B>,
+    /// `[num_patches, hidden_size]`
+    position_embedding: Tensor<B, 2>,
+    layers: Vec<EncoderLayer<B>>,
+    post_layernorm: Norm<B>,
+    /// Pixel-shuffled features to language model embeddings, `[text_hidden_size, hidden_size * scale_factor²]`
+    connector: Tensor<B, 2>,
+    text: LlmModel<B>,
+}
+
+impl<B: Backend> VisionModel<B> {
+    /// Create a model from HuggingFace Idefics3/SmolVLM weights
+    pub fn from_weights(config: &VisionConfig, weights: &WeightMap, device: &B::Device) -> Result<Self, String> {
+        let hidden = config.hidden_size;
+        if config.num_attention_heads == 0 || !hidden.is_multiple_of(config.num_attention_heads) {
+            return Err(format!("Hidden size {} does not split into {} heads", hidden, config.num_attention_heads));
+        }
+        let side = config.patches_per_side();
+        if side == 0 || config.scale_factor == 0 || !side.is_multiple_of(config.scale_factor) {
+            return Err(format!("{} patches per side do not shuffle by {}", side, config.scale_factor));
+        }
+        let tensor = |name: &str, dims: &[usize]| -> Result<Tensor<B, 2>, String> {
+            match weights.shape(name) {
+                Some(shape) if shape != dims => Err(format!("Tensor {} has shape {:?}, expected {:?}", name, shape, dims)),
+                _ => weights.tensor(name, device),
+            }
+        };
+        let vector = |name: &str, size: usize| -> Result<Tensor<B, 1>, String> {
+            match weights.shape(name) {
+                Some(shape) if shape != [size] => Err(format!("Tensor {} has shape {:?}, expected [{}]", name, shape, size)),
+                _ => weights.tensor(name, device),
+            }
+        };
+        let linear = |name: &str, out_features: usize, in_features: usize| -> Result<Linear<B>, String> {
+            Ok(Linear {
+                weight: tensor(&format!("{}.weight", name), &[out_features, in_features])?,
+                bias: vector(&format!("{}.bias", name), out_features)?,
+            })
+        };
+        let norm = |name: &str| -> Result<Norm<B>, String> {
+            Ok(Norm {
+                gamma: vector(&format!("{}.weight", name), hidden)?,
+                beta: vector(&format!("{}.bias", name), hidden)?,
+            })
+        };
+
+        // The patch embedding is a convolution with a stride of its kernel size
+        let patch = config.patch_size;
+        let name = "model.vision_model.embeddings.patch_embedding.weight";
+        let kernel: Tensor<B, 4> = match weights.shape(name) {
+            Some(shape) if shape != [hidden, 3, patch, patch] => {
+                return Err(format!("Tensor {} has shape {:?}, expected {:?}", name, shape, [hidden, 3, patch, patch]))
+            }
+            _ => weights.tensor(name, device)?,
+        };
+        let patch_embedding = Linear {
+            weight: kernel.reshape([hidden, 3 * patch * patch]),
+            bias: vector("model.vision_model.embeddings.patch_embedding.bias", hidden)?,
+        };
+
+        let mut layers = Vec::with_capacity(config.num_layers);
+        for i in 0..config.num_layers {
+            let name = |module: &str| format!("model.vision_model.encoder.layers.{}.{}", i, module);
+            layers.push(EncoderLayer {
+                layer_norm1: norm(&name("layer_norm1"))?,
+                q_proj: linear(&name("self_attn.q_proj"), hidden, hidden)?,
+                k_proj: linear(&name("self_attn.k_proj"), hidden, hidden)?,
+                v_proj: linear(&name("self_attn.v_proj"), hidden, hidden)?,
+                out_proj: linear(&name("self_attn.out_proj"), hidden, hidden)?,
+                layer_norm2: norm(&name("layer_norm2"))?,
+                fc1: linear(&name("mlp.fc1"), config.intermediate_size, hidden)?,
+                fc2: linear(&name("mlp.fc2"), hidden, config.intermediate_size)?,
+            });
+        }
+
+        let text_weights = weights.renamed(&[("model.text_model.", "model."), ("lm_head.", "lm_head.")]);
+        let shuffled = hidden * config.scale_factor * config.scale_factor;
+        Ok(Self {
+            config: config.clone(),
+            patch_embedding,
+            position_embedding: tensor(
+                "model.vision_model.embeddings.position_embedding.weight",
+                &[config.num_patches(), hidden],
+            )?,
+            layers,
+            post_layernorm: norm("model.vision_model.post_layernorm")?,
+            connector: tensor("model.connector.modality_projection.proj.weight", &[config.text.hidden_size, shuffled])?,
+            text: LlmModel::from_weights(&config.text, &text_weights, device)?,
+        })
+    }
+
+    /// Get the device the model runs on
+    pub fn device(&self) -> B::Device {
+        self.position_embedding.device()
+    }
+
+    pub fn config(&self) -> &VisionConfig {
+        &self.config
+    }
+
+    /// Language model that decodes replies about the image
+    pub fn text_model(&self) -> &LlmModel<B> {
+        &self.text
+    }
+
+    /// Encode normalized pixel values into language model embeddings
+    ///
+    /// # Arguments
+    /// * `pixel_values` - `[batch, 3, image_size, image_size]`
+    ///
+    /// # Returns
+    /// `[batch, num_image_tokens, text.hidden_size]`
+    pub fn encode_image(&self, pixel_values: Tensor<B, 4>) -> Tensor<B, 3> {
+        let patches = patchify(pixel_values, self.config.patch_size);
+        let [batch, len, _] = patches.dims();
+        let hidden = self.config.hidden_size;
+        let heads = self.config.num_attention_heads;
+        let head_dim = hidden / heads;
+
+        let mut x = self.patch_embedding.forward(patches) + self.position_embedding.clone().unsqueeze_dim::<3>(0);
+        let scale = (head_dim as f32).sqrt();
+        for layer in &self.layers {
+            let h = layer.layer_norm1.forward(x.clone());
+            let split = |t: Tensor<B, 3>| t.reshape([batch, len, heads, head_dim]).swap_dims(1, 2);
+            let q = split(layer.q_proj.forward(h.clone()));
+            let k = split(layer.k_proj.forward(h.clone()));
+            let v = split(layer.v_proj.forward(h));
+            let scores = q.matmul(k.swap_dims(2, 3)) / scale;
+            let attention = softmax(scores, 3).matmul(v).swap_dims(1, 2).reshape([batch, len, hidden]);
+            x = x + layer.out_proj.forward(attention);
+
+            let h = gelu_tanh(layer.fc1.forward(layer.layer_norm2.forward(x.clone())));
+            x = x + layer.fc2.forward(h);
+        }
+        let features = pixel_shuffle(self.post_layernorm.forward(x), self.config.scale_factor);
+        let [batch, tokens, shuffled] = features.dims();
+        features
+            .reshape([batch * tokens, shuffled])
+            .matmul(self.connector.clone().transpose())
+            .reshape([batch, tokens, self.config.text.hidden_size])
+    }
+}
+
+/// GELU with the tanh approximation SigLIP was trained with
+fn gelu_tanh<B: Backend>(input: Tensor<B, 3>) -> Tensor<B, 3> {
+    let inner = (input.clone() + input.clone().powf_scalar(3.0) * 0.044715) * (2.0 / PI).sqrt();
+    input * (inner.tanh() + 1.0) * 0.5
+}
+
+/// Split an image into flattened, non-overlapping patches
+fn patchify<B: Backend>(pixel_values: Tensor<B, 4>, patch: usize) -> Tensor<B, 3> {
+    let [batch, channels, height, width] = pixel_values.dims();
+    let (rows, cols) = (height / patch, width / patch);
+
+    pixel_values
+        .reshape([batch, channels, rows, patch, cols, patch])
+        .permute([0, 2, 4, 1, 3, 5])
+        .reshape([batch, rows * cols, channels * patch * patch])
+}
+
+/// Merge each `scale × scale` block of patches into one token
+fn pixel_shuffle<B: Backend>(features: Tensor<B, 3>, scale: usize) -> Tensor<B, 3> {
+    let [batch, n_patches, hidden] = features.dims();
+    let side = (n_patches as f64).sqrt() as usize;
+    let grid = side / scale;
+
+    features
+        .reshape([batch, grid, scale, grid, scale * hidden])
+        .permute([0, 1, 3, 2, 4])
+        .reshape([batch, grid * grid, scale * scale * hidden])
+}
+
+/// Create a vision-language model from Idefics3/SmolVLM weights
+///
+/// Descriptions from random parameters would be made up, so missing
+/// weights are an error.
+pub fn create_vision_model<B: Backend>(
+    config: &VisionConfig,
+    weights: &WeightMap,
+    device: &B::Device,
+) -> Result<VisionModel<B>, String> {
+    if weights.is_empty() {
+        return Err("Vision models need their weights".to_string());
+    }
+    log::info!(
+        "Loading vision model weights: {} tensors, {} parameters",
+        weights.len(),
+        weights.parameter_count()
+    );
+    VisionModel::from_weights(config, weights, device)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::llm::tests::{assert_close, checkpoint, llama_config, llama_shapes};
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    /// A 16-pixel image of 4×4 patches, shuffled into 4 tokens for a tiny Llama
+    pub(crate) fn tiny_config() -> VisionConfig {
+        VisionConfig {
+            image_size: 16,
+            patch_size: 4,
+            hidden_size: 8,
+            num_layers: 1,
+            num_attention_heads: 2,
+            intermediate_size: 12,
+            scale_factor: 2,
+            text: LlmConfig { max_position_embeddings: 64, ..llama_config() },
+            image_mean: [0.5, 0.5, 0.5],
+            image_std: [0.5, 0.5, 0.5],
+        }
+    }
+
+    /// Safetensors of a SmolVLM checkpoint with pseudo-random weights
+    pub(crate) fn smolvlm_weights(config: &VisionConfig) -> Vec<u8> {
+        let (hidden, patch, inter) = (config.hidden_size, config.patch_size, config.intermediate_size);
+        let vision = |name: &str| format!("model.vision_model.{}", name);
+        let mut shapes = vec![
+            (vision("embeddings.patch_embedding.weight"), vec![hidden, 3, patch, patch]),
+            (vision("embeddings.patch_embedding.bias"), vec![hidden]),
+            (vision("embeddings.position_embedding.weight"), vec![config.num_patches(), hidden]),
+        ];
+        for i in 0..config.num_layers {
+            let modules = [
+                ("layer_norm1", hidden, None),
+                ("self_attn.q_proj", hidden, Some(hidden)),
+                ("self_attn.k_proj", hidden, Some(hidden)),
+                ("self_attn.v_proj", hidden, Some(hidden)),
+                ("self_attn.out_proj", hidden, Some(hidden)),
+                ("layer_norm2", hidden, None),
+                ("mlp.fc1", inter, Some(hidden)),
+                ("mlp.fc2", hidden, Some(inter)),
+            ];
+            for (module, out, input) in modules {
+                let name = |param: &str| vision(&format!("encoder.layers.{}.{}.{}", i, module, param));
+                shapes.push((name("weight"), input.map_or(vec![out], |input| vec![out, input])));
+                shapes.push((name("bias"), vec![out]));
+            }
+        }
+        let shuffled = hidden * config.scale_factor * config.scale_factor;
+        shapes.extend([
+            (vision("post_layernorm.weight"), vec![hidden]),
+            (vision("post_layernorm.bias"), vec![hidden]),
+            ("model.connector.modality_projection.proj.weight".to_string(), vec![config.text.hidden_size, shuffled]),
+        ]);
+        let text = llama_shapes(&config.text, 1, false).into_iter();
+        shapes.extend(text.map(|(name, shape)| (name.replacen("model.", "model.text_model.", 1), shape)));
+        checkpoint(&shapes)
+    }
+
+    /// Image embeddings computed one value at a time
+    fn reference_encoding(config: &VisionConfig, weights: &WeightMap, pixels: &[f32]) -> Vec<f32> {
+        let values = |name: &str| -> Vec<f32> {
+            let name = format!("model.vision_model.{}", name);
+            let dims = weights.shape(&name).unwrap().len();
+            match dims {
+                1 => weights.tensor::<B, 1>(&name, &Default::default()).unwrap().into_data().to_vec().unwrap(),
+                2 => weights.tensor::<B, 2>(&name, &Default::default()).unwrap().into_data().to_vec().unwrap(),
+                _ => weights.tensor::<B, 4>(&name, &Default::default()).unwrap().into_data().to_vec().unwrap(),
+            }
+        };
+        let linear = |x: &[f32], weight: &[f32], bias: Option<&[f32]>| -> Vec<f32> {
+            let rows = weight.len() / x.len();
+            (0..rows)
+                .map(|r| x.iter().zip(&weight[r * x.len()..]).map(|(a, b)| a * b).sum::<f32>() + bias.map_or(0.0, |b| b[r]))
+                .collect()
+        };
+        let layer_norm = |x: &[f32], name: &str| -> Vec<f32> {
+            let (gamma, beta) = (values(&format!("{}.weight", name)), values(&format!("{}.bias", name)));
+            let mean = x.iter().sum::<f32>() / x.len() as f32;
+            let var = x.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / x.len() as f32;
+            x.iter().enumerate().map(|(i, v)| (v - mean) / (var + LAYER_NORM_EPS).sqrt() * gamma[i] + beta[i]).collect()
+        };
+        let (side, patch, hidden) = (config.patches_per_side(), config.patch_size, config.hidden_size);
+        let size = config.image_size;
+
+        let (kernel, bias, positions) = (
+            values("embeddings.patch_embedding.weight"),
+            values("embeddings.patch_embedding.bias"),
+            values("embeddings.position_embedding.weight"),
+        );
+        let mut x: Vec<Vec<f32>> = (0..side * side)
+            .map(|p| {
+                let (row, col) = (p / side, p % side);
+                let mut flat = Vec::new();
+                for c in 0..3 {
+                    for y in 0..patch {
+                        for z in 0..patch {
+                            flat.push(pixels[c * size * size + (row * patch + y) * size + col * patch + z]);
+                        }
+                    }
+                }
+                let embedded = linear(&flat, &kernel, Some(&bias));
+                embedded.iter().zip(&positions[p * hidden..]).map(|(e, pos)| e + pos).collect()
+            })
+            .collect();
+
+        let heads = config.num_attention_heads;
+        let head_dim = hidden / heads;
+        for i in 0..config.num_layers {
+            let layer = |name: &str| format!("encoder.layers.{}.{}", i, name);
+            let proj = |h: &[f32], name: &str| {
+                linear(h, &values(&layer(&format!("{}.weight", name))), Some(&values(&layer(&format!("{}.bias", name)))))
+            };
+            let h: Vec<Vec<f32>> = x.iter().map(|row| layer_norm(row, &layer("layer_norm1"))).collect();
+            let q: Vec<Vec<f32>> = h.iter().map(|r| proj(r, "self_attn.q_proj")).collect();
+            let k: Vec<Vec<f32>> = h.iter().map(|r| proj(r, "self_attn.k_proj")).collect();
+            let v: Vec<Vec<f32>> = h.iter().map(|r| proj(r, "self_attn.v_proj")).collect();
+            for (t, row) in x.iter_mut().enumerate() {
+                let mut attention = vec![0.0; hidden];
+                for head in 0..heads {
+                    let dims = head * head_dim..(head + 1) * head_dim;
+                    let scores: Vec<f32> = k
+                        .iter()
+                        .map(|key| q[t][dims.clone()].iter().zip(&key[dims.clone()]).map(|(a, b)| a * b).sum::<f32>() / (head_dim as f32).sqrt())
+                        .collect();
+                    let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                    let total: f32 = scores.iter().map(|s| (s - max).exp()).sum();
+                    for (s, value) in scores.iter().zip(&v) {
+                        for d in dims.clone() {
+                            attention[d] += (s - max).exp() / total * value[d];
+                        }
+                    }
+                }
+                let out = proj(&attention, "self_attn.out_proj");
+                row.iter_mut().zip(out).for_each(|(a, b)| *a += b);
+                let h = proj(&layer_norm(row, &layer("layer_norm2")), "mlp.fc1");
+                let h: Vec<f32> = h.iter().map(|&v| 0.5 * v * (1.0 + ((2.0 / PI).sqrt() * (v + 0.044715 * v.powi(3))).tanh())).collect();
+                row.iter_mut().zip(proj(&h, "mlp.fc2")).for_each(|(a, b)| *a += b);
+            }
+        }
+        let x: Vec<Vec<f32>> = x.iter().map(|row| layer_norm(row, "post_layernorm")).collect();
+
+        let connector: Vec<f32> = weights
+            .tensor::<B, 2>("model.connector.modality_projection.proj.weight", &Default::default())
+            .unwrap()
+            .into_data()
+            .to_vec()
+            .unwrap();
+        let (s, grid) = (config.scale_factor, side / config.scale_factor);
+        let mut output = Vec::new();
+        for token in 0..grid * grid {
+            let (gr, gc) = (token / grid, token % grid);
+            let mut shuffled = Vec::new();
+            for i in 0..s {
+                for j in 0..s {
+                    shuffled.extend_from_slice(&x[(gr * s + i) * side + gc * s + j]);
+                }
+            }
+            output.extend(linear(&shuffled, &connector, None));
+        }
+        output
+    }
+
+    #[test]
+    fn test_smolvlm_token_count() {
+        let config = VisionConfig::smolvlm_256m();
+        assert_eq!(config.num_patches(), 1024);
+        assert_eq!(config.num_image_tokens(), 64);
+        assert_eq!(config.image_prompt("Hi").matches(IMAGE_TOKEN).count(), 64);
+
+        let preprocess = config.preprocess_config();
+        assert_eq!((preprocess.width, preprocess.height), (512, 512));
+    }
+
+    #[test]
+    fn test_encode_image_matches_reference() {
+        let config = tiny_config();
+        let device = Default::default();
+        let bytes = smolvlm_weights(&config);
+        let weights = WeightMap::from_shards(&[&bytes]).unwrap();
+        let model = create_vision_model::<B>(&config, &weights, &device).unwrap();
+        assert!(model.text_model().has_weights());
+
+        let pixels: Vec<f32> = (0..3 * 16 * 16).map(|i| ((i * 37) % 101) as f32 / 50.0 - 1.0).collect();
+        let input = Tensor::<B, 4>::from_data(TensorData::new(pixels.clone(), [1, 3, 16, 16]), &device);
+        let tokens = model.encode_image(input);
+        assert_eq!(tokens.dims(), [1, config.num_image_tokens(), config.text.hidden_size]);
+        assert_close(&tokens.into_data().to_vec::<f32>().unwrap(), &reference_encoding(&config, &weights, &pixels));
+
+        assert!(create_vision_model::<B>(&config, &WeightMap::default(), &device).is_err());
+        let wider = VisionConfig { hidden_size: 16, ..config };
+        assert!(create_vision_model::<B>(&wider, &weights, &device).is_err());
+    }
+
+    #[test]
+    fn test_patchify_order() {
+        let device = Default::default();
+        let values: Vec<f32> = (0..48).map(|v| v as f32).collect();
+        let pixels = Tensor::<B, 4>::from_data(TensorData::new(values, [1, 3, 4, 4]), &device);
+
+        let patches = patchify(pixels, 2).into_data().to_vec::<f32>().unwrap();
+        // First patch: top-left 2x2 block of each channel in turn
+        assert_eq!(&patches[..12], &[0., 1., 4., 5., 16., 17., 20., 21., 32., 33., 36., 37.]);
+    }
+}
@@ -0,0 +1,284 @@
+//! Safetensors and GGUF weights read in place
+//!
+//! A [`WeightMap`] indexes the tensors in a model's weight shards without
+//! copying them: on native builds the shards are memory-mapped files. A
+//! tensor's values are decoded straight from the shard into the Burn tensor,
+//! which is the only copy made.
+
+use super::gguf::{self, TensorType};
+use super::{FileData, ModelFiles};
+use burn::prelude::*;
+use burn::tensor::{bf16, f16};
+use safetensors::{Dtype, SafeTensors};
+use std::collections::HashMap;
+
+/// How a tensor's values are stored
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    F32,
+    F16,
+    BF16,
+    Q8_0,
+    Safetensors(Dtype),
+    Gguf(u32),
+}
+
+impl From<Dtype> for Encoding {
+    fn from(dtype: Dtype) -> Self {
+        match dtype {
+            Dtype::F32 => Self::F32,
+            Dtype::F16 => Self::F16,
+            Dtype::BF16 => Self::BF16,
+            other => Self::Safetensors(other),
+        }
+    }
+}
+
+impl From<TensorType> for Encoding {
+    fn from(kind: TensorType) -> Self {
+        match kind {
+            TensorType::F32 => Self::F32,
+            TensorType::F16 => Self::F16,
+            TensorType::BF16 => Self::BF16,
+            TensorType::Q8_0 => Self::Q8_0,
+            TensorType::Other(id) => Self::Gguf(id),
+        }
+    }
+}
+
+/// A tensor in a shard, and the file holding the shard if it is known
+#[derive(Clone)]
+struct Entry<'a> {
+    shape: Vec<usize>,
+    encoding: Encoding,
+    data: &'a [u8],
+    /// Heads whose rows llama.cpp interleaved, for GGUF query and key projections
+    interleaved_heads: Option<usize>,
+    file: Option<&'a FileData>,
+}
+
+/// Tensors in a model's weight shards, by name
+#[derive(Default)]
+pub struct WeightMap<'a> {
+    tensors: HashMap<String, Entry<'a>>,
+}
+
+impl<'a> WeightMap<'a> {
+    /// Index the tensors in safetensors or GGUF shards
+    ///
+    /// Only the headers are parsed. Llama tensors in GGUF shards are indexed
+    /// under their HuggingFace names.
+    pub fn from_shards(shards: &[&'a [u8]]) -> Result<Self, String> {
+        Self::index(shards.iter().map(|shard| (*shard, None)))
+    }
+
+    /// Index the tensors in a model's weight shards
+    ///
+    /// Pages of memory-mapped shards are released as tensors are read from
+    /// them, so building a model holds little more than the model itself.
+    pub fn from_files(files: &'a ModelFiles) -> Result<Self, String> {
+        Self::index(files.weight_data().into_iter().map(|file| (&**file, Some(file))))
+    }
+
+    fn index(shards: impl Iterator<Item = (&'a [u8], Option<&'a FileData>)>) -> Result<Self, String> {
+        let mut tensors = HashMap::new();
+        for (i, (shard, file)) in shards.enumerate() {
+            if shard.starts_with(gguf::MAGIC) {
+                let shard = gguf::parse(shard).map_err(|e| format!("Invalid GGUF shard {}: {}", i, e))?;
+                tensors.extend(shard.tensors.into_iter().map(|tensor| {
+                    let entry = Entry {
+                        shape: tensor.shape,
+                        encoding: tensor.kind.into(),
+                        data: tensor.data,
+                        interleaved_heads: tensor.interleaved_heads,
+                        file,
+                    };
+                    (tensor.name, entry)
+                }));
+                continue;
+            }
+            let shard = SafeTensors::deserialize(shard).map_err(|e| format!("Invalid safetensors shard {}: {}", i, e))?;
+            tensors.extend(shard.tensors().into_iter().map(|(name, view)| {
+                let entry = Entry {
+                    shape: view.shape().to_vec(),
+                    encoding: view.dtype().into(),
+                    data: view.data(),
+                    interleaved_heads: None,
+                    file,
+                };
+                (name, entry)
+            }));
+        }
+        Ok(Self { tensors })
+    }
+
+    /// Tensors whose names start with one of the `prefixes`, renamed to
+    /// start with its replacement instead
+    ///
+    /// E.g. the text model of a vision-language checkpoint, under the names
+    /// of an LLM checkpoint.
+    pub fn renamed(&self, prefixes: &[(&str, &str)]) -> Self {
+        let tensors = self
+            .tensors
+            .iter()
+            .filter_map(|(name, entry)| {
+                prefixes.iter().find_map(|(prefix, replacement)| {
+                    let rest = name.strip_prefix(prefix)?;
+                    Some((format!("{}{}", replacement, rest), entry.clone()))
+                })
+            })
+            .collect();
+        Self { tensors }
+    }
+
+    /// Number of tensors
+    pub fn len(&self) -> usize {
+        self.tensors.len()
+    }
+
+    /// Check whether there are no tensors
+    pub fn is_empty(&self) -> bool {
+        self.tensors.is_empty()
+    }
+
+    /// Total number of parameters across all tensors
+    pub fn parameter_count(&self) -> usize {
+        self.tensors.values().map(|entry| entry.shape.iter().product::<usize>()).sum()
+    }
+
+    /// Names of all tensors, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tensors.keys().map(String::as_str)
+    }
+
+    /// Check whether a tensor exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.tensors.contains_key(name)
+    }
+
+    /// Shape of a tensor
+    pub fn shape(&self, name: &str) -> Option<&[usize]> {
+        self.tensors.get(name).map(|entry| entry.shape.as_slice())
+    }
+
+    /// Create a float tensor on `device`, decoding its values straight from the shard
+    pub fn tensor<B: Backend, const D: usize>(&self, name: &str, device: &B::Device) -> Result<Tensor<B, D>, String> {
+        let entry = self.tensors.get(name).ok_or_else(|| format!("Missing tensor {}", name))?;
+        if entry.shape.len() != D {
+            return Err(format!("Tensor {} has rank {}, expected {}", name, entry.shape.len(), D));
+        }
+        let bytes = entry.data;
+        let mut values: Vec<f32> = match entry.encoding {
+            Encoding::F32 => bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+            Encoding::F16 => bytes.chunks_exact(2).map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32()).collect(),
+            Encoding::BF16 => bytes.chunks_exact(2).map(|b| bf16::from_le_bytes([b[0], b[1]]).to_f32()).collect(),
+            Encoding::Q8_0 => bytes
+                .chunks_exact(2 + gguf::Q8_0_BLOCK)
+                .flat_map(|block| {
+                    let scale = f16::from_le_bytes([block[0], block[1]]).to_f32();
+                    block[2..].iter().map(move |q| *q as i8 as f32 * scale)
+                })
+                .collect(),
+            Encoding::Safetensors(dtype) => return Err(format!("Tensor {} has unsupported dtype {:?}", name, dtype)),
+            Encoding::Gguf(id) => return Err(format!("Tensor {} has unsupported GGUF type {}", name, id)),
+        };
+        if let Some(file) = entry.file {
+            file.release(bytes);
+        }
+        if let Some(heads) = entry.interleaved_heads {
+            values = gguf::deinterleave_rows(&values, heads, entry.shape.last().copied().unwrap_or(1));
+        }
+        let data = TensorData::new(values, entry.shape.clone());
+        Ok(Tensor::from_data(data.convert::<B::FloatElem>(), device))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+    use safetensors::tensor::TensorView;
+
+    #[test]
+    fn test_tensors_from_shards() {
+        let values: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let view = TensorView::new(Dtype::F32, vec![2, 3], &values).unwrap();
+        let shard = safetensors::serialize([("proj.weight", view)], &None).unwrap();
+
+        let weights = WeightMap::from_shards(&[&shard]).unwrap();
+        assert_eq!(weights.len(), 1);
+        assert_eq!(weights.parameter_count(), 6);
+
+        let device = Default::default();
+        let tensor = weights.tensor::<NdArray<f32>, 2>("proj.weight", &device).unwrap();
+        assert_eq!(tensor.dims(), [2, 3]);
+        assert_eq!(tensor.into_data().to_vec::<f32>().unwrap(), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        assert!(weights.tensor::<NdArray<f32>, 1>("proj.weight", &device).is_err());
+        assert!(weights.tensor::<NdArray<f32>, 2>("missing", &device).is_err());
+        let renamed = weights.renamed(&[("proj.", "model.proj."), ("other.", "")]);
+        assert_eq!(renamed.names().collect::<Vec<_>>(), ["model.proj.weight"]);
+        assert!(WeightMap::from_shards(&[b"not safetensors"]).is_err());
+        assert!(WeightMap::from_shards(&[b"GGUF\x03\x00\x00\x00"]).is_err());
+    }
+
+    #[test]
+    fn test_tensors_from_gguf() {
+        let expected: Vec<f32> = (0..64).map(|v| (v as f32 - 32.0) / 8.0).collect();
+        let f32_bytes: Vec<u8> = expected.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let f16_bytes: Vec<u8> = expected.iter().flat_map(|v| f16::from_f32(*v).to_le_bytes()).collect();
+        // Each block of 32 stores a scale and the values divided by it
+        let q8_bytes: Vec<u8> = expected
+            .chunks(gguf::Q8_0_BLOCK)
+            .flat_map(|block| {
+                let scale = block.iter().fold(0.0f32, |m, v| m.max(v.abs())) / 127.0;
+                let quants = block.iter().map(move |v| (v / scale).round() as i8 as u8);
+                f16::from_f32(scale).to_le_bytes().into_iter().chain(quants)
+            })
+            .collect();
+        let shard = gguf::tests::gguf_bytes(
+            &[],
+            &[
+                ("a", vec![2, 32], 0, f32_bytes),
+                ("b", vec![2, 32], 1, f16_bytes),
+                ("c", vec![2, 32], 8, q8_bytes),
+                ("d", vec![2, 32], 2, Vec::new()),
+            ],
+        );
+
+        let weights = WeightMap::from_shards(&[&shard]).unwrap();
+        assert_eq!(weights.len(), 4);
+        assert_eq!(weights.shape("c"), Some(&[2, 32][..]));
+        let device = Default::default();
+        for name in ["a", "b", "c"] {
+            let tensor = weights.tensor::<NdArray<f32>, 2>(name, &device).unwrap();
+            assert_eq!(tensor.dims(), [2, 32]);
+            let values = tensor.into_data().to_vec::<f32>().unwrap();
+            assert!(values.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 0.02), "{}: {:?}", name, values);
+        }
+        assert!(weights.tensor::<NdArray<f32>, 2>("d", &device).unwrap_err().contains("GGUF type 2"));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_tensors_from_mapped_files() {
+        let values: Vec<u8> = (0..4096).flat_map(|v| (v as f32).to_le_bytes()).collect();
+        let view = TensorView::new(Dtype::F32, vec![64, 64], &values).unwrap();
+        let path = std::env::temp_dir().join(format!("jarvis-weights-{}.safetensors", uuid::Uuid::new_v4()));
+        safetensors::serialize_to_file([("embed.weight", view)], &None, &path).unwrap();
+        let files = ModelFiles::new(
+            "local",
+            HashMap::from([("model.safetensors".to_string(), FileData::map(&path).unwrap())]),
+            vec!["model.safetensors".to_string()],
+        );
+
+        // Released pages read back unchanged
+        let weights = WeightMap::from_files(&files).unwrap();
+        let device = Default::default();
+        for _ in 0..2 {
+            let tensor = weights.tensor::<NdArray<f32>, 2>("embed.weight", &device).unwrap();
+            assert_eq!(tensor.into_data().to_vec::<f32>().unwrap(), (0..4096).map(|v| v as f32).collect::<Vec<_>>());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
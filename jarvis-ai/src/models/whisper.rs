@@ -1,10 +1,12 @@
 //! Whisper model implementation using Burn
 
+use super::weights::WeightMap;
 use burn::prelude::*;
+use serde::{Deserialize, Serialize};
 use log;
 
 /// Configuration for Whisper model
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WhisperConfig {
     pub vocab_size: usize,
     pub num_mel_bins: usize,
@@ -49,18 +51,23 @@ impl WhisperConfig {
 /// Whisper model implementation
 pub struct WhisperModel<B: Backend> {
     config: WhisperConfig,
-    phantom: std::marker::PhantomData<B>,
+    device: B::Device,
 }
 
 impl<B: Backend> WhisperModel<B> {
-    /// Create a new Whisper model
-    pub fn new(config: &WhisperConfig) -> Self {
+    /// Create a new Whisper model on the given device
+    pub fn new(config: &WhisperConfig, device: &B::Device) -> Self {
         Self {
             config: config.clone(),
-            phantom: std::marker::PhantomData,
+            device: device.clone(),
         }
     }
 
+    /// Get the device the model runs on
+    pub fn device(&self) -> &B::Device {
+        &self.device
+    }
+
     /// Encode mel spectrogram input
     pub fn encode(&self, mel_spectrogram: Tensor<B, 3>) -> Tensor<B, 3> {
         // Simple encoder implementation
@@ -87,23 +94,22 @@ impl<B: Backend> WhisperModel<B> {
     }
 }
 
-/// Function to create Whisper model with loaded weights
+/// Create a Whisper model
+///
+/// The layers do not take their parameters from `weights` yet; the weights
+/// are only counted.
 pub fn create_whisper_model<B: Backend>(
-    model_type: crate::models::ModelType,
-    model_data: &[u8],
+    config: &WhisperConfig,
+    weights: &WeightMap,
+    device: &B::Device,
 ) -> Result<WhisperModel<B>, String> {
-    let config = match model_type {
-        crate::models::ModelType::WhisperTiny => WhisperConfig::tiny(),
-        crate::models::ModelType::WhisperBase => WhisperConfig::base(),
-        crate::models::ModelType::WhisperSmall => WhisperConfig::base(), // Use base config for small for now
-        _ => return Err("Invalid model type for Whisper".to_string()),
-    };
-
-    // Load model weights from safetensors data
-    // Note: This is a placeholder - actual weight loading would parse the safetensors format
-    if !model_data.is_empty() {
-        log::info!("Loading Whisper model weights from {} bytes of data", model_data.len());
+    if !weights.is_empty() {
+        log::warn!(
+            "Ignoring Whisper model weights, which are not supported yet: {} tensors, {} parameters",
+            weights.len(),
+            weights.parameter_count()
+        );
     }
 
-    Ok(WhisperModel::new(&config))
+    Ok(WhisperModel::new(config, device))
 }
\ No newline at end of file
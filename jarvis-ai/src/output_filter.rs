@@ -0,0 +1,456 @@
+//! Post-processing of model output before display or speech synthesis
+//!
+//! Small models ignore the persona's formatting rules, so an [`OutputFilters`]
+//! pipeline removes what the rules forbid (emojis, markdown emphasis,
+//! ellipses) and, for speech, spells out numbers and abbreviations.
+//!
+//! Filters work word by word. [`FilterStream`] holds back the last word of
+//! each chunk until the whitespace after it arrives, so filtering a reply as it
+//! streams in gives the same text as filtering it at once.
+
+use serde::{Deserialize, Serialize};
+
+/// One post-processing step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFilter {
+    /// Remove emojis and pictographs
+    StripEmojis,
+    /// Remove emphasis markers, inline code ticks, headings and `*` bullets
+    StripMarkdown,
+    /// Replace `...` and `…` with a full stop
+    StripEllipses,
+    /// Collapse runs of spaces, keep at most one blank line, trim the ends
+    NormalizeWhitespace,
+    /// Spell out numbers, e.g. `21°C` as "twenty-one degrees Celsius"
+    ExpandNumbers,
+    /// Spell out abbreviations, e.g. `e.g.` as "for example"
+    ExpandAbbreviations,
+}
+
+impl OutputFilter {
+    /// Filter one word; an empty result removes the word
+    fn apply(&self, word: &str) -> String {
+        match self {
+            OutputFilter::StripEmojis => word.chars().filter(|&c| !is_emoji(c)).collect(),
+            OutputFilter::StripMarkdown => strip_markdown(word),
+            OutputFilter::StripEllipses => strip_ellipses(word),
+            OutputFilter::NormalizeWhitespace => word.to_string(),
+            OutputFilter::ExpandNumbers => map_core(word, expand_number),
+            OutputFilter::ExpandAbbreviations => map_core(word, expand_abbreviation),
+        }
+    }
+}
+
+/// Ordered list of filters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputFilters {
+    pub filters: Vec<OutputFilter>,
+}
+
+impl OutputFilters {
+    pub fn new(filters: Vec<OutputFilter>) -> Self {
+        Self { filters }
+    }
+
+    /// Filters for text shown in the chat
+    pub fn display() -> Self {
+        Self::new(vec![
+            OutputFilter::StripMarkdown,
+            OutputFilter::StripEmojis,
+            OutputFilter::StripEllipses,
+            OutputFilter::NormalizeWhitespace,
+        ])
+    }
+
+    /// Filters for text sent to speech synthesis
+    pub fn speech() -> Self {
+        Self::new(vec![
+            OutputFilter::StripMarkdown,
+            OutputFilter::StripEmojis,
+            OutputFilter::StripEllipses,
+            OutputFilter::ExpandAbbreviations,
+            OutputFilter::ExpandNumbers,
+            OutputFilter::NormalizeWhitespace,
+        ])
+    }
+
+    /// Filter a complete text
+    pub fn apply(&self, text: &str) -> String {
+        let mut stream = self.stream();
+        let mut output = stream.push(text);
+        output.push_str(&stream.finish());
+        output
+    }
+
+    /// Start filtering text that arrives in chunks
+    pub fn stream(&self) -> FilterStream {
+        FilterStream {
+            filters: self.filters.clone(),
+            normalize: self.filters.contains(&OutputFilter::NormalizeWhitespace),
+            buffer: String::new(),
+            whitespace: String::new(),
+            started: false,
+        }
+    }
+}
+
+/// Incremental filtering of streamed text
+pub struct FilterStream {
+    filters: Vec<OutputFilter>,
+    normalize: bool,
+    /// Text of a word that may continue in the next chunk
+    buffer: String,
+    /// Whitespace seen since the last word written
+    whitespace: String,
+    /// Whether any word has been written
+    started: bool,
+}
+
+impl FilterStream {
+    /// Add a chunk of text, returning the filtered text that is final so far
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.buffer.push_str(chunk);
+        self.drain(false)
+    }
+
+    /// Filter the rest of the text once the stream has ended
+    pub fn finish(&mut self) -> String {
+        let output = self.drain(true);
+        if !self.normalize {
+            return output + &std::mem::take(&mut self.whitespace);
+        }
+        self.whitespace.clear();
+        output
+    }
+
+    fn drain(&mut self, end: bool) -> String {
+        let mut output = String::new();
+        let mut rest = self.buffer.as_str();
+        while !rest.is_empty() {
+            let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if word_end == 0 {
+                let space_end = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+                self.whitespace.push_str(&rest[..space_end]);
+                rest = &rest[space_end..];
+                continue;
+            }
+            if word_end == rest.len() && !end {
+                break;
+            }
+            let raw = &rest[..word_end];
+            let word = self.filters.iter().fold(raw.to_string(), |word, filter| {
+                if word.is_empty() {
+                    word
+                } else {
+                    filter.apply(&word)
+                }
+            });
+            if !word.is_empty() {
+                let whitespace = std::mem::take(&mut self.whitespace);
+                // Punctuation left behind by a removed emoji, as in "evening 👋, sir",
+                // goes after the previous word rather than on its own
+                let attached = self.started
+                    && starts_with_closing_punctuation(&word)
+                    && !starts_with_closing_punctuation(raw)
+                    && !whitespace.contains('\n');
+                if !self.normalize && !attached {
+                    output.push_str(&whitespace);
+                } else if self.started && !attached {
+                    output.push_str(match whitespace.matches('\n').count() {
+                        0 => " ",
+                        1 => "\n",
+                        _ => "\n\n",
+                    });
+                }
+                output.push_str(&word);
+                self.started = true;
+            }
+            rest = &rest[word_end..];
+        }
+        self.buffer = rest.to_string();
+        output
+    }
+}
+
+fn starts_with_closing_punctuation(word: &str) -> bool {
+    word.starts_with([',', '.', ';', ':', '!', '?', ')', ']', '}'])
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF   // Pictographs, emoticons, transport, flags, skin tones
+            | 0x2600..=0x27BF // Miscellaneous symbols and dingbats
+            | 0x2B00..=0x2BFF // Arrows, stars
+            | 0xFE00..=0xFE0F // Variation selectors
+            | 0x200D          // Zero-width joiner
+            | 0x20E3          // Keycap
+            | 0xE0020..=0xE007F // Tag characters
+    )
+}
+
+fn strip_markdown(word: &str) -> String {
+    if word.chars().all(|c| c == '#') && word.len() <= 6 {
+        return String::new();
+    }
+    let word = word.replace("~~", "").replace('`', "");
+    let chars: Vec<char> = word.chars().collect();
+    // `*` and `_` inside a word, as in `2*3` or `snake_case`, are kept
+    chars
+        .iter()
+        .enumerate()
+        .filter(|&(i, &c)| {
+            if c != '*' && c != '_' {
+                return true;
+            }
+            let alphanumeric = |j: Option<usize>| j.and_then(|j| chars.get(j)).is_some_and(|c| c.is_alphanumeric());
+            alphanumeric(i.checked_sub(1)) && alphanumeric(Some(i + 1))
+        })
+        .map(|(_, &c)| c)
+        .collect()
+}
+
+fn strip_ellipses(word: &str) -> String {
+    let mut output = String::new();
+    let mut rest = word;
+    while !rest.is_empty() {
+        let dots = rest.chars().take_while(|&c| c == '.').count();
+        let length = if rest.starts_with('…') {
+            '…'.len_utf8()
+        } else if dots >= 3 {
+            dots
+        } else {
+            let c = rest.chars().next().unwrap_or_default();
+            output.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+        rest = &rest[length..];
+        // Leading ellipses and those before other punctuation are dropped
+        if output.is_empty() || rest.starts_with(|c: char| c.is_ascii_punctuation()) {
+            continue;
+        }
+        output.push_str(if rest.is_empty() { "." } else { ". " });
+    }
+    output
+}
+
+/// Apply `expand` to a word without its surrounding punctuation
+fn map_core(word: &str, expand: fn(&str) -> Option<String>) -> String {
+    let start = word.find(|c: char| !matches!(c, '(' | '"' | '\'')).unwrap_or(word.len());
+    let end = word.trim_end_matches([',', ';', ':', '!', '?', ')', '"', '\'']).len().max(start);
+    let (lead, core, trail) = (&word[..start], &word[start..end], &word[end..]);
+    if let Some(expanded) = expand(core) {
+        return format!("{}{}{}", lead, expanded, trail);
+    }
+    // A full stop after the word is punctuation, not part of a number
+    match core.strip_suffix('.').and_then(expand) {
+        Some(expanded) => format!("{}{}.{}", lead, expanded, trail),
+        None => word.to_string(),
+    }
+}
+
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("Dr.", "Doctor"),
+    ("Mr.", "Mister"),
+    ("Mrs.", "Missus"),
+    ("Ms.", "Miz"),
+    ("Prof.", "Professor"),
+    ("e.g.", "for example"),
+    ("i.e.", "that is"),
+    ("etc.", "et cetera"),
+    ("vs.", "versus"),
+    ("approx.", "approximately"),
+    ("km", "kilometers"),
+    ("km/h", "kilometers per hour"),
+    ("mph", "miles per hour"),
+    ("kg", "kilograms"),
+    ("°C", "degrees Celsius"),
+    ("°F", "degrees Fahrenheit"),
+];
+
+fn expand_abbreviation(core: &str) -> Option<String> {
+    ABBREVIATIONS.iter().find(|(short, _)| *short == core).map(|(_, long)| long.to_string())
+}
+
+const ONES: [&str; 20] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten", "eleven", "twelve", "thirteen",
+    "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen",
+];
+const TENS: [&str; 10] = ["", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety"];
+const SCALES: [(u64, &str); 4] = [
+    (1_000_000_000_000, "trillion"),
+    (1_000_000_000, "billion"),
+    (1_000_000, "million"),
+    (1_000, "thousand"),
+];
+
+/// English words for a whole number, e.g. "one hundred twenty-three"
+fn number_words(n: u64) -> String {
+    if n < 20 {
+        return ONES[n as usize].to_string();
+    }
+    if n < 100 {
+        let tens = TENS[(n / 10) as usize];
+        return if n.is_multiple_of(10) { tens.to_string() } else { format!("{}-{}", tens, ONES[(n % 10) as usize]) };
+    }
+    if n < 1000 {
+        let hundreds = format!("{} hundred", ONES[(n / 100) as usize]);
+        return if n.is_multiple_of(100) { hundreds } else { format!("{} {}", hundreds, number_words(n % 100)) };
+    }
+    let (scale, name) = SCALES.iter().find(|(scale, _)| n >= *scale).copied().unwrap_or(SCALES[3]);
+    let head = format!("{} {}", number_words(n / scale), name);
+    if n.is_multiple_of(scale) {
+        head
+    } else {
+        format!("{} {}", head, number_words(n % scale))
+    }
+}
+
+/// Digits read one by one, e.g. after a decimal point
+fn digit_words(digits: &str) -> String {
+    digits.chars().filter_map(|c| c.to_digit(10)).map(|d| ONES[d as usize]).collect::<Vec<_>>().join(" ")
+}
+
+/// Words for digits with optional thousands separators; very long numbers are read digit by digit
+fn integer_words(digits: &str) -> Option<String> {
+    let plain = digits.replace(',', "");
+    let grouped = !digits.contains(',')
+        || digits.split(',').enumerate().all(|(i, group)| if i == 0 { (1..=3).contains(&group.len()) } else { group.len() == 3 });
+    if plain.is_empty() || !grouped || !plain.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    match plain.parse::<u64>() {
+        Ok(n) if n < 1_000_000_000_000_000 => Some(number_words(n)),
+        _ => Some(digit_words(&plain)),
+    }
+}
+
+fn ordinal_words(words: &str) -> String {
+    let split = words.rfind([' ', '-']).map_or(0, |i| i + 1);
+    let (head, last) = words.split_at(split);
+    let last = match last {
+        "one" => "first".to_string(),
+        "two" => "second".to_string(),
+        "three" => "third".to_string(),
+        "five" => "fifth".to_string(),
+        "eight" => "eighth".to_string(),
+        "nine" => "ninth".to_string(),
+        "twelve" => "twelfth".to_string(),
+        _ if last.ends_with('y') => format!("{}ieth", &last[..last.len() - 1]),
+        _ => format!("{}th", last),
+    };
+    format!("{}{}", head, last)
+}
+
+fn expand_number(core: &str) -> Option<String> {
+    if !core.contains(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Some(rest) = core.strip_prefix('-') {
+        return expand_number(rest).map(|words| format!("minus {}", words));
+    }
+    for (symbol, unit, cents) in [('$', "dollars", "cents"), ('€', "euros", "cents"), ('£', "pounds", "pence")] {
+        if let Some(amount) = core.strip_prefix(symbol) {
+            let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+            let whole = format!("{} {}", integer_words(whole)?, unit);
+            return match fraction.len() {
+                0 => Some(whole),
+                2 => Some(format!("{} and {} {}", whole, integer_words(fraction)?, cents)),
+                _ => None,
+            };
+        }
+    }
+    for (suffix, unit) in [("%", "percent"), ("°C", "degrees Celsius"), ("°F", "degrees Fahrenheit"), ("°", "degrees")] {
+        if let Some(number) = core.strip_suffix(suffix) {
+            return expand_number(number).map(|words| format!("{} {}", words, unit));
+        }
+    }
+    for suffix in ["st", "nd", "rd", "th"] {
+        if let Some(number) = core.strip_suffix(suffix) {
+            return integer_words(number).map(|words| ordinal_words(&words));
+        }
+    }
+    if let Some((hours, minutes)) = core.split_once(':') {
+        let hour: u64 = hours.parse().ok().filter(|h| *h < 24 && hours.len() <= 2)?;
+        let minute: u64 = minutes.parse().ok().filter(|m| *m < 60 && minutes.len() == 2)?;
+        return Some(match minute {
+            0 => format!("{} o'clock", number_words(hour)),
+            1..=9 => format!("{} oh {}", number_words(hour), number_words(minute)),
+            _ => format!("{} {}", number_words(hour), number_words(minute)),
+        });
+    }
+    match core.split_once('.') {
+        Some((whole, fraction)) if !fraction.is_empty() && fraction.chars().all(|c| c.is_ascii_digit()) => {
+            let whole = if whole.is_empty() { "zero".to_string() } else { integer_words(whole)? };
+            Some(format!("{} point {}", whole, digit_words(fraction)))
+        }
+        Some(_) => None,
+        None => integer_words(core),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_filters() {
+        let filters = OutputFilters::display();
+        let reply = "  ## Status\n\n\n\nAll **systems** are _online_... 🚀 Shall I run `diagnostics`?\n* Yes…   or no  ";
+        assert_eq!(
+            filters.apply(reply),
+            "Status\n\nAll systems are online. Shall I run diagnostics?\nYes. or no"
+        );
+        assert_eq!(filters.apply("Set snake_case to 2*3... ...done...now"), "Set snake_case to 2*3. done. now");
+        assert_eq!(filters.apply("Wait...?"), "Wait?");
+        assert_eq!(filters.apply("Good evening 👋, sir. Ready 🚀!"), "Good evening, sir. Ready!");
+        assert_eq!(filters.apply("Options: a , b"), "Options: a , b");
+        assert_eq!(OutputFilters::default().apply("  *as is*  "), "  *as is*  ");
+    }
+
+    #[test]
+    fn test_speech_filters() {
+        let filters = OutputFilters::speech();
+        assert_eq!(
+            filters.apply("Dr. Banner arrives at 9:05, e.g. in 21 minutes."),
+            "Doctor Banner arrives at nine oh five, for example in twenty-one minutes."
+        );
+        assert_eq!(
+            filters.apply("It is 8°C, 95% humidity and 1,250 km away."),
+            "It is eight degrees Celsius, ninety-five percent humidity and one thousand two hundred fifty kilometers away."
+        );
+        assert_eq!(
+            filters.apply("The 3rd suit costs $1,000,000.50 (at -3.14 margin) on the 21st at 12:00."),
+            "The third suit costs one million dollars and fifty cents (at minus three point one four margin) on the twenty-first at twelve o'clock."
+        );
+        assert_eq!(filters.apply("Model 12a v2"), "Model 12a v2");
+        assert_eq!(number_words(1_000_019), "one million nineteen");
+        assert_eq!(ordinal_words(&number_words(40)), "fortieth");
+    }
+
+    #[test]
+    fn test_streaming_matches_whole_text() {
+        let reply = "  **Good** evening 👋, sir...\n\n\n\nIt's 21°C   — e.g. mild.  ";
+        for filters in [OutputFilters::display(), OutputFilters::speech(), OutputFilters::default()] {
+            let whole = filters.apply(reply);
+            let chars: Vec<char> = reply.chars().collect();
+            for size in 1..6 {
+                let mut stream = filters.stream();
+                let mut streamed = String::new();
+                for chunk in chars.chunks(size) {
+                    streamed.push_str(&stream.push(&chunk.iter().collect::<String>()));
+                }
+                streamed.push_str(&stream.finish());
+                assert_eq!(streamed, whole, "chunk size {}", size);
+            }
+        }
+
+        // Words are only released once complete
+        let mut stream = OutputFilters::speech().stream();
+        assert_eq!(stream.push("It is 2"), "It is");
+        assert_eq!(stream.push("1 degrees"), " twenty-one");
+        assert_eq!(stream.finish(), " degrees");
+    }
+}
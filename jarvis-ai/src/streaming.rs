@@ -0,0 +1,441 @@
+//! Streaming speech-to-text for live captions
+//!
+//! [`StreamingTranscriber`] accepts audio chunks as they arrive, keeps a
+//! rolling mel spectrogram of the current window and periodically re-decodes
+//! it. Words are only committed once consecutive hypotheses agree on them
+//! (the LocalAgreement policy), so captions can show tentative text while the
+//! user is still speaking without the stable part flickering.
+
+use crate::audio::{MelBuffer, StreamResampler, HOP_LENGTH, N_FRAMES, WHISPER_SAMPLE_RATE};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Longest run of words compared when removing text repeated across a window trim
+const MAX_OVERLAP_WORDS: usize = 32;
+
+/// Anything that can turn a log mel window into text
+pub trait SpeechRecognizer {
+    /// Transcribe a normalized log mel spectrogram (frame-major, `N_MEL_BINS` per frame)
+    fn transcribe_mel(&self, mel: &[f32], n_frames: usize) -> Result<String, String>;
+}
+
+/// Streaming transcription configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamingConfig {
+    /// Sample rate of the pushed audio in Hz
+    pub sample_rate: u32,
+    /// Minimum new audio in seconds before the window is decoded again
+    pub step_secs: f32,
+    /// Longest window decoded at once; Whisper's limit is 30 seconds
+    pub max_window_secs: f32,
+    /// Audio kept after the window is trimmed, giving the next decode context
+    pub overlap_secs: f32,
+    /// Number of consecutive hypotheses that must agree before words are committed
+    pub agreement: usize,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: WHISPER_SAMPLE_RATE,
+            step_secs: 1.0,
+            max_window_secs: 30.0,
+            overlap_secs: 2.0,
+            agreement: 2,
+        }
+    }
+}
+
+/// Transcript update produced by [`StreamingTranscriber`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TranscriptEvent {
+    /// Newly stabilized text; it will not change and follows earlier commits
+    Committed(String),
+    /// Tentative text after everything committed so far; replaces the previous partial
+    Partial(String),
+}
+
+/// Incremental transcriber with a sliding window and local-agreement commits
+pub struct StreamingTranscriber {
+    config: StreamingConfig,
+    resampler: StreamResampler,
+    mel: MelBuffer,
+    /// Frames added since the last decode
+    frames_since_decode: usize,
+    /// Most recent hypotheses for the current window, as words
+    history: VecDeque<Vec<String>>,
+    /// Words of the current window's hypothesis that are already committed
+    window_committed: usize,
+    /// Everything committed since the stream started
+    committed: Vec<String>,
+    /// Whether the window was trimmed, so hypotheses may repeat committed words
+    trimmed: bool,
+}
+
+impl StreamingTranscriber {
+    /// Create a new streaming transcriber
+    pub fn new(config: StreamingConfig) -> Self {
+        Self {
+            resampler: StreamResampler::new(config.sample_rate, WHISPER_SAMPLE_RATE),
+            config,
+            mel: MelBuffer::new(),
+            frames_since_decode: 0,
+            history: VecDeque::new(),
+            window_committed: 0,
+            committed: Vec::new(),
+            trimmed: false,
+        }
+    }
+
+    /// Get the streaming configuration
+    pub fn config(&self) -> &StreamingConfig {
+        &self.config
+    }
+
+    /// All text committed so far
+    pub fn committed_text(&self) -> String {
+        self.committed.join(" ")
+    }
+
+    /// Push a chunk of mono audio at the configured sample rate
+    ///
+    /// The window is re-decoded once at least `step_secs` of new audio has
+    /// accumulated; otherwise no events are produced.
+    pub fn push_audio<R: SpeechRecognizer + ?Sized>(
+        &mut self,
+        recognizer: &R,
+        samples: &[f32],
+    ) -> Result<Vec<TranscriptEvent>, String> {
+        let samples = self.resampler.push(samples)?;
+        self.frames_since_decode += self.mel.push(&samples);
+
+        if self.frames_since_decode < secs_to_frames(self.config.step_secs).max(1) {
+            return Ok(Vec::new());
+        }
+
+        self.decode(recognizer)?;
+        let mut events = Vec::new();
+        self.commit_agreed(&mut events);
+        self.push_partial(&mut events);
+
+        if self.mel.n_frames() >= self.max_window_frames() {
+            self.trim_window(&mut events);
+        }
+
+        Ok(events)
+    }
+
+    /// Flush the stream, committing the latest hypothesis as final
+    ///
+    /// Buffered audio is discarded afterwards; committed text is kept until
+    /// [`Self::reset`] is called.
+    pub fn finish<R: SpeechRecognizer + ?Sized>(&mut self, recognizer: &R) -> Result<Vec<TranscriptEvent>, String> {
+        if self.frames_since_decode > 0 && self.mel.n_frames() > 0 {
+            self.decode(recognizer)?;
+        }
+        let mut events = Vec::new();
+        self.commit_latest(&mut events);
+        events.push(TranscriptEvent::Partial(String::new()));
+
+        self.resampler.reset();
+        self.mel.clear();
+        self.frames_since_decode = 0;
+        self.history.clear();
+        self.window_committed = 0;
+        self.trimmed = false;
+        Ok(events)
+    }
+
+    /// Discard all audio, hypotheses and committed text
+    pub fn reset(&mut self) {
+        self.resampler.reset();
+        self.mel.clear();
+        self.frames_since_decode = 0;
+        self.history.clear();
+        self.window_committed = 0;
+        self.committed.clear();
+        self.trimmed = false;
+    }
+
+    fn max_window_frames(&self) -> usize {
+        secs_to_frames(self.config.max_window_secs).clamp(1, N_FRAMES)
+    }
+
+    /// Decode the current window and record the hypothesis
+    fn decode<R: SpeechRecognizer + ?Sized>(&mut self, recognizer: &R) -> Result<(), String> {
+        self.frames_since_decode = 0;
+        let text = recognizer.transcribe_mel(&self.mel.window(), self.mel.n_frames())?;
+
+        let mut words: Vec<String> = text.split_whitespace().map(str::to_string).collect();
+        if self.trimmed {
+            let overlap = committed_overlap(&self.committed, &words);
+            words.drain(..overlap);
+        }
+
+        self.history.push_back(words);
+        while self.history.len() > self.config.agreement.max(1) {
+            self.history.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Commit the prefix shared by the last `agreement` hypotheses
+    fn commit_agreed(&mut self, events: &mut Vec<TranscriptEvent>) {
+        if self.history.len() < self.config.agreement.max(1) {
+            return;
+        }
+
+        let latest = self.history.back().expect("history is not empty");
+        let agreed = self
+            .history
+            .iter()
+            .map(|h| common_prefix_len(h, latest))
+            .min()
+            .unwrap_or(0);
+
+        if agreed > self.window_committed {
+            let words = latest[self.window_committed..agreed].to_vec();
+            self.window_committed = agreed;
+            self.emit_committed(words, events);
+        }
+    }
+
+    /// Commit everything in the latest hypothesis
+    fn commit_latest(&mut self, events: &mut Vec<TranscriptEvent>) {
+        if let Some(latest) = self.history.back() {
+            if latest.len() > self.window_committed {
+                let words = latest[self.window_committed..].to_vec();
+                self.window_committed = latest.len();
+                self.emit_committed(words, events);
+            }
+        }
+    }
+
+    fn emit_committed(&mut self, words: Vec<String>, events: &mut Vec<TranscriptEvent>) {
+        events.push(TranscriptEvent::Committed(words.join(" ")));
+        self.committed.extend(words);
+    }
+
+    fn push_partial(&self, events: &mut Vec<TranscriptEvent>) {
+        let tentative = self
+            .history
+            .back()
+            .map(|latest| latest.get(self.window_committed..).unwrap_or_default().join(" "))
+            .unwrap_or_default();
+        events.push(TranscriptEvent::Partial(tentative));
+    }
+
+    /// Slide the window forward once it reaches the decoder's limit
+    ///
+    /// Without word timestamps we cannot tell which audio the tentative words
+    /// came from, so they are committed before the window is cut down to the
+    /// overlap. Words the next window repeats are removed by matching them
+    /// against the end of the committed text.
+    fn trim_window(&mut self, events: &mut Vec<TranscriptEvent>) {
+        let had_partial = self
+            .history
+            .back()
+            .is_some_and(|latest| latest.len() > self.window_committed);
+        self.commit_latest(events);
+        if had_partial {
+            events.push(TranscriptEvent::Partial(String::new()));
+        }
+
+        self.mel.keep_last(secs_to_frames(self.config.overlap_secs));
+        self.history.clear();
+        self.window_committed = 0;
+        self.trimmed = true;
+    }
+}
+
+impl Default for StreamingTranscriber {
+    fn default() -> Self {
+        Self::new(StreamingConfig::default())
+    }
+}
+
+fn secs_to_frames(secs: f32) -> usize {
+    (secs.max(0.0) * WHISPER_SAMPLE_RATE as f32 / HOP_LENGTH as f32) as usize
+}
+
+/// Compare words ignoring case and punctuation, which Whisper often revises
+fn normalize_word(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn common_prefix_len(a: &[String], b: &[String]) -> usize {
+    a.iter()
+        .zip(b)
+        .take_while(|(x, y)| normalize_word(x) == normalize_word(y))
+        .count()
+}
+
+/// Length of the longest suffix of `committed` that `words` starts with
+fn committed_overlap(committed: &[String], words: &[String]) -> usize {
+    let max = committed.len().min(words.len()).min(MAX_OVERLAP_WORDS);
+    (1..=max)
+        .rev()
+        .find(|&k| common_prefix_len(&committed[committed.len() - k..], &words[..k]) == k)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::N_MEL_BINS;
+    use std::cell::RefCell;
+
+    /// Returns scripted hypotheses in order and records the window sizes it saw
+    struct ScriptedRecognizer {
+        hypotheses: RefCell<VecDeque<&'static str>>,
+        windows: RefCell<Vec<usize>>,
+    }
+
+    impl ScriptedRecognizer {
+        fn new(hypotheses: &[&'static str]) -> Self {
+            Self {
+                hypotheses: RefCell::new(hypotheses.iter().copied().collect()),
+                windows: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl SpeechRecognizer for ScriptedRecognizer {
+        fn transcribe_mel(&self, mel: &[f32], n_frames: usize) -> Result<String, String> {
+            assert_eq!(mel.len(), n_frames * N_MEL_BINS);
+            self.windows.borrow_mut().push(n_frames);
+            self.hypotheses
+                .borrow_mut()
+                .pop_front()
+                .map(str::to_string)
+                .ok_or_else(|| "script exhausted".to_string())
+        }
+    }
+
+    fn one_second() -> Vec<f32> {
+        (0..WHISPER_SAMPLE_RATE).map(|i| (i as f32 * 0.03).sin() * 0.1).collect()
+    }
+
+    #[test]
+    fn test_local_agreement_commits_stable_prefix() {
+        let recognizer = ScriptedRecognizer::new(&[
+            "hello",
+            "hello there",
+            "Hello, there how",
+            "hello there how are you",
+        ]);
+        let mut stream = StreamingTranscriber::default();
+        let audio = one_second();
+
+        // Slightly more than a second so the first push crosses the step
+        let mut first = audio.clone();
+        first.extend_from_slice(&audio[..4000]);
+        let events = stream.push_audio(&recognizer, &first).unwrap();
+        assert_eq!(events, vec![TranscriptEvent::Partial("hello".into())]);
+
+        let events = stream.push_audio(&recognizer, &audio).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                TranscriptEvent::Committed("hello".into()),
+                TranscriptEvent::Partial("there".into()),
+            ]
+        );
+
+        let events = stream.push_audio(&recognizer, &audio).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                TranscriptEvent::Committed("there".into()),
+                TranscriptEvent::Partial("how".into()),
+            ]
+        );
+
+        // Trailing audio shorter than a step is still decoded when the stream ends
+        assert!(stream.push_audio(&recognizer, &audio[..8000]).unwrap().is_empty());
+        let events = stream.finish(&recognizer).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                TranscriptEvent::Committed("how are you".into()),
+                TranscriptEvent::Partial(String::new()),
+            ]
+        );
+        assert_eq!(stream.committed_text(), "hello there how are you");
+    }
+
+    #[test]
+    fn test_no_decode_before_step() {
+        let recognizer = ScriptedRecognizer::new(&[]);
+        let mut stream = StreamingTranscriber::default();
+        let events = stream.push_audio(&recognizer, &one_second()[..8000]).unwrap();
+        assert!(events.is_empty());
+        assert!(recognizer.windows.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_window_trim_dedupes_overlap() {
+        let recognizer = ScriptedRecognizer::new(&[
+            "one two",
+            "one two three",
+            "one two three four",
+            "four five",
+            "four five six",
+        ]);
+        let config = StreamingConfig {
+            step_secs: 0.5,
+            max_window_secs: 2.5,
+            overlap_secs: 0.5,
+            ..StreamingConfig::default()
+        };
+        let mut stream = StreamingTranscriber::new(config);
+        let audio = one_second();
+
+        stream.push_audio(&recognizer, &audio).unwrap();
+        stream.push_audio(&recognizer, &audio).unwrap();
+        let events = stream.push_audio(&recognizer, &audio).unwrap();
+        // The window reached its limit: agreed words, then the tentative tail, are committed
+        assert_eq!(
+            events,
+            vec![
+                TranscriptEvent::Committed("three".into()),
+                TranscriptEvent::Partial("four".into()),
+                TranscriptEvent::Committed("four".into()),
+                TranscriptEvent::Partial(String::new()),
+            ]
+        );
+
+        // "four" is repeated by the trimmed window and must not be committed twice
+        let events = stream.push_audio(&recognizer, &audio).unwrap();
+        assert_eq!(events, vec![TranscriptEvent::Partial("five".into())]);
+        stream.push_audio(&recognizer, &audio).unwrap();
+        assert_eq!(stream.committed_text(), "one two three four five six");
+
+        let windows = recognizer.windows.borrow();
+        assert!(windows[3] < windows[2], "window should shrink after trimming");
+    }
+
+    #[test]
+    fn test_resamples_input() {
+        let recognizer = ScriptedRecognizer::new(&["hi"]);
+        let config = StreamingConfig {
+            sample_rate: 48000,
+            ..StreamingConfig::default()
+        };
+        let mut stream = StreamingTranscriber::new(config);
+        let audio: Vec<f32> = vec![0.0; 48000 + 4800];
+        let events = stream.push_audio(&recognizer, &audio).unwrap();
+        assert_eq!(events, vec![TranscriptEvent::Partial("hi".into())]);
+    }
+
+    #[test]
+    fn test_committed_overlap() {
+        let words = |s: &str| s.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(committed_overlap(&words("a b c"), &words("b c d")), 2);
+        assert_eq!(committed_overlap(&words("a b c"), &words("d e")), 0);
+        assert_eq!(committed_overlap(&words("a b C."), &words("c d")), 1);
+    }
+}
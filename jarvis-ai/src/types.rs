@@ -61,13 +61,29 @@ pub struct MessagePartTool {
     pub response_media: Option<ResponseMedia>,
 }
 
+impl MessagePartTool {
+    /// Create a call that has not run yet
+    pub fn new(function_name: String, parameters: serde_json::Value) -> Self {
+        Self {
+            base: MessagePartBase {
+                id: Uuid::new_v4().to_string(),
+                part_type: MessagePartType::ToolCall,
+            },
+            function_name,
+            parameters,
+            response: String::new(),
+            response_media: None,
+        }
+    }
+}
+
 /// Message part enum
+///
+/// Untagged because each part already carries its `type` in [`MessagePartBase`].
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
+#[serde(untagged)]
 pub enum MessagePart {
-    #[serde(rename = "TEXT")]
     Text(MessagePartText),
-    #[serde(rename = "TOOL_CALL")]
     ToolCall(MessagePartTool),
 }
 
@@ -124,6 +140,39 @@ impl Message {
             })],
         }
     }
+
+    /// Create an assistant message that calls tools, with any text before the calls
+    pub fn assistant_with_tool_calls(text: String, calls: Vec<MessagePartTool>) -> Self {
+        let mut message = Self::assistant(text);
+        if message.text().is_empty() {
+            message.message_parts.clear();
+        }
+        message.message_parts.extend(calls.into_iter().map(MessagePart::ToolCall));
+        message
+    }
+
+    /// Get the tool call parts, in order
+    pub fn tool_calls(&self) -> Vec<&MessagePartTool> {
+        self.message_parts
+            .iter()
+            .filter_map(|part| match part {
+                MessagePart::ToolCall(tool_part) => Some(tool_part),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Get the text of all text parts, joined by newlines
+    pub fn text(&self) -> String {
+        self.message_parts
+            .iter()
+            .filter_map(|part| match part {
+                MessagePart::Text(text_part) => Some(text_part.text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 /// Configuration options for a conversation
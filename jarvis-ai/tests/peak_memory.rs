@@ -0,0 +1,159 @@
+//! Peak memory while downloading and initializing a model
+//!
+//! The model is a real Llama checkpoint, so initializing it builds every
+//! parameter from the memory-mapped download.
+//!
+//! Runs in its own test binary because resident memory is measured for the
+//! whole process.
+#![cfg(target_os = "linux")]
+
+use jarvis_ai::inference::InferenceEngine;
+use jarvis_ai::models::cache::sha256_hex;
+use jarvis_ai::models::{LlmConfig, ModelManifest};
+use jarvis_ai::{Architecture, DownloadConfig, ModelRole, ModelSpec, Quantization};
+use safetensors::tensor::TensorView;
+use safetensors::Dtype;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+
+/// A small Llama checkpoint of about 110 MB, with no tensor over 2 MB
+fn config() -> LlmConfig {
+    LlmConfig {
+        vocab_size: 1024,
+        hidden_size: 512,
+        num_layers: 10,
+        num_attention_heads: 8,
+        intermediate_size: 1024,
+        max_position_embeddings: 64,
+        partial_rotary_factor: 1.0,
+        rope_theta: 10000.0,
+    }
+}
+
+/// Names and shapes of the checkpoint's tensors
+fn tensor_shapes(config: &LlmConfig) -> Vec<(String, Vec<usize>)> {
+    let hidden = config.hidden_size;
+    let mut shapes = vec![
+        ("model.embed_tokens.weight".to_string(), vec![config.vocab_size, hidden]),
+        ("model.norm.weight".to_string(), vec![hidden]),
+        ("lm_head.weight".to_string(), vec![config.vocab_size, hidden]),
+    ];
+    for i in 0..config.num_layers {
+        let name = |module: &str| format!("model.layers.{}.{}.weight", i, module);
+        shapes.extend([
+            (name("input_layernorm"), vec![hidden]),
+            (name("post_attention_layernorm"), vec![hidden]),
+            (name("self_attn.q_proj"), vec![hidden, hidden]),
+            (name("self_attn.k_proj"), vec![hidden, hidden]),
+            (name("self_attn.v_proj"), vec![hidden, hidden]),
+            (name("self_attn.o_proj"), vec![hidden, hidden]),
+            (name("mlp.gate_proj"), vec![config.intermediate_size, hidden]),
+            (name("mlp.up_proj"), vec![config.intermediate_size, hidden]),
+            (name("mlp.down_proj"), vec![hidden, config.intermediate_size]),
+        ]);
+    }
+    shapes
+}
+
+/// A `VmRSS`/`VmHWM` line from `/proc/self/status`, in KB
+fn status_kb(field: &str) -> u64 {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let line = status.lines().find(|line| line.starts_with(field)).unwrap();
+    line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+/// Serve each file for the paths ending in its name, with its LFS hash
+fn serve(files: Vec<(&'static str, Arc<Vec<u8>>)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            let path = String::from_utf8_lossy(&head).split_whitespace().nth(1).unwrap_or_default().to_string();
+            let Some((_, body)) = files.iter().find(|(name, _)| path.ends_with(name)) else {
+                let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+                continue;
+            };
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nx-linked-etag: \"{}\"\r\nconnection: close\r\n\r\n",
+                body.len(),
+                sha256_hex(body)
+            );
+            let _ = stream.write_all(response.as_bytes());
+            if head.starts_with(b"GET") {
+                let _ = stream.write_all(body);
+            }
+        }
+    });
+    url
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_peak_memory_near_model_size() {
+    let config = config();
+    let shapes = tensor_shapes(&config);
+    let data: Vec<Vec<u8>> = shapes
+        .iter()
+        .map(|(_, shape)| 0.01f32.to_le_bytes().repeat(shape.iter().product()))
+        .collect();
+    let views = shapes
+        .iter()
+        .zip(&data)
+        .map(|((name, shape), data)| (name.as_str(), TensorView::new(Dtype::F32, shape.clone(), data).unwrap()));
+    let body = Arc::new(safetensors::serialize(views, &None).unwrap());
+    drop(data);
+    let model_kb = body.len() as u64 / 1024;
+    let tokenizer = br#"{"added_tokens": [{"id": 0, "content": "</s>"}], "model": {"vocab": {"a": 1}}}"#;
+    let url = serve(vec![("model.safetensors", body), ("tokenizer.json", Arc::new(tokenizer.to_vec()))]);
+
+    let cache = std::env::temp_dir().join(format!("jarvis-peak-memory-{}", std::process::id()));
+    std::env::set_var("JARVIS_MODEL_CACHE", &cache);
+
+    let spec = ModelSpec {
+        id: "peak-memory".to_string(),
+        architecture: Architecture::Llm(config),
+        repo: "test/peak-memory".to_string(),
+        files: ModelManifest::single(&["tokenizer.json"]),
+        quantization: Quantization::F32,
+        size_mb: (model_kb / 1024) as u32,
+        ram_mb: (model_kb / 1024) as u32,
+        chat_template: None,
+    };
+    let mut engine = InferenceEngine::new();
+    engine.set_download_config(DownloadConfig {
+        endpoint: url,
+        ..DownloadConfig::default()
+    });
+
+    // Writing 5 resets the peak (VmHWM) to the current resident set
+    std::fs::write("/proc/self/clear_refs", "5").unwrap();
+    let baseline_kb = status_kb("VmRSS:");
+
+    engine.download_model(spec, |_, _| {}).await.unwrap();
+    engine.initialize_model(ModelRole::TextGeneration).unwrap();
+
+    let peak_kb = status_kb("VmHWM:").saturating_sub(baseline_kb);
+    let retained_kb = status_kb("VmRSS:").saturating_sub(baseline_kb);
+    std::fs::remove_dir_all(&cache).unwrap();
+
+    assert!(!engine.has_model_data(ModelRole::TextGeneration));
+    assert!(
+        peak_kb < model_kb * 5 / 4,
+        "peak grew by {} KB for a {} KB model",
+        peak_kb,
+        model_kb
+    );
+    // The parameters stay resident, but the mapped file does not
+    assert!(
+        retained_kb > model_kb * 3 / 4 && retained_kb < model_kb * 5 / 4,
+        "{} KB still resident after initializing a {} KB model",
+        retained_kb,
+        model_kb
+    );
+}
@@ -1,4 +1,4 @@
-use jarvis_ai::{Message, MessagePart, MessageRole};
+use jarvis_ai::{Message, MessagePart, MessageRole, OutputFilters};
 use leptos::prelude::*;
 
 /// Message view component
@@ -11,12 +11,14 @@ pub fn MessageView(message: Message) -> impl IntoView {
         "bg-gray-700"
     };
     let align_class = if is_user { "ml-auto" } else { "mr-auto" };
+    // Replies are cleaned of the markdown, emojis and ellipses the persona forbids
+    let filters = if is_user { OutputFilters::default() } else { OutputFilters::display() };
 
     let content = message
         .message_parts
         .iter()
         .map(|part| match part {
-            MessagePart::Text(text_part) => text_part.text.clone(),
+            MessagePart::Text(text_part) => filters.apply(&text_part.text),
             MessagePart::ToolCall(tool_part) => {
                 format!("[Tool: {}] {}", tool_part.function_name, tool_part.response)
             }
@@ -10,11 +10,13 @@ mod pages;
 mod state;
 mod utils;
 
-use pages::{chat::ChatPage, home::HomePage, mcp::McpPage};
+use pages::{chat::ChatPage, home::HomePage, mcp::McpPage, settings::SettingsPage};
 
 /// Main application component
 #[component]
 pub fn App() -> impl IntoView {
+    state::provide_app_state();
+
     view! {
         <Router>
             <main class="min-h-screen bg-gradient-to-br from-slate-900 via-slate-800 to-slate-900">
@@ -22,6 +24,7 @@ pub fn App() -> impl IntoView {
                     <Route path=path!("/") view=HomePage/>
                     <Route path=path!("/chat") view=ChatPage/>
                     <Route path=path!("/mcp") view=McpPage/>
+                    <Route path=path!("/settings") view=SettingsPage/>
                 </Routes>
             </main>
         </Router>
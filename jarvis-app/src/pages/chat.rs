@@ -1,5 +1,6 @@
 use crate::components::{Button, MessageView};
-use crate::state::AiService;
+use crate::state::{use_app_state, AiService};
+use crate::utils::speech;
 use jarvis_ai::{Message, ModelType};
 use leptos::prelude::*;
 use leptos_router::hooks::use_navigate;
@@ -13,11 +14,19 @@ pub fn ChatPage() -> impl IntoView {
     let (is_loading, set_loading) = signal(false);
     let (model_status, set_model_status) = signal("Not loaded".to_string());
     let (error_msg, set_error) = signal(Option::<String>::None);
+    let (speak_replies, set_speak_replies) = signal(false);
     let navigate = use_navigate();
 
     // Create AI service
     let ai_service = Rc::new(AiService::new());
 
+    // Apply the stored download settings, and any changes made on the settings page
+    {
+        let service = ai_service.clone();
+        let download_config = use_app_state().download_config;
+        Effect::new(move |_| service.set_download_config(download_config.get()));
+    }
+
     // Initialize model on mount
     let ai_service_init = ai_service.clone();
     {
@@ -37,6 +46,30 @@ pub fn ChatPage() -> impl IntoView {
         });
     }
 
+    // Load a model from files picked or dropped by the user
+    let ai_service_local = ai_service.clone();
+    let load_local = Rc::new(move |files: web_sys::FileList| {
+        let service = ai_service_local.clone();
+        let id = jarvis_ai::models::local::file_list_id(&files);
+        leptos::task::spawn_local(async move {
+            set_model_status.set("Reading model files...".to_string());
+            let result = match jarvis_ai::models::local::read_file_list(&files).await {
+                Ok(files) => service.load_from_files(&id, files),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(role) => set_model_status.set(format!("Local {} model {} ready", role.name(), id)),
+                Err(e) => {
+                    set_model_status.set(format!("Load failed: {}", e));
+                    log::error!("Failed to load local model: {}", e);
+                }
+            }
+        });
+    });
+    let load_picked = load_local.clone();
+    let load_picked_dir = load_local.clone();
+    let load_dropped = load_local.clone();
+
     let ai_service_send = ai_service.clone();
     let do_send = Rc::new(move || {
         let text = input.get();
@@ -58,6 +91,11 @@ pub fn ChatPage() -> impl IntoView {
             // Try to generate response using AI
             match service.generate(&msgs) {
                 Ok(response_text) => {
+                    if speak_replies.get_untracked() {
+                        if let Err(e) = speech::speak(&response_text) {
+                            log::warn!("Speech synthesis failed: {:?}", e);
+                        }
+                    }
                     let response = Message::assistant(response_text);
                     set_messages.update(|msgs| msgs.push(response));
                 }
@@ -95,12 +133,28 @@ pub fn ChatPage() -> impl IntoView {
         set_error.set(None);
     };
 
+    let toggle_voice = move || {
+        if speak_replies.get_untracked() {
+            speech::stop();
+        }
+        set_speak_replies.update(|on| *on = !*on);
+    };
+
     let go_home = move || {
         navigate("/", Default::default());
     };
 
     view! {
-        <div class="min-h-screen flex flex-col p-8 bg-gray-900">
+        <div
+            class="min-h-screen flex flex-col p-8 bg-gray-900"
+            on:dragover=move |ev: web_sys::DragEvent| ev.prevent_default()
+            on:drop=move |ev: web_sys::DragEvent| {
+                ev.prevent_default();
+                if let Some(files) = ev.data_transfer().and_then(|transfer| transfer.files()) {
+                    load_dropped(files);
+                }
+            }
+        >
             // Header
             <div class="flex items-center justify-between mb-6">
                 <div class="flex items-center gap-4">
@@ -110,6 +164,43 @@ pub fn ChatPage() -> impl IntoView {
                     </span>
                 </div>
                 <div class="flex gap-2">
+                    <label
+                        class="px-4 py-2 rounded-lg bg-gray-700 text-gray-200 hover:bg-gray-600 cursor-pointer"
+                        title="Choose config.json, tokenizer and weights, or drop them on the page"
+                    >
+                        "Open model files"
+                        <input
+                            type="file"
+                            multiple
+                            class="hidden"
+                            on:change=move |ev| {
+                                let input: web_sys::HtmlInputElement = event_target(&ev);
+                                if let Some(files) = input.files() {
+                                    load_picked(files);
+                                }
+                            }
+                        />
+                    </label>
+                    <label
+                        class="px-4 py-2 rounded-lg bg-gray-700 text-gray-200 hover:bg-gray-600 cursor-pointer"
+                        title="Choose a model directory, such as a HuggingFace snapshot"
+                    >
+                        "Open model folder"
+                        <input
+                            type="file"
+                            prop:webkitdirectory=true
+                            class="hidden"
+                            on:change=move |ev| {
+                                let input: web_sys::HtmlInputElement = event_target(&ev);
+                                if let Some(files) = input.files() {
+                                    load_picked_dir(files);
+                                }
+                            }
+                        />
+                    </label>
+                    <Button on_click=Box::new(toggle_voice) variant=crate::components::button::ButtonVariant::Secondary>
+                        {move || if speak_replies.get() { "Voice on" } else { "Voice off" }}
+                    </Button>
                     <Button on_click=Box::new(clear_chat) variant=crate::components::button::ButtonVariant::Secondary>
                         "Clear"
                     </Button>
@@ -8,6 +8,7 @@ pub fn HomePage() -> impl IntoView {
     let (is_listening, set_listening) = signal(false);
     let navigate = use_navigate();
     let navigate_clone = navigate.clone();
+    let navigate_settings = navigate.clone();
 
     let toggle_listening = move || {
         set_listening.update(|listening| *listening = !*listening);
@@ -21,6 +22,10 @@ pub fn HomePage() -> impl IntoView {
         navigate_clone("/mcp", Default::default());
     };
 
+    let go_to_settings = move || {
+        navigate_settings("/settings", Default::default());
+    };
+
     view! {
         <div class="min-h-screen flex flex-col items-center justify-center p-8">
             <h1 class="text-6xl font-bold text-white mb-12">JARVIS</h1>
@@ -47,6 +52,9 @@ pub fn HomePage() -> impl IntoView {
                 <Button on_click=Box::new(go_to_mcp) variant=crate::components::button::ButtonVariant::Secondary>
                     "MCP Settings"
                 </Button>
+                <Button on_click=Box::new(go_to_settings) variant=crate::components::button::ButtonVariant::Secondary>
+                    "Settings"
+                </Button>
             </div>
         </div>
     }
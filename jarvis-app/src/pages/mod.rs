@@ -1,3 +1,4 @@
 pub mod chat;
 pub mod home;
 pub mod mcp;
+pub mod settings;
@@ -0,0 +1,127 @@
+use crate::components::Button;
+use crate::state::use_app_state;
+use jarvis_ai::DownloadConfig;
+use leptos::prelude::*;
+use leptos_router::hooks::use_navigate;
+
+/// Model download settings page
+#[component]
+pub fn SettingsPage() -> impl IntoView {
+    let state = use_app_state();
+    let current = state.download_config.get_untracked();
+    let (endpoint, set_endpoint) = signal(current.endpoint.clone());
+    let (mirrors, set_mirrors) = signal(current.mirrors.join("\n"));
+    let (revision, set_revision) = signal(current.revision.clone());
+    let (token, set_token) = signal(current.token.clone().unwrap_or_default());
+    let (token_for_mirrors, set_token_for_mirrors) = signal(current.token_for_mirrors);
+    let (remember_token, set_remember_token) = signal(state.remember_token.get_untracked());
+    let (saved, set_saved) = signal(false);
+    let navigate = use_navigate();
+
+    let save = move || {
+        let token = token.get();
+        let config = DownloadConfig {
+            revision: Some(revision.get().trim().to_string())
+                .filter(|revision| !revision.is_empty())
+                .unwrap_or_else(|| DownloadConfig::default().revision),
+            endpoint: endpoint.get().trim().to_string(),
+            mirrors: mirrors
+                .get()
+                .lines()
+                .map(str::trim)
+                .filter(|mirror| !mirror.is_empty())
+                .map(str::to_string)
+                .collect(),
+            token: Some(token.trim().to_string()).filter(|token| !token.is_empty()),
+            token_for_mirrors: token_for_mirrors.get(),
+        };
+        state.set_download_config(config, remember_token.get());
+        set_saved.set(true);
+    };
+
+    let go_home = move || {
+        navigate("/", Default::default());
+    };
+
+    let input_class = "w-full bg-gray-700 text-white px-4 py-2 rounded-lg focus:outline-none focus:ring-2 focus:ring-blue-500";
+
+    view! {
+        <div class="min-h-screen p-8">
+            <div class="flex items-center justify-between mb-8">
+                <h1 class="text-4xl font-bold text-white">"Settings"</h1>
+                <Button on_click=Box::new(go_home) variant=crate::components::button::ButtonVariant::Secondary>
+                    "Back to Home"
+                </Button>
+            </div>
+
+            <div class="max-w-2xl mx-auto bg-gray-800 rounded-lg p-6">
+                <h2 class="text-2xl font-bold text-white mb-4">"Model Downloads"</h2>
+                <div class="space-y-4">
+                    <label class="block text-gray-300">
+                        "Hub endpoint"
+                        <input
+                            type="url"
+                            class=input_class
+                            placeholder="https://huggingface.co"
+                            prop:value=move || endpoint.get()
+                            on:input=move |ev| set_endpoint.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <label class="block text-gray-300">
+                        "Mirrors, one per line, tried in order when the endpoint fails"
+                        <textarea
+                            rows="3"
+                            class=input_class
+                            prop:value=move || mirrors.get()
+                            on:input=move |ev| set_mirrors.set(event_target_value(&ev))
+                        ></textarea>
+                    </label>
+                    <label class="block text-gray-300">
+                        "Revision"
+                        <input
+                            type="text"
+                            class=input_class
+                            placeholder="main"
+                            prop:value=move || revision.get()
+                            on:input=move |ev| set_revision.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <label class="block text-gray-300">
+                        "Access token for gated repositories"
+                        <input
+                            type="password"
+                            autocomplete="off"
+                            class=input_class
+                            prop:value=move || token.get()
+                            on:input=move |ev| set_token.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <label class="flex items-center gap-2 text-gray-300">
+                        <input
+                            type="checkbox"
+                            prop:checked=move || token_for_mirrors.get()
+                            on:change=move |ev| set_token_for_mirrors.set(event_target_checked(&ev))
+                        />
+                        "Also send the token to the mirrors"
+                    </label>
+                    <label class="flex items-center gap-2 text-gray-300">
+                        <input
+                            type="checkbox"
+                            prop:checked=move || remember_token.get()
+                            on:change=move |ev| set_remember_token.set(event_target_checked(&ev))
+                        />
+                        "Remember the token in this browser"
+                    </label>
+                    <p class="text-sm text-yellow-300">
+                        "A remembered token is saved unencrypted in local storage, where scripts \
+                        on this page can read it. Otherwise it is forgotten when the page closes."
+                    </p>
+                    <div class="flex items-center gap-4">
+                        <Button on_click=Box::new(save)>"Save"</Button>
+                        {move || saved.get().then(|| view! { <span class="text-green-400">"Saved"</span> })}
+                    </div>
+                </div>
+            </div>
+        </div>
+    }
+}
@@ -4,12 +4,16 @@
 //! This module provides global state management for the JARVIS application
 //! using Leptos signals and context.
 
-use jarvis_ai::{InferenceConfig, InferenceEngine, Message, ModelType};
+use crate::utils::storage::LocalStorage;
+use jarvis_ai::{DownloadConfig, InferenceConfig, InferenceEngine, Message, ModelRole, ModelType};
 use jarvis_mcp::{McpClient, McpServerConfig};
 use leptos::prelude::*;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// Local storage key of the model download settings
+const DOWNLOAD_CONFIG_KEY: &str = "jarvis.download_config";
+
 /// Global application state
 #[derive(Clone)]
 pub struct AppState {
@@ -27,11 +31,16 @@ pub struct AppState {
     pub mcp_servers: RwSignal<Vec<McpServerConfig>>,
     /// Inference configuration
     pub inference_config: RwSignal<InferenceConfig>,
+    /// Model hub endpoint, mirrors, access token and revision
+    pub download_config: RwSignal<DownloadConfig>,
+    /// Whether the access token is saved to local storage with the other download settings
+    pub remember_token: RwSignal<bool>,
 }
 
 impl AppState {
     /// Create a new application state
     pub fn new() -> Self {
+        let download_config = Self::stored_download_config();
         Self {
             messages: RwSignal::new(Vec::new()),
             is_processing: RwSignal::new(false),
@@ -40,7 +49,36 @@ impl AppState {
             error: RwSignal::new(None),
             mcp_servers: RwSignal::new(Vec::new()),
             inference_config: RwSignal::new(InferenceConfig::default()),
+            remember_token: RwSignal::new(download_config.token.is_some()),
+            download_config: RwSignal::new(download_config),
+        }
+    }
+
+    /// Download settings saved in local storage, or the defaults
+    fn stored_download_config() -> DownloadConfig {
+        // Local storage only exists in the browser
+        if !cfg!(target_arch = "wasm32") {
+            return DownloadConfig::default();
+        }
+        LocalStorage::get(DOWNLOAD_CONFIG_KEY)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Update the download settings and save them to local storage
+    ///
+    /// Local storage is not encrypted, so the access token is left out unless
+    /// `remember_token` is set; it then lasts until the page is closed.
+    pub fn set_download_config(&self, config: DownloadConfig, remember_token: bool) {
+        let stored = DownloadConfig {
+            token: config.token.clone().filter(|_| remember_token),
+            ..config.clone()
+        };
+        if let (true, Ok(json)) = (cfg!(target_arch = "wasm32"), serde_json::to_string(&stored)) {
+            let _ = LocalStorage::set(DOWNLOAD_CONFIG_KEY, &json);
         }
+        self.remember_token.set(remember_token);
+        self.download_config.set(config);
     }
 
     /// Add a user message to the conversation
@@ -113,7 +151,18 @@ impl AiService {
     /// Load a model
     pub fn load_model(&self, model: ModelType) -> Result<(), String> {
         let mut engine = self.engine.borrow_mut();
-        engine.load_model(model)
+        engine.load_model(model).map_err(|e| e.to_string())
+    }
+
+    /// Load a model from files the user picked or dropped on the page
+    pub fn load_from_files(&self, name: &str, files: Vec<(String, Vec<u8>)>) -> Result<ModelRole, String> {
+        let files = jarvis_ai::models::local::files_from_bytes(files)?;
+        self.engine.borrow_mut().load_from_files(name, files)
+    }
+
+    /// Set where and how models are downloaded
+    pub fn set_download_config(&self, config: DownloadConfig) {
+        self.engine.borrow_mut().set_download_config(config);
     }
 
     /// Generate a response from messages
@@ -121,17 +170,22 @@ impl AiService {
         self.engine.borrow().generate(messages)
     }
 
+    /// Embed texts for semantic search
+    pub fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        self.engine.borrow().embed(texts)
+    }
+
     /// Transcribe audio
     pub fn transcribe(&self, audio: &[f32]) -> Result<String, String> {
         self.engine.borrow().transcribe(audio)
     }
 
-    /// Check if model is ready
-    pub fn is_ready(&self) -> bool {
-        self.engine.borrow().is_ready()
+    /// Check if the model for a role is ready
+    pub fn is_ready(&self, role: ModelRole) -> bool {
+        self.engine.borrow().is_ready(role)
     }
 
-    /// Check if model is loading
+    /// Check if any model is loading
     pub fn is_loading(&self) -> bool {
         self.engine.borrow().is_loading()
     }
@@ -198,5 +252,7 @@ mod tests {
         assert!(state.messages.get_untracked().is_empty());
         assert!(!state.is_processing.get_untracked());
         assert!(state.current_model.get_untracked().is_none());
+        assert_eq!(state.download_config.get_untracked(), DownloadConfig::default());
+        assert!(!state.remember_token.get_untracked());
     }
 }
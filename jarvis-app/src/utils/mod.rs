@@ -1,2 +1,3 @@
 // Utility modules
+pub mod speech;
 pub mod storage;
@@ -0,0 +1,26 @@
+use jarvis_ai::OutputFilters;
+use wasm_bindgen::prelude::*;
+use web_sys::{window, SpeechSynthesisUtterance};
+
+/// Read a reply aloud with the browser's speech synthesis
+///
+/// The reply goes through the speech filters first, so markdown and emojis
+/// are not read out and numbers are spoken as words. Anything still being
+/// spoken is cut off.
+pub fn speak(reply: &str) -> Result<(), JsValue> {
+    let text = OutputFilters::speech().apply(reply);
+    if text.is_empty() {
+        return Ok(());
+    }
+    let synthesis = window().ok_or("No window")?.speech_synthesis()?;
+    synthesis.cancel();
+    synthesis.speak(&SpeechSynthesisUtterance::new_with_text(&text)?);
+    Ok(())
+}
+
+/// Stop speaking
+pub fn stop() {
+    if let Some(synthesis) = window().and_then(|window| window.speech_synthesis().ok()) {
+        synthesis.cancel();
+    }
+}
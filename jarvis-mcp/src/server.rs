@@ -5,34 +5,49 @@ use wasm_bindgen::JsCast;
 use web_sys::{HtmlCanvasElement, HtmlVideoElement, MediaStream, MediaStreamConstraints};
 
 /// Memories server for storing conversation context
+///
+/// Memories can be stored with an embedding (e.g. from
+/// `InferenceEngine::embed`) to be found by meaning with [`Self::search_similar`].
 pub struct MemoriesServer {
     memories: Vec<String>,
+    /// Embedding of each memory, if it was stored with one
+    embeddings: Vec<Option<Vec<f32>>>,
     max_memories: usize,
 }
 
 impl MemoriesServer {
     /// Create a new memories server
     pub fn new() -> Self {
-        Self {
-            memories: Vec::new(),
-            max_memories: 100,
-        }
+        Self::with_limit(100)
     }
 
     /// Create with custom max memories limit
     pub fn with_limit(max_memories: usize) -> Self {
         Self {
             memories: Vec::new(),
+            embeddings: Vec::new(),
             max_memories,
         }
     }
 
     /// Store a memory
     pub fn store(&mut self, memory: String) {
+        self.push(memory, None);
+    }
+
+    /// Store a memory with its embedding, for semantic search
+    pub fn store_with_embedding(&mut self, memory: String, embedding: Vec<f32>) {
+        self.push(memory, Some(embedding));
+    }
+
+    fn push(&mut self, memory: String, embedding: Option<Vec<f32>>) {
         if self.memories.len() >= self.max_memories {
-            self.memories.remove(0); // Remove oldest
+            // Remove oldest
+            self.memories.remove(0);
+            self.embeddings.remove(0);
         }
         self.memories.push(memory);
+        self.embeddings.push(embedding);
     }
 
     /// Retrieve all memories
@@ -55,9 +70,26 @@ impl MemoriesServer {
             .collect()
     }
 
+    /// Find the memories most similar in meaning to a query embedding
+    ///
+    /// Memories stored without an embedding are skipped. Results are ordered
+    /// by cosine similarity, highest first.
+    pub fn search_similar(&self, query: &[f32], limit: usize) -> Vec<(&String, f32)> {
+        let mut results: Vec<(&String, f32)> = self
+            .memories
+            .iter()
+            .zip(&self.embeddings)
+            .filter_map(|(memory, embedding)| Some((memory, cosine_similarity(query, embedding.as_ref()?))))
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1));
+        results.truncate(limit);
+        results
+    }
+
     /// Clear all memories
     pub fn clear(&mut self) {
         self.memories.clear();
+        self.embeddings.clear();
     }
 
     /// Get memory count
@@ -66,6 +98,16 @@ impl MemoriesServer {
     }
 }
 
+/// Cosine similarity of two vectors; 0 if either is zero or their lengths differ
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
 impl Default for MemoriesServer {
     fn default() -> Self {
         Self::new()
@@ -302,6 +344,24 @@ mod tests {
         assert_eq!(results.len(), 2);
     }
 
+    #[test]
+    fn test_memories_search_similar() {
+        let mut server = MemoriesServer::with_limit(3);
+        server.store_with_embedding("Meeting with John".to_string(), vec![1.0, 0.0]);
+        server.store_with_embedding("Lunch appointment".to_string(), vec![0.0, 1.0]);
+        server.store("No embedding".to_string());
+
+        let results = server.search_similar(&[0.9, 0.1], 5);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "Meeting with John");
+        assert!(results[0].1 > results[1].1);
+        assert_eq!(server.search_similar(&[0.0, 1.0], 1)[0].0, "Lunch appointment");
+
+        // Evicting the oldest memory drops its embedding too
+        server.store_with_embedding("Dinner".to_string(), vec![1.0, 0.0]);
+        assert_eq!(server.search_similar(&[1.0, 0.0], 1)[0].0, "Dinner");
+    }
+
     #[test]
     fn test_memories_recent() {
         let mut server = MemoriesServer::new();